@@ -0,0 +1,418 @@
+//! HTTP API to control a user, mirrors the chat commands.
+//!
+//! Every request except `POST /api/token` needs an `Authorization: Bearer <token>`
+//! header. Tokens are random, only kept in memory and expire, changing the
+//! password invalidates them.
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{config, screenshot, switcher, user_manager::UserManager, ws, Noalbs};
+
+/// How long an issued token can be used
+const TOKEN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Tokens issued by `POST /api/token`, a restart logs everyone out
+static TOKENS: Lazy<Mutex<Tokens>> = Lazy::new(Default::default);
+
+#[derive(Debug)]
+enum ApiError {
+    Unauthorized,
+    InvalidLogin,
+    NoChat,
+    SwitchFailed(String),
+    InvalidValue(String),
+}
+
+impl warp::reject::Reject for ApiError {}
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct Enabled {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct TriggerValue {
+    /// Use 0 to disable the trigger
+    value: u32,
+}
+
+#[derive(Deserialize)]
+struct Scene {
+    scene: String,
+}
+
+#[derive(Deserialize)]
+struct ChatSetup {
+    platform: config::ConfigChatPlatform,
+    username: String,
+}
+
+#[derive(Serialize)]
+struct Token {
+    token: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    bitrate_switcher_enabled: bool,
+    notify: bool,
+
+    /// Not available when the user has no chat configured
+    autostop: Option<bool>,
+
+    triggers: switcher::Triggers,
+    current_scene: String,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+    error: String,
+}
+
+struct IssuedToken {
+    token: String,
+    username: String,
+
+    /// Password hash at the time the token was issued
+    password_hash: String,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Tokens {
+    issued: Vec<IssuedToken>,
+}
+
+impl Tokens {
+    /// Issues a new random token for the user
+    fn issue(&mut self, username: &str, password_hash: &str, now: Instant) -> String {
+        self.issued.retain(|t| t.expires > now);
+
+        let token = ws::generate_token();
+        self.issued.push(IssuedToken {
+            token: token.to_owned(),
+            username: username.to_owned(),
+            password_hash: password_hash.to_owned(),
+            expires: now + TOKEN_TTL,
+        });
+
+        token
+    }
+
+    /// Username and password hash the token was issued for, every token is
+    /// compared in full so the time taken doesn't give the token away
+    fn find(&self, bearer: &str, now: Instant) -> Option<(&str, &str)> {
+        let mut found = None;
+
+        for issued in &self.issued {
+            if constant_time_eq(issued.token.as_bytes(), bearer.as_bytes()) && issued.expires > now
+            {
+                found = Some((issued.username.as_str(), issued.password_hash.as_str()));
+            }
+        }
+
+        found
+    }
+}
+
+/// Compares without returning early on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+pub fn routes(
+    user_manager: UserManager,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let users = warp::any().map(move || user_manager.clone());
+
+    let login = warp::path!("api" / "token")
+        .and(warp::post())
+        .and(users.clone())
+        .and(warp::body::json())
+        .and_then(login);
+
+    // No token, the links are opened by mods in their browser
+    let screenshot = warp::path!("screenshots" / String / String)
+        .and(warp::get())
+        .and(users.clone())
+        .and_then(get_screenshot);
+
+    let user = warp::header::optional::<String>("authorization")
+        .and(users)
+        .and_then(authenticate);
+
+    let status = warp::path!("api" / "status")
+        .and(warp::get())
+        .and(user.clone())
+        .and_then(get_status);
+
+    let trigger = warp::path!("api" / "triggers" / switcher::TriggerType)
+        .and(warp::put())
+        .and(user.clone())
+        .and(warp::body::json())
+        .and_then(
+            |kind: switcher::TriggerType, user: Arc<Noalbs>, body: TriggerValue| async move {
+                user.update_trigger(kind, body.value)
+                    .await
+                    .map_err(|e| warp::reject::custom(ApiError::InvalidValue(e.to_string())))?;
+
+                save_and_status(&user).await
+            },
+        );
+
+    let bitrate_switcher = warp::path!("api" / "switcher")
+        .and(warp::put())
+        .and(user.clone())
+        .and(warp::body::json())
+        .and_then(|user: Arc<Noalbs>, body: Enabled| async move {
+            if let Err(e) = user.set_bitrate_switcher_state(body.enabled).await {
+                tracing::error!("Error saving config: {}", e);
+            }
+
+            get_status(user).await
+        });
+
+    let notify = warp::path!("api" / "notify")
+        .and(warp::put())
+        .and(user.clone())
+        .and(warp::body::json())
+        .and_then(|user: Arc<Noalbs>, body: Enabled| async move {
+            user.set_notify(body.enabled).await;
+            save_and_status(&user).await
+        });
+
+    let autostop = warp::path!("api" / "autostop")
+        .and(warp::put())
+        .and(user.clone())
+        .and(warp::body::json())
+        .and_then(|user: Arc<Noalbs>, body: Enabled| async move {
+            user.set_autostop(body.enabled)
+                .await
+                .map_err(|_| warp::reject::custom(ApiError::NoChat))?;
+            save_and_status(&user).await
+        });
+
+    let chat = warp::path!("api" / "chat")
+        .and(warp::put())
+        .and(user.clone())
+        .and(warp::body::json())
+        .and_then(|user: Arc<Noalbs>, body: ChatSetup| async move {
+            user.enable_chat(body.platform, body.username)
+                .await
+                .map_err(|e| warp::reject::custom(ApiError::InvalidValue(e.to_string())))?;
+            get_status(user).await
+        });
+
+    let scene = warp::path!("api" / "scene")
+        .and(warp::post())
+        .and(user)
+        .and(warp::body::json())
+        .and_then(|user: Arc<Noalbs>, body: Scene| async move {
+            user.switch_scene(&body.scene)
+                .await
+                .map_err(|e| warp::reject::custom(ApiError::SwitchFailed(e.to_string())))?;
+            get_status(user).await
+        });
+
+    login
+        .or(status)
+        .or(trigger)
+        .or(bitrate_switcher)
+        .or(notify)
+        .or(autostop)
+        .or(chat)
+        .or(scene)
+        .or(screenshot)
+        .recover(handle_rejection)
+}
+
+async fn login(user_manager: UserManager, login: Login) -> Result<impl Reply, Rejection> {
+    let users = user_manager.get();
+    let users = users.read().await;
+
+    let user = users
+        .get(&login.username)
+        .ok_or_else(|| warp::reject::custom(ApiError::InvalidLogin))?;
+
+    let password_hash = { user.state.read().await.config.user.password_hash.clone() };
+    let password_hash =
+        password_hash.ok_or_else(|| warp::reject::custom(ApiError::InvalidLogin))?;
+
+    if !ws::verify(&password_hash, login.password.as_bytes()) {
+        return Err(warp::reject::custom(ApiError::InvalidLogin));
+    }
+
+    let token = TOKENS
+        .lock()
+        .unwrap()
+        .issue(&login.username, &password_hash, Instant::now());
+
+    Ok(warp::reply::json(&Token { token }))
+}
+
+/// Finds the user the bearer token belongs to
+async fn authenticate(
+    header: Option<String>,
+    user_manager: UserManager,
+) -> Result<Arc<Noalbs>, Rejection> {
+    let bearer = header
+        .as_deref()
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .ok_or_else(|| warp::reject::custom(ApiError::Unauthorized))?;
+
+    let (username, issued_hash) = TOKENS
+        .lock()
+        .unwrap()
+        .find(bearer, Instant::now())
+        .map(|(username, hash)| (username.to_owned(), hash.to_owned()))
+        .ok_or_else(|| warp::reject::custom(ApiError::Unauthorized))?;
+
+    let user = user_manager
+        .get_user(&username)
+        .await
+        .ok_or_else(|| warp::reject::custom(ApiError::Unauthorized))?;
+
+    let password_hash = { user.state.read().await.config.user.password_hash.clone() };
+
+    if password_hash.as_deref() != Some(issued_hash.as_str()) {
+        return Err(warp::reject::custom(ApiError::Unauthorized));
+    }
+
+    Ok(user)
+}
+
+async fn get_status(user: Arc<Noalbs>) -> Result<warp::reply::Json, Rejection> {
+    let state = user.state.read().await;
+
+    let status = Status {
+        bitrate_switcher_enabled: state.config.switcher.bitrate_switcher_enabled,
+        notify: state.config.switcher.auto_switch_notification,
+        autostop: state
+            .config
+            .chat
+            .as_ref()
+            .map(|c| c.enable_auto_stop_stream_on_host_or_raid),
+        triggers: state.config.switcher.triggers.clone(),
+        current_scene: state.broadcasting_software.current_scene.to_owned(),
+    };
+
+    Ok(warp::reply::json(&status))
+}
+
+/// Serves a screenshot saved in the screenshot directory of the user
+async fn get_screenshot(
+    username: String,
+    file: String,
+    user_manager: UserManager,
+) -> Result<impl Reply, Rejection> {
+    if !screenshot::is_screenshot(&file) {
+        return Err(warp::reject::not_found());
+    }
+
+    let directory = {
+        let users = user_manager.get();
+        let users = users.read().await;
+        let user = users.get(&username).ok_or_else(warp::reject::not_found)?;
+        let state = user.state.read().await;
+
+        state.config.optional_options.screenshot.directory.clone()
+    };
+
+    let directory = directory.ok_or_else(warp::reject::not_found)?;
+    let image = tokio::fs::read(std::path::Path::new(&directory).join(&file))
+        .await
+        .map_err(|_| warp::reject::not_found())?;
+
+    Ok(warp::reply::with_header(
+        image,
+        "content-type",
+        "image/jpeg",
+    ))
+}
+
+async fn save_and_status(user: &Arc<Noalbs>) -> Result<warp::reply::Json, Rejection> {
+    if let Err(e) = user.save_config().await {
+        tracing::error!("Error saving config: {}", e);
+    }
+
+    get_status(user.clone()).await
+}
+
+async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Infallible> {
+    let (code, error) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found".to_string())
+    } else if let Some(e) = rejection.find::<ApiError>() {
+        match e {
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "Invalid token".to_string()),
+            ApiError::InvalidLogin => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid username or password".to_string(),
+            ),
+            ApiError::NoChat => (StatusCode::BAD_REQUEST, "No chat configured".to_string()),
+            ApiError::SwitchFailed(e) => (StatusCode::BAD_GATEWAY, e.to_owned()),
+            ApiError::InvalidValue(e) => (StatusCode::BAD_REQUEST, e.to_owned()),
+        }
+    } else if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        tracing::debug!("Invalid API request body: {}", e);
+        (StatusCode::BAD_REQUEST, "Invalid request body".to_string())
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method not allowed".to_string(),
+        )
+    } else {
+        // Only logged, the details are of no use to the client
+        tracing::debug!("Unhandled API rejection: {:?}", rejection);
+        (StatusCode::BAD_REQUEST, "Bad request".to_string())
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorMessage { error }),
+        code,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_tokens_expire() {
+        let mut tokens = Tokens::default();
+        let now = Instant::now();
+
+        let first = tokens.issue("user", "$argon2id$hash", now);
+        let second = tokens.issue("user", "$argon2id$hash", now);
+
+        assert_ne!(first, second);
+        assert_eq!(tokens.find(&first, now), Some(("user", "$argon2id$hash")));
+        assert_eq!(tokens.find("not a token", now), None);
+        assert_eq!(tokens.find(&first, now + TOKEN_TTL), None);
+    }
+
+    #[test]
+    fn compare_tokens() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokem"));
+        assert!(!constant_time_eq(b"token", b"tokens"));
+    }
+}
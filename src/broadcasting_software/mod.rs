@@ -1,15 +1,72 @@
+use std::{collections::HashSet, time::Duration};
+
 use async_trait::async_trait;
 use tokio::sync;
+use tracing::{error, info, warn};
 
-use crate::{error::Error, state};
+use crate::{config, error::Error, noalbs, state};
 
 pub mod obs;
 pub mod obs_v5;
+pub mod supervisor;
+pub mod vmix;
+
+/// How often OBS is asked whether it's still there
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long OBS gets to answer a heartbeat
+pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Missed heartbeats in a row before reconnecting
+pub const HEARTBEAT_MAX_MISSED: u32 = 2;
+
+/// How long the software gets to show the new scene after a switch,
+/// on top of the transition duration
+const SCENE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the current scene is read back while waiting for a switch
+const SCENE_CONFIRM_POLL: Duration = Duration::from_millis(200);
+
+/// Minimum similarity to pick a scene when the name isn't exactly the same
+const MIN_SCENE_SIMILARITY: f64 = 0.5;
+
+/// Status of the broadcasting software
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub scene: String,
+    pub streaming: bool,
+    pub recording: bool,
+}
 
 #[async_trait]
 pub trait BroadcastingSoftwareLogic: Send + Sync {
     async fn switch_scene(&self, scene: &str) -> Result<String, Error>;
 
+    /// Switches the scene using the transition, software without transition
+    /// support will just switch the scene
+    async fn switch_scene_with_transition(
+        &self,
+        scene: &str,
+        _transition: &config::SceneTransition,
+    ) -> Result<String, Error> {
+        self.switch_scene(scene).await
+    }
+
+    /// Currently selected transition and its duration
+    async fn current_transition(&self) -> Result<config::SceneTransition, Error> {
+        Err(Error::NotSupported)
+    }
+
+    /// Names of all the transitions
+    async fn transition_list(&self) -> Result<Vec<String>, Error> {
+        Err(Error::NotSupported)
+    }
+
+    /// Selects the transition, keeps the current duration when none is set
+    async fn set_transition(&self, _transition: &config::SceneTransition) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
     async fn start_streaming(&self) -> Result<(), Error>;
 
     async fn stop_streaming(&self) -> Result<(), Error>;
@@ -18,10 +75,39 @@ pub trait BroadcastingSoftwareLogic: Send + Sync {
 
     async fn is_recording(&self) -> Result<bool, Error>;
 
+    /// Starts recording, does nothing when already recording
+    async fn start_recording(&self) -> Result<(), Error> {
+        if !self.is_recording().await? {
+            self.toggle_recording().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops recording, does nothing when not recording
+    async fn stop_recording(&self) -> Result<(), Error> {
+        if self.is_recording().await? {
+            self.toggle_recording().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the software knows where to save recordings
+    async fn recording_path_configured(&self) -> Result<bool, Error> {
+        Ok(true)
+    }
+
     async fn fix(&self) -> Result<(), Error>;
 
     async fn current_scene(&self) -> Result<String, Error>;
 
+    /// Names of all the scenes
+    async fn scene_list(&self) -> Result<Vec<String>, Error>;
+
+    /// Lightweight status query of the current scene and outputs
+    async fn health(&self) -> Result<Health, Error>;
+
     async fn toggle_source(&self, source: &str) -> Result<(String, bool), Error>;
 
     async fn set_collection_and_profile(
@@ -39,6 +125,13 @@ pub trait BroadcastingSoftwareLogic: Send + Sync {
         source_name: &str,
     ) -> Result<(obws::responses::MediaState, i64), Error>;
 
+    /// Whether the media source is playing its input
+    async fn source_active(&self, source_name: &str) -> Result<bool, Error> {
+        let (state, _) = self.get_media_source_status(source_name).await?;
+
+        Ok(matches!(state, obws::responses::MediaState::Playing))
+    }
+
     async fn create_special_media_source(
         &self,
         source_name: &str,
@@ -50,4 +143,263 @@ pub trait BroadcastingSoftwareLogic: Send + Sync {
         source_name: &str,
         scene: &str,
     ) -> Result<(), Error>;
+
+    /// JPEG of what the source or scene shows, scaled to the width when set
+    async fn screenshot(&self, _source: &str, _width: Option<u32>) -> Result<Vec<u8>, Error> {
+        Err(Error::NotSupported)
+    }
+}
+
+/// Decodes the image of a data URI like `data:image/jpeg;base64,...`
+pub fn decode_data_uri(uri: &str) -> Option<Vec<u8>> {
+    use base64::Engine as _;
+
+    let (_, data) = uri.split_once("base64,")?;
+    base64::engine::general_purpose::STANDARD.decode(data).ok()
+}
+
+/// Starts or stops recording along with the stream when `record_while_streaming`
+/// is enabled, call after the streaming state changed
+pub async fn sync_recording(user_state: &noalbs::UserState) {
+    let action = {
+        let mut state = user_state.write().await;
+
+        if !state.config.optional_options.record_while_streaming {
+            return;
+        }
+
+        let bs = &mut state.broadcasting_software;
+        let streaming = bs.is_streaming;
+        bs.recording_sync.update(streaming)
+    };
+
+    let Some(action) = action else {
+        return;
+    };
+
+    let state = user_state.read().await;
+    let Some(bsc) = &state.broadcasting_software.connection else {
+        return;
+    };
+
+    let result = match action {
+        state::RecordAction::Start => match bsc.recording_path_configured().await {
+            Ok(false) => {
+                warn!("Not recording, no recording path is configured");
+                return;
+            }
+            _ => bsc.start_recording().await,
+        },
+        state::RecordAction::Stop => bsc.stop_recording().await,
+    };
+
+    let verb = match action {
+        state::RecordAction::Start => "start",
+        state::RecordAction::Stop => "stop",
+    };
+
+    match result {
+        Ok(()) => info!("Recording {} with the stream", verb),
+        Err(e) => error!("Unable to {} recording: {}", verb, e),
+    }
+}
+
+/// Switches the scene and reads the current scene back until it matches,
+/// tries the switch once more when it doesn't. Returns the scene the
+/// software is on.
+pub async fn switch_scene_confirmed(
+    bsc: &dyn BroadcastingSoftwareLogic,
+    scene: &str,
+    transition: Option<&config::SceneTransition>,
+) -> Result<String, Error> {
+    let timeout = SCENE_CONFIRM_TIMEOUT
+        + transition
+            .and_then(|t| t.duration_ms)
+            .map_or(Duration::ZERO, |ms| Duration::from_millis(ms.into()));
+
+    for attempt in 1..=2 {
+        let switched = match transition {
+            Some(transition) => bsc.switch_scene_with_transition(scene, transition).await?,
+            None => bsc.switch_scene(scene).await?,
+        };
+
+        if confirm_scene(bsc, &switched, timeout).await {
+            return Ok(switched);
+        }
+
+        warn!(
+            "Switch to {} not confirmed within {:?} (attempt {})",
+            switched, timeout, attempt
+        );
+    }
+
+    Err(Error::SceneSwitchNotConfirmed(scene.to_owned()))
+}
+
+/// Polls the current scene until it's `scene`, false after `timeout`
+async fn confirm_scene(
+    bsc: &dyn BroadcastingSoftwareLogic,
+    scene: &str,
+    timeout: Duration,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        match bsc.current_scene().await {
+            Ok(current) if current == scene => return true,
+            Ok(_) => {}
+            Err(e) => warn!("Unable to read back the current scene: {}", e),
+        }
+
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+
+        tokio::time::sleep(SCENE_CONFIRM_POLL).await;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SceneMatch {
+    Exact(String),
+
+    /// Same name apart from the capitalization and whitespace,
+    /// or the only closest name
+    Similar(String),
+}
+
+/// Exact match always wins, otherwise the only scene with the same trimmed
+/// lowercase name, otherwise the only most similar scene
+fn match_scene(scenes: &[String], wanted: &str) -> Option<SceneMatch> {
+    if scenes.iter().any(|s| s == wanted) {
+        return Some(SceneMatch::Exact(wanted.to_owned()));
+    }
+
+    let normalize = |s: &str| s.trim().to_lowercase();
+    let wanted = normalize(wanted);
+
+    let same: Vec<_> = scenes.iter().filter(|s| normalize(s) == wanted).collect();
+
+    match same.as_slice() {
+        [scene] => return Some(SceneMatch::Similar(scene.to_string())),
+        [] => {}
+        _ => return None,
+    }
+
+    let mut similarity: Vec<_> = scenes
+        .iter()
+        .map(|s| {
+            (
+                s,
+                strsim::normalized_damerau_levenshtein(&wanted, &normalize(s)),
+            )
+        })
+        .filter(|(_, score)| *score >= MIN_SCENE_SIMILARITY)
+        .collect();
+    similarity.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    match similarity.as_slice() {
+        [(best, score), rest @ ..] if rest.first().map_or(true, |(_, next)| next < score) => {
+            Some(SceneMatch::Similar(best.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Resolves scene names to the scenes in the software, warns once per name
+/// when it isn't an exact match
+#[derive(Debug, Default)]
+pub struct SceneResolver {
+    warned: std::sync::Mutex<HashSet<String>>,
+}
+
+impl SceneResolver {
+    /// Forgets the warnings, scenes are different after switching collections
+    pub fn reset(&self) {
+        self.warned.lock().unwrap().clear();
+    }
+
+    pub fn resolve(&self, scenes: &[String], wanted: &str) -> Result<String, Error> {
+        match match_scene(scenes, wanted) {
+            Some(SceneMatch::Exact(scene)) => Ok(scene),
+            Some(SceneMatch::Similar(scene)) => {
+                if self.warned.lock().unwrap().insert(wanted.to_owned()) {
+                    warn!(
+                        "Scene \"{}\" doesn't exist, using \"{}\" instead",
+                        wanted, scene
+                    );
+                }
+
+                Ok(scene)
+            }
+            None => {
+                error!(
+                    "Scene \"{}\" not found, available scenes: {}",
+                    wanted,
+                    scenes.join(", ")
+                );
+
+                Err(Error::SceneNotFound(wanted.to_owned()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scenes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn screenshot_data_uri_is_decoded() {
+        assert_eq!(
+            decode_data_uri("data:image/jpg;base64,/9j/4A=="),
+            Some(vec![0xff, 0xd8, 0xff, 0xe0])
+        );
+        assert_eq!(decode_data_uri("not an image"), None);
+    }
+
+    #[test]
+    fn exact_match_wins() {
+        let scenes = scenes(&["Live", "live", "low"]);
+
+        assert_eq!(
+            match_scene(&scenes, "live"),
+            Some(SceneMatch::Exact("live".to_string()))
+        );
+    }
+
+    #[test]
+    fn case_and_whitespace_are_ignored() {
+        let scenes = scenes(&["Live ", "Low", "BRB"]);
+
+        assert_eq!(
+            match_scene(&scenes, "live"),
+            Some(SceneMatch::Similar("Live ".to_string()))
+        );
+        assert_eq!(
+            match_scene(&scenes, " brb"),
+            Some(SceneMatch::Similar("BRB".to_string()))
+        );
+    }
+
+    #[test]
+    fn ambiguous_names_are_not_guessed() {
+        assert_eq!(match_scene(&scenes(&["Live", "LIVE "]), "live"), None);
+        assert_eq!(match_scene(&scenes(&["cam1", "cam2"]), "cam"), None);
+        assert_eq!(match_scene(&scenes(&["live", "low"]), "offline"), None);
+    }
+
+    #[test]
+    fn unique_fuzzy_match() {
+        let scenes = scenes(&["intro", "live", "offline"]);
+
+        assert_eq!(
+            match_scene(&scenes, "intr"),
+            Some(SceneMatch::Similar("intro".to_string()))
+        );
+    }
 }
@@ -11,12 +11,9 @@ use obws::{
 };
 use serde::Deserialize;
 use tokio::sync::{self, mpsc, Mutex};
-use tracing::{error, info, warn, Instrument};
+use tracing::{debug, error, info, warn, Instrument};
 
-use crate::{
-    config, error, noalbs,
-    state::{self, ClientStatus},
-};
+use crate::{config, error, noalbs, state};
 
 use super::BroadcastingSoftwareLogic;
 
@@ -24,18 +21,35 @@ pub struct Obs {
     connection: Arc<Mutex<Option<obws::Client>>>,
     connection_join: tokio::task::JoinHandle<()>,
     event_join: tokio::task::JoinHandle<()>,
+    scene_resolver: Arc<super::SceneResolver>,
 }
 
 impl Obs {
     pub fn new(connection_info: config::ObsConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, true)
+    }
+
+    /// Connects to an extra instance which won't update the state of the user
+    pub fn new_secondary(connection_info: config::ObsConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, false)
+    }
+
+    fn connect(
+        connection_info: config::ObsConfig,
+        state: noalbs::UserState,
+        primary: bool,
+    ) -> Self {
         // OBS connection will be held in this arc mutex
         let connection = Arc::new(Mutex::new(None));
 
         // Will be used to receive events from OBS
         let (event_tx, event_rx) = mpsc::channel(100);
 
+        let scene_resolver = Arc::new(super::SceneResolver::default());
+
         let connection_inner = connection.clone();
         let state_inner = state.clone();
+        let resolver_inner = scene_resolver.clone();
         let connection_join = tokio::spawn(async {
             let user = { state_inner.read().await.config.user.name.to_owned() };
 
@@ -45,6 +59,8 @@ impl Obs {
                     state: state_inner,
                     connection: connection_inner,
                     event_sender: event_tx,
+                    scene_resolver: resolver_inner,
+                    primary,
                 };
 
                 // TODO: Any errors to handle?
@@ -54,15 +70,25 @@ impl Obs {
             .await
         });
 
-        let event_join = tokio::spawn(Self::event_handler(event_rx, state));
+        let event_join = if primary {
+            tokio::spawn(Self::event_handler(event_rx, state))
+        } else {
+            tokio::spawn(Self::drain_events(event_rx))
+        };
 
         Self {
             connection,
             connection_join,
             event_join,
+            scene_resolver,
         }
     }
 
+    /// Events of secondary instances are ignored
+    async fn drain_events<T>(mut events: mpsc::Receiver<T>) {
+        while events.recv().await.is_some() {}
+    }
+
     async fn event_handler(
         mut events: mpsc::Receiver<obws::events::Event>,
         state: noalbs::UserState,
@@ -82,7 +108,7 @@ impl Obs {
                             .notify_waiters();
                     }
 
-                    l.broadcasting_software.current_scene = scene_name;
+                    l.broadcasting_software.set_current_scene(scene_name);
                 }
                 EventType::StreamStarted => {
                     let mut l = state.write().await;
@@ -92,12 +118,23 @@ impl Obs {
                     l.broadcasting_software
                         .start_streaming_notifier()
                         .notify_waiters();
+                    drop(l);
+
+                    super::sync_recording(&state).await;
                 }
                 EventType::StreamStopped => {
                     let mut l = state.write().await;
                     l.broadcasting_software.is_streaming = false;
                     l.broadcasting_software.stream_status = None;
                     l.broadcasting_software.initial_stream_status = None;
+                    l.broadcasting_software.dropped_frames.clear();
+
+                    l.broadcasting_software
+                        .stop_streaming_notifier()
+                        .notify_waiters();
+                    drop(l);
+
+                    super::sync_recording(&state).await;
                 }
                 EventType::StreamStatus {
                     kbits_per_sec,
@@ -123,6 +160,13 @@ impl Obs {
 
                     let mut l = state.write().await;
 
+                    let window = l.config.switcher.dropped_frames_window;
+                    l.broadcasting_software.dropped_frames.add_sample(
+                        num_total_frames,
+                        num_dropped_frames,
+                        Duration::from_secs(window.into()),
+                    );
+
                     if l.broadcasting_software.initial_stream_status.is_none() {
                         l.broadcasting_software.initial_stream_status = Some(ss);
                     } else {
@@ -248,22 +292,7 @@ async fn get_media_sources_rec(
 impl BroadcastingSoftwareLogic for Obs {
     async fn switch_scene(&self, scene: &str) -> Result<String, error::Error> {
         let scenes = self.get_scenes().await?;
-        let scene = scene.to_lowercase();
-
-        let res = scenes
-            .iter()
-            .enumerate()
-            .map(|(i, s)| {
-                let s = &s.to_lowercase();
-                (i, strsim::normalized_damerau_levenshtein(&scene, s))
-            })
-            .min_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-
-        let scene = if let Some(s) = res {
-            scenes[s.0].to_owned()
-        } else {
-            scene
-        };
+        let scene = self.scene_resolver.resolve(&scenes, scene)?;
 
         let connection = self.connection.lock().await;
 
@@ -387,6 +416,17 @@ impl BroadcastingSoftwareLogic for Obs {
         Ok(status.is_recording)
     }
 
+    async fn recording_path_configured(&self) -> Result<bool, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let folder = client.recording().get_recording_folder().await?;
+        Ok(!folder.trim().is_empty())
+    }
+
     async fn get_media_source_status(
         &self,
         source_name: &str,
@@ -518,6 +558,10 @@ impl BroadcastingSoftwareLogic for Obs {
         Ok(())
     }
 
+    async fn scene_list(&self) -> Result<Vec<String>, error::Error> {
+        self.get_scenes().await
+    }
+
     async fn current_scene(&self) -> Result<String, error::Error> {
         let connection = &self.connection.lock().await;
 
@@ -530,6 +574,22 @@ impl BroadcastingSoftwareLogic for Obs {
         Ok(current.name)
     }
 
+    async fn health(&self) -> Result<super::Health, error::Error> {
+        let connection = &self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let status = client.streaming().get_streaming_status().await?;
+
+        Ok(super::Health {
+            scene: client.scenes().get_current_scene().await?.name,
+            streaming: status.streaming,
+            recording: status.recording,
+        })
+    }
+
     async fn info(
         &self,
         state: &sync::RwLockReadGuard<state::State>,
@@ -589,9 +649,29 @@ impl BroadcastingSoftwareLogic for Obs {
 
     async fn set_collection_and_profile(
         &self,
-        _source: &config::CollectionPair,
+        source: &config::CollectionPair,
     ) -> Result<(), error::Error> {
-        Err(error::Error::UnableInitialConnection)
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        client
+            .scene_collections()
+            .set_current_scene_collection(&source.collection)
+            .await?;
+
+        if !client.streaming().get_streaming_status().await?.streaming {
+            client
+                .profiles()
+                .set_current_profile(&source.profile)
+                .await?;
+        }
+
+        self.scene_resolver.reset();
+
+        Ok(())
     }
 }
 
@@ -601,6 +681,12 @@ pub struct InnerConnection {
     state: noalbs::UserState,
     connection: Arc<Mutex<Option<obws::Client>>>,
     event_sender: mpsc::Sender<obws::events::Event>,
+
+    /// Reset on every connection since the scenes might have changed
+    scene_resolver: Arc<super::SceneResolver>,
+
+    /// Only the main instance updates the state
+    primary: bool,
 }
 
 impl InnerConnection {
@@ -608,20 +694,20 @@ impl InnerConnection {
         loop {
             let client = self.get_client().await;
             let event_stream = client.events();
+            self.scene_resolver.reset();
 
-            {
+            if self.primary {
                 let state = &mut self.state.write().await;
                 let bs = &mut state.broadcasting_software;
 
                 let scenes = client.scenes().get_scene_list().await.unwrap();
                 let streaming_status = client.streaming().get_streaming_status().await.unwrap();
 
-                bs.current_scene = scenes.current_scene;
+                bs.set_current_scene(scenes.current_scene);
                 bs.is_streaming = streaming_status.streaming;
-                bs.status = ClientStatus::Connected;
+                bs.set_connected();
 
                 let bs = &state.broadcasting_software;
-                bs.connected_notifier().notify_waiters();
 
                 if bs.is_streaming {
                     bs.start_streaming_notifier().notify_waiters();
@@ -645,15 +731,26 @@ impl InnerConnection {
                 *connection = Some(client);
             }
 
-            Self::event_loop(event_stream.unwrap(), self.event_sender.clone()).await;
+            if self.primary {
+                super::sync_recording(&self.state).await;
+            }
+
+            tokio::select! {
+                _ = Self::event_loop(event_stream.unwrap(), self.event_sender.clone()) => {}
+                _ = self.heartbeat() => {}
+            }
 
             warn!("Disconnected");
 
-            {
-                let state = &mut self.state.write().await;
-                let bs = &mut state.broadcasting_software;
-                bs.status = ClientStatus::Disconnected;
-                bs.is_streaming = false;
+            // Requests fail right away instead of waiting on a dead connection
+            *self.connection.lock().await = None;
+
+            if self.primary {
+                self.state
+                    .write()
+                    .await
+                    .broadcasting_software
+                    .set_disconnected();
             }
         }
     }
@@ -686,6 +783,15 @@ impl InnerConnection {
                 Err(e) => error!("Error while trying to connect to OBS: {}", e),
             };
 
+            if self.primary {
+                self.state
+                    .write()
+                    .await
+                    .broadcasting_software
+                    .reconnect
+                    .attempts += 1;
+            }
+
             let wait = 1 << retry_grow;
             warn!("Unable to connect");
             info!("trying to connect again in {} seconds", wait);
@@ -697,6 +803,39 @@ impl InnerConnection {
         }
     }
 
+    /// Returns once OBS stops answering, after the computer slept the
+    /// connection can look open while nothing is on the other end
+    async fn heartbeat(&self) {
+        let mut interval = tokio::time::interval(super::HEARTBEAT_INTERVAL);
+        let mut missed = 0;
+
+        loop {
+            interval.tick().await;
+
+            let response = {
+                let connection = self.connection.lock().await;
+                let Some(client) = connection.as_ref() else {
+                    continue;
+                };
+
+                tokio::time::timeout(super::HEARTBEAT_TIMEOUT, client.general().get_version()).await
+            };
+
+            if matches!(response, Ok(Ok(_))) {
+                missed = 0;
+                continue;
+            }
+
+            missed += 1;
+            debug!("OBS didn't answer the heartbeat ({} missed)", missed);
+
+            if missed >= super::HEARTBEAT_MAX_MISSED {
+                warn!("OBS stopped answering");
+                return;
+            }
+        }
+    }
+
     /// Sends all received events to the MPSC
     ///
     /// Blocks until the stream gets disconnected.
@@ -9,17 +9,17 @@ use obwsv5::{
         inputs::{self, InputId},
         scene_items::SetEnabled,
         scenes::SceneId,
+        sources::{SourceId, TakeScreenshot},
     },
     responses::media_inputs::MediaState,
     Client,
 };
 use tokio::sync::{self, mpsc, Mutex};
-use tracing::{error, info, warn, Instrument};
+use tracing::{debug, error, info, warn, Instrument};
 
 use crate::{
     config::{self, ObsConfig},
-    error, noalbs,
-    state::{self, ClientStatus},
+    error, noalbs, state,
 };
 
 use super::{
@@ -27,22 +27,49 @@ use super::{
     BroadcastingSoftwareLogic,
 };
 
+/// How often the stream output stats get requested
+const DROPPED_FRAMES_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Used to restore the previous transition when no duration is configured
+const DEFAULT_TRANSITION_DURATION: Duration = Duration::from_millis(300);
+
 pub struct Obsv5 {
     connection: Arc<Mutex<Option<obwsv5::Client>>>,
     connection_join: tokio::task::JoinHandle<()>,
     event_join: tokio::task::JoinHandle<()>,
+    scene_resolver: Arc<super::SceneResolver>,
+
+    /// Switch through the preview when OBS is in studio mode
+    switch_through_preview: bool,
 }
 
 impl Obsv5 {
     pub fn new(connection_info: config::ObsConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, true)
+    }
+
+    /// Connects to an extra instance which won't update the state of the user
+    pub fn new_secondary(connection_info: config::ObsConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, false)
+    }
+
+    fn connect(
+        connection_info: config::ObsConfig,
+        state: noalbs::UserState,
+        primary: bool,
+    ) -> Self {
         // OBS connection will be held in this arc mutex
         let connection = Arc::new(Mutex::new(None));
 
         // Will be used to receive events from OBS
         let (event_tx, event_rx) = mpsc::channel(100);
 
+        let scene_resolver = Arc::new(super::SceneResolver::default());
+        let switch_through_preview = connection_info.switch_through_preview;
+
         let connection_inner = connection.clone();
         let state_inner = state.clone();
+        let resolver_inner = scene_resolver.clone();
         let connection_join = tokio::spawn(async {
             let user = { state_inner.read().await.config.user.name.to_owned() };
 
@@ -52,6 +79,8 @@ impl Obsv5 {
                     state: state_inner,
                     connection: connection_inner,
                     event_sender: event_tx,
+                    scene_resolver: resolver_inner,
+                    primary,
                 };
 
                 connection.run().await;
@@ -60,15 +89,26 @@ impl Obsv5 {
             .await
         });
 
-        let event_join = tokio::spawn(Self::event_handler(event_rx, state));
+        let event_join = if primary {
+            tokio::spawn(Self::event_handler(event_rx, state))
+        } else {
+            tokio::spawn(Self::drain_events(event_rx))
+        };
 
         Self {
             connection,
             connection_join,
             event_join,
+            scene_resolver,
+            switch_through_preview,
         }
     }
 
+    /// Events of secondary instances are ignored
+    async fn drain_events<T>(mut events: mpsc::Receiver<T>) {
+        while events.recv().await.is_some() {}
+    }
+
     async fn event_handler(mut events: mpsc::Receiver<Event>, user_state: noalbs::UserState) {
         while let Some(event) = events.recv().await {
             match event {
@@ -83,7 +123,7 @@ impl Obsv5 {
                             .notify_waiters();
                     }
 
-                    l.broadcasting_software.current_scene = name;
+                    l.broadcasting_software.set_current_scene(name);
                 }
                 Event::StreamStateChanged { active, .. } => {
                     let mut l = user_state.write().await;
@@ -116,7 +156,15 @@ impl Obsv5 {
                         l.broadcasting_software.is_streaming = false;
                         l.broadcasting_software.stream_status = None;
                         l.broadcasting_software.initial_stream_status = None;
+                        l.broadcasting_software.dropped_frames.clear();
+
+                        l.broadcasting_software
+                            .stop_streaming_notifier()
+                            .notify_waiters();
+                        drop(l);
                     }
+
+                    super::sync_recording(&user_state).await;
                 }
                 _ => {}
             }
@@ -283,40 +331,150 @@ async fn get_sources_rec(
     }
 }
 
+impl Obsv5 {
+    /// Finds the scene in OBS with the closest name
+    async fn find_scene(&self, scene: &str) -> Result<String, error::Error> {
+        let scenes = self.get_scenes().await?;
+        self.scene_resolver.resolve(&scenes, scene)
+    }
+
+    /// Puts the scene on program, in studio mode through the preview and
+    /// the current transition when `switch_through_preview` is set
+    async fn set_program_scene(&self, client: &Client, scene: &str) -> Result<(), error::Error> {
+        if self.switch_through_preview && client.ui().studio_mode_enabled().await? {
+            client
+                .scenes()
+                .set_current_preview_scene(SceneId::Name(scene))
+                .await?;
+
+            return Ok(client.transitions().trigger().await?);
+        }
+
+        Ok(client
+            .scenes()
+            .set_current_program_scene(SceneId::Name(scene))
+            .await?)
+    }
+}
+
 #[async_trait]
 impl BroadcastingSoftwareLogic for Obsv5 {
     async fn switch_scene(&self, scene: &str) -> Result<String, error::Error> {
-        let scenes = self.get_scenes().await?;
-        let scene = scene.to_lowercase();
+        let scene = self.find_scene(scene).await?;
+        let connection = self.connection.lock().await;
 
-        let res = scenes
-            .iter()
-            .enumerate()
-            .map(|(i, s)| {
-                let s = &s.to_lowercase();
-                (i, strsim::normalized_damerau_levenshtein(&scene, s))
-            })
-            .min_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
 
-        let scene = if let Some(s) = res {
-            scenes[s.0].to_owned()
-        } else {
-            scene
-        };
+        self.set_program_scene(client, &scene).await?;
+        Ok(scene)
+    }
 
+    async fn switch_scene_with_transition(
+        &self,
+        scene: &str,
+        transition: &config::SceneTransition,
+    ) -> Result<String, error::Error> {
+        let scene = self.find_scene(scene).await?;
         let connection = self.connection.lock().await;
 
         let client = connection
             .as_ref()
             .ok_or(error::Error::UnableInitialConnection)?;
 
-        client
-            .scenes()
-            .set_current_program_scene(SceneId::Name(&scene))
-            .await?;
+        let transitions = client.transitions();
+        let previous = transitions.current().await?;
+
+        let duration = match transition.duration_ms {
+            Some(ms) => {
+                transitions
+                    .set_current_duration(::time::Duration::milliseconds(ms.into()))
+                    .await?;
+                Duration::from_millis(ms.into())
+            }
+            None => DEFAULT_TRANSITION_DURATION,
+        };
+
+        if let Err(e) = transitions.set_current(&transition.name).await {
+            error!("Unable to use transition {}: {}", transition.name, e);
+        }
+
+        self.set_program_scene(client, &scene).await?;
+
+        // OBS only has one current transition, put back the one that was
+        // used before once the transition is done
+        if previous.name != transition.name {
+            let connection = self.connection.clone();
+
+            tokio::spawn(async move {
+                tokio::time::sleep(duration + Duration::from_millis(500)).await;
+
+                if let Some(client) = connection.lock().await.as_ref() {
+                    let transitions = client.transitions();
+                    let _ = transitions.set_current(&previous.name).await;
+
+                    if let Some(duration) = previous.duration {
+                        let _ = transitions.set_current_duration(duration).await;
+                    }
+                }
+            });
+        }
+
         Ok(scene)
     }
 
+    async fn current_transition(&self) -> Result<config::SceneTransition, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let current = client.transitions().current().await?;
+
+        Ok(config::SceneTransition {
+            name: current.name,
+            duration_ms: current
+                .duration
+                .map(|d| d.whole_milliseconds().clamp(0, u32::MAX.into()) as u32),
+        })
+    }
+
+    async fn transition_list(&self) -> Result<Vec<String>, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let list = client.transitions().list().await?;
+
+        Ok(list.transitions.into_iter().map(|t| t.name).collect())
+    }
+
+    async fn set_transition(
+        &self,
+        transition: &config::SceneTransition,
+    ) -> Result<(), error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let transitions = client.transitions();
+        transitions.set_current(&transition.name).await?;
+
+        if let Some(ms) = transition.duration_ms {
+            transitions
+                .set_current_duration(::time::Duration::milliseconds(ms.into()))
+                .await?;
+        }
+
+        Ok(())
+    }
+
     async fn start_streaming(&self) -> Result<(), error::Error> {
         let connection = self.connection.lock().await;
 
@@ -415,11 +573,63 @@ impl BroadcastingSoftwareLogic for Obsv5 {
         Ok(status.active)
     }
 
+    async fn start_recording(&self) -> Result<(), error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        if !client.recording().status().await?.active {
+            client.recording().start().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn stop_recording(&self) -> Result<(), error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        if client.recording().status().await?.active {
+            client.recording().stop().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn recording_path_configured(&self) -> Result<bool, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let directory = client.config().record_directory().await?;
+        Ok(!directory.trim().is_empty())
+    }
+
     async fn get_media_source_status(
         &self,
-        _source_name: &str,
+        source_name: &str,
     ) -> Result<(obws::responses::MediaState, i64), error::Error> {
-        Err(error::Error::UnableInitialConnection)
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let status = client
+            .media_inputs()
+            .status(InputId::Name(source_name))
+            .await?;
+
+        let cursor = status.cursor.map(|c| c.whole_seconds()).unwrap_or_default();
+
+        Ok((to_v4_media_state(status.state), cursor))
     }
 
     async fn create_special_media_source(
@@ -438,6 +648,31 @@ impl BroadcastingSoftwareLogic for Obsv5 {
         Err(error::Error::UnableInitialConnection)
     }
 
+    async fn screenshot(&self, source: &str, width: Option<u32>) -> Result<Vec<u8>, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        let image = client
+            .sources()
+            .take_screenshot(TakeScreenshot {
+                source: SourceId::Name(source),
+                width,
+                height: None,
+                compression_quality: None,
+                format: "jpg",
+            })
+            .await?;
+
+        super::decode_data_uri(&image).ok_or(error::Error::InvalidScreenshot)
+    }
+
+    async fn scene_list(&self) -> Result<Vec<String>, error::Error> {
+        self.get_scenes().await
+    }
+
     async fn current_scene(&self) -> Result<String, error::Error> {
         let connection = self.connection.lock().await;
 
@@ -448,6 +683,20 @@ impl BroadcastingSoftwareLogic for Obsv5 {
         Ok(client.scenes().current_program_scene().await?.id.name)
     }
 
+    async fn health(&self) -> Result<super::Health, error::Error> {
+        let connection = self.connection.lock().await;
+
+        let client = connection
+            .as_ref()
+            .ok_or(error::Error::UnableInitialConnection)?;
+
+        Ok(super::Health {
+            scene: client.scenes().current_program_scene().await?.id.name,
+            streaming: client.streaming().status().await?.active,
+            recording: client.recording().status().await?.active,
+        })
+    }
+
     async fn info(
         &self,
         state: &sync::RwLockReadGuard<state::State>,
@@ -566,15 +815,40 @@ impl BroadcastingSoftwareLogic for Obsv5 {
             client.profiles().set_current(&source.profile).await?;
         }
 
+        self.scene_resolver.reset();
+
         Ok(())
     }
 }
 
+/// The media source stream server was written against the v4 types
+fn to_v4_media_state(state: MediaState) -> obws::responses::MediaState {
+    use obws::responses::MediaState as V4;
+
+    match state {
+        MediaState::None => V4::None,
+        MediaState::Playing => V4::Playing,
+        MediaState::Opening => V4::Opening,
+        MediaState::Buffering => V4::Buffering,
+        MediaState::Paused => V4::Paused,
+        MediaState::Stopped => V4::Stopped,
+        MediaState::Ended => V4::Ended,
+        MediaState::Error => V4::Error,
+        _ => V4::Unknown,
+    }
+}
+
 pub struct InnerConnection {
     connection_info: config::ObsConfig,
     state: noalbs::UserState,
     connection: Arc<Mutex<Option<obwsv5::Client>>>,
     event_sender: mpsc::Sender<Event>,
+
+    /// Reset on every connection since the scenes might have changed
+    scene_resolver: Arc<super::SceneResolver>,
+
+    /// Only the main instance updates the state
+    primary: bool,
 }
 
 impl InnerConnection {
@@ -589,23 +863,23 @@ impl InnerConnection {
             };
 
             let event_stream = client.events();
+            self.scene_resolver.reset();
 
-            {
+            if self.primary {
                 let state = &mut self.state.write().await;
                 let bs = &mut state.broadcasting_software;
 
                 if let Ok(s) = client.scenes().current_program_scene().await {
-                    bs.current_scene = s.id.name;
+                    bs.set_current_scene(s.id.name);
                 }
 
                 if let Ok(s) = client.streaming().status().await {
                     bs.is_streaming = s.active;
                 }
 
-                bs.status = ClientStatus::Connected;
+                bs.set_connected();
 
                 let bs = &state.broadcasting_software;
-                bs.connected_notifier().notify_waiters();
 
                 if bs.is_streaming {
                     bs.start_streaming_notifier().notify_waiters();
@@ -624,12 +898,17 @@ impl InnerConnection {
                 error!("Error getting event stream: {}", e);
             }
 
+            if self.primary {
+                self.check_transitions(&client).await;
+                self.check_studio_mode(&client).await;
+            }
+
             {
                 let mut connection = self.connection.lock().await;
                 *connection = Some(client);
             }
 
-            {
+            if self.primary {
                 let ss = {
                     let read = &self.state.read().await;
                     let bs = &read.broadcasting_software;
@@ -649,17 +928,27 @@ impl InnerConnection {
                     .await
                     .broadcasting_software
                     .initial_stream_status = ss;
+
+                super::sync_recording(&self.state).await;
             }
 
-            Self::event_loop(event_stream.unwrap(), self.event_sender.clone()).await;
+            tokio::select! {
+                _ = Self::event_loop(event_stream.unwrap(), self.event_sender.clone()) => {}
+                _ = self.poll_dropped_frames(), if self.primary => {}
+                _ = self.heartbeat() => {}
+            }
 
             warn!("Disconnected");
 
-            {
-                let state = &mut self.state.write().await;
-                let bs = &mut state.broadcasting_software;
-                bs.status = ClientStatus::Disconnected;
-                bs.is_streaming = false;
+            // Requests fail right away instead of waiting on a dead connection
+            *self.connection.lock().await = None;
+
+            if self.primary {
+                self.state
+                    .write()
+                    .await
+                    .broadcasting_software
+                    .set_disconnected();
             }
         }
     }
@@ -698,6 +987,15 @@ impl InnerConnection {
                 }
             };
 
+            if self.primary {
+                self.state
+                    .write()
+                    .await
+                    .broadcasting_software
+                    .reconnect
+                    .attempts += 1;
+            }
+
             let wait = 1 << retry_grow;
             info!("trying to connect again in {} seconds", wait);
             tokio::time::sleep(Duration::from_secs(wait)).await;
@@ -708,6 +1006,129 @@ impl InnerConnection {
         }
     }
 
+    /// Lets the streamer know the preview is skipped in studio mode
+    async fn check_studio_mode(&self, client: &obwsv5::Client) {
+        if self.connection_info.switch_through_preview {
+            return;
+        }
+
+        if let Ok(true) = client.ui().studio_mode_enabled().await {
+            info!(
+                "OBS is in studio mode, scenes are switched on program directly. \
+                Enable switchThroughPreview to switch through the preview"
+            );
+        }
+    }
+
+    /// Warns about configured transitions that don't exist in OBS
+    async fn check_transitions(&self, client: &obwsv5::Client) {
+        let configured = {
+            let state = self.state.read().await;
+            state
+                .config
+                .switcher
+                .transitions
+                .values()
+                .map(|t| t.name.to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        if configured.is_empty() {
+            return;
+        }
+
+        let list = match client.transitions().list().await {
+            Ok(list) => list,
+            Err(e) => {
+                error!("Unable to get the transitions: {}", e);
+                return;
+            }
+        };
+
+        for name in configured {
+            if !list.transitions.iter().any(|t| t.name == name) {
+                warn!(
+                    "Transition {} doesn't exist in OBS, a cut will be used",
+                    name
+                );
+            }
+        }
+    }
+
+    /// Polls the stream output stats to keep track of the dropped frames
+    ///
+    /// OBS v5 doesn't send the stats as an event anymore so they need to be
+    /// requested.
+    async fn poll_dropped_frames(&self) {
+        let mut interval = tokio::time::interval(DROPPED_FRAMES_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if !self.state.read().await.broadcasting_software.is_streaming {
+                continue;
+            }
+
+            let status = {
+                let connection = self.connection.lock().await;
+                let Some(client) = connection.as_ref() else {
+                    continue;
+                };
+
+                client.streaming().status().await
+            };
+
+            let status = match status {
+                Ok(status) => status,
+                Err(e) => {
+                    debug!("Unable to get stream status: {}", e);
+                    continue;
+                }
+            };
+
+            let mut state = self.state.write().await;
+            let window = state.config.switcher.dropped_frames_window;
+            state.broadcasting_software.dropped_frames.add_sample(
+                status.total_frames as u64,
+                status.skipped_frames as u64,
+                Duration::from_secs(window.into()),
+            );
+        }
+    }
+
+    /// Returns once OBS stops answering, after the computer slept the
+    /// connection can look open while nothing is on the other end
+    async fn heartbeat(&self) {
+        let mut interval = tokio::time::interval(super::HEARTBEAT_INTERVAL);
+        let mut missed = 0;
+
+        loop {
+            interval.tick().await;
+
+            let response = {
+                let connection = self.connection.lock().await;
+                let Some(client) = connection.as_ref() else {
+                    continue;
+                };
+
+                tokio::time::timeout(super::HEARTBEAT_TIMEOUT, client.general().version()).await
+            };
+
+            if matches!(response, Ok(Ok(_))) {
+                missed = 0;
+                continue;
+            }
+
+            missed += 1;
+            debug!("OBS didn't answer the heartbeat ({} missed)", missed);
+
+            if missed >= super::HEARTBEAT_MAX_MISSED {
+                warn!("OBS stopped answering");
+                return;
+            }
+        }
+    }
+
     /// Sends all received events to the MPSC
     ///
     /// Blocks until the stream gets disconnected.
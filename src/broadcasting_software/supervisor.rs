@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use tokio::{sync::Notify, time};
+use tracing::{debug, info, warn};
+
+use crate::{chat, noalbs, state::ClientStatus};
+
+/// A quick reconnect, like OBS restarting, doesn't need a message in chat
+const DISCONNECT_GRACE: Duration = Duration::from_secs(10);
+
+/// Lets chat know when the connection to the broadcasting software is lost
+/// while streaming and when it's back. The connections retry on their own,
+/// the switcher holds in the meantime.
+pub async fn watch(user_state: noalbs::UserState, chat_sender: noalbs::ChatSender) {
+    let (disconnected, connected) = {
+        let state = user_state.read().await;
+        let bs = &state.broadcasting_software;
+        (bs.disconnected_notifier(), bs.connected_notifier())
+    };
+
+    loop {
+        disconnected.notified().await;
+
+        let reconnect = {
+            let state = user_state.read().await;
+            state.broadcasting_software.reconnect.clone()
+        };
+
+        let Some(since) = reconnect.since else {
+            continue;
+        };
+
+        if !reconnect.was_streaming {
+            continue;
+        }
+
+        if reconnected(&user_state, &connected, DISCONNECT_GRACE).await {
+            debug!("Connection came back within {:?}", DISCONNECT_GRACE);
+            continue;
+        }
+
+        warn!("Connection lost while streaming, switching holds until it's back");
+        notify_chat(
+            &user_state,
+            &chat_sender,
+            chat::InternalUpdate::BroadcastingSoftwareDisconnected,
+        )
+        .await;
+
+        while !reconnected(&user_state, &connected, DISCONNECT_GRACE).await {}
+
+        let attempts = {
+            let state = user_state.read().await;
+            state.broadcasting_software.reconnect.attempts
+        };
+
+        info!(
+            "Connection back after {:?} and {} attempts",
+            since.elapsed(),
+            attempts
+        );
+
+        notify_chat(
+            &user_state,
+            &chat_sender,
+            chat::InternalUpdate::BroadcastingSoftwareReconnected(since.elapsed()),
+        )
+        .await;
+    }
+}
+
+/// Waits until connected again, false when it took longer than `timeout`
+async fn reconnected(
+    user_state: &noalbs::UserState,
+    connected: &Notify,
+    timeout: Duration,
+) -> bool {
+    // Created before checking so a reconnect in between isn't missed
+    let notified = connected.notified();
+
+    if user_state.read().await.broadcasting_software.status == ClientStatus::Connected {
+        return true;
+    }
+
+    time::timeout(timeout, notified).await.is_ok()
+}
+
+pub async fn notify_chat(
+    user_state: &noalbs::UserState,
+    chat_sender: &noalbs::ChatSender,
+    kind: chat::InternalUpdate,
+) {
+    let chat = {
+        let state = user_state.read().await;
+        state
+            .config
+            .chat
+            .as_ref()
+            .map(|chat| (chat.platform.kind(), chat.username.to_owned()))
+    };
+
+    let Some((platform, channel)) = chat else {
+        return;
+    };
+
+    let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+        platform,
+        channel,
+        kind,
+    });
+
+    let _ = chat_sender.send(message).await;
+}
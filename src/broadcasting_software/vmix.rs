@@ -0,0 +1,368 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::sync;
+use tracing::{error, info, trace, warn, Instrument};
+
+use crate::{
+    config::{self, VmixConfig},
+    error, noalbs,
+    state::{self, ClientStatus},
+};
+
+use super::BroadcastingSoftwareLogic;
+
+/// How often the vMix state gets requested
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timeout for every request to the vMix API
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug)]
+struct VmixXml {
+    inputs: VmixInputs,
+
+    /// Number of the input that is currently live
+    active: u32,
+    streaming: VmixFlag,
+    recording: VmixFlag,
+}
+
+#[derive(Deserialize, Debug)]
+struct VmixInputs {
+    #[serde(default)]
+    input: Vec<VmixInput>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VmixInput {
+    number: u32,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct VmixFlag {
+    #[serde(rename = "$value")]
+    value: String,
+}
+
+impl VmixFlag {
+    fn enabled(&self) -> bool {
+        self.value.eq_ignore_ascii_case("true")
+    }
+}
+
+impl VmixXml {
+    fn active_input(&self) -> Option<&VmixInput> {
+        self.inputs.input.iter().find(|i| i.number == self.active)
+    }
+}
+
+/// Talks to the vMix web API
+#[derive(Clone)]
+struct Api {
+    client: reqwest::Client,
+    config: VmixConfig,
+}
+
+impl Api {
+    fn url(&self) -> String {
+        format!("http://{}:{}/api/", self.config.host, self.config.port)
+    }
+
+    fn request(&self) -> reqwest::RequestBuilder {
+        let req = self.client.get(self.url()).timeout(REQUEST_TIMEOUT);
+
+        match &self.config.username {
+            Some(username) => req.basic_auth(username, self.config.password.as_ref()),
+            None => req,
+        }
+    }
+
+    async fn state(&self) -> Result<VmixXml, error::Error> {
+        let res = self.request().send().await?.error_for_status()?;
+        let text = res.text().await?;
+        trace!("{}", text);
+
+        Ok(quick_xml::de::from_str(&text)?)
+    }
+
+    async fn function(&self, function: &str, params: &[(&str, &str)]) -> Result<(), error::Error> {
+        self.request()
+            .query(&[("Function", function)])
+            .query(params)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+pub struct Vmix {
+    api: Api,
+    poll_join: tokio::task::JoinHandle<()>,
+    scene_resolver: super::SceneResolver,
+}
+
+impl Vmix {
+    pub fn new(connection_info: VmixConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, true)
+    }
+
+    /// Connects to an extra instance which won't update the state of the user
+    pub fn new_secondary(connection_info: VmixConfig, state: noalbs::UserState) -> Self {
+        Self::connect(connection_info, state, false)
+    }
+
+    fn connect(connection_info: VmixConfig, state: noalbs::UserState, primary: bool) -> Self {
+        let api = Api {
+            client: reqwest::Client::new(),
+            config: connection_info,
+        };
+
+        let api_inner = api.clone();
+        let poll_join = tokio::spawn(async move {
+            if !primary {
+                return;
+            }
+
+            let user = { state.read().await.config.user.name.to_owned() };
+
+            Self::poll(api_inner, state)
+                .instrument(tracing::info_span!("vMix", %user))
+                .await
+        });
+
+        Self {
+            api,
+            poll_join,
+            scene_resolver: Default::default(),
+        }
+    }
+
+    /// vMix has no events so the state gets polled instead
+    async fn poll(api: Api, user_state: noalbs::UserState) {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let vmix = match api.state().await {
+                Ok(vmix) => vmix,
+                Err(e) => {
+                    let mut l = user_state.write().await;
+                    let bs = &mut l.broadcasting_software;
+
+                    if bs.status == ClientStatus::Connected {
+                        warn!("Disconnected: {}", e);
+                        bs.set_disconnected();
+                    } else if bs.reconnect.since.is_some() {
+                        bs.reconnect.attempts += 1;
+                    }
+
+                    continue;
+                }
+            };
+
+            let mut l = user_state.write().await;
+
+            if l.broadcasting_software.status == ClientStatus::Disconnected {
+                info!("Connected");
+                l.broadcasting_software.set_connected();
+            }
+
+            if let Some(input) = vmix.active_input() {
+                if l.broadcasting_software.current_scene != input.title {
+                    if l.switcher_state.switchable_scenes.contains(&input.title) {
+                        l.broadcasting_software
+                            .switch_scene_notifier()
+                            .notify_waiters();
+                    }
+
+                    l.broadcasting_software
+                        .set_current_scene(input.title.to_owned());
+                }
+            }
+
+            let streaming = vmix.streaming.enabled();
+            let bs = &mut l.broadcasting_software;
+
+            if streaming && !bs.is_streaming {
+                bs.is_streaming = true;
+                bs.last_stream_started_at = std::time::Instant::now();
+                bs.start_streaming_notifier().notify_waiters();
+            } else if !streaming && bs.is_streaming {
+                bs.is_streaming = false;
+                bs.stream_status = None;
+                bs.initial_stream_status = None;
+                bs.dropped_frames.clear();
+                bs.stop_streaming_notifier().notify_waiters();
+            }
+
+            drop(l);
+            super::sync_recording(&user_state).await;
+        }
+    }
+}
+
+impl Drop for Vmix {
+    fn drop(&mut self) {
+        self.poll_join.abort();
+    }
+}
+
+#[async_trait]
+impl BroadcastingSoftwareLogic for Vmix {
+    /// Scenes are vMix inputs, switching cuts the input to the output
+    async fn switch_scene(&self, scene: &str) -> Result<String, error::Error> {
+        let vmix = self.api.state().await?;
+        let titles: Vec<_> = vmix
+            .inputs
+            .input
+            .iter()
+            .map(|i| i.title.to_owned())
+            .collect();
+        let title = self.scene_resolver.resolve(&titles, scene)?;
+
+        let input = vmix
+            .inputs
+            .input
+            .iter()
+            .find(|i| i.title == title)
+            .ok_or(error::Error::NoSourceFound)?;
+
+        self.api
+            .function("CutDirect", &[("Input", &input.number.to_string())])
+            .await?;
+
+        Ok(input.title.to_owned())
+    }
+
+    async fn start_streaming(&self) -> Result<(), error::Error> {
+        self.api.function("StartStreaming", &[]).await
+    }
+
+    async fn stop_streaming(&self) -> Result<(), error::Error> {
+        self.api.function("StopStreaming", &[]).await
+    }
+
+    async fn toggle_recording(&self) -> Result<(), error::Error> {
+        self.api.function("StartStopRecording", &[]).await
+    }
+
+    async fn is_recording(&self) -> Result<bool, error::Error> {
+        Ok(self.api.state().await?.recording.enabled())
+    }
+
+    async fn fix(&self) -> Result<(), error::Error> {
+        Err(error::Error::NotSupported)
+    }
+
+    async fn current_scene(&self) -> Result<String, error::Error> {
+        let vmix = self.api.state().await?;
+
+        vmix.active_input()
+            .map(|i| i.title.to_owned())
+            .ok_or(error::Error::NoSourceFound)
+    }
+
+    async fn scene_list(&self) -> Result<Vec<String>, error::Error> {
+        let vmix = self.api.state().await?;
+
+        Ok(vmix.inputs.input.into_iter().map(|i| i.title).collect())
+    }
+
+    async fn health(&self) -> Result<super::Health, error::Error> {
+        let vmix = self.api.state().await?;
+
+        Ok(super::Health {
+            scene: vmix
+                .active_input()
+                .map(|i| i.title.to_owned())
+                .unwrap_or_default(),
+            streaming: vmix.streaming.enabled(),
+            recording: vmix.recording.enabled(),
+        })
+    }
+
+    async fn toggle_source(&self, _source: &str) -> Result<(String, bool), error::Error> {
+        Err(error::Error::NotSupported)
+    }
+
+    /// Opens the preset at the path of the collection, vMix has no profiles
+    async fn set_collection_and_profile(
+        &self,
+        source: &config::CollectionPair,
+    ) -> Result<(), error::Error> {
+        if self.api.state().await?.streaming.enabled() {
+            error!("Can't open a vMix preset while streaming");
+            return Err(error::Error::NotSupported);
+        }
+
+        self.api
+            .function("OpenPreset", &[("Value", &source.collection)])
+            .await?;
+
+        self.scene_resolver.reset();
+
+        Ok(())
+    }
+
+    async fn info(
+        &self,
+        _state: &sync::RwLockReadGuard<state::State>,
+    ) -> Result<state::StreamStatus, error::Error> {
+        Err(error::Error::NotSupported)
+    }
+
+    async fn get_media_source_status(
+        &self,
+        _source_name: &str,
+    ) -> Result<(obws::responses::MediaState, i64), error::Error> {
+        Err(error::Error::NotSupported)
+    }
+
+    async fn create_special_media_source(
+        &self,
+        _source_name: &str,
+        _scene_name: &str,
+    ) -> Result<String, error::Error> {
+        Err(error::Error::NotSupported)
+    }
+
+    async fn remove_special_media_source(
+        &self,
+        _source_name: &str,
+        _scene: &str,
+    ) -> Result<(), error::Error> {
+        Err(error::Error::NotSupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vmix_state() {
+        let xml = r#"<vmix>
+            <version>27.0.0.49</version>
+            <inputs>
+                <input key="a" number="1" type="Capture" title="live">live</input>
+                <input key="b" number="2" type="Image" title="brb">brb</input>
+            </inputs>
+            <active>2</active>
+            <preview>1</preview>
+            <recording>False</recording>
+            <streaming>True</streaming>
+        </vmix>"#;
+
+        let vmix: VmixXml = quick_xml::de::from_str(xml).unwrap();
+
+        assert_eq!(vmix.active_input().unwrap().title, "brb");
+        assert!(vmix.streaming.enabled());
+        assert!(!vmix.recording.enabled());
+    }
+}
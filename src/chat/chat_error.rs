@@ -0,0 +1,133 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+/// Wait this long before trying again after the credentials got rejected
+const AUTH_FAILED_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Wait this long before trying again after hitting a rate limit or quota
+const RATE_LIMITED_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// Errors of the chat connections, the kind of error decides how the
+/// connection recovers instead of retrying everything the same way
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+
+    #[error("The channel isn't live")]
+    NotLive,
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    #[error("Network error: {0}")]
+    Network(String),
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ChatError {
+    /// Classifies a failed HTTP response, the body tells a quota apart from
+    /// missing permissions
+    pub fn from_status(status: StatusCode, body: &str) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::AuthFailed(status.to_string()),
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited(status.to_string()),
+            StatusCode::FORBIDDEN
+                if body.contains("quotaExceeded") || body.contains("rateLimitExceeded") =>
+            {
+                Self::RateLimited(body.to_owned())
+            }
+            StatusCode::FORBIDDEN => Self::AuthFailed(body.to_owned()),
+            StatusCode::NOT_FOUND => Self::NotLive,
+            status => Self::Other(status.to_string()),
+        }
+    }
+
+    /// Classifies an error reported by a chat client library
+    pub fn classify(error: &anyhow::Error) -> Self {
+        if let Some(e) = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<reqwest::Error>())
+        {
+            return Self::from(e);
+        }
+
+        // Libraries report a channel without a broadcast as a plain message
+        let message = error.to_string();
+        let lower = message.to_lowercase();
+
+        if lower.contains("live") && (lower.contains("not found") || lower.contains("find")) {
+            return Self::NotLive;
+        }
+
+        Self::Other(message)
+    }
+
+    /// How long to wait before connecting again, `None` uses the normal retry
+    pub fn backoff(&self) -> Option<Duration> {
+        match self {
+            Self::AuthFailed(_) => Some(AUTH_FAILED_BACKOFF),
+            Self::RateLimited(_) => Some(RATE_LIMITED_BACKOFF),
+            _ => None,
+        }
+    }
+}
+
+impl From<&reqwest::Error> for ChatError {
+    fn from(error: &reqwest::Error) -> Self {
+        match error.status() {
+            Some(status) => Self::from_status(status, ""),
+            None if error.is_connect() || error.is_timeout() || error.is_request() => {
+                Self::Network(error.to_string())
+            }
+            None => Self::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ChatError {
+    fn from(error: reqwest::Error) -> Self {
+        Self::from(&error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_status() {
+        assert!(matches!(
+            ChatError::from_status(StatusCode::UNAUTHORIZED, ""),
+            ChatError::AuthFailed(_)
+        ));
+        assert!(matches!(
+            ChatError::from_status(StatusCode::FORBIDDEN, r#"{"reason": "quotaExceeded"}"#),
+            ChatError::RateLimited(_)
+        ));
+        assert!(matches!(
+            ChatError::from_status(StatusCode::FORBIDDEN, r#"{"reason": "forbidden"}"#),
+            ChatError::AuthFailed(_)
+        ));
+        assert!(matches!(
+            ChatError::from_status(StatusCode::NOT_FOUND, ""),
+            ChatError::NotLive
+        ));
+        assert!(matches!(
+            ChatError::from_status(StatusCode::INTERNAL_SERVER_ERROR, ""),
+            ChatError::Other(_)
+        ));
+    }
+
+    #[test]
+    fn classify_library_message() {
+        let not_live = anyhow::anyhow!("Could not find live_id for the channel");
+        assert!(matches!(ChatError::classify(&not_live), ChatError::NotLive));
+
+        let other = anyhow::anyhow!("Unexpected response");
+        assert!(matches!(ChatError::classify(&other), ChatError::Other(_)));
+    }
+}
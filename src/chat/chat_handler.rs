@@ -3,13 +3,16 @@ use std::fmt::Write as _;
 use std::sync::Arc;
 
 use rust_i18n::t;
-use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::time;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::chat::{self, HandleMessage, OptionalScene, Permission};
-use crate::{config, error, events, switcher, user_manager, Noalbs};
+use crate::chat::{self, format, template, HandleMessage, OptionalScene, Permission};
+use crate::{
+    config, error, events,
+    state::{self, ClientStatus},
+    switcher, twitch_transcoding, user_manager, Noalbs,
+};
 
 pub struct ChatHandler {
     chat_handler_rx: mpsc::Receiver<super::HandleMessage>,
@@ -17,6 +20,7 @@ pub struct ChatHandler {
     chat_senders: HashMap<chat::ChatPlatform, Arc<dyn chat::ChatLogic>>,
 
     timeouts: HashMap<chat::ChatPlatform, Vec<Timeout>>,
+    cooldowns: Cooldowns,
     default_commands: HashMap<chat::Command, config::CommandInfo>,
 }
 
@@ -29,6 +33,8 @@ impl ChatHandler {
         timeouts.insert(chat::ChatPlatform::Twitch, Vec::new());
         timeouts.insert(chat::ChatPlatform::Kick, Vec::new());
         timeouts.insert(chat::ChatPlatform::Youtube, Vec::new());
+        timeouts.insert(chat::ChatPlatform::Discord, Vec::new());
+        timeouts.insert(chat::ChatPlatform::Trovo, Vec::new());
 
         Self {
             chat_handler_rx,
@@ -36,6 +42,7 @@ impl ChatHandler {
             chat_senders: HashMap::new(),
             default_commands: Self::default_command_settings(),
             timeouts,
+            cooldowns: Cooldowns::default(),
         }
     }
 
@@ -60,6 +67,14 @@ impl ChatHandler {
             },
         );
 
+        default.insert(
+            Command::BitrateHistory,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
         default.insert(
             Command::Fix,
             config::CommandInfo {
@@ -76,6 +91,62 @@ impl ChatHandler {
             },
         );
 
+        default.insert(
+            Command::ObsInfo,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Servers,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Server,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Maintenance,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Retry,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Rename,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::MuteNotify,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
         default.insert(
             Command::Otrigger,
             config::CommandInfo {
@@ -92,6 +163,14 @@ impl ChatHandler {
             },
         );
 
+        default.insert(
+            Command::Reload,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
         default.insert(
             Command::Rtrigger,
             config::CommandInfo {
@@ -116,6 +195,87 @@ impl ChatHandler {
             },
         );
 
+        default.insert(
+            Command::PrivacyScene,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Transition,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Status,
+            config::CommandInfo {
+                permission: Some(Permission::Public),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::RestartChat,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Why,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Preset,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Triggers,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::ForceLive,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Screenshot,
+            config::CommandInfo {
+                permission: Some(Permission::Mod),
+                cooldown_seconds: Some(30),
+                ..Default::default()
+            },
+        );
+
+        default.insert(
+            Command::Commands,
+            config::CommandInfo {
+                permission: Some(Permission::Public),
+                ..Default::default()
+            },
+        );
+
         default.insert(
             Command::Version,
             config::CommandInfo {
@@ -127,12 +287,16 @@ impl ChatHandler {
         default
     }
 
+    /// Every message sent to the platform goes through its rate limit
     pub fn add_chat_sender(
         &mut self,
         platform: chat::ChatPlatform,
         connection: Arc<dyn chat::ChatLogic>,
     ) {
-        self.chat_senders.insert(platform, connection);
+        let limit = chat::RateLimit::from_env(&platform);
+        let connection = chat::RateLimited::new(platform.clone(), connection, limit);
+
+        self.chat_senders.insert(platform, Arc::new(connection));
     }
 
     pub async fn handle_messages(&mut self) {
@@ -145,6 +309,7 @@ impl ChatHandler {
                         continue;
                     }
 
+                    self.relay_chat_message(&msg).await;
                     self.handle_chat_message(msg).await;
                 }
                 HandleMessage::InternalChatUpdate(update) => {
@@ -154,7 +319,28 @@ impl ChatHandler {
                             let target_info = target_info.to_owned();
                             self.handle_raid(update, target_info).await
                         }
-                        InternalUpdate::OfflineTimeout => self.handle_offline_timeout(update).await,
+                        InternalUpdate::OfflineTimeout | InternalUpdate::OfflineTimeoutWarning => {
+                            self.handle_offline_timeout(update).await
+                        }
+                        InternalUpdate::LowBitrateWarning => {
+                            self.handle_low_bitrate_warning(update).await
+                        }
+                        InternalUpdate::NoTranscoding => self.handle_no_transcoding(update).await,
+                        InternalUpdate::PlatformStreamOnline(online) => {
+                            self.handle_platform_stream_online(update, online).await
+                        }
+                        InternalUpdate::BroadcastingSoftwareDisconnected
+                        | InternalUpdate::BroadcastingSoftwareReconnected(_) => {
+                            self.handle_connection_update(update).await
+                        }
+                        InternalUpdate::SceneSwitchFailed(ref scene) => {
+                            let scene = scene.to_owned();
+                            self.handle_scene_switch_failed(update, scene).await
+                        }
+                        InternalUpdate::SwitcherRestarted => {
+                            self.handle_switcher_restarted(update).await
+                        }
+                        InternalUpdate::ChatEnabled => self.handle_chat_enabled(update).await,
                     };
                 }
                 HandleMessage::AutomaticSwitchingScene(ss) => {
@@ -174,6 +360,12 @@ impl ChatHandler {
             .user_manager
             .get_user_by_chat_platform(&ss.channel, &ss.platform)
             .await?;
+        let notification = template::Notification::from(ss.switch_type);
+        if let Some(msg) = custom_notification(&user, notification, &ss.scene).await {
+            send_notification(sender.as_ref(), &user, notification, ss.channel, msg).await;
+            return Some(());
+        }
+
         let lang = &user.chat_language().await.unwrap().to_string();
         let mut msg = t!("sceneSwitch.switch", locale = lang, scene = &ss.scene);
 
@@ -186,7 +378,49 @@ impl ChatHandler {
             Previous | Offline => {}
         }
 
-        sender.send_message(ss.channel, msg).await;
+        send_notification(sender.as_ref(), &user, notification, ss.channel, msg).await;
+
+        Some(())
+    }
+
+    /// Forwards the message to the chats in the relay of the user,
+    /// commands and the messages of the bot itself stay where they are
+    async fn relay_chat_message(&self, msg: &chat::ChatMessage) -> Option<()> {
+        if self
+            .chat_senders
+            .get(&msg.platform)?
+            .sent_recently(&msg.channel, &msg.message)
+        {
+            return None;
+        }
+
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&msg.channel, &msg.platform)
+            .await?;
+
+        let targets = {
+            let state = user.state.read().await;
+            let chat = state.config.chat.as_ref()?;
+
+            if chat.relay.is_empty() || msg.message.starts_with(&chat.prefix) {
+                return None;
+            }
+
+            chat.relay.clone()
+        };
+
+        let relayed = relay_text(msg);
+
+        for target in targets
+            .into_iter()
+            .filter(|t| t.platform != msg.platform || t.channel != msg.channel)
+        {
+            match self.chat_senders.get(&target.platform) {
+                Some(sender) => sender.send_message(target.channel, relayed.clone()).await,
+                None => debug!("{:?} chat isn't connected, not relaying", target.platform),
+            }
+        }
 
         Some(())
     }
@@ -214,6 +448,11 @@ impl ChatHandler {
             return None;
         }
 
+        if self.is_on_cooldown(&user, &msg, &command).await {
+            debug!("{:?} is on cooldown", command);
+            return None;
+        }
+
         match command {
             chat::Command::Unknown(_) => {}
             _ => {
@@ -279,52 +518,7 @@ impl ChatHandler {
         let state = user.state.read().await;
         let chat = state.config.chat.as_ref()?;
 
-        let chat::CommandPermissions {
-            permission,
-            user_permissions,
-        } = permission;
-
-        let user_permission = &msg.permission;
-
-        if *user_permission == chat::Permission::Admin || chat.admins.contains(&msg.sender) {
-            return Some(true);
-        }
-
-        if let Some(user_permissions) = user_permissions {
-            if user_permissions.contains(&msg.sender) {
-                return Some(true);
-            }
-        }
-
-        if *user_permission == chat::Permission::Mod
-            && !chat.enable_public_commands
-            && !chat.enable_mod_commands
-        {
-            debug!("Public and Mod commands disabled");
-            return Some(false);
-        }
-
-        if let Some(permission) = permission {
-            if *user_permission == chat::Permission::Mod
-                && *permission == chat::Permission::Mod
-                && !chat.enable_mod_commands
-            {
-                debug!("Mod commands disabled");
-                return Some(false);
-            }
-        }
-
-        if *user_permission == chat::Permission::Public && !chat.enable_public_commands {
-            debug!("Public commands disabled");
-            return Some(false);
-        }
-
-        debug!("Not an admin checking permission");
-        if let Some(permission) = permission {
-            return Some(permission_is_allowed(permission, user_permission));
-        }
-
-        Some(false)
+        Some(is_allowed_to_use_command(chat, msg, permission))
     }
 
     pub async fn handle_raid(
@@ -337,26 +531,24 @@ impl ChatHandler {
             .get_user_by_chat_platform(&raid.channel, &raid.platform)
             .await?;
 
-        let state = user.state.read().await;
+        let command = {
+            let state = user.state.read().await;
+            let bs = &state.broadcasting_software;
 
-        if !state
-            .config
-            .chat
-            .as_ref()?
-            .enable_auto_stop_stream_on_host_or_raid
-        {
-            return None;
-        }
+            raid_auto_stop(
+                state.config.chat.as_ref()?,
+                bs.is_streaming,
+                bs.last_stream_started_at.elapsed(),
+                target_info,
+            )
+        };
 
-        let bs = &state.broadcasting_software;
-        if !bs.is_streaming || bs.last_stream_started_at.elapsed().as_secs() < 60 {
+        let Some(command) = command else {
+            debug!(
+                "Channel raided, not stopping the stream ({:?}) {}",
+                raid.platform, raid.channel
+            );
             return None;
-        }
-
-        let command = if state.config.chat.as_ref()?.announce_raid_on_auto_stop {
-            chat::Command::StopOnRaid(target_info)
-        } else {
-            chat::Command::Stop
         };
 
         info!(
@@ -390,54 +582,288 @@ impl ChatHandler {
             .get_user_by_chat_platform(&host.channel, &host.platform)
             .await?;
         let lang = &user.chat_language().await.unwrap().to_string();
-        let msg = t!("offlineTimeout.timeout", locale = lang);
+        let (notification, msg) = match host.kind {
+            chat::InternalUpdate::OfflineTimeoutWarning => (
+                template::Notification::OfflineTimeoutWarning,
+                t!("offlineTimeout.warning", locale = lang),
+            ),
+            _ => (
+                template::Notification::OfflineTimeout,
+                t!("offlineTimeout.timeout", locale = lang),
+            ),
+        };
 
-        sender.send_message(host.channel, msg).await;
+        let scene = {
+            let state = user.state.read().await;
+            state.broadcasting_software.current_scene.to_owned()
+        };
+
+        let msg = custom_notification(&user, notification, &scene)
+            .await
+            .unwrap_or(msg);
+
+        send_notification(sender.as_ref(), &user, notification, host.channel, msg).await;
 
         Some(())
     }
 
-    // TODO: Maybe remove when timeout passed
-    pub async fn handle_timeout(&mut self, platform: &chat::ChatPlatform, channel: &str) -> bool {
-        let platform_timeouts = self.timeouts.get_mut(platform).unwrap();
-        let channel_timeout = platform_timeouts.iter_mut().find(|x| x.channel == channel);
+    pub async fn handle_low_bitrate_warning(&self, host: chat::InternalChatUpdate) -> Option<()> {
+        let sender = self.chat_senders.get(&host.platform)?;
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+        let lang = &user.chat_language().await.unwrap().to_string();
 
-        if let Some(timeout) = channel_timeout {
-            let delta = timeout.time.elapsed().as_secs();
+        let scene = {
+            let state = user.state.read().await;
+            state.broadcasting_software.current_scene.to_owned()
+        };
 
-            if delta <= 5 {
-                return true;
-            } else {
-                timeout.time = std::time::Instant::now();
+        let notification = template::Notification::LowBitrateWarning;
+        let msg = match custom_notification(&user, notification, &scene).await {
+            Some(msg) => msg,
+            None => {
+                let bitrate = bitrate_msg(&user, lang).await;
+                format!("{} | {}", t!("lowWarning.warning", locale = lang), bitrate)
             }
-        } else {
-            platform_timeouts.push(Timeout {
-                channel: channel.to_owned(),
-                time: std::time::Instant::now(),
-            });
-        }
+        };
 
-        false
+        send_notification(sender.as_ref(), &user, notification, host.channel, msg).await;
+
+        Some(())
     }
-}
 
-fn get_permission(
-    command: &chat::Command,
-    user_commands: &Option<HashMap<chat::Command, config::CommandInfo>>,
-    default_commands: &HashMap<chat::Command, config::CommandInfo>,
-) -> chat::CommandPermissions {
-    user_commands
-        .as_ref()
-        .and_then(|uc| try_get_permission(command, uc))
-        .map(|mut p| {
-            if p.permission.is_none() {
-                p.permission =
-                    try_get_permission(command, default_commands).and_then(|d| d.permission);
-            }
-            p
-        })
-        .or_else(|| try_get_permission(command, default_commands))
-        .unwrap_or(chat::CommandPermissions {
+    pub async fn handle_platform_stream_online(
+        &self,
+        update: chat::InternalChatUpdate,
+        online: Option<bool>,
+    ) -> Option<()> {
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&update.channel, &update.platform)
+            .await?;
+
+        user.set_platform_stream_online(online).await;
+
+        Some(())
+    }
+
+    pub async fn handle_connection_update(&self, host: chat::InternalChatUpdate) -> Option<()> {
+        let sender = self.chat_senders.get(&host.platform)?;
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+        let lang = &user.chat_language().await.unwrap().to_string();
+
+        let msg = match host.kind {
+            chat::InternalUpdate::BroadcastingSoftwareReconnected(duration) => t!(
+                "connection.reconnected",
+                locale = lang,
+                duration = &format_duration(duration, lang)
+            ),
+            _ => t!("connection.disconnected", locale = lang),
+        };
+
+        sender.send_message(host.channel, msg).await;
+
+        Some(())
+    }
+
+    pub async fn handle_no_transcoding(&self, host: chat::InternalChatUpdate) -> Option<()> {
+        let sender = self.chat_senders.get(&host.platform)?;
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+        let lang = &user.chat_language().await.unwrap().to_string();
+
+        sender
+            .send_message(
+                host.channel,
+                t!("startTwitchTranscoding.missing", locale = lang),
+            )
+            .await;
+
+        Some(())
+    }
+
+    pub async fn handle_scene_switch_failed(
+        &self,
+        host: chat::InternalChatUpdate,
+        scene: String,
+    ) -> Option<()> {
+        let sender = self.chat_senders.get(&host.platform)?;
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+        let lang = &user.chat_language().await.unwrap().to_string();
+
+        sender
+            .send_message(
+                host.channel,
+                t!("sceneSwitch.failed", locale = lang, scene = &scene),
+            )
+            .await;
+
+        Some(())
+    }
+
+    pub async fn handle_switcher_restarted(&self, host: chat::InternalChatUpdate) -> Option<()> {
+        let sender = self.chat_senders.get(&host.platform)?;
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+        let lang = &user.chat_language().await.unwrap().to_string();
+
+        sender
+            .send_message(host.channel, t!("switcher.restarted", locale = lang))
+            .await;
+
+        Some(())
+    }
+
+    /// Joins the channel of a user that set up chat while running
+    pub async fn handle_chat_enabled(&self, host: chat::InternalChatUpdate) -> Option<()> {
+        let Some(sender) = self.chat_senders.get(&host.platform) else {
+            warn!(
+                "Not connected to {:?}, restart NOALBS with its credentials to join {}",
+                host.platform, host.channel
+            );
+            return None;
+        };
+
+        let user = self
+            .user_manager
+            .get_user_by_chat_platform(&host.channel, &host.platform)
+            .await?;
+
+        let (platform, prefix, lang) = {
+            let state = user.state.read().await;
+            let chat = state.config.chat.as_ref()?;
+
+            (
+                chat.platform.clone(),
+                chat.prefix.to_owned(),
+                chat.language.to_string(),
+            )
+        };
+
+        if !sender.join(platform, host.channel.to_owned()).await {
+            warn!(
+                "{:?} only joins channels at startup, restart NOALBS to join {}",
+                host.platform, host.channel
+            );
+            return None;
+        }
+
+        info!("Chat enabled for {}", host.channel);
+
+        sender
+            .send_message(
+                host.channel,
+                t!("chat.enabled", locale = &lang, prefix = &prefix),
+            )
+            .await;
+
+        Some(())
+    }
+
+    /// Checks the cooldown of the command, starts it when not on cooldown.
+    /// Admins bypass cooldowns.
+    async fn is_on_cooldown(
+        &mut self,
+        user: &Noalbs,
+        msg: &chat::ChatMessage,
+        command: &chat::Command,
+    ) -> bool {
+        let (seconds, scope) = {
+            let state = user.state.read().await;
+            let Some(chat) = state.config.chat.as_ref() else {
+                return false;
+            };
+
+            if msg.permission == chat::Permission::Admin
+                || chat.is_admin(&msg.platform, &msg.sender)
+            {
+                return false;
+            }
+
+            // The default cooldown applies when the config doesn't set one
+            let info = chat.commands.as_ref().and_then(|c| c.get(command));
+            let default = self.default_commands.get(command);
+            let cooldown = |info: Option<&config::CommandInfo>| {
+                info.and_then(|i| Some((i.cooldown_seconds?, i.cooldown_scope)))
+            };
+
+            let Some((seconds, scope)) = cooldown(info).or_else(|| cooldown(default)) else {
+                return false;
+            };
+
+            (seconds, scope.unwrap_or_default())
+        };
+
+        let key = CooldownKey {
+            platform: msg.platform.to_owned(),
+            channel: msg.channel.to_owned(),
+            command: command.to_owned(),
+            user: match scope {
+                config::CooldownScope::Global => None,
+                config::CooldownScope::User => Some(msg.sender.to_owned()),
+            },
+        };
+
+        self.cooldowns.check(
+            key,
+            std::time::Duration::from_secs(seconds),
+            std::time::Instant::now(),
+        )
+    }
+
+    // TODO: Maybe remove when timeout passed
+    pub async fn handle_timeout(&mut self, platform: &chat::ChatPlatform, channel: &str) -> bool {
+        let platform_timeouts = self.timeouts.get_mut(platform).unwrap();
+        let channel_timeout = platform_timeouts.iter_mut().find(|x| x.channel == channel);
+
+        if let Some(timeout) = channel_timeout {
+            let delta = timeout.time.elapsed().as_secs();
+
+            if delta <= 5 {
+                return true;
+            } else {
+                timeout.time = std::time::Instant::now();
+            }
+        } else {
+            platform_timeouts.push(Timeout {
+                channel: channel.to_owned(),
+                time: std::time::Instant::now(),
+            });
+        }
+
+        false
+    }
+}
+
+fn get_permission(
+    command: &chat::Command,
+    user_commands: &Option<HashMap<chat::Command, config::CommandInfo>>,
+    default_commands: &HashMap<chat::Command, config::CommandInfo>,
+) -> chat::CommandPermissions {
+    user_commands
+        .as_ref()
+        .and_then(|uc| try_get_permission(command, uc))
+        .map(|mut p| {
+            if p.permission.is_none() {
+                p.permission =
+                    try_get_permission(command, default_commands).and_then(|d| d.permission);
+            }
+            p
+        })
+        .or_else(|| try_get_permission(command, default_commands))
+        .unwrap_or(chat::CommandPermissions {
             permission: Some(chat::Permission::Admin),
             user_permissions: None,
         })
@@ -457,16 +883,126 @@ fn try_get_permission(
     None
 }
 
+/// Whether the sender of the message may use a command with these permissions
+fn is_allowed_to_use_command(
+    chat: &config::Chat,
+    msg: &chat::ChatMessage,
+    permission: &chat::CommandPermissions,
+) -> bool {
+    let chat::CommandPermissions {
+        permission,
+        user_permissions,
+    } = permission;
+
+    let user_permission = &msg.permission;
+
+    if *user_permission == chat::Permission::Admin || chat.is_admin(&msg.platform, &msg.sender) {
+        return true;
+    }
+
+    if let Some(user_permissions) = user_permissions {
+        if user_permissions.contains(&msg.sender) {
+            return true;
+        }
+    }
+
+    if *user_permission == chat::Permission::Mod
+        && !chat.enable_public_commands
+        && !chat.enable_mod_commands
+    {
+        debug!("Public and Mod commands disabled");
+        return false;
+    }
+
+    if let Some(permission) = permission {
+        if *user_permission == chat::Permission::Mod
+            && *permission == chat::Permission::Mod
+            && !chat.enable_mod_commands
+        {
+            debug!("Mod commands disabled");
+            return false;
+        }
+    }
+
+    if *user_permission == chat::Permission::Public && !chat.enable_public_commands {
+        debug!("Public commands disabled");
+        return false;
+    }
+
+    debug!("Not an admin checking permission");
+    if let Some(permission) = permission {
+        return permission_is_allowed(permission, user_permission);
+    }
+
+    false
+}
+
+/// Commands the sender of the message may use with their aliases,
+/// like `!bitrate (!b)`
+fn available_commands(
+    chat: &config::Chat,
+    msg: &chat::ChatMessage,
+    default_commands: &HashMap<chat::Command, config::CommandInfo>,
+) -> Vec<String> {
+    chat::Command::NAMES
+        .iter()
+        .filter_map(|name| {
+            let command = chat::Command::from(*name);
+            let permission = get_permission(&command, &chat.commands, default_commands);
+
+            if !is_allowed_to_use_command(chat, msg, &permission) {
+                return None;
+            }
+
+            let aliases = chat
+                .commands
+                .iter()
+                .chain(std::iter::once(default_commands))
+                .filter_map(|commands| commands.get(&command)?.alias.as_ref())
+                .flatten()
+                .map(|alias| format!("{}{}", chat.prefix, alias))
+                .collect::<Vec<_>>();
+
+            if aliases.is_empty() {
+                return Some(format!("{}{}", chat.prefix, name));
+            }
+
+            Some(format!("{}{} ({})", chat.prefix, name, aliases.join(", ")))
+        })
+        .collect()
+}
+
+/// Joins the items into as few messages as possible that each stay
+/// within the length
+fn join_within(items: &[String], max_len: usize) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut current = String::new();
+
+    for item in items {
+        if !current.is_empty() && current.len() + item.len() + 2 > max_len {
+            messages.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push_str(", ");
+        }
+
+        current.push_str(item);
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+/// Public < Vip < Mod < Admin
 fn permission_is_allowed(
     permission: &chat::Permission,
     user_permission: &chat::Permission,
 ) -> bool {
-    matches!(
-        (permission, user_permission),
-        (&Permission::Public, _)
-            | (&Permission::Mod, &Permission::Mod)
-            | (&Permission::Vip, &Permission::Mod | &Permission::Vip)
-    )
+    user_permission >= permission
 }
 
 fn try_get_command_from_alias(
@@ -523,10 +1059,13 @@ impl DispatchCommand {
             chat::Command::Alias => self.alias(params).await,
             chat::Command::Autostop => self.autostop(params.next()).await,
             chat::Command::Bitrate => self.bitrate().await,
+            chat::Command::BitrateHistory => self.bitrate_history(params.next()).await,
             chat::Command::Fix => self.fix().await,
             chat::Command::Refresh => self.refresh().await,
+            chat::Command::Reload => self.reload().await,
             chat::Command::Noalbs => self.noalbs(params.next(), params).await,
             chat::Command::Notify => self.notify(params.next()).await,
+            chat::Command::MuteNotify => self.mute_notify(params.next()).await,
             chat::Command::Rec => self.record().await,
             chat::Command::Start => self.start().await,
             chat::Command::Stop => self.stop(None).await,
@@ -535,6 +1074,7 @@ impl DispatchCommand {
                 self.trigger(switcher::TriggerType::Low, params.next())
                     .await
             }
+            chat::Command::Triggers => self.triggers(params.next()).await,
             chat::Command::Otrigger => {
                 self.trigger(switcher::TriggerType::Offline, params.next())
                     .await
@@ -548,10 +1088,8 @@ impl DispatchCommand {
                     .await
             }
             chat::Command::Version => self.version().await,
-            chat::Command::PrivacyScene => {
-                self.switch_optional_scene(chat::OptionalScene::Privacy)
-                    .await
-            }
+            chat::Command::PrivacyScene => self.privacy().await,
+            chat::Command::Maintenance => self.maintenance(params).await,
             chat::Command::StartingScene => {
                 self.switch_optional_scene(chat::OptionalScene::Starting)
                     .await
@@ -562,6 +1100,16 @@ impl DispatchCommand {
             }
             chat::Command::LiveScene => self.live_scene().await,
             chat::Command::ServerInfo => self.server_info().await,
+            chat::Command::ObsInfo => self.obs_info().await,
+            chat::Command::Servers => self.servers().await,
+            chat::Command::Status => self.status().await,
+            chat::Command::Server => self.server(params).await,
+            chat::Command::Rename => self.rename(params).await,
+            chat::Command::RestartChat => self.restart_chat().await,
+            chat::Command::Retry => {
+                let msg = self.set_retry_attempts(params.next()).await;
+                self.send(msg).await;
+            }
             chat::Command::Mod => self.enable_mod(params.next()).await,
             chat::Command::Public => self.enable_public(params.next()).await,
             chat::Command::Sourceinfo => self.source_info(params).await,
@@ -572,6 +1120,12 @@ impl DispatchCommand {
                 self.stop_on_raid(target_info.to_owned()).await;
             }
             chat::Command::Collection => self.collection(params.next()).await,
+            chat::Command::Commands => self.commands().await,
+            chat::Command::Why => self.why().await,
+            chat::Command::Screenshot => self.screenshot().await,
+            chat::Command::Preset => self.preset(params).await,
+            chat::Command::ForceLive => self.force_live(params.next()).await,
+            chat::Command::Transition => self.transition(params).await,
         };
     }
 
@@ -630,77 +1184,187 @@ impl DispatchCommand {
             return;
         }
 
-        if self.user.add_alias(a1.to_string(), command).await.is_ok() {
-            self.save_config().await;
-            self.send(t!(
-                "alias.success",
-                locale = &self.lang,
-                alias = a1,
-                command = a2
-            ))
-            .await;
-        }
-    }
-
-    async fn bitrate(&self) {
-        let msg = bitrate_msg(&self.user, &self.lang).await;
-
-        self.send(msg).await;
-    }
-
-    // TODO: more than one word?
-    async fn switch(&self, name: Option<&str>) {
-        let name = match name {
-            Some(name) => name,
-            None => {
-                self.send(t!("switch.noParams", locale = &self.lang)).await;
-                return;
+        let msg = match self.user.add_alias(a1.to_string(), command).await {
+            Ok(()) => {
+                self.save_config().await;
+                t!(
+                    "alias.success",
+                    locale = &self.lang,
+                    alias = a1,
+                    command = a2
+                )
             }
-        };
-
-        let msg = match self.switch_scene(name).await {
-            Ok(scene) => t!("switch.success", locale = &self.lang, scene = &scene),
-            Err(e) => {
-                error!("{}", e);
-                t!("switch.error", locale = &self.lang, scene = name)
+            Err(error::Error::AliasAlreadyUsed(_)) => {
+                t!("alias.errorAlreadyUsed", locale = &self.lang, alias = a1)
+            }
+            Err(error::Error::AliasIsCommand(_)) => {
+                t!("alias.errorIsCommand", locale = &self.lang, alias = a1)
             }
+            Err(_) => return,
         };
 
         self.send(msg).await;
     }
 
-    async fn start(&self) {
-        let (twitch_transcoding, record, starting) = {
+    async fn commands(&self) {
+        let default_commands = ChatHandler::default_command_settings();
+
+        let commands = {
             let state = self.user.state.read().await;
-            let options = &state.config.optional_options;
-            (
-                options.twitch_transcoding_check,
-                options.record_while_streaming,
-                options.switch_to_starting_scene_on_stream_start,
-            )
+            let Some(chat) = &state.config.chat else {
+                return;
+            };
+
+            available_commands(chat, &self.chat_message, &default_commands)
         };
 
-        let success =
-            if self.chat_message.platform == chat::ChatPlatform::Twitch && twitch_transcoding {
-                self.start_twitch_transcoding().await
+        for (i, commands) in join_within(&commands, COMMANDS_MESSAGE_LENGTH)
+            .into_iter()
+            .enumerate()
+        {
+            let msg = if i == 0 {
+                t!("commands.list", locale = &self.lang, commands = &commands)
             } else {
-                self.start_normal().await
+                commands
             };
 
-        if success && starting {
-            self.switch_optional_scene(OptionalScene::Starting).await;
-        }
-
-        if success && record {
-            self.record().await;
+            self.send(msg).await;
         }
     }
 
-    async fn start_bsc(&self) -> Result<(), error::Error> {
-        let state = self.user.state.read().await;
-
-        let bsc = state
-            .broadcasting_software
+    async fn restart_chat(&self) {
+        let msg = match self.chat_sender.restart().await {
+            Some(Ok(())) => t!("restartChat.success", locale = &self.lang),
+            Some(Err(chat::ChatError::NotLive)) => {
+                t!("restartChat.notLive", locale = &self.lang)
+            }
+            Some(Err(e)) => {
+                error!("Unable to restart chat: {}", e);
+                t!(
+                    "restartChat.error",
+                    locale = &self.lang,
+                    error = &e.to_string()
+                )
+            }
+            None => t!("restartChat.unsupported", locale = &self.lang),
+        };
+
+        self.send(msg).await;
+    }
+
+    async fn bitrate(&self) {
+        let msg = bitrate_msg(&self.user, &self.lang).await;
+
+        self.send(msg).await;
+    }
+
+    async fn bitrate_history(&self, seconds: Option<&str>) {
+        let seconds = seconds
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(BITRATE_HISTORY_SECONDS);
+        let window = std::time::Duration::from_secs(seconds);
+
+        let mut messages = Vec::new();
+
+        {
+            let state = self.user.state.read().await;
+            let history = &state.switcher_state.bitrate_history;
+
+            for server in state.config.switcher.stream_servers.iter() {
+                if !server.enabled {
+                    continue;
+                }
+
+                if let Some(summary) = history.summary(&server.name, window) {
+                    messages.push(t!(
+                        "bitrateHistory.success",
+                        locale = &self.lang,
+                        name = &server.name,
+                        min = &format::number(&language(&self.lang), summary.min.into()),
+                        avg = &format::number(&language(&self.lang), summary.avg.into()),
+                        max = &format_bitrate(summary.max.into(), &self.lang)
+                    ));
+                }
+            }
+        }
+
+        if messages.is_empty() {
+            self.send(t!(
+                "bitrateHistory.error",
+                locale = &self.lang,
+                seconds = &seconds.to_string()
+            ))
+            .await;
+            return;
+        }
+
+        self.send(messages.join(" - ")).await;
+    }
+
+    // TODO: more than one word?
+    async fn switch(&self, name: Option<&str>) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                self.send(t!("switch.noParams", locale = &self.lang)).await;
+                return;
+            }
+        };
+
+        let msg = match self.switch_scene(name).await {
+            Ok(scene) => t!("switch.success", locale = &self.lang, scene = &scene),
+            Err(e) => {
+                error!("{}", e);
+                t!("switch.error", locale = &self.lang, scene = name)
+            }
+        };
+
+        self.send(msg).await;
+    }
+
+    async fn start(&self) {
+        let (twitch_transcoding, starting, collection) = {
+            let state = self.user.state.read().await;
+            let options = &state.config.optional_options;
+            (
+                options.twitch_transcoding_check,
+                options.switch_to_starting_scene_on_stream_start,
+                options.start_collection.to_owned(),
+            )
+        };
+
+        if let Some(collection) = collection {
+            if !self.switch_collection(&collection).await {
+                return;
+            }
+        }
+
+        let success =
+            if self.chat_message.platform == chat::ChatPlatform::Twitch && twitch_transcoding {
+                self.set_transcoding_restart(true).await;
+                let success = self.start_twitch_transcoding().await;
+                self.set_transcoding_restart(false).await;
+
+                success
+            } else {
+                self.start_normal().await
+            };
+
+        if success && starting {
+            self.switch_optional_scene(OptionalScene::Starting).await;
+        }
+    }
+
+    async fn set_transcoding_restart(&self, restarting: bool) {
+        let mut state = self.user.state.write().await;
+        state.broadcasting_software.transcoding_restart = restarting;
+    }
+
+    async fn start_bsc(&self) -> Result<(), error::Error> {
+        let state = self.user.state.read().await;
+
+        let bsc = state
+            .broadcasting_software
             .connection
             .as_ref()
             .ok_or(error::Error::UnableInitialConnection)?;
@@ -761,7 +1425,7 @@ impl DispatchCommand {
 
             time::sleep(time::Duration::from_secs(delay)).await;
 
-            if let Ok(true) = check_if_transcoding(&self.chat_message.channel).await {
+            if let Ok(true) = twitch_transcoding::is_transcoding(&self.chat_message.channel).await {
                 attempts = i + 1;
                 break;
             }
@@ -812,16 +1476,6 @@ impl DispatchCommand {
     }
 
     async fn stop(&self, raid: Option<chat::RaidedInfo>) {
-        let record = {
-            self.user
-                .state
-                .read()
-                .await
-                .config
-                .optional_options
-                .record_while_streaming
-        };
-
         let stop = self.stop_bsc().await;
 
         let success_msg = if let Some(info) = raid {
@@ -829,6 +1483,8 @@ impl DispatchCommand {
                 chat::ChatPlatform::Twitch => "https://twitch.tv/",
                 chat::ChatPlatform::Kick => "https://kick.com/",
                 chat::ChatPlatform::Youtube => "https://youtube.com/",
+                chat::ChatPlatform::Discord => "https://discord.com/",
+                chat::ChatPlatform::Trovo => "https://trovo.live/s/",
             };
             let channel = format!("{url}{}", &info.target);
 
@@ -848,18 +1504,9 @@ impl DispatchCommand {
         };
 
         self.send(msg).await;
-
-        if stop.is_ok() && record {
-            self.record().await;
-        }
     }
 
     async fn trigger(&self, kind: switcher::TriggerType, value_string: Option<&str>) {
-        let symbol = match kind {
-            switcher::TriggerType::Low | switcher::TriggerType::Offline => "Kbps",
-            switcher::TriggerType::Rtt | switcher::TriggerType::RttOffline => "ms",
-        };
-
         let value = match value_string {
             Some(name) => name,
             None => {
@@ -867,7 +1514,7 @@ impl DispatchCommand {
                     Some(bitrate) => t!(
                         "trigger.current",
                         locale = &self.lang,
-                        number = &format!("{} {}", bitrate, symbol)
+                        number = &format_trigger(kind, *bitrate, &self.lang)
                     ),
                     None => t!("trigger.disabled", locale = &self.lang),
                 };
@@ -887,22 +1534,69 @@ impl DispatchCommand {
         };
 
         let msg = match &self.user.update_trigger(kind, value).await {
-            Some(value) => t!(
+            Ok(Some(value)) => t!(
                 "trigger.success",
                 locale = &self.lang,
-                number = &format!("{} {}", value, symbol)
+                number = &format_trigger(kind, *value, &self.lang)
             ),
-            None => t!(
+            Ok(None) => t!(
                 "trigger.successDisabled",
                 locale = &self.lang,
                 number = &value.to_string()
             ),
+            Err(error::Error::TriggerOutOfRange(min, max)) => {
+                let msg = t!(
+                    "trigger.outOfRange",
+                    locale = &self.lang,
+                    number = &format_trigger(kind, value, &self.lang),
+                    min = &format_trigger(kind, *min, &self.lang),
+                    max = &format_trigger(kind, *max, &self.lang)
+                );
+                self.send(msg).await;
+                return;
+            }
+            Err(e) => {
+                error!("Error updating trigger: {}", e);
+                return;
+            }
         };
 
         self.save_config().await;
         self.send(msg).await;
     }
 
+    /// Saves the triggers before experimenting and puts them back with
+    /// restore, shows the current triggers without an argument
+    async fn triggers(&self, action: Option<&str>) {
+        let msg = match action.map(str::to_lowercase).as_deref() {
+            None => t!(
+                "triggers.current",
+                locale = &self.lang,
+                triggers = &trigger_values(&self.user.get_triggers().await, &self.lang)
+            ),
+            Some("save") => t!(
+                "triggers.saved",
+                locale = &self.lang,
+                triggers = &trigger_values(&self.user.save_triggers().await, &self.lang)
+            ),
+            Some("restore") => match self.user.restore_triggers().await {
+                Some(triggers) => {
+                    self.save_config().await;
+
+                    t!(
+                        "triggers.restored",
+                        locale = &self.lang,
+                        triggers = &trigger_values(&triggers, &self.lang)
+                    )
+                }
+                None => t!("triggers.notSaved", locale = &self.lang),
+            },
+            Some(_) => t!("triggers.usage", locale = &self.lang),
+        };
+
+        self.send(msg).await;
+    }
+
     async fn notify(&self, enabled: Option<&str>) {
         if let Some(enabled) = enabled {
             if let Ok(b) = enabled_to_bool(enabled) {
@@ -920,6 +1614,42 @@ impl DispatchCommand {
         self.send(msg).await;
     }
 
+    /// Mutes the notifications for the given minutes, or shows how long
+    /// they're still muted
+    async fn mute_notify(&self, minutes: Option<&str>) {
+        if !self.user.get_notify().await {
+            self.send(t!("muteNotify.disabled", locale = &self.lang))
+                .await;
+            return;
+        }
+
+        match minutes {
+            Some("off") => self.user.mute_notifications(None).await,
+            Some(minutes) => match minutes.parse::<u64>() {
+                Ok(minutes) if minutes > 0 => {
+                    let duration = std::time::Duration::from_secs(minutes.saturating_mul(60));
+                    self.user.mute_notifications(Some(duration)).await;
+                }
+                _ => {
+                    self.send(t!("muteNotify.error", locale = &self.lang)).await;
+                    return;
+                }
+            },
+            None => {}
+        }
+
+        let msg = match self.user.notifications_muted().await {
+            Some(remaining) => t!(
+                "muteNotify.muted",
+                locale = &self.lang,
+                minutes = &remaining.as_secs().div_ceil(60).to_string()
+            ),
+            None => t!("muteNotify.unmuted", locale = &self.lang),
+        };
+
+        self.send(msg).await;
+    }
+
     async fn autostop(&self, enabled: Option<&str>) {
         if let Some(enabled) = enabled {
             if let Ok(b) = enabled_to_bool(enabled) {
@@ -953,49 +1683,65 @@ impl DispatchCommand {
         };
     }
 
+    async fn screenshot(&self) {
+        let msg = match self.user.screenshot().await {
+            Ok(link) => t!("screenshot.taken", locale = &self.lang, link = &link),
+            Err(error::Error::NoScreenshotTarget) => {
+                t!("screenshot.notConfigured", locale = &self.lang)
+            }
+            Err(e) => {
+                error!("Unable to take a screenshot: {}", e);
+                t!("screenshot.error", locale = &self.lang)
+            }
+        };
+
+        self.send(msg).await;
+    }
+
     async fn refresh(&self) {
         let state = self.user.state.read().await;
 
-        let scene = match &state.config.optional_scenes.refresh {
-            Some(s) => s.to_owned(),
-            None => {
-                self.send(t!("refresh.noScene", locale = &self.lang)).await;
-                self.fix().await;
-                return;
-            }
-        };
-        let prev_scene = state.broadcasting_software.current_scene.to_owned();
+        if state.config.optional_scenes.refresh.is_none() {
+            drop(state);
+            self.send(t!("refresh.noScene", locale = &self.lang)).await;
+            self.fix().await;
+            return;
+        }
         drop(state);
 
         self.send(t!("refresh.try", locale = &self.lang)).await;
 
-        let err = t!("refresh.error", locale = &self.lang);
-        if (self.switch_scene(&scene).await).is_err() {
-            self.send(err).await;
-            return;
+        let msg = match self.user.refresh().await {
+            Ok(_) => t!("refresh.success", locale = &self.lang),
+            Err(e) => {
+                error!("Unable to refresh: {}", e);
+                t!("refresh.error", locale = &self.lang)
+            }
         };
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        self.send(msg).await;
+    }
 
-        if (self.switch_scene(&prev_scene).await).is_err() {
-            self.send(err).await;
-            return;
+    async fn reload(&self) {
+        let msg = match self.user.reload_config().await {
+            Ok(Some(restart)) if !restart.is_empty() => t!(
+                "reload.restart",
+                locale = &self.lang,
+                settings = &restart.join(", ")
+            ),
+            Ok(Some(_)) => t!("reload.success", locale = &self.lang),
+            Ok(None) => t!("reload.unchanged", locale = &self.lang),
+            Err(e) => {
+                error!("Unable to reload the config: {}", e);
+                t!("reload.error", locale = &self.lang, error = &e.to_string())
+            }
         };
 
-        self.send(t!("refresh.success", locale = &self.lang)).await;
+        self.send(msg).await;
     }
 
     async fn switch_scene(&self, scene: &str) -> Result<String, error::Error> {
-        self.user
-            .state
-            .read()
-            .await
-            .broadcasting_software
-            .connection
-            .as_ref()
-            .ok_or(error::Error::NoSoftwareSet)?
-            .switch_scene(scene)
-            .await
+        self.user.switch_scene(scene).await
     }
 
     // Record is a toggle
@@ -1064,13 +1810,17 @@ impl DispatchCommand {
                 }
             }
             "start" => {
-                self.user.set_bitrate_switcher_state(true).await;
-                self.save_config().await;
+                if let Err(e) = self.user.set_bitrate_switcher_state(true).await {
+                    error!("Error saving config: {}", e)
+                }
+
                 t!("noalbs.switcherEnabled", locale = &self.lang)
             }
             "stop" => {
-                self.user.set_bitrate_switcher_state(false).await;
-                self.save_config().await;
+                if let Err(e) = self.user.set_bitrate_switcher_state(false).await {
+                    error!("Error saving config: {}", e)
+                }
+
                 t!("noalbs.switcherDisabled", locale = &self.lang)
             }
             "instant" => {
@@ -1129,7 +1879,17 @@ impl DispatchCommand {
             }
         };
 
-        self.user.set_retry_attempts(value).await;
+        if let Err(error::Error::RetryAttemptsOutOfRange(min, max)) =
+            self.user.set_retry_attempts(value).await
+        {
+            return t!(
+                "noalbs.retryOutOfRange",
+                locale = &self.lang,
+                min = &min.to_string(),
+                max = &max.to_string()
+            );
+        }
+
         self.save_config().await;
 
         t!(
@@ -1158,6 +1918,122 @@ impl DispatchCommand {
         }
     }
 
+    /// Toggles the privacy scene, switching is paused while it's active
+    async fn privacy(&self) {
+        if self.user.privacy_active().await {
+            let msg = match self.user.disable_privacy().await {
+                Ok(Some(scene)) => t!("privacy.disabled", locale = &self.lang, scene = &scene),
+                Ok(None) => return,
+                Err(e) => {
+                    error!("{}", e);
+                    t!("privacy.error", locale = &self.lang)
+                }
+            };
+
+            self.send(msg).await;
+            return;
+        }
+
+        let msg = match self.user.enable_privacy().await {
+            Ok(_) => t!("privacy.enabled", locale = &self.lang),
+            Err(error::Error::NoPrivacyScene) => {
+                t!(
+                    "scene.error",
+                    locale = &self.lang,
+                    scene = OptionalScene::Privacy
+                )
+            }
+            Err(e) => {
+                error!("{}", e);
+                t!("privacy.error", locale = &self.lang)
+            }
+        };
+
+        self.send(msg).await;
+    }
+
+    /// Holds a scene and ignores the triggers until maintenance mode is
+    /// turned off, without a scene it toggles
+    async fn maintenance<'a, I>(&self, args: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let scene = args.into_iter().collect::<Vec<_>>().join(" ");
+        let active = self.user.maintenance_scene().await.is_some();
+
+        if scene.eq_ignore_ascii_case("off") || (scene.is_empty() && active) {
+            let msg = match self.user.disable_maintenance().await {
+                Ok(Some(scene)) => t!("maintenance.disabled", locale = &self.lang, scene = &scene),
+                Ok(None) => t!("maintenance.notActive", locale = &self.lang),
+                Err(e) => {
+                    error!("{}", e);
+                    t!("maintenance.error", locale = &self.lang)
+                }
+            };
+
+            self.send(msg).await;
+            return;
+        }
+
+        let scene = Some(scene).filter(|s| !s.is_empty());
+
+        let msg = match self.user.enable_maintenance(scene).await {
+            Ok(scene) => t!("maintenance.enabled", locale = &self.lang, scene = &scene),
+            Err(e) => {
+                error!("{}", e);
+                t!("maintenance.error", locale = &self.lang)
+            }
+        };
+
+        self.send(msg).await;
+    }
+
+    /// Keeps the live scene over the low scene for a while, the offline
+    /// scene still ends it. Without a duration it uses the configured one
+    async fn force_live(&self, arg: Option<&str>) {
+        if arg.is_some_and(|a| a.eq_ignore_ascii_case("off")) {
+            let msg = if self.user.end_force_live().await {
+                t!("forceLive.disabled", locale = &self.lang)
+            } else {
+                t!("forceLive.notActive", locale = &self.lang)
+            };
+
+            self.send(msg).await;
+            return;
+        }
+
+        if self.user.maintenance_scene().await.is_some() {
+            self.send(t!("forceLive.maintenance", locale = &self.lang))
+                .await;
+            return;
+        }
+
+        let duration = match arg.map(str::parse::<u64>) {
+            Some(Ok(seconds)) if seconds > 0 => Some(std::time::Duration::from_secs(seconds)),
+            Some(_) => {
+                self.send(t!("forceLive.invalid", locale = &self.lang))
+                    .await;
+                return;
+            }
+            None => None,
+        };
+
+        let msg = match self.user.force_live(duration).await {
+            Ok((scene, duration)) => t!(
+                "forceLive.enabled",
+                locale = &self.lang,
+                scene = &scene,
+                duration = &format_duration(duration, &self.lang)
+            ),
+            Err(e) => {
+                error!("{}", e);
+                t!("forceLive.error", locale = &self.lang)
+            }
+        };
+
+        self.send(msg).await;
+    }
+
     // TODO: Actually switch to the right scene
     async fn live_scene(&self) {
         let state = self.user.state.read().await;
@@ -1264,18 +2140,296 @@ impl DispatchCommand {
         }
     }
 
-    async fn server_info(&self) {
+    async fn obs_info(&self) {
         let state = self.user.state.read().await;
 
-        if state.broadcasting_software.initial_stream_status.is_none() {
-            self.send(t!("serverinfo.noInfo", locale = &self.lang))
-                .await;
-            return;
+        let address = match &state.config.software {
+            config::SoftwareConnection::Vmix(v) => format!("{}:{}", v.host, v.port),
+            software => software
+                .obs_config()
+                .map(|o| format!("{}:{}", o.host, o.port))
+                .unwrap_or_default(),
         };
 
-        let bsc = match &state.broadcasting_software.connection {
-            Some(b) => b,
-            None => return,
+        let connection = match &state.broadcasting_software.connection {
+            Some(connection) if state.broadcasting_software.status == ClientStatus::Connected => {
+                connection
+            }
+            _ => {
+                let msg = t!(
+                    "obsinfo.disconnected",
+                    locale = &self.lang,
+                    address = &address
+                );
+                drop(state);
+                self.send(msg).await;
+                return;
+            }
+        };
+
+        let health = time::timeout(HEALTH_TIMEOUT, connection.health()).await;
+        let on_off = |active| {
+            if active {
+                t!("obsinfo.on", locale = &self.lang)
+            } else {
+                t!("obsinfo.off", locale = &self.lang)
+            }
+        };
+
+        let msg = match health {
+            Ok(Ok(health)) => t!(
+                "obsinfo.connected",
+                locale = &self.lang,
+                address = &address,
+                scene = &health.scene,
+                streaming = &on_off(health.streaming),
+                recording = &on_off(health.recording)
+            ),
+            Ok(Err(e)) => {
+                error!("Unable to get the OBS status: {}", e);
+                t!("obsinfo.error", locale = &self.lang, address = &address)
+            }
+            Err(_) => t!("obsinfo.timeout", locale = &self.lang, address = &address),
+        };
+
+        drop(state);
+        self.send(msg).await;
+    }
+
+    async fn servers(&self) {
+        let mut messages = Vec::new();
+
+        {
+            let state = self.user.state.read().await;
+
+            for server in state.config.switcher.stream_servers.iter() {
+                let status = if server.enabled {
+                    server
+                        .stream_server
+                        .bitrate()
+                        .await
+                        .message
+                        .unwrap_or_else(|| t!("servers.offline", locale = &self.lang))
+                } else {
+                    t!("servers.disabled", locale = &self.lang)
+                };
+
+                let priority = server
+                    .priority
+                    .map_or_else(|| "-".to_string(), |p| p.to_string());
+
+                messages.push(t!(
+                    "servers.server",
+                    locale = &self.lang,
+                    name = &server.name,
+                    priority = &priority,
+                    status = &status
+                ));
+            }
+        }
+
+        if messages.is_empty() {
+            self.send(t!("servers.none", locale = &self.lang)).await;
+            return;
+        }
+
+        self.send(messages.join(" - ")).await;
+    }
+
+    /// Summary of the stream, scene, switcher and every stream server
+    async fn why(&self) {
+        let decision = self
+            .user
+            .state
+            .read()
+            .await
+            .switcher_state
+            .last_decision
+            .clone();
+
+        let msg = match decision {
+            Some(decision) => why_msg(&decision, &self.lang),
+            None => t!("why.none", locale = &self.lang),
+        };
+
+        self.send(msg).await;
+    }
+
+    async fn status(&self) {
+        let state = self.user.state.read().await;
+        let bs = &state.broadcasting_software;
+
+        let on_off = |active| {
+            if active {
+                t!("obsinfo.on", locale = &self.lang)
+            } else {
+                t!("obsinfo.off", locale = &self.lang)
+            }
+        };
+
+        let stream = if bs.is_streaming {
+            t!(
+                "status.live",
+                locale = &self.lang,
+                uptime = &format_duration(bs.last_stream_started_at.elapsed(), &self.lang)
+            )
+        } else {
+            t!("status.notLive", locale = &self.lang)
+        };
+
+        let duration = bs.scene_since.map_or_else(
+            || "-".to_string(),
+            |since| format_duration(since.elapsed(), &self.lang),
+        );
+
+        let mut servers = Vec::new();
+
+        for server in &state.config.switcher.stream_servers {
+            let status = if server.enabled {
+                server
+                    .stream_server
+                    .bitrate()
+                    .await
+                    .message
+                    .unwrap_or_else(|| t!("servers.offline", locale = &self.lang))
+            } else {
+                t!("servers.disabled", locale = &self.lang)
+            };
+
+            servers.push(t!(
+                "status.server",
+                locale = &self.lang,
+                name = &server.name,
+                status = &status
+            ));
+        }
+
+        if servers.is_empty() {
+            servers.push(t!("servers.none", locale = &self.lang));
+        }
+
+        let msg = t!(
+            "status.summary",
+            locale = &self.lang,
+            stream = &stream,
+            scene = &bs.current_scene,
+            duration = &duration,
+            switcher = &on_off(state.config.switcher.bitrate_switcher_enabled),
+            servers = &servers.join(", ")
+        );
+
+        drop(state);
+        self.send(msg).await;
+    }
+
+    async fn server<'a, I>(&self, args: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        // Names can contain spaces, the last argument is on or off
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        let enabled = match args.pop().map(enabled_to_bool) {
+            Some(Ok(enabled)) if !args.is_empty() => enabled,
+            _ => {
+                self.send(t!("servers.errorArguments", locale = &self.lang))
+                    .await;
+                return;
+            }
+        };
+
+        let name = args.join(" ");
+
+        let msg = match self.user.set_stream_server_enabled(&name, enabled).await {
+            Ok(name) => {
+                self.save_config().await;
+
+                t!(
+                    "servers.toggled",
+                    locale = &self.lang,
+                    name = &name,
+                    condition = &condition_to_text(enabled, &self.lang)
+                )
+            }
+            Err(_) => t!("servers.notFound", locale = &self.lang, name = &name),
+        };
+
+        self.send(msg).await;
+    }
+
+    /// Renames a stream server, both names can contain spaces. The longest
+    /// existing name at the start is the server, the rest the new name
+    async fn rename<'a, I>(&self, args: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let args = args.into_iter().collect::<Vec<_>>();
+
+        let names = {
+            let state = self.user.state.read().await;
+            state
+                .config
+                .switcher
+                .stream_servers
+                .iter()
+                .map(|s| s.name.to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let split = (1..args.len()).rev().find(|&i| {
+            names
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(&args[..i].join(" ")))
+        });
+
+        let Some(split) = split else {
+            let msg = match args.first() {
+                Some(_) if args.len() > 1 => t!(
+                    "servers.notFound",
+                    locale = &self.lang,
+                    name = &args[..args.len() - 1].join(" ")
+                ),
+                _ => t!("rename.errorArguments", locale = &self.lang),
+            };
+
+            self.send(msg).await;
+            return;
+        };
+
+        let name = args[..split].join(" ");
+        let new_name = args[split..].join(" ");
+
+        let msg = match self.user.rename_stream_server(&name, &new_name).await {
+            Ok(old_name) => {
+                self.save_config().await;
+
+                t!(
+                    "rename.success",
+                    locale = &self.lang,
+                    name = &old_name,
+                    new_name = &new_name
+                )
+            }
+            Err(error::Error::StreamServerNameTaken(_)) => {
+                t!("rename.taken", locale = &self.lang, name = &new_name)
+            }
+            Err(_) => t!("servers.notFound", locale = &self.lang, name = &name),
+        };
+
+        self.send(msg).await;
+    }
+
+    async fn server_info(&self) {
+        let state = self.user.state.read().await;
+
+        if state.broadcasting_software.initial_stream_status.is_none() {
+            self.send(t!("serverinfo.noInfo", locale = &self.lang))
+                .await;
+            return;
+        };
+
+        let bsc = match &state.broadcasting_software.connection {
+            Some(b) => b,
+            None => return,
         };
 
         let ss = match bsc.info(&state).await {
@@ -1311,7 +2465,7 @@ impl DispatchCommand {
             "serverinfo.success",
             locale = &self.lang,
             fps = &ss.fps.round().to_string(),
-            bitrate = &ss.bitrate.to_string(),
+            bitrate = &format_bitrate(ss.bitrate, &self.lang),
             network = &network,
             rendering = &rendering,
             encoding = &encoding,
@@ -1381,64 +2535,213 @@ impl DispatchCommand {
             }
         };
 
-        let state = self.user.state.read().await;
-        let collections = match &state.config.software {
-            config::SoftwareConnection::ObsOld(o) => &o.collections,
-            config::SoftwareConnection::Obs(o) => &o.collections,
+        if !self.switch_collection(&name).await {
+            return;
+        }
+
+        self.send(t!(
+            "collection.success",
+            locale = &self.lang,
+            collection = name
+        ))
+        .await;
+
+        if self
+            .user
+            .state
+            .read()
+            .await
+            .broadcasting_software
+            .is_streaming
+        {
+            self.send(t!("collection.note", locale = &self.lang,)).await;
+        }
+    }
+
+    /// Switches to a collection of the config, refuses while streaming
+    /// unless forced since OBS only changes the profile when not streaming
+    async fn preset<'a, I>(&self, args: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter();
+        let name = args.next().map(str::to_lowercase);
+        let force = args.next().is_some_and(|a| a.eq_ignore_ascii_case("force"));
+
+        let (mut presets, streaming) = {
+            let state = self.user.state.read().await;
+            let presets = state
+                .config
+                .software
+                .obs_config()
+                .and_then(|o| o.collections.as_ref())
+                .map(|c| c.keys().cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+
+            (presets, state.broadcasting_software.is_streaming)
         };
 
-        let collections = match collections {
-            Some(c) => c,
-            None => {
-                self.send(t!(
-                    "collection.error",
+        if presets.is_empty() {
+            self.send(t!("preset.none", locale = &self.lang)).await;
+            return;
+        }
+
+        presets.sort();
+        let list = presets.join(", ");
+
+        let name = match name {
+            Some(name) if presets.contains(&name) => name,
+            Some(name) => {
+                let msg = t!(
+                    "preset.unknown",
                     locale = &self.lang,
-                    collection = name
-                ))
-                .await;
+                    preset = &name,
+                    presets = &list
+                );
+                self.send(msg).await;
+                return;
+            }
+            None => {
+                self.send(t!("preset.list", locale = &self.lang, presets = &list))
+                    .await;
                 return;
             }
         };
 
-        let collection = match collections.get(&name) {
-            Some(c) => c,
-            None => {
-                self.send(t!(
-                    "collection.notFound",
-                    locale = &self.lang,
-                    collection = name
-                ))
+        if streaming && !force {
+            self.send(t!("preset.streaming", locale = &self.lang, preset = &name))
                 .await;
-                return;
+            return;
+        }
+
+        if !self.switch_collection(&name).await {
+            return;
+        }
+
+        self.send(t!("preset.success", locale = &self.lang, preset = &name))
+            .await;
+    }
+
+    async fn transition<'a, I>(&self, args: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        // Names can contain spaces, the optional last argument is the duration
+        let mut args = args.into_iter().collect::<Vec<_>>();
+        let duration_ms = match args.last().map(|d| d.parse::<u32>()) {
+            Some(Ok(ms)) if args.len() > 1 => {
+                args.pop();
+                Some(ms)
             }
+            _ => None,
         };
+        let name = args.join(" ");
 
-        let bsc = match &state.broadcasting_software.connection {
-            Some(b) => b,
-            None => return,
+        let state = self.user.state.read().await;
+        let Some(bsc) = &state.broadcasting_software.connection else {
+            self.send(t!("transition.error", locale = &self.lang)).await;
+            return;
+        };
+
+        if name.is_empty() {
+            let msg = match bsc.current_transition().await {
+                Ok(current) => match current.duration_ms {
+                    Some(ms) => t!(
+                        "transition.currentDuration",
+                        locale = &self.lang,
+                        name = &current.name,
+                        duration = &ms.to_string()
+                    ),
+                    None => t!(
+                        "transition.current",
+                        locale = &self.lang,
+                        name = &current.name
+                    ),
+                },
+                Err(e) => self.transition_error(e),
+            };
+
+            self.send(msg).await;
+            return;
+        }
+
+        let transitions = match bsc.transition_list().await {
+            Ok(transitions) => transitions,
+            Err(e) => {
+                self.send(self.transition_error(e)).await;
+                return;
+            }
         };
 
-        if (bsc.set_collection_and_profile(collection).await).is_err() {
+        let Some(found) = transitions.iter().find(|t| t.eq_ignore_ascii_case(&name)) else {
             self.send(t!(
-                "collection.error",
+                "transition.notFound",
                 locale = &self.lang,
-                collection = name
+                name = &name,
+                transitions = &transitions.join(", ")
             ))
             .await;
             return;
         };
 
-        self.send(t!(
-            "collection.success",
-            locale = &self.lang,
-            collection = name
-        ))
-        .await;
+        let transition = config::SceneTransition {
+            name: found.to_owned(),
+            duration_ms,
+        };
 
-        if state.broadcasting_software.is_streaming {
-            self.send(t!("collection.note", locale = &self.lang,)).await;
+        let msg = match bsc.set_transition(&transition).await {
+            Ok(()) => match duration_ms {
+                Some(ms) => t!(
+                    "transition.successDuration",
+                    locale = &self.lang,
+                    name = &transition.name,
+                    duration = &ms.to_string()
+                ),
+                None => t!(
+                    "transition.success",
+                    locale = &self.lang,
+                    name = &transition.name
+                ),
+            },
+            Err(e) => self.transition_error(e),
+        };
+
+        self.send(msg).await;
+    }
+
+    fn transition_error(&self, error: error::Error) -> String {
+        match error {
+            error::Error::NotSupported => t!("transition.notSupported", locale = &self.lang),
+            e => {
+                error!("Unable to use the transitions: {}", e);
+                t!("transition.error", locale = &self.lang)
+            }
         }
     }
+
+    /// Sends the error to chat when switching fails
+    async fn switch_collection(&self, name: &str) -> bool {
+        let msg = match self.user.switch_collection(name).await {
+            Ok(()) => return true,
+            Err(error::Error::CollectionNotFound(_)) => t!(
+                "collection.notFound",
+                locale = &self.lang,
+                collection = name
+            ),
+            Err(error::Error::CollectionNotLoaded(_)) => t!(
+                "collection.notLoaded",
+                locale = &self.lang,
+                collection = name
+            ),
+            Err(e) => {
+                error!("Unable to switch to collection {}: {}", name, e);
+                t!("collection.error", locale = &self.lang, collection = name)
+            }
+        };
+
+        self.send(msg).await;
+        false
+    }
 }
 
 fn condition_to_text(condition: bool, lang: &str) -> String {
@@ -1449,6 +2752,123 @@ fn condition_to_text(condition: bool, lang: &str) -> String {
     }
 }
 
+/// Stream needs to be live this long before a raid stops it, so a raid right
+/// after going live doesn't end the new stream
+const RAID_STOP_MIN_UPTIME: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// The command to run when the channel raided someone, `None` when the
+/// stream shouldn't be stopped
+fn raid_auto_stop(
+    chat: &config::Chat,
+    is_streaming: bool,
+    uptime: std::time::Duration,
+    target_info: chat::RaidedInfo,
+) -> Option<chat::Command> {
+    if !chat.enable_auto_stop_stream_on_host_or_raid {
+        return None;
+    }
+
+    // The stream already ended or just started
+    if !is_streaming || uptime < RAID_STOP_MIN_UPTIME {
+        return None;
+    }
+
+    if chat.announce_raid_on_auto_stop {
+        Some(chat::Command::StopOnRaid(target_info))
+    } else {
+        Some(chat::Command::Stop)
+    }
+}
+
+/// The language of a locale name, English when it's unknown
+fn language(lang: &str) -> chat::ChatLanguage {
+    lang.parse().unwrap_or(chat::ChatLanguage::EN)
+}
+
+/// Short duration in the chat language like 1h 5m or 42s
+fn format_duration(duration: std::time::Duration, lang: &str) -> String {
+    format::duration(&language(lang), duration)
+}
+
+/// Bitrate in the chat language like 2,500 Kbps or 2 500 kb/s
+fn format_bitrate(kbps: u64, lang: &str) -> String {
+    format::bitrate(&language(lang), kbps)
+}
+
+/// Trigger value with its unit like 800 Kbps or 5 %
+fn format_trigger(kind: switcher::TriggerType, value: u32, lang: &str) -> String {
+    use switcher::TriggerType;
+
+    match kind {
+        TriggerType::Low | TriggerType::Offline => format_bitrate(value.into(), lang),
+        _ => format!(
+            "{} {}",
+            format::number(&language(lang), value.into()),
+            kind.unit()
+        ),
+    }
+}
+
+/// Describes the last switch decision, e.g. "Switched to low 12s ago:
+/// bitrate under the low trigger | server: belabox, 400 Kbps, RTT 80 ms"
+fn why_msg(decision: &state::SwitchDecision, lang: &str) -> String {
+    use switcher::{SwitchType, TriggerType};
+
+    let reason = match (decision.trigger, decision.switch_type) {
+        (Some(TriggerType::Low), _) => t!("why.reason.low", locale = lang),
+        (Some(TriggerType::Rtt), _) => t!("why.reason.rtt", locale = lang),
+        (Some(TriggerType::Offline), _) => t!("why.reason.offline", locale = lang),
+        (Some(TriggerType::RttOffline), _) => t!("why.reason.rttOffline", locale = lang),
+        (Some(TriggerType::DroppedFrames), _) => t!("why.reason.droppedFrames", locale = lang),
+        (Some(TriggerType::PacketLoss), _) => t!("why.reason.packetLoss", locale = lang),
+        (None, SwitchType::Normal) => t!("why.reason.normal", locale = lang),
+        (None, SwitchType::Low) => t!("why.reason.low", locale = lang),
+        (None, SwitchType::Previous) => t!("why.reason.previous", locale = lang),
+        (None, SwitchType::Offline) => t!("why.reason.noServer", locale = lang),
+    };
+
+    let mut msg = t!(
+        "why.last",
+        locale = lang,
+        scene = &decision.scene,
+        ago = &format_duration(decision.at.elapsed(), lang),
+        reason = &reason
+    );
+
+    if let Some(server) = &decision.stream_server {
+        let mut details = vec![t!("why.server", locale = lang, server = server)];
+
+        if let Some(bitrate) = decision.bitrate {
+            details.push(t!(
+                "why.bitrate",
+                locale = lang,
+                bitrate = &format_bitrate(bitrate.into(), lang)
+            ));
+        }
+
+        if let Some(rtt) = decision.rtt {
+            details.push(t!("why.rtt", locale = lang, rtt = &format!("{:.0}", rtt)));
+        }
+
+        let _ = write!(msg, " | {}", details.join(", "));
+    }
+
+    msg
+}
+
+/// The relayed message with its origin, e.g. "[YT] someone: hello"
+fn relay_text(msg: &chat::ChatMessage) -> String {
+    let origin = match msg.platform {
+        chat::ChatPlatform::Twitch => "TW",
+        chat::ChatPlatform::Kick => "KICK",
+        chat::ChatPlatform::Youtube => "YT",
+        chat::ChatPlatform::Discord => "DC",
+        chat::ChatPlatform::Trovo => "TROVO",
+    };
+
+    format!("[{}] {}: {}", origin, msg.sender, msg.message)
+}
+
 fn enabled_to_bool(enabled: &str) -> Result<bool, error::Error> {
     if enabled.to_lowercase() == "on" {
         return Ok(true);
@@ -1461,6 +2881,98 @@ fn enabled_to_bool(enabled: &str) -> Result<bool, error::Error> {
     Err(error::Error::EnabledToBoolConversionError)
 }
 
+/// The triggers that are set with their unit, like `low 800 Kbps, rtt 2500 ms`
+fn trigger_values(triggers: &switcher::Triggers, lang: &str) -> String {
+    let mut values: Vec<_> = switcher::TriggerType::ALL
+        .iter()
+        .filter_map(|kind| {
+            triggers
+                .get(*kind)
+                .map(|value| format!("{} {}", kind.as_str(), format_trigger(*kind, value, lang)))
+        })
+        .collect();
+
+    if let Some(expression) = &triggers.expression {
+        values.push(format!("expression \"{}\"", expression));
+    }
+
+    if values.is_empty() {
+        return t!("triggers.allDisabled", locale = lang);
+    }
+
+    values.join(", ")
+}
+
+/// Sends the notification as an announcement when configured
+async fn send_notification(
+    sender: &dyn chat::ChatLogic,
+    user: &Noalbs,
+    notification: template::Notification,
+    channel: String,
+    msg: String,
+) {
+    let color = {
+        let state = user.state.read().await;
+
+        state
+            .config
+            .chat
+            .as_ref()
+            .and_then(|chat| chat.announcements.color_for(notification))
+    };
+
+    match color {
+        Some(color) => sender.send_announcement(channel, msg, color).await,
+        None => sender.send_message(channel, msg).await,
+    }
+}
+
+/// Formats the notification with the template from the config when it has one
+async fn custom_notification(
+    user: &Noalbs,
+    notification: template::Notification,
+    scene: &str,
+) -> Option<String> {
+    let state = user.state.read().await;
+    let chat = state.config.chat.as_ref()?;
+    let template = chat.notification_templates.get(&notification)?;
+
+    let server_name = state.switcher_state.last_used_server.as_deref();
+    let server = server_name.and_then(|name| {
+        state
+            .config
+            .switcher
+            .stream_servers
+            .iter()
+            .find(|s| s.name == name)
+    });
+
+    let bitrate = match server {
+        Some(server) => server.stream_server.bitrate().await.kbps,
+        None => None,
+    }
+    .map(|kbps| format::number(&chat.language, kbps.into()))
+    .unwrap_or_default();
+
+    Some(template::render(
+        template,
+        &[
+            ("scene", scene),
+            ("bitrate", &bitrate),
+            ("server", server_name.unwrap_or_default()),
+        ],
+    ))
+}
+
+/// Default window of the bitrate history command
+const BITRATE_HISTORY_SECONDS: u64 = 60;
+
+/// Leaves room for the header within the chat message limits
+const COMMANDS_MESSAGE_LENGTH: usize = 400;
+
+/// How long to wait for OBS to answer the obsinfo command
+const HEALTH_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
 async fn bitrate_msg(user: &Noalbs, lang: &str) -> String {
     let mut msg = String::new();
 
@@ -1494,76 +3006,305 @@ pub struct Timeout {
     pub time: std::time::Instant,
 }
 
-const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
-const USHER_BASE: &str = "https://usher.ttvnw.net";
-const GQL_BASE: &str = "https://gql.twitch.tv/gql";
-
-// TODO: Check if not an ad?
-async fn check_if_transcoding(channel: &str) -> Result<bool, error::Error> {
-    let req_string = r#"{"query": "{streamPlaybackAccessToken(channelName: \"%USER%\",params: {platform: \"web\",playerBackend: \"mediaplayer\",playerType: \"site\"}){value signature}}"}"#;
-    let req_string = req_string.replace("%USER%", channel);
-
-    let client = reqwest::Client::new();
-    let res = client
-        .post(GQL_BASE)
-        .header("Client-ID", CLIENT_ID)
-        .body(req_string)
-        .send()
-        .await?;
-
-    let json = res.json::<serde_json::Value>().await?;
-    let json = json["data"]["streamPlaybackAccessToken"].to_owned();
-    let json: StreamPlaybackAccessToken = serde_json::from_value(json)?;
-
-    use rand::Rng;
-    let rng = rand::thread_rng().gen_range(1000000..10000000);
-    let query = M3u8Query {
-        allow_source: String::from("true"),
-        allow_audio_only: String::from("true"),
-        allow_spectre: String::from("true"),
-        p: rng,
-        player: String::from("twitchweb"),
-        playlist_include_framerate: String::from("true"),
-        segment_preference: String::from("4"),
-        sig: json.signature,
-        token: json.value,
-    };
+#[derive(Debug, Hash, PartialEq, Eq)]
+struct CooldownKey {
+    platform: chat::ChatPlatform,
+    channel: String,
+    command: chat::Command,
+
+    /// Only set when the cooldown is per user
+    user: Option<String>,
+}
 
-    let res = client
-        .get(format!("{}/api/channel/hls/{}.m3u8", USHER_BASE, channel))
-        .header("Client-ID", CLIENT_ID)
-        .query(&query)
-        .send()
-        .await?;
+/// When a command was last used
+#[derive(Debug, Default)]
+struct Cooldowns {
+    last_used: HashMap<CooldownKey, std::time::Instant>,
+}
 
-    let text = res.text().await?;
-    // println!("Response:\n{}", text);
+impl Cooldowns {
+    /// Returns true when the command is still on cooldown, otherwise the
+    /// cooldown starts again from now
+    fn check(
+        &mut self,
+        key: CooldownKey,
+        cooldown: std::time::Duration,
+        now: std::time::Instant,
+    ) -> bool {
+        if let Some(last_used) = self.last_used.get(&key) {
+            if now.duration_since(*last_used) < cooldown {
+                return true;
+            }
+        }
 
-    if text.contains("TRANSCODESTACK=\"transmux\"")
-        || text.contains("Can not find channel")
-        || text.contains("transcode_does_not_exist")
-    {
-        return Ok(false);
+        self.last_used.insert(key, now);
+        false
     }
-
-    Ok(true)
 }
 
-#[derive(Debug, Serialize)]
-struct M3u8Query {
-    allow_source: String,
-    allow_audio_only: String,
-    allow_spectre: String,
-    p: u32,
-    player: String,
-    playlist_include_framerate: String,
-    segment_preference: String,
-    sig: String,
-    token: String,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn raid_target() -> chat::RaidedInfo {
+        chat::RaidedInfo {
+            target: "someone".to_string(),
+            display: "Someone".to_string(),
+            platform: chat::ChatPlatform::Twitch,
+        }
+    }
+
+    #[test]
+    fn raid_stops_and_announces() {
+        let chat = config::Chat::default();
+        let live = Duration::from_secs(600);
+
+        assert_eq!(
+            raid_auto_stop(&chat, true, live, raid_target()),
+            Some(chat::Command::StopOnRaid(raid_target()))
+        );
+
+        let chat = config::Chat {
+            announce_raid_on_auto_stop: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            raid_auto_stop(&chat, true, live, raid_target()),
+            Some(chat::Command::Stop)
+        );
+
+        let chat = config::Chat {
+            enable_auto_stop_stream_on_host_or_raid: false,
+            ..Default::default()
+        };
+        assert_eq!(raid_auto_stop(&chat, true, live, raid_target()), None);
+    }
+
+    #[test]
+    fn raid_doesnt_stop_ended_or_new_stream() {
+        let chat = config::Chat::default();
+
+        assert_eq!(
+            raid_auto_stop(&chat, false, Duration::from_secs(600), raid_target()),
+            None
+        );
+        assert_eq!(
+            raid_auto_stop(&chat, true, Duration::from_secs(10), raid_target()),
+            None
+        );
+    }
+
+    #[test]
+    fn replies_follow_the_chat_language() {
+        let kind = switcher::TriggerType::Low;
+        assert_eq!(format_trigger(kind, 2500, "en"), "2,500 Kbps");
+        assert_eq!(format_trigger(kind, 2500, "fr"), "2 500 kb/s");
+
+        let kind = switcher::TriggerType::Rtt;
+        assert_eq!(format_trigger(kind, 2500, "de"), "2.500 ms");
+
+        let uptime = Duration::from_secs(3600 + 5 * 60);
+        assert_eq!(format_duration(uptime, "en"), "1h 5m");
+        assert_eq!(format_duration(uptime, "pt_br"), "1 h 5 min");
+    }
+
+    fn key(user: Option<&str>) -> CooldownKey {
+        CooldownKey {
+            platform: chat::ChatPlatform::Twitch,
+            channel: "715209".to_string(),
+            command: chat::Command::Bitrate,
+            user: user.map(String::from),
+        }
+    }
+
+    #[test]
+    fn command_only_executes_once_within_cooldown() {
+        let mut cooldowns = Cooldowns::default();
+        let cooldown = Duration::from_secs(10);
+        let now = Instant::now();
+
+        let executed = [now, now + Duration::from_secs(3)]
+            .into_iter()
+            .filter(|at| !cooldowns.check(key(None), cooldown, *at))
+            .count();
+
+        assert_eq!(executed, 1);
+        assert!(!cooldowns.check(key(None), cooldown, now + Duration::from_secs(10)));
+    }
 
-#[derive(Debug, Deserialize)]
-struct StreamPlaybackAccessToken {
-    value: String,
-    signature: String,
+    #[test]
+    fn per_user_cooldowns_are_separate() {
+        let mut cooldowns = Cooldowns::default();
+        let cooldown = Duration::from_secs(10);
+        let now = Instant::now();
+
+        assert!(!cooldowns.check(key(Some("a")), cooldown, now));
+        assert!(!cooldowns.check(key(Some("b")), cooldown, now));
+        assert!(cooldowns.check(key(Some("a")), cooldown, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn permission_ordering() {
+        assert!(Permission::Public < Permission::Vip);
+        assert!(Permission::Vip < Permission::Mod);
+        assert!(Permission::Mod < Permission::Admin);
+    }
+
+    #[test]
+    fn vip_allowed_vip_commands_only() {
+        assert!(permission_is_allowed(&Permission::Vip, &Permission::Vip));
+        assert!(permission_is_allowed(&Permission::Public, &Permission::Vip));
+        assert!(!permission_is_allowed(&Permission::Mod, &Permission::Vip));
+
+        assert!(permission_is_allowed(&Permission::Vip, &Permission::Mod));
+        assert!(!permission_is_allowed(
+            &Permission::Vip,
+            &Permission::Public
+        ));
+    }
+
+    #[test]
+    fn permissions_deserialize_by_name() {
+        let permissions: Vec<Permission> =
+            serde_json::from_str(r#"["Public", "Vip", "Mod", "Admin"]"#).unwrap();
+
+        assert_eq!(
+            permissions,
+            vec![
+                Permission::Public,
+                Permission::Vip,
+                Permission::Mod,
+                Permission::Admin
+            ]
+        );
+    }
+
+    fn message(permission: Permission) -> chat::ChatMessage {
+        chat::ChatMessage {
+            platform: chat::ChatPlatform::Twitch,
+            permission,
+            channel: "715209".to_string(),
+            sender: "someone".to_string(),
+            message: "!commands".to_string(),
+        }
+    }
+
+    #[test]
+    fn commands_listed_by_permission() {
+        let defaults = ChatHandler::default_command_settings();
+        let mut commands = HashMap::new();
+        commands.insert(
+            chat::Command::Bitrate,
+            config::CommandInfo {
+                alias: Some(vec!["b".to_string()]),
+                ..Default::default()
+            },
+        );
+
+        let mut chat = config::Chat {
+            prefix: "?".to_string(),
+            commands: Some(commands),
+            ..Default::default()
+        };
+
+        let public = available_commands(&chat, &message(Permission::Public), &defaults);
+        assert!(public.contains(&"?bitrate (?b)".to_string()));
+        assert!(public.contains(&"?commands".to_string()));
+        assert!(!public.contains(&"?switch".to_string()));
+
+        let moderator = available_commands(&chat, &message(Permission::Mod), &defaults);
+        assert!(moderator.contains(&"?switch".to_string()));
+        assert!(!moderator.contains(&"?noalbs".to_string()));
+
+        chat.enable_mod_commands = false;
+        let moderator = available_commands(&chat, &message(Permission::Mod), &defaults);
+        assert!(moderator.contains(&"?bitrate (?b)".to_string()));
+        assert!(!moderator.contains(&"?switch".to_string()));
+
+        chat.enable_public_commands = false;
+        let public = available_commands(&chat, &message(Permission::Public), &defaults);
+        assert!(public.is_empty());
+    }
+
+    #[test]
+    fn command_names_are_known() {
+        for name in chat::Command::NAMES {
+            assert!(!matches!(
+                chat::Command::from(*name),
+                chat::Command::Unknown(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn commands_split_over_messages() {
+        let items = ["!aa", "!bb", "!cc"].map(String::from);
+
+        assert_eq!(join_within(&items, 100), vec!["!aa, !bb, !cc"]);
+        assert_eq!(join_within(&items, 8), vec!["!aa, !bb", "!cc"]);
+        assert!(join_within(&[], 8).is_empty());
+    }
+
+    #[test]
+    fn relayed_message_shows_origin() {
+        let msg = chat::ChatMessage {
+            platform: chat::ChatPlatform::Youtube,
+            permission: Permission::Public,
+            channel: "channel".to_string(),
+            sender: "someone".to_string(),
+            message: "hello there".to_string(),
+        };
+
+        assert_eq!(relay_text(&msg), "[YT] someone: hello there");
+    }
+
+    #[test]
+    fn why_describes_the_last_switch() {
+        let mut decision = state::SwitchDecision {
+            at: Instant::now(),
+            switch_type: switcher::SwitchType::Low,
+            trigger: Some(switcher::TriggerType::Rtt),
+            scene: "low".to_string(),
+            stream_server: Some("belabox".to_string()),
+            bitrate: Some(400),
+            rtt: Some(812.4),
+        };
+
+        assert_eq!(
+            why_msg(&decision, "en"),
+            "Switched to low 0s ago: RTT over the RTT trigger | server: belabox, 400 Kbps, RTT 812 ms"
+        );
+
+        decision.switch_type = switcher::SwitchType::Offline;
+        decision.trigger = None;
+        decision.stream_server = None;
+
+        assert_eq!(
+            why_msg(&decision, "en"),
+            "Switched to low 0s ago: no stream server online"
+        );
+    }
+
+    #[test]
+    fn trigger_values_with_units() {
+        let triggers = switcher::Triggers {
+            low: Some(800),
+            rtt: None,
+            packet_loss: Some(5),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            trigger_values(&triggers, "en"),
+            "low 800 Kbps, packet_loss 5 %"
+        );
+
+        let triggers = switcher::Triggers {
+            low: None,
+            rtt: None,
+            ..Default::default()
+        };
+        assert_eq!(trigger_values(&triggers, "en"), "all disabled");
+    }
 }
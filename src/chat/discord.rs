@@ -0,0 +1,548 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use futures_util::{SinkExt as _, StreamExt as _};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time};
+use tokio_tungstenite::tungstenite::Message as TMessage;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    chat::{self, ChatPlatform, HandleMessage},
+    config, ChatSender,
+};
+
+const DISCORD_GATEWAY: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+const DISCORD_API: &str = "https://discord.com/api/v10";
+
+/// GUILDS, GUILD_MESSAGES and MESSAGE_CONTENT
+const INTENTS: u64 = (1 << 0) | (1 << 9) | (1 << 15);
+
+/// Permission bit of the ADMINISTRATOR permission
+const ADMINISTRATOR: u64 = 1 << 3;
+
+pub struct Discord {
+    client: reqwest::Client,
+    channels: Arc<Mutex<Vec<Channel>>>,
+    gateway_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Discord {
+    fn drop(&mut self) {
+        self.gateway_handle.abort();
+    }
+}
+
+impl Discord {
+    pub fn new(token: String, chat_handler_tx: ChatSender) -> Result<Self, anyhow::Error> {
+        let mut auth = reqwest::header::HeaderValue::from_str(&format!("Bot {}", token))?;
+        auth.set_sensitive(true);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, auth);
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+        let channels = Arc::new(Mutex::new(Vec::new()));
+
+        let mut gateway = Gateway {
+            token,
+            channels: channels.clone(),
+            chat_handler_tx,
+            guilds: HashMap::new(),
+            bot_id: None,
+            sequence: None,
+        };
+
+        let gateway_handle = tokio::spawn(async move {
+            gateway.run().await;
+        });
+
+        Ok(Self {
+            client,
+            channels,
+            gateway_handle,
+        })
+    }
+
+    pub async fn join_channel(&self, platform: config::ConfigChatPlatform, username: String) {
+        let config::ConfigChatPlatform::Discord(config) = platform else {
+            error!("Join called with a non Discord platform for {}", username);
+            return;
+        };
+
+        info!(
+            "Listening to Discord channel {} for {}",
+            config.channel_id, username
+        );
+
+        let channel = Channel {
+            username,
+            channel_id: config.channel_id,
+            mod_role_id: config.mod_role_id,
+        };
+
+        let mut channels = self.channels.lock().await;
+        if !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+}
+
+#[async_trait]
+impl super::ChatLogic for Discord {
+    async fn send_message(&self, channel: String, message: String) {
+        let channel_id = {
+            let channels = self.channels.lock().await;
+            channels
+                .iter()
+                .find(|c| c.username == channel)
+                .map(|c| c.channel_id.to_owned())
+        };
+
+        let Some(channel_id) = channel_id else {
+            error!("No Discord channel found for {}", channel);
+            return;
+        };
+
+        let res = self
+            .client
+            .post(format!("{}/channels/{}/messages", DISCORD_API, channel_id))
+            .json(&serde_json::json!({ "content": message }))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                warn!("Rate limited by Discord, message not sent");
+            }
+            Ok(res) if !res.status().is_success() => {
+                error!("Error sending message to Discord: {}", res.status());
+            }
+            Ok(_) => {}
+            Err(e) => error!("Error sending message to Discord: {}", e),
+        }
+    }
+
+    async fn join(&self, platform: config::ConfigChatPlatform, channel: String) -> bool {
+        self.join_channel(platform, channel).await;
+        true
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Channel {
+    /// Username of the NOALBS chat config
+    pub username: String,
+
+    /// Discord channel to read commands from and reply in
+    pub channel_id: String,
+
+    /// Members with this role get mod permissions
+    pub mod_role_id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Guild {
+    owner_id: String,
+
+    /// Roles that have the administrator permission
+    admin_roles: HashSet<String>,
+}
+
+struct Gateway {
+    token: String,
+    channels: Arc<Mutex<Vec<Channel>>>,
+    chat_handler_tx: ChatSender,
+    guilds: HashMap<String, Guild>,
+    bot_id: Option<String>,
+    sequence: Option<u64>,
+}
+
+impl Gateway {
+    async fn run(&mut self) {
+        let mut retry_grow = 1;
+
+        loop {
+            info!("Connecting to Discord");
+
+            match self.session().await {
+                Ok(()) => {
+                    info!("Disconnected from Discord");
+                    retry_grow = 1;
+                }
+                Err(e) => error!("Discord gateway error: {}", e),
+            }
+
+            let wait = 1 << retry_grow;
+            info!("Reconnecting to Discord in {} seconds", wait);
+            time::sleep(Duration::from_secs(wait)).await;
+
+            if retry_grow < 5 {
+                retry_grow += 1;
+            }
+        }
+    }
+
+    async fn session(&mut self) -> Result<(), anyhow::Error> {
+        let (mut ws, _) = tokio_tungstenite::connect_async(DISCORD_GATEWAY).await?;
+
+        let hello = loop {
+            match ws.next().await {
+                Some(Ok(TMessage::Text(text))) => break serde_json::from_str::<Payload>(&text)?,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => bail!("Connection closed before hello"),
+            }
+        };
+
+        let heartbeat_interval = hello.d["heartbeat_interval"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("No heartbeat interval received"))?;
+
+        let identify = serde_json::json!({
+            "op": 2,
+            "d": {
+                "token": self.token,
+                "intents": INTENTS,
+                "properties": {
+                    "os": std::env::consts::OS,
+                    "browser": "noalbs",
+                    "device": "noalbs",
+                }
+            }
+        });
+        ws.send(TMessage::Text(identify.to_string())).await?;
+
+        let mut heartbeat = time::interval(Duration::from_millis(heartbeat_interval));
+        let mut acknowledged = true;
+
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if !acknowledged {
+                        bail!("Heartbeat not acknowledged");
+                    }
+
+                    acknowledged = false;
+                    ws.send(self.heartbeat()).await?;
+                }
+                msg = ws.next() => {
+                    let Some(msg) = msg else {
+                        return Ok(());
+                    };
+
+                    let TMessage::Text(text) = msg? else {
+                        continue;
+                    };
+
+                    let payload: Payload = serde_json::from_str(&text)?;
+
+                    if let Some(s) = payload.s {
+                        self.sequence = Some(s);
+                    }
+
+                    match payload.op {
+                        0 => {
+                            if let Err(e) = self.handle_dispatch(payload.t.as_deref(), payload.d).await {
+                                debug!("Error handling Discord event: {}", e);
+                            }
+                        }
+                        1 => ws.send(self.heartbeat()).await?,
+                        // Reconnect or invalid session
+                        7 | 9 => return Ok(()),
+                        11 => acknowledged = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn heartbeat(&self) -> TMessage {
+        TMessage::Text(serde_json::json!({ "op": 1, "d": self.sequence }).to_string())
+    }
+
+    async fn handle_dispatch(
+        &mut self,
+        event: Option<&str>,
+        data: serde_json::Value,
+    ) -> Result<(), anyhow::Error> {
+        match event {
+            Some("READY") => {
+                let ready: Ready = serde_json::from_value(data)?;
+                info!("Connected to Discord as {}", ready.user.username);
+                self.bot_id = Some(ready.user.id);
+            }
+            Some("GUILD_CREATE") => {
+                let guild: GuildCreate = serde_json::from_value(data)?;
+                let admin_roles = guild
+                    .roles
+                    .into_iter()
+                    .filter(Role::is_admin)
+                    .map(|r| r.id)
+                    .collect();
+
+                self.guilds.insert(
+                    guild.id,
+                    Guild {
+                        owner_id: guild.owner_id,
+                        admin_roles,
+                    },
+                );
+            }
+            Some("GUILD_ROLE_CREATE") | Some("GUILD_ROLE_UPDATE") => {
+                let update: RoleUpdate = serde_json::from_value(data)?;
+                let guild = self.guilds.entry(update.guild_id).or_default();
+
+                if update.role.is_admin() {
+                    guild.admin_roles.insert(update.role.id);
+                } else {
+                    guild.admin_roles.remove(&update.role.id);
+                }
+            }
+            Some("GUILD_ROLE_DELETE") => {
+                let delete: RoleDelete = serde_json::from_value(data)?;
+
+                if let Some(guild) = self.guilds.get_mut(&delete.guild_id) {
+                    guild.admin_roles.remove(&delete.role_id);
+                }
+            }
+            Some("MESSAGE_CREATE") => {
+                let msg: MessageCreate = serde_json::from_value(data)?;
+                self.handle_chat_message(msg).await;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    async fn handle_chat_message(&self, msg: MessageCreate) {
+        if msg.author.bot || Some(&msg.author.id) == self.bot_id.as_ref() {
+            return;
+        }
+
+        let channel = {
+            let channels = self.channels.lock().await;
+            channels
+                .iter()
+                .find(|c| c.channel_id == msg.channel_id)
+                .cloned()
+        };
+
+        let Some(channel) = channel else {
+            return;
+        };
+
+        let roles = msg.member.map(|m| m.roles).unwrap_or_default();
+        let guild = msg.guild_id.as_ref().and_then(|id| self.guilds.get(id));
+
+        let permission = permission(guild, &channel, &msg.author.id, &roles);
+
+        let _ = self
+            .chat_handler_tx
+            .send(HandleMessage::ChatMessage(chat::ChatMessage {
+                platform: ChatPlatform::Discord,
+                permission,
+                channel: channel.username,
+                sender: msg.author.username,
+                message: msg.content,
+            }))
+            .await;
+    }
+}
+
+/// The guild owner and roles with the administrator permission are admins,
+/// members with the mod role of the channel are mods
+fn permission(
+    guild: Option<&Guild>,
+    channel: &Channel,
+    author_id: &str,
+    roles: &[String],
+) -> chat::Permission {
+    let is_admin = guild.is_some_and(|g| {
+        g.owner_id == author_id || roles.iter().any(|r| g.admin_roles.contains(r))
+    });
+
+    let is_mod = channel
+        .mod_role_id
+        .as_ref()
+        .is_some_and(|mod_role| roles.contains(mod_role));
+
+    if is_admin {
+        chat::Permission::Admin
+    } else if is_mod {
+        chat::Permission::Mod
+    } else {
+        chat::Permission::Public
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Payload {
+    op: u8,
+    #[serde(default)]
+    d: serde_json::Value,
+    s: Option<u64>,
+    t: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ready {
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+    username: String,
+    #[serde(default)]
+    bot: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GuildCreate {
+    id: String,
+    owner_id: String,
+    #[serde(default)]
+    roles: Vec<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Role {
+    id: String,
+    permissions: String,
+}
+
+impl Role {
+    fn is_admin(&self) -> bool {
+        self.permissions
+            .parse::<u64>()
+            .is_ok_and(|p| p & ADMINISTRATOR == ADMINISTRATOR)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleUpdate {
+    guild_id: String,
+    role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleDelete {
+    guild_id: String,
+    role_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageCreate {
+    channel_id: String,
+    guild_id: Option<String>,
+    author: User,
+    member: Option<Member>,
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel() -> Channel {
+        Channel {
+            username: "715209".to_string(),
+            channel_id: "100".to_string(),
+            mod_role_id: Some("mods".to_string()),
+        }
+    }
+
+    fn guild() -> Guild {
+        Guild {
+            owner_id: "1".to_string(),
+            admin_roles: HashSet::from(["admins".to_string()]),
+        }
+    }
+
+    fn roles(roles: &[&str]) -> Vec<String> {
+        roles.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn map_roles_to_permission() {
+        let guild = guild();
+        let channel = channel();
+
+        assert_eq!(
+            permission(Some(&guild), &channel, "1", &[]),
+            chat::Permission::Admin
+        );
+        assert_eq!(
+            permission(Some(&guild), &channel, "2", &roles(&["admins"])),
+            chat::Permission::Admin
+        );
+        assert_eq!(
+            permission(Some(&guild), &channel, "2", &roles(&["mods"])),
+            chat::Permission::Mod
+        );
+        assert_eq!(
+            permission(Some(&guild), &channel, "2", &roles(&["other"])),
+            chat::Permission::Public
+        );
+        assert_eq!(
+            permission(None, &channel, "1", &[]),
+            chat::Permission::Public
+        );
+    }
+
+    #[test]
+    fn parse_message_create() {
+        let text = r#"{
+            "op": 0,
+            "s": 3,
+            "t": "MESSAGE_CREATE",
+            "d": {
+                "channel_id": "100",
+                "guild_id": "200",
+                "author": { "id": "2", "username": "someone" },
+                "member": { "roles": ["mods"] },
+                "content": "!bitrate"
+            }
+        }"#;
+
+        let payload: Payload = serde_json::from_str(text).unwrap();
+        assert_eq!(payload.t.as_deref(), Some("MESSAGE_CREATE"));
+        assert_eq!(payload.s, Some(3));
+
+        let msg: MessageCreate = serde_json::from_value(payload.d).unwrap();
+        assert_eq!(msg.channel_id, "100");
+        assert_eq!(msg.guild_id.as_deref(), Some("200"));
+        assert_eq!(msg.author.username, "someone");
+        assert!(!msg.author.bot);
+        assert_eq!(msg.member.unwrap().roles, roles(&["mods"]));
+        assert_eq!(msg.content, "!bitrate");
+    }
+
+    #[test]
+    fn admin_permission_bit() {
+        let role = |permissions: &str| Role {
+            id: "1".to_string(),
+            permissions: permissions.to_string(),
+        };
+
+        assert!(role("8").is_admin());
+        assert!(!role("2048").is_admin());
+        assert!(!role("not a number").is_admin());
+    }
+}
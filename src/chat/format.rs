@@ -0,0 +1,150 @@
+//! Numbers and durations written the way the chat language expects them.
+
+use std::time::Duration;
+
+use super::ChatLanguage;
+
+/// Unit names of a language, `spaced` puts a space between a value and its unit
+struct Units {
+    thousands: &'static str,
+    kbps: &'static str,
+    hours: &'static str,
+    minutes: &'static str,
+    seconds: &'static str,
+    spaced: bool,
+}
+
+fn units(language: &ChatLanguage) -> Units {
+    let (thousands, kbps, hours, minutes, seconds, spaced) = match language {
+        ChatLanguage::EN => (",", "Kbps", "h", "m", "s", false),
+        ChatLanguage::ZHTW => (",", "Kbps", "小時", "分", "秒", false),
+        ChatLanguage::DE => (".", "kbit/s", "Std.", "Min.", "Sek.", true),
+        ChatLanguage::DK => (".", "kbit/s", "t", "min", "s", true),
+        ChatLanguage::ES => (".", "kb/s", "h", "min", "s", true),
+        ChatLanguage::FR => (" ", "kb/s", "h", "min", "s", true),
+        ChatLanguage::IT => (".", "kb/s", "h", "min", "s", true),
+        ChatLanguage::NB => (" ", "kbit/s", "t", "min", "s", true),
+        ChatLanguage::NL => (".", "kbit/s", "u", "min", "s", true),
+        ChatLanguage::PL => (" ", "kb/s", "godz.", "min", "s", true),
+        ChatLanguage::PTBR => (".", "kb/s", "h", "min", "s", true),
+        ChatLanguage::RU => (" ", "кбит/с", "ч", "мин", "с", true),
+        ChatLanguage::SV => (" ", "kbit/s", "h", "min", "s", true),
+        ChatLanguage::TR => (".", "kb/s", "sa", "dk", "sn", true),
+    };
+
+    Units {
+        thousands,
+        kbps,
+        hours,
+        minutes,
+        seconds,
+        spaced,
+    }
+}
+
+impl Units {
+    fn with_unit(&self, value: impl std::fmt::Display, unit: &str) -> String {
+        if self.spaced {
+            format!("{} {}", value, unit)
+        } else {
+            format!("{}{}", value, unit)
+        }
+    }
+}
+
+/// Whole number with the thousands separator of the language, like 2,500
+pub fn number(language: &ChatLanguage, value: u64) -> String {
+    let digits = value.to_string();
+    let separator = units(language).thousands;
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push_str(separator);
+        }
+
+        out.push(digit);
+    }
+
+    out
+}
+
+/// Bitrate with its unit, like 2,500 Kbps or 2 500 kb/s
+pub fn bitrate(language: &ChatLanguage, kbps: u64) -> String {
+    format!("{} {}", number(language, kbps), units(language).kbps)
+}
+
+/// Short duration like 1h 5m or 42s, seconds are left out from an hour on
+pub fn duration(language: &ChatLanguage, duration: Duration) -> String {
+    let units = units(language);
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    let parts = match (hours, minutes) {
+        (0, 0) => vec![units.with_unit(seconds, units.seconds)],
+        (0, _) => vec![
+            units.with_unit(minutes, units.minutes),
+            units.with_unit(seconds, units.seconds),
+        ],
+        _ => vec![
+            units.with_unit(number(language, hours), units.hours),
+            units.with_unit(minutes, units.minutes),
+        ],
+    };
+
+    // Chinese doesn't separate the parts
+    let separator = match language {
+        ChatLanguage::ZHTW => "",
+        _ => " ",
+    };
+
+    parts.join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_use_the_language_separator() {
+        assert_eq!(number(&ChatLanguage::EN, 800), "800");
+        assert_eq!(number(&ChatLanguage::EN, 2500), "2,500");
+        assert_eq!(number(&ChatLanguage::EN, 1_234_567), "1,234,567");
+        assert_eq!(number(&ChatLanguage::DE, 2500), "2.500");
+        assert_eq!(number(&ChatLanguage::FR, 12500), "12 500");
+    }
+
+    #[test]
+    fn bitrate_with_localized_unit() {
+        assert_eq!(bitrate(&ChatLanguage::EN, 2500), "2,500 Kbps");
+        assert_eq!(bitrate(&ChatLanguage::FR, 2500), "2 500 kb/s");
+        assert_eq!(bitrate(&ChatLanguage::DE, 400), "400 kbit/s");
+    }
+
+    #[test]
+    fn durations() {
+        let en = ChatLanguage::EN;
+        assert_eq!(duration(&en, Duration::from_secs(42)), "42s");
+        assert_eq!(duration(&en, Duration::from_secs(5 * 60 + 3)), "5m 3s");
+        assert_eq!(
+            duration(&en, Duration::from_secs(3600 + 5 * 60 + 3)),
+            "1h 5m"
+        );
+
+        let de = ChatLanguage::DE;
+        assert_eq!(duration(&de, Duration::from_secs(42)), "42 Sek.");
+        assert_eq!(
+            duration(&de, Duration::from_secs(3600 + 5 * 60)),
+            "1 Std. 5 Min."
+        );
+
+        let fr = ChatLanguage::FR;
+        assert_eq!(duration(&fr, Duration::from_secs(5 * 60 + 3)), "5 min 3 s");
+
+        let zh = ChatLanguage::ZHTW;
+        assert_eq!(
+            duration(&zh, Duration::from_secs(3600 + 5 * 60)),
+            "1小時5分"
+        );
+    }
+}
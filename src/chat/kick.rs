@@ -1,9 +1,14 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::{bail, Context as _};
 use async_trait::async_trait;
+use base64::Engine as _;
 use futures_util::{SinkExt as _, StreamExt as _};
 use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
     sync::{mpsc, Mutex},
     time,
 };
@@ -15,21 +20,31 @@ use crate::{
     config, error, ChatSender,
 };
 
+/// Channel info by slug, used when the ids aren't in the config
+const KICK_CHANNEL_API: &str = "https://kick.com/api/v2/channels";
+
+/// How long a Kick API request may take
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
 const KICK_CHAT_WS: &str = "wss://ws-us2.pusher.com/app/32cbd69e4b950bf97679?protocol=7&client=js&version=7.6.0&flash=false";
 
+/// A connection that lasted shorter than this counts as a failed attempt
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+
+type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>;
+
 pub struct Kick {
-    _req_client: reqwest::Client,
-    chat: KickChat,
+    chat_handler_tx: ChatSender,
+
+    /// Chat connections by IRL proxy, channels without a proxy share `None`
+    chats: Mutex<HashMap<Option<String>, KickChat>>,
 }
 
 impl Kick {
     pub fn new(chat_handler_tx: ChatSender) -> Self {
-        let client = reqwest::Client::new();
-        let chat = KickChat::connect(chat_handler_tx);
-
         Self {
-            _req_client: client,
-            chat,
+            chat_handler_tx,
+            chats: Mutex::new(HashMap::new()),
         }
     }
 
@@ -40,29 +55,49 @@ impl Kick {
             panic!("Join called with wrong platform");
         };
 
-        let channel = if config.use_irlproxy.unwrap_or_default() {
-            tracing::error!("IRL Proxy is not implemented yet");
-            return;
-        } else {
-            let config::KickConfig {
-                channel_id,
-                chatroom_id,
-                ..
-            } = config;
-
-            let (Some(channel_id), Some(chatroom_id)) = (channel_id, chatroom_id) else {
-                tracing::error!("Kick channel_id or chatroom_id is not set, ignoring channel");
+        let proxy = if config.use_irlproxy.unwrap_or_default() {
+            let Some(proxy) = &config.irlproxy_url else {
+                tracing::error!("Kick useIrlproxy is set without an irlproxyUrl, ignoring channel");
                 return;
             };
 
-            Channel {
-                username: channel,
-                channel_id,
-                chatroom_id,
+            match reqwest::Url::parse(proxy) {
+                Ok(url) => Some(url),
+                Err(e) => {
+                    tracing::error!("Invalid Kick irlproxyUrl {}: {}", proxy, e);
+                    return;
+                }
             }
+        } else {
+            None
+        };
+
+        let (channel_id, chatroom_id) = match (config.channel_id, config.chatroom_id) {
+            (Some(channel_id), Some(chatroom_id)) => (channel_id, chatroom_id),
+            _ => match lookup_channel(&channel, proxy.as_ref()).await {
+                Ok(ids) => ids,
+                Err(e) => {
+                    tracing::error!(
+                        "Kick channelId or chatroomId is not set and looking them up failed, ignoring channel: {:#}",
+                        e
+                    );
+                    return;
+                }
+            },
         };
 
-        self.chat.add_channel(channel).await;
+        let channel = Channel {
+            username: channel,
+            channel_id,
+            chatroom_id,
+        };
+
+        let mut chats = self.chats.lock().await;
+        let chat = chats
+            .entry(proxy.as_ref().map(|p| p.to_string()))
+            .or_insert_with(|| KickChat::connect(self.chat_handler_tx.clone(), proxy));
+
+        chat.add_channel(channel).await;
     }
 }
 
@@ -71,6 +106,11 @@ impl super::ChatLogic for Kick {
     async fn send_message(&self, channel: String, message: String) {
         tracing::debug!(?channel, ?message, "Sending message to KICK");
     }
+
+    async fn join(&self, platform: config::ConfigChatPlatform, channel: String) -> bool {
+        self.join_channel(platform, channel.to_lowercase()).await;
+        true
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -103,13 +143,13 @@ impl Drop for KickChat {
 }
 
 impl KickChat {
-    fn connect(chat_handler_tx: mpsc::Sender<HandleMessage>) -> Self {
+    fn connect(chat_handler_tx: mpsc::Sender<HandleMessage>, proxy: Option<reqwest::Url>) -> Self {
         let (inner_tx, inner_rx) = mpsc::unbounded_channel();
         let channels = Arc::new(Mutex::new(Vec::new()));
 
         let inner_channels = channels.clone();
         let inner_handle = tokio::spawn(async move {
-            let mut inner = Inner::new(inner_rx, inner_channels, chat_handler_tx).await;
+            let mut inner = Inner::new(inner_rx, inner_channels, chat_handler_tx, proxy).await;
             inner.kick_conn_loop().await;
         });
 
@@ -140,9 +180,8 @@ struct Inner {
     inner_rx: mpsc::UnboundedReceiver<InnerMessage>,
     channels: Arc<Mutex<Vec<Channel>>>,
     chat_handler_tx: mpsc::Sender<HandleMessage>,
-    connection: tokio_tungstenite::WebSocketStream<
-        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
-    >,
+    proxy: Option<reqwest::Url>,
+    connection: WebSocket,
     last_pong: time::Instant,
 }
 
@@ -151,12 +190,14 @@ impl Inner {
         inner_rx: mpsc::UnboundedReceiver<InnerMessage>,
         channels: Arc<Mutex<Vec<Channel>>>,
         chat_handler_tx: mpsc::Sender<HandleMessage>,
+        proxy: Option<reqwest::Url>,
     ) -> Self {
         Self {
             inner_rx,
             channels,
             chat_handler_tx,
-            connection: get_connection().await,
+            connection: get_connection(proxy.as_ref()).await,
+            proxy,
             last_pong: time::Instant::now(),
         }
     }
@@ -164,8 +205,11 @@ impl Inner {
     async fn kick_conn_loop(&mut self) {
         let mut activity_timeout = time::interval(Duration::from_secs(120));
         let (ping_tx, mut ping_rx) = mpsc::unbounded_channel();
+        let mut retry_grow = 0;
 
         loop {
+            let connected_at = time::Instant::now();
+
             loop {
                 tokio::select! {
                     msg = self.inner_rx.recv() => {
@@ -216,7 +260,22 @@ impl Inner {
             }
 
             tracing::info!("Disconnected from KICK chat");
-            self.connection = get_connection().await;
+
+            // Back off when the socket keeps dropping right after connecting
+            if connected_at.elapsed() >= STABLE_CONNECTION {
+                retry_grow = 0;
+            } else {
+                let wait = 1 << retry_grow;
+                info!("Reconnecting to KICK chat in {} seconds", wait);
+                time::sleep(Duration::from_secs(wait)).await;
+
+                if retry_grow < 5 {
+                    retry_grow += 1;
+                }
+            }
+
+            self.connection = get_connection(self.proxy.as_ref()).await;
+            self.last_pong = time::Instant::now();
             self.reconnect_subscriptions().await;
         }
     }
@@ -239,15 +298,7 @@ impl Inner {
                     return Ok(());
                 }
 
-                let permission = msg.sender.identity.badges.iter().fold(
-                    chat::Permission::Public,
-                    |acc, badge| match badge.kind.as_str() {
-                        "vip" => chat::Permission::Vip,
-                        "moderator" => chat::Permission::Mod,
-                        "broadcaster" => chat::Permission::Admin,
-                        _ => acc,
-                    },
-                );
+                let permission = permission(&msg.sender.identity.badges);
 
                 let Some(channel) = self.chatroom_id_to_username(msg.chatroom_id).await else {
                     tracing::error!("Chatroom id not found for {}", msg.chatroom_id);
@@ -337,20 +388,44 @@ impl Inner {
     }
 }
 
-async fn get_connection(
-) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>> {
+/// Maps the Kick identity badges of a sender to the highest permission
+fn permission(badges: &[Badge]) -> chat::Permission {
+    let has_badge = |kind: &str| badges.iter().any(|b| b.kind == kind);
+
+    if has_badge("broadcaster") {
+        chat::Permission::Admin
+    } else if has_badge("moderator") {
+        chat::Permission::Mod
+    } else if has_badge("vip") {
+        chat::Permission::Vip
+    } else {
+        chat::Permission::Public
+    }
+}
+
+async fn get_connection(proxy: Option<&reqwest::Url>) -> WebSocket {
     let mut retry_grow = 1;
 
     loop {
         info!("Connecting");
 
-        if let Ok((ws_stream, _)) = tokio_tungstenite::connect_async(KICK_CHAT_WS).await {
-            info!("Connected");
-            break ws_stream;
+        let connection = match proxy {
+            Some(proxy) => connect_via_proxy(proxy).await,
+            None => tokio_tungstenite::connect_async(KICK_CHAT_WS)
+                .await
+                .map(|(ws_stream, _)| ws_stream)
+                .map_err(Into::into),
+        };
+
+        match connection {
+            Ok(ws_stream) => {
+                info!("Connected");
+                break ws_stream;
+            }
+            Err(e) => warn!("Unable to connect: {}", e),
         }
 
         let wait = 1 << retry_grow;
-        warn!("Unable to connect");
         info!("trying to connect again in {} seconds", wait);
         time::sleep(Duration::from_secs(wait)).await;
 
@@ -360,6 +435,117 @@ async fn get_connection(
     }
 }
 
+/// Client for the Kick API, never goes direct when the IRL proxy is set
+fn http_client(proxy: Option<&reqwest::Url>) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(API_TIMEOUT);
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+    }
+
+    builder.build()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ChannelInfo {
+    id: usize,
+    chatroom: Chatroom,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Chatroom {
+    id: usize,
+}
+
+/// Looks up the channel and chatroom id of the channel on the Kick API
+async fn lookup_channel(
+    slug: &str,
+    proxy: Option<&reqwest::Url>,
+) -> Result<(usize, usize), anyhow::Error> {
+    let client = http_client(proxy)?;
+    let url = format!("{}/{}", KICK_CHANNEL_API, slug);
+
+    let res = client.get(&url).send().await.map_err(|e| match proxy {
+        Some(proxy) if e.is_connect() => anyhow::anyhow!(
+            "IRL proxy {}:{} is unreachable: {}",
+            proxy.host_str().unwrap_or_default(),
+            proxy.port_or_known_default().unwrap_or_default(),
+            e
+        ),
+        _ => e.into(),
+    })?;
+
+    let info: ChannelInfo = res.error_for_status()?.json().await?;
+    info!(
+        "Found Kick channel {} ({}, chatroom {})",
+        slug, info.id, info.chatroom.id
+    );
+
+    Ok((info.id, info.chatroom.id))
+}
+
+/// Tunnels the chat WebSocket through the IRL proxy with HTTP CONNECT
+async fn connect_via_proxy(proxy: &reqwest::Url) -> Result<WebSocket, anyhow::Error> {
+    let target = reqwest::Url::parse(KICK_CHAT_WS)?;
+    let target_host = target.host_str().context("Kick chat URL has no host")?;
+    let target = format!(
+        "{}:{}",
+        target_host,
+        target.port_or_known_default().unwrap_or(443)
+    );
+
+    let proxy_host = proxy.host_str().context("IRL proxy URL has no host")?;
+    let proxy_port = proxy
+        .port_or_known_default()
+        .context("IRL proxy URL has no port")?;
+
+    let mut stream = TcpStream::connect((proxy_host, proxy_port))
+        .await
+        .with_context(|| format!("IRL proxy {}:{} is unreachable", proxy_host, proxy_port))?;
+
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+
+    if !proxy.username().is_empty() {
+        let credentials = format!(
+            "{}:{}",
+            proxy.username(),
+            proxy.password().unwrap_or_default()
+        );
+        let credentials = base64::engine::general_purpose::STANDARD.encode(credentials);
+        request += &format!("Proxy-Authorization: Basic {}\r\n", credentials);
+    }
+
+    request += "\r\n";
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response headers byte by byte so none of the tunnel is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            bail!("IRL proxy response too large");
+        }
+
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("IRL proxy refused the connection: {}", status);
+    }
+
+    let (ws_stream, _) = tokio_tungstenite::client_async_tls(KICK_CHAT_WS, stream).await?;
+
+    Ok(ws_stream)
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(tag = "event", content = "data")]
 pub enum Request<'a> {
@@ -491,3 +677,57 @@ pub struct Badge {
     pub text: String,
     pub count: Option<usize>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_chat_message() {
+        let text = r##"{
+            "event": "App\\Events\\ChatMessageEvent",
+            "data": "{\"id\":\"1\",\"chatroom_id\":177815,\"content\":\"!bitrate\",\"type\":\"message\",\"sender\":{\"id\":1,\"username\":\"Mod_User\",\"slug\":\"mod-user\",\"identity\":{\"color\":\"#75FD46\",\"badges\":[{\"type\":\"vip\",\"text\":\"VIP\"},{\"type\":\"moderator\",\"text\":\"Moderator\"}]}}}",
+            "channel": "chatrooms.177815.v2"
+        }"##;
+
+        let event: Event = serde_json::from_str(text).unwrap();
+
+        let EventData::ChatMessageEvent(msg) = event.data else {
+            panic!("Expected a chat message");
+        };
+
+        assert_eq!(msg.chatroom_id, 177815);
+        assert_eq!(msg.content, "!bitrate");
+        assert_eq!(msg.sender.slug, "mod-user");
+        assert_eq!(
+            permission(&msg.sender.identity.badges),
+            chat::Permission::Mod
+        );
+    }
+
+    #[test]
+    fn parse_channel_info() {
+        let text = r#"{"id":4598,"slug":"715209","chatroom":{"id":4526,"chatable_id":4598}}"#;
+
+        let info: ChannelInfo = serde_json::from_str(text).unwrap();
+
+        assert_eq!((info.id, info.chatroom.id), (4598, 4526));
+    }
+
+    #[test]
+    fn map_badges_to_permission() {
+        let badge = |kind: &str| Badge {
+            kind: kind.to_string(),
+            text: kind.to_string(),
+            count: None,
+        };
+
+        assert_eq!(
+            permission(&[badge("moderator"), badge("broadcaster")]),
+            chat::Permission::Admin
+        );
+        assert_eq!(permission(&[badge("vip")]), chat::Permission::Vip);
+        assert_eq!(permission(&[badge("subscriber")]), chat::Permission::Public);
+        assert_eq!(permission(&[]), chat::Permission::Public);
+    }
+}
@@ -4,22 +4,78 @@ use std::fmt::Display;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use crate::{error, switcher};
+use crate::{config, error, switcher};
 
+pub mod chat_error;
 pub mod chat_handler;
+pub mod discord;
+pub mod format;
 pub mod kick;
+pub mod rate_limit;
+pub mod template;
+pub mod trovo;
 pub mod twitch;
 pub mod youtube;
 
+pub use crate::chat::ChatPlatform::Youtube;
+pub use chat_error::ChatError;
 pub use chat_handler::ChatHandler;
+pub use discord::Discord;
 pub use kick::Kick;
+pub use rate_limit::{RateLimit, RateLimited};
+pub use trovo::Trovo;
 pub use twitch::Twitch;
-pub use crate::chat::ChatPlatform::Youtube;
 
 #[async_trait]
 pub trait ChatLogic: Send + Sync {
     // TODO: This should return an error
     async fn send_message(&self, channel: String, message: String);
+
+    /// Connects to the chat again, `None` when the platform can't be restarted
+    async fn restart(&self) -> Option<Result<(), ChatError>> {
+        None
+    }
+
+    /// Sends a highlighted message, platforms without announcements send
+    /// a normal message
+    async fn send_announcement(&self, channel: String, message: String, _color: AnnouncementColor) {
+        self.send_message(channel, message).await;
+    }
+
+    /// Whether the bot just sent this message to the channel
+    fn sent_recently(&self, _channel: &str, _message: &str) -> bool {
+        false
+    }
+
+    /// Joins the chat of a user that enabled chat while running, false when
+    /// the platform only joins its channels at startup
+    async fn join(&self, _platform: config::ConfigChatPlatform, _channel: String) -> bool {
+        false
+    }
+}
+
+/// Color of a Twitch announcement, primary uses the accent color of the channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementColor {
+    #[default]
+    Primary,
+    Blue,
+    Green,
+    Orange,
+    Purple,
+}
+
+impl AnnouncementColor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnouncementColor::Primary => "primary",
+            AnnouncementColor::Blue => "blue",
+            AnnouncementColor::Green => "green",
+            AnnouncementColor::Orange => "orange",
+            AnnouncementColor::Purple => "purple",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -27,25 +83,43 @@ pub enum Command {
     Alias,
     Autostop,
     Bitrate,
+    BitrateHistory,
     Fix,
+    Maintenance,
     Mod,
+    MuteNotify,
     Noalbs,
     Notify,
+    ObsInfo,
     ServerInfo,
+    Servers,
+    Server,
     Otrigger,
     Ortrigger,
     Public,
     Rec,
     Refresh,
+    Reload,
+    Rename,
+    RestartChat,
+    Retry,
     Rtrigger,
     Source,
     Sourceinfo,
     Start,
+    Status,
     Stop,
     Collection,
+    Commands,
     Switch,
+    Transition,
     Trigger,
+    Triggers,
     Version,
+    Why,
+    Screenshot,
+    Preset,
+    ForceLive,
     LiveScene,
     StartingScene,
     EndingScene,
@@ -64,24 +138,42 @@ impl From<&str> for Command {
             "alias" => Command::Alias,
             "autostop" => Command::Autostop,
             "bitrate" => Command::Bitrate,
+            "bitratehistory" => Command::BitrateHistory,
             "fix" => Command::Fix,
+            "maintenance" => Command::Maintenance,
             "mod" => Command::Mod,
+            "mutenotify" => Command::MuteNotify,
             "noalbs" => Command::Noalbs,
             "notify" => Command::Notify,
+            "obsinfo" => Command::ObsInfo,
             "serverinfo" => Command::ServerInfo,
+            "servers" => Command::Servers,
+            "server" => Command::Server,
             "otrigger" => Command::Otrigger,
             "ortrigger" => Command::Ortrigger,
             "public" => Command::Public,
             "record" => Command::Rec,
             "refresh" => Command::Refresh,
+            "reload" => Command::Reload,
+            "rename" => Command::Rename,
+            "restartchat" => Command::RestartChat,
+            "retry" => Command::Retry,
             "rtrigger" => Command::Rtrigger,
             "sourceinfo" => Command::Sourceinfo,
             "start" => Command::Start,
+            "status" => Command::Status,
             "stop" => Command::Stop,
             "collection" => Command::Collection,
+            "commands" => Command::Commands,
             "switch" => Command::Switch,
+            "transition" => Command::Transition,
             "trigger" => Command::Trigger,
+            "triggers" => Command::Triggers,
             "source" => Command::Source,
+            "why" => Command::Why,
+            "screenshot" => Command::Screenshot,
+            "preset" => Command::Preset,
+            "forcelive" => Command::ForceLive,
 
             "noalbsversion" => Command::Version,
 
@@ -98,12 +190,63 @@ impl From<&str> for Command {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+impl Command {
+    /// Every command by the name used in chat
+    pub const NAMES: &'static [&'static str] = &[
+        "alias",
+        "autostop",
+        "bitrate",
+        "bitratehistory",
+        "fix",
+        "maintenance",
+        "mod",
+        "mutenotify",
+        "noalbs",
+        "notify",
+        "obsinfo",
+        "serverinfo",
+        "servers",
+        "server",
+        "otrigger",
+        "ortrigger",
+        "public",
+        "record",
+        "refresh",
+        "reload",
+        "rename",
+        "restartchat",
+        "retry",
+        "rtrigger",
+        "sourceinfo",
+        "start",
+        "status",
+        "stop",
+        "collection",
+        "commands",
+        "switch",
+        "transition",
+        "trigger",
+        "triggers",
+        "source",
+        "why",
+        "screenshot",
+        "preset",
+        "forcelive",
+        "noalbsversion",
+        "live",
+        "privacy",
+        "starting",
+        "ending",
+    ];
+}
+
+/// Ordered from the least to the most privileged
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Permission {
-    Admin,
-    Mod,
     Public,
     Vip,
+    Mod,
+    Admin,
 }
 
 #[derive(Debug)]
@@ -117,6 +260,8 @@ pub enum ChatPlatform {
     Twitch,
     Kick,
     Youtube,
+    Discord,
+    Trovo,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -211,6 +356,20 @@ pub struct InternalChatUpdate {
 pub enum InternalUpdate {
     Raided(RaidedInfo),
     OfflineTimeout,
+    OfflineTimeoutWarning,
+    LowBitrateWarning,
+    NoTranscoding,
+    /// Whether the channel is live on the platform, None when unknown
+    PlatformStreamOnline(Option<bool>),
+    BroadcastingSoftwareDisconnected,
+    /// Reconnected after being disconnected for this long
+    BroadcastingSoftwareReconnected(std::time::Duration),
+    /// The software didn't show this scene after switching to it
+    SceneSwitchFailed(String),
+    /// The switcher stopped checking and was restarted
+    SwitcherRestarted,
+    /// Chat was set up while running, the channel still has to be joined
+    ChatEnabled,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::{sync::mpsc, time};
+use tracing::{debug, warn};
+
+use super::{AnnouncementColor, ChatError, ChatLogic, ChatPlatform};
+use crate::config;
+
+/// Identical messages to the same channel within this window are only sent once
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long a sent message is remembered, platforms like YouTube show the
+/// messages of the bot in the chat it reads as well
+const SENT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Messages waiting to be sent, newer messages are dropped when full
+const QUEUE_SIZE: usize = 100;
+
+/// How many messages can be sent per period
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub messages: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    /// Safe limits for a bot without elevated rate limits on the platform
+    pub fn default_for(platform: &ChatPlatform) -> Self {
+        let (messages, seconds) = match platform {
+            ChatPlatform::Twitch => (20, 30),
+            ChatPlatform::Kick => (10, 30),
+            ChatPlatform::Youtube => (5, 10),
+            ChatPlatform::Discord => (5, 5),
+            ChatPlatform::Trovo => (10, 30),
+        };
+
+        Self {
+            messages,
+            per: Duration::from_secs(seconds),
+        }
+    }
+
+    /// Uses `<PLATFORM>_CHAT_RATE_LIMIT` formatted as `messages/seconds`,
+    /// for example `TWITCH_CHAT_RATE_LIMIT=100/30` for a verified bot
+    pub fn from_env(platform: &ChatPlatform) -> Self {
+        let var = format!("{:?}_CHAT_RATE_LIMIT", platform).to_uppercase();
+
+        match env::var(&var) {
+            Ok(value) => Self::parse(&value).unwrap_or_else(|| {
+                warn!(
+                    "Ignoring invalid {}: {}, expected messages/seconds",
+                    var, value
+                );
+                Self::default_for(platform)
+            }),
+            Err(_) => Self::default_for(platform),
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        let (messages, seconds) = value.split_once('/')?;
+        let messages = messages.trim().parse::<u32>().ok().filter(|m| *m > 0)?;
+        let seconds = seconds.trim().parse::<u64>().ok().filter(|s| *s > 0)?;
+
+        Some(Self {
+            messages,
+            per: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// Starts full so a few messages can go out right away, refills continuously
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        let capacity = limit.messages as f64;
+
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: capacity / limit.per.as_secs_f64(),
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token, otherwise returns how long until one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64(
+            (1.0 - self.tokens) / self.refill_per_second,
+        ))
+    }
+}
+
+/// Message waiting to be sent, announcements have a color
+struct Queued {
+    channel: String,
+    message: String,
+    color: Option<AnnouncementColor>,
+}
+
+/// Queues the outgoing messages of a chat connection and sends them at a
+/// rate the platform won't time out or ban the bot for
+pub struct RateLimited {
+    connection: Arc<dyn ChatLogic>,
+    tx: mpsc::Sender<Queued>,
+    handle: tokio::task::JoinHandle<()>,
+
+    /// Messages sent within the `SENT_WINDOW` by channel and message
+    sent: Arc<Mutex<HashMap<(String, String), Instant>>>,
+}
+
+impl RateLimited {
+    pub fn new(platform: ChatPlatform, connection: Arc<dyn ChatLogic>, limit: RateLimit) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+
+        debug!(
+            "Limiting {:?} chat to {} messages per {:?}",
+            platform, limit.messages, limit.per
+        );
+
+        let sent = Arc::new(Mutex::new(HashMap::new()));
+        let handle = tokio::spawn(Self::drain(
+            platform,
+            connection.clone(),
+            limit,
+            rx,
+            sent.clone(),
+        ));
+
+        Self {
+            connection,
+            tx,
+            handle,
+            sent,
+        }
+    }
+
+    async fn drain(
+        platform: ChatPlatform,
+        connection: Arc<dyn ChatLogic>,
+        limit: RateLimit,
+        mut rx: mpsc::Receiver<Queued>,
+        sent: Arc<Mutex<HashMap<(String, String), Instant>>>,
+    ) {
+        let mut bucket = TokenBucket::new(&limit, Instant::now());
+        let mut recent: HashMap<(String, String), Instant> = HashMap::new();
+
+        while let Some(queued) = rx.recv().await {
+            let now = Instant::now();
+            recent.retain(|_, sent| now.duration_since(*sent) < DUPLICATE_WINDOW);
+
+            let message = (queued.channel, queued.message);
+
+            if recent.contains_key(&message) {
+                debug!("Skipping duplicate {:?} message: {}", platform, message.1);
+                continue;
+            }
+
+            recent.insert(message.clone(), now);
+
+            while let Err(wait) = bucket.take(Instant::now()) {
+                debug!("{:?} chat rate limited, waiting {:?}", platform, wait);
+                time::sleep(wait).await;
+            }
+
+            {
+                let mut sent = sent.lock().unwrap();
+                sent.retain(|_, at| now.duration_since(*at) < SENT_WINDOW);
+                sent.insert(message.clone(), Instant::now());
+            }
+
+            let (channel, message) = message;
+
+            match queued.color {
+                Some(color) => connection.send_announcement(channel, message, color).await,
+                None => connection.send_message(channel, message).await,
+            }
+        }
+    }
+
+    fn queue(&self, channel: String, message: String, color: Option<AnnouncementColor>) {
+        let queued = Queued {
+            channel,
+            message,
+            color,
+        };
+
+        if let Err(mpsc::error::TrySendError::Full(queued)) = self.tx.try_send(queued) {
+            warn!("Chat queue is full, dropping message: {}", queued.message);
+        }
+    }
+}
+
+#[async_trait]
+impl ChatLogic for RateLimited {
+    async fn send_message(&self, channel: String, message: String) {
+        self.queue(channel, message, None);
+    }
+
+    async fn send_announcement(&self, channel: String, message: String, color: AnnouncementColor) {
+        self.queue(channel, message, Some(color));
+    }
+
+    async fn restart(&self) -> Option<Result<(), ChatError>> {
+        self.connection.restart().await
+    }
+
+    async fn join(&self, platform: config::ConfigChatPlatform, channel: String) -> bool {
+        self.connection.join(platform, channel).await
+    }
+
+    fn sent_recently(&self, channel: &str, message: &str) -> bool {
+        let sent = self.sent.lock().unwrap();
+
+        sent.get(&(channel.to_owned(), message.to_owned()))
+            .is_some_and(|at| at.elapsed() < SENT_WINDOW)
+    }
+}
+
+impl Drop for RateLimited {
+    // Abort the spawned task
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_limit() {
+        assert_eq!(
+            RateLimit::parse("100/30"),
+            Some(RateLimit {
+                messages: 100,
+                per: Duration::from_secs(30)
+            })
+        );
+        assert_eq!(RateLimit::parse("0/30"), None);
+        assert_eq!(RateLimit::parse("20"), None);
+    }
+
+    #[test]
+    fn bucket_bursts_then_waits() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            messages: 2,
+            per: Duration::from_secs(10),
+        };
+        let mut bucket = TokenBucket::new(&limit, now);
+
+        assert!(bucket.take(now).is_ok());
+        assert!(bucket.take(now).is_ok());
+        assert_eq!(bucket.take(now), Err(Duration::from_secs(5)));
+
+        assert!(bucket.take(now + Duration::from_secs(5)).is_ok());
+    }
+}
@@ -0,0 +1,72 @@
+//! Custom wording for the notifications NOALBS sends to chat.
+
+use serde::{Deserialize, Serialize};
+
+use crate::switcher::SwitchType;
+
+/// Placeholders that can be used in a template
+pub const PLACEHOLDERS: [&str; 3] = ["scene", "bitrate", "server"];
+
+/// Notifications that can use a custom template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Notification {
+    SwitchNormal,
+    SwitchLow,
+    SwitchPrevious,
+    SwitchOffline,
+    OfflineTimeout,
+    OfflineTimeoutWarning,
+    LowBitrateWarning,
+}
+
+impl From<SwitchType> for Notification {
+    fn from(switch_type: SwitchType) -> Self {
+        match switch_type {
+            SwitchType::Normal => Notification::SwitchNormal,
+            SwitchType::Low => Notification::SwitchLow,
+            SwitchType::Previous => Notification::SwitchPrevious,
+            SwitchType::Offline => Notification::SwitchOffline,
+        }
+    }
+}
+
+/// Returns the first placeholder in the template that isn't supported
+pub fn unknown_placeholder(template: &str) -> Option<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}').map(|(name, _)| name))
+        .find(|name| !PLACEHOLDERS.contains(name))
+}
+
+/// Replaces every `{name}` placeholder with its value
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_owned(), |msg, (name, value)| {
+            msg.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_placeholders() {
+        let msg = render(
+            "Going to backup feed 📡 {scene} ({server} {bitrate} Kbps)",
+            &[("scene", "low"), ("bitrate", "420"), ("server", "SLS")],
+        );
+
+        assert_eq!(msg, "Going to backup feed 📡 low (SLS 420 Kbps)");
+    }
+
+    #[test]
+    fn find_unknown_placeholder() {
+        assert_eq!(unknown_placeholder("{scene} at {bitrate} Kbps"), None);
+        assert_eq!(unknown_placeholder("no placeholders"), None);
+        assert_eq!(unknown_placeholder("{scene} {viewers}"), Some("viewers"));
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use futures_util::{SinkExt as _, StreamExt as _};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time};
+use tokio_tungstenite::tungstenite::Message as TMessage;
+use tracing::{debug, error, info};
+
+use crate::{
+    chat::{self, ChatPlatform, HandleMessage},
+    config, ChatSender,
+};
+
+const TROVO_CHAT_WS: &str = "wss://open-chat.trovo.live/chat";
+const TROVO_API: &str = "https://open-api.trovo.live/openplatform";
+
+/// Default time between pings, Trovo tells us the next gap in every pong
+const DEFAULT_PING_GAP: u64 = 30;
+
+/// Trovo chat message type of a normal chat message
+const CHAT_TYPE_NORMAL: i64 = 0;
+
+pub struct Trovo {
+    client: reqwest::Client,
+    channels: Arc<Mutex<Vec<Channel>>>,
+    chat_handler_tx: ChatSender,
+    connections: std::sync::Mutex<HashMap<String, tokio::task::JoinHandle<()>>>,
+}
+
+impl Drop for Trovo {
+    fn drop(&mut self) {
+        if let Ok(connections) = self.connections.lock() {
+            for handle in connections.values() {
+                handle.abort();
+            }
+        }
+    }
+}
+
+impl Trovo {
+    /// The client id is used to read chat, the access token to send messages
+    pub fn new(
+        client_id: String,
+        access_token: String,
+        chat_handler_tx: ChatSender,
+    ) -> Result<Self, anyhow::Error> {
+        let mut auth = reqwest::header::HeaderValue::from_str(&format!("OAuth {}", access_token))?;
+        auth.set_sensitive(true);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::AUTHORIZATION, auth);
+        headers.insert("Client-ID", client_id.parse()?);
+        headers.insert(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+
+        Ok(Self {
+            client,
+            channels: Arc::new(Mutex::new(Vec::new())),
+            chat_handler_tx,
+            connections: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn join_channel(&self, platform: config::ConfigChatPlatform, username: String) {
+        let config::ConfigChatPlatform::Trovo(config) = platform else {
+            panic!("Join called with wrong platform");
+        };
+
+        info!(
+            "Joining Trovo channel {} for {}",
+            config.channel_id, username
+        );
+
+        let channel = Channel {
+            username,
+            channel_id: config.channel_id,
+        };
+
+        {
+            let mut channels = self.channels.lock().await;
+            if channels.contains(&channel) {
+                return;
+            }
+
+            channels.push(channel.clone());
+        }
+
+        let connection = Connection {
+            client: self.client.clone(),
+            channel: channel.clone(),
+            chat_handler_tx: self.chat_handler_tx.clone(),
+        };
+
+        let handle = tokio::spawn(connection.run());
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(channel.channel_id, handle);
+    }
+}
+
+#[async_trait]
+impl super::ChatLogic for Trovo {
+    async fn send_message(&self, channel: String, message: String) {
+        let channel_id = {
+            let channels = self.channels.lock().await;
+            channels
+                .iter()
+                .find(|c| c.username == channel)
+                .map(|c| c.channel_id.to_owned())
+        };
+
+        let Some(channel_id) = channel_id else {
+            error!("No Trovo channel found for {}", channel);
+            return;
+        };
+
+        let res = self
+            .client
+            .post(format!("{}/chat/send", TROVO_API))
+            .json(&serde_json::json!({ "content": message, "channel_id": channel_id }))
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if !res.status().is_success() => {
+                error!("Error sending message to Trovo: {}", res.status());
+            }
+            Ok(_) => {}
+            Err(e) => error!("Error sending message to Trovo: {}", e),
+        }
+    }
+
+    async fn join(&self, platform: config::ConfigChatPlatform, channel: String) -> bool {
+        self.join_channel(platform, channel).await;
+        true
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Channel {
+    /// Username of the NOALBS chat config
+    pub username: String,
+
+    /// Trovo channel to read commands from and reply in
+    pub channel_id: String,
+}
+
+/// Chat connection of a single channel
+struct Connection {
+    client: reqwest::Client,
+    channel: Channel,
+    chat_handler_tx: ChatSender,
+}
+
+impl Connection {
+    async fn run(self) {
+        let mut retry_grow = 1;
+
+        loop {
+            info!("Connecting to Trovo chat of {}", self.channel.username);
+
+            match self.session().await {
+                Ok(()) => {
+                    info!("Disconnected from Trovo chat");
+                    retry_grow = 1;
+                }
+                Err(e) => error!("Trovo chat error: {}", e),
+            }
+
+            let wait = 1 << retry_grow;
+            info!("Reconnecting to Trovo in {} seconds", wait);
+            time::sleep(Duration::from_secs(wait)).await;
+
+            if retry_grow < 5 {
+                retry_grow += 1;
+            }
+        }
+    }
+
+    /// Every connection needs a fresh token for the channel
+    async fn chat_token(&self) -> Result<String, anyhow::Error> {
+        let res = self
+            .client
+            .get(format!(
+                "{}/chat/channel-token/{}",
+                TROVO_API, self.channel.channel_id
+            ))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(res.json::<ChatToken>().await?.token)
+    }
+
+    /// User id of the access token, the bot shouldn't read its own replies
+    async fn bot_user_id(&self) -> Result<i64, anyhow::Error> {
+        let res = self
+            .client
+            .get(format!("{}/getuserinfo", TROVO_API))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(res.json::<UserInfo>().await?.user_id.parse()?)
+    }
+
+    async fn session(&self) -> Result<(), anyhow::Error> {
+        let token = self.chat_token().await?;
+
+        let bot_user_id = match self.bot_user_id().await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                error!("Unable to get the Trovo user of the access token: {}", e);
+                None
+            }
+        };
+
+        // Trovo sends the recent chat history after connecting
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(TROVO_CHAT_WS).await?;
+
+        let auth = serde_json::json!({
+            "type": "AUTH",
+            "nonce": "auth",
+            "data": { "token": token },
+        });
+        ws.send(TMessage::Text(auth.to_string())).await?;
+
+        let ping = time::sleep(Duration::from_secs(DEFAULT_PING_GAP));
+        tokio::pin!(ping);
+
+        loop {
+            tokio::select! {
+                _ = &mut ping => {
+                    let msg = serde_json::json!({ "type": "PING", "nonce": "ping" });
+                    ws.send(TMessage::Text(msg.to_string())).await?;
+
+                    ping.as_mut().reset(time::Instant::now() + Duration::from_secs(DEFAULT_PING_GAP));
+                }
+                msg = ws.next() => {
+                    let Some(msg) = msg else {
+                        return Ok(());
+                    };
+
+                    let TMessage::Text(text) = msg? else {
+                        continue;
+                    };
+
+                    let payload: Payload = serde_json::from_str(&text)?;
+
+                    match payload.kind.as_str() {
+                        "RESPONSE" => {
+                            if let Some(error) = payload.error {
+                                bail!("Authentication failed: {}", error);
+                            }
+
+                            info!("Connected to Trovo chat of {}", self.channel.username);
+                        }
+                        "PONG" => {
+                            let gap = payload.data["gap"].as_u64().unwrap_or(DEFAULT_PING_GAP);
+                            ping.as_mut().reset(time::Instant::now() + Duration::from_secs(gap));
+                        }
+                        "RECONNECT" => return Ok(()),
+                        "CHAT" => {
+                            match serde_json::from_value::<ChatData>(payload.data) {
+                                Ok(data) => {
+                                    let chats = data
+                                        .chats
+                                        .into_iter()
+                                        .filter(|c| is_new_chat(c, connected_at, bot_user_id))
+                                        .collect();
+
+                                    self.handle_chats(chats).await
+                                }
+                                Err(e) => debug!("Error parsing Trovo chat: {}", e),
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_chats(&self, chats: Vec<Chat>) {
+        for chat in chats {
+            if chat.kind != CHAT_TYPE_NORMAL {
+                continue;
+            }
+
+            let _ = self
+                .chat_handler_tx
+                .send(HandleMessage::ChatMessage(chat::ChatMessage {
+                    platform: ChatPlatform::Trovo,
+                    permission: permission(&chat.roles),
+                    channel: self.channel.username.to_owned(),
+                    sender: chat.nick_name,
+                    message: chat.content,
+                }))
+                .await;
+        }
+    }
+}
+
+/// Whether the chat was sent by someone else after connecting
+fn is_new_chat(chat: &Chat, connected_at: i64, bot_user_id: Option<i64>) -> bool {
+    let from_bot = bot_user_id.is_some() && chat.sender_id == bot_user_id;
+
+    chat.send_time >= connected_at && !from_bot
+}
+
+/// Maps the Trovo roles of a sender to a permission
+fn permission(roles: &[String]) -> chat::Permission {
+    let has_role = |role: &str| roles.iter().any(|r| r.eq_ignore_ascii_case(role));
+
+    if has_role("streamer") {
+        chat::Permission::Admin
+    } else if has_role("mod") || has_role("supermod") {
+        chat::Permission::Mod
+    } else {
+        // Followers and everyone else
+        chat::Permission::Public
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatToken {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserInfo {
+    user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Payload {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    data: serde_json::Value,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatData {
+    #[serde(default)]
+    chats: Vec<Chat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    #[serde(rename = "type")]
+    kind: i64,
+    content: String,
+    nick_name: String,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    sender_id: Option<i64>,
+    /// Unix timestamp in seconds
+    #[serde(default)]
+    send_time: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles(roles: &[&str]) -> Vec<String> {
+        roles.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn map_roles_to_permission() {
+        assert_eq!(permission(&roles(&["streamer"])), chat::Permission::Admin);
+        assert_eq!(
+            permission(&roles(&["follower", "mod"])),
+            chat::Permission::Mod
+        );
+        assert_eq!(permission(&roles(&["follower"])), chat::Permission::Public);
+        assert_eq!(permission(&[]), chat::Permission::Public);
+    }
+
+    #[test]
+    fn skip_history_and_own_chats() {
+        let chat = |sender_id, send_time| Chat {
+            kind: CHAT_TYPE_NORMAL,
+            content: "!bitrate".to_string(),
+            nick_name: "someone".to_string(),
+            roles: Vec::new(),
+            sender_id: Some(sender_id),
+            send_time,
+        };
+
+        assert!(is_new_chat(&chat(1, 100), 100, Some(2)));
+        assert!(!is_new_chat(&chat(1, 99), 100, Some(2)));
+        assert!(!is_new_chat(&chat(2, 100), 100, Some(2)));
+        assert!(is_new_chat(&chat(2, 100), 100, None));
+    }
+}
@@ -1,8 +1,10 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use anyhow::{bail, Context};
 use async_trait::async_trait;
-use tokio::sync::mpsc;
-use tracing::{error, info, trace};
+use serde_json::json;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, info, trace, warn};
 use twitch_irc::{
     login::StaticLoginCredentials,
     message,
@@ -11,15 +13,33 @@ use twitch_irc::{
 };
 
 use crate::{
-    chat::{self, ChatPlatform, HandleMessage},
+    chat::{self, AnnouncementColor, ChatPlatform, HandleMessage},
+    config,
+    twitch_eventsub::{self, EventSub, TokenInfo},
     twitch_pubsub::PubsubManager,
     ChatSender,
 };
 
+const TW_ANNOUNCEMENTS: &str = "https://api.twitch.tv/helix/chat/announcements";
+
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 pub struct Twitch {
     client: TwitchIRCClient<TCPTransport<TLS>, StaticLoginCredentials>,
     pub event_loop: Arc<tokio::task::JoinHandle<()>>,
+
+    /// Used for the Helix API, the announcements need the
+    /// `moderator:manage:announcements` scope
+    oauth: String,
+
+    /// Checked once when the first announcement is sent
+    token_info: Arc<Mutex<Option<TokenInfo>>>,
+
+    /// Twitch id of every joined channel by login
+    channel_ids: Arc<Mutex<HashMap<String, String>>>,
+
+    http_client: reqwest::Client,
 }
 
 impl Twitch {
@@ -37,12 +57,28 @@ impl Twitch {
             TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(config);
 
         let pubsub = PubsubManager::new(chat_handler_tx.clone());
-        let event_loop_handle =
-            tokio::spawn(Self::chat_loop(incoming_messages, chat_handler_tx, pubsub));
+        let eventsub = EventSub::from_env(&oauth, chat_handler_tx.clone());
+        let channel_ids = Arc::new(Mutex::new(HashMap::new()));
+        let event_loop_handle = tokio::spawn(Self::chat_loop(
+            incoming_messages,
+            chat_handler_tx,
+            pubsub,
+            eventsub,
+            channel_ids.clone(),
+        ));
+
+        let http_client = reqwest::Client::builder()
+            .timeout(API_TIMEOUT)
+            .build()
+            .expect("Failed to create reqwest client");
 
         Self {
             client,
             event_loop: Arc::new(event_loop_handle),
+            oauth,
+            token_info: Arc::new(Mutex::new(None)),
+            channel_ids,
+            http_client,
         }
     }
 
@@ -54,6 +90,8 @@ impl Twitch {
         mut incoming_messages: mpsc::UnboundedReceiver<message::ServerMessage>,
         chat_handler_tx: tokio::sync::mpsc::Sender<super::HandleMessage>,
         pubsub: PubsubManager,
+        eventsub: Option<EventSub>,
+        channel_ids: Arc<Mutex<HashMap<String, String>>>,
     ) {
         while let Some(message) = incoming_messages.recv().await {
             // println!("Received message: {:?}", message);
@@ -65,6 +103,20 @@ impl Twitch {
                         state.channel_id,
                         state.channel_login
                     );
+                    channel_ids
+                        .lock()
+                        .await
+                        .insert(state.channel_login.to_owned(), state.channel_id.to_owned());
+
+                    if let Some(eventsub) = &eventsub {
+                        eventsub
+                            .add_channel(
+                                state.channel_id.to_owned(),
+                                state.channel_login.to_owned(),
+                            )
+                            .await;
+                    }
+
                     pubsub.add_raid(state.channel_id, state.channel_login).await;
                 }
                 message::ServerMessage::Notice(msg) => {
@@ -80,12 +132,15 @@ impl Twitch {
                         msg.badges
                             .iter()
                             .fold(chat::Permission::Public, |acc, badge| {
-                                match badge.name.as_str() {
+                                let permission = match badge.name.as_str() {
                                     "vip" => chat::Permission::Vip,
                                     "moderator" => chat::Permission::Mod,
                                     "broadcaster" => chat::Permission::Admin,
-                                    _ => acc,
-                                }
+                                    _ => chat::Permission::Public,
+                                };
+
+                                // Badges can come in any order
+                                acc.max(permission)
                             });
 
                     chat_handler_tx
@@ -110,6 +165,58 @@ impl Twitch {
             error!("Error joining channel: {}", e);
         }
     }
+
+    /// The bot has to be a moderator of the channel to send announcements
+    async fn announce(
+        &self,
+        channel: &str,
+        message: &str,
+        color: AnnouncementColor,
+    ) -> anyhow::Result<()> {
+        let broadcaster_id = self
+            .channel_ids
+            .lock()
+            .await
+            .get(channel)
+            .cloned()
+            .context("the channel id isn't known yet")?;
+
+        let token_info = self.token_info().await?;
+
+        let res = self
+            .http_client
+            .post(TW_ANNOUNCEMENTS)
+            .header("Client-Id", &token_info.client_id)
+            .bearer_auth(&self.oauth)
+            .query(&[
+                ("broadcaster_id", broadcaster_id.as_str()),
+                ("moderator_id", token_info.user_id.as_str()),
+            ])
+            .json(&json!({ "message": message, "color": color.as_str() }))
+            .send()
+            .await?;
+
+        if !res.status().is_success() {
+            let status = res.status();
+            let text = res.text().await.unwrap_or_default();
+            bail!("{} {}", status, text);
+        }
+
+        Ok(())
+    }
+
+    async fn token_info(&self) -> anyhow::Result<TokenInfo> {
+        let mut token_info = self.token_info.lock().await;
+
+        if let Some(info) = &*token_info {
+            return Ok(info.clone());
+        }
+
+        let info = twitch_eventsub::validate(&self.oauth).await?;
+        *token_info = Some(info.clone());
+
+        Ok(info)
+    }
 }
 
 #[async_trait]
@@ -119,6 +226,21 @@ impl super::ChatLogic for Twitch {
             error!("Error sending message to twitch: {}", err);
         }
     }
+
+    async fn send_announcement(&self, channel: String, message: String, color: AnnouncementColor) {
+        if let Err(e) = self.announce(&channel, &message, color).await {
+            warn!(
+                "Unable to send the announcement to {}, sending a message instead: {}",
+                channel, e
+            );
+            self.send_message(channel, message).await;
+        }
+    }
+
+    async fn join(&self, _platform: config::ConfigChatPlatform, channel: String) -> bool {
+        self.join_channel(channel.to_lowercase());
+        true
+    }
 }
 
 impl Drop for Twitch {
@@ -1,40 +1,133 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::task;
-use tokio::time::{self, Duration};
-use youtube_chat::live_chat::{LiveChatClient, LiveChatClientBuilder};
+use tokio::time::{self, Duration, Instant};
 use youtube_chat::item::{ChatItem, MessageItem};
+use youtube_chat::live_chat::{LiveChatClient, LiveChatClientBuilder};
+
+use crate::{
+    chat::{ChatError, ChatLogic, ChatMessage, ChatPlatform, HandleMessage, Permission},
+    ChatSender,
+};
+use tracing::{debug, error, info, warn};
 
-use crate::{ChatSender, chat::{ChatMessage, ChatPlatform, Permission, HandleMessage, ChatLogic}};
-use tracing::{debug, error, info};
+const YOUTUBE_API: &str = "https://www.googleapis.com/youtube/v3";
+
+/// How often new chat messages are fetched
+const CHAT_POLL_INTERVAL: Duration = Duration::from_millis(3000);
+
+/// Restart the live chat after this many failed fetches in a row
+const MAX_CONSECUTIVE_ERRORS: u32 = 5;
 
 pub struct YoutubeChat {
-    live_chat: Arc<Mutex<LiveChatClient<
-        Box<dyn Fn(String) + Send + Sync>,
-        Box<dyn Fn() + Send + Sync>,
-        Box<dyn Fn(ChatItem) + Send + Sync>,
-        Box<dyn Fn(anyhow::Error) + Send + Sync>,
-    >>>,
+    live_chat: Arc<
+        Mutex<
+            LiveChatClient<
+                Box<dyn Fn(String) + Send + Sync>,
+                Box<dyn Fn() + Send + Sync>,
+                Box<dyn Fn(ChatItem) + Send + Sync>,
+                Box<dyn Fn(anyhow::Error) + Send + Sync>,
+            >,
+        >,
+    >,
+
+    /// Client with the OAuth2 bearer token set, used to post messages
+    client: Option<reqwest::Client>,
+
+    /// The liveChatId of the active broadcast, resolved when the chat starts
+    live_chat_id: Arc<Mutex<Option<String>>>,
+
+    /// Don't try to send any messages until this instant has passed
+    backoff_until: Mutex<Option<Instant>>,
+
+    /// Set when the live chat of the broadcast ended
+    ended: Arc<AtomicBool>,
+
+    /// Amount of errors reported by the live chat client
+    errors: Arc<AtomicU32>,
+
+    /// Last error reported by the live chat client
+    last_error: Arc<std::sync::Mutex<Option<ChatError>>>,
+
+    chat_handle: std::sync::Mutex<Option<task::JoinHandle<()>>>,
+
+    /// Held while restarting so restarts don't run at the same time
+    restarting: Mutex<()>,
 }
 
 impl YoutubeChat {
-    pub async fn new(yt_channel_id: String, chat_tx: ChatSender) -> Result<Self, anyhow::Error> {
+    pub async fn new(
+        yt_channel_id: String,
+        oauth: Option<String>,
+        chat_tx: ChatSender,
+    ) -> Result<Self, ChatError> {
+        let client = match oauth {
+            Some(token) => Some(authorized_client(&token)?),
+            None => {
+                warn!(
+                    "YOUTUBE_OAUTH not set, NOALBS won't be able to send messages to YouTube chat"
+                );
+                None
+            }
+        };
+
+        let live_chat_id = Arc::new(Mutex::new(None));
+        let ended = Arc::new(AtomicBool::new(false));
+        let errors = Arc::new(AtomicU32::new(0));
+        let last_error = Arc::new(std::sync::Mutex::new(None));
+
+        let error_count = errors.clone();
+        let error_last = last_error.clone();
+        let end_ended = ended.clone();
+
+        let start_client = client.clone();
+        let start_live_chat_id = live_chat_id.clone();
+        let end_live_chat_id = live_chat_id.clone();
+
         let live_chat = LiveChatClientBuilder::new()
             .channel_id(yt_channel_id.clone())
-            .on_start(Box::new(|_live_id| {
+            .on_start(Box::new(move |live_id| {
                 debug!("YouTube live chat started");
+
+                let Some(client) = start_client.clone() else {
+                    return;
+                };
+
+                let live_chat_id = start_live_chat_id.clone();
+                tokio::spawn(async move {
+                    match resolve_live_chat_id(&client, &live_id).await {
+                        Ok(id) => {
+                            debug!("Resolved YouTube liveChatId: {}", id);
+                            *live_chat_id.lock().await = Some(id);
+                        }
+                        Err(e) => error!("Unable to resolve YouTube liveChatId: {}", e),
+                    }
+                });
             }) as Box<dyn Fn(String) + Send + Sync>)
-            .on_error(Box::new(|err| {
-                error!("YouTube live chat error: {:?}", err);
+            .on_error(Box::new(move |err| {
+                let err = ChatError::classify(&err);
+                error!("YouTube live chat error: {}", err);
+
+                *error_last.lock().unwrap() = Some(err);
+                error_count.fetch_add(1, Ordering::Relaxed);
             }) as Box<dyn Fn(anyhow::Error) + Send + Sync>)
             .on_chat(Box::new(move |chat_item: ChatItem| {
                 let chat_tx = chat_tx.clone();
                 let yt_channel_id = yt_channel_id.clone();
-                let author_name = chat_item.author.name.clone().unwrap_or_else(|| "Unknown".to_string());
-                let message_content: String = chat_item.message.iter().map(|m| match m {
-                    MessageItem::Text(text) => text.clone(),
-                    _ => "".to_string(),
-                }).collect();
+                let author_name = chat_item
+                    .author
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| "Unknown".to_string());
+                let message_content: String = chat_item
+                    .message
+                    .iter()
+                    .map(|m| match m {
+                        MessageItem::Text(text) => text.clone(),
+                        _ => "".to_string(),
+                    })
+                    .collect();
 
                 info!("{}: {}", author_name, message_content);
 
@@ -42,6 +135,8 @@ impl YoutubeChat {
                     Permission::Admin
                 } else if chat_item.is_moderator {
                     Permission::Mod
+                } else if chat_item.is_membership {
+                    Permission::Vip
                 } else {
                     Permission::Public
                 };
@@ -60,44 +155,283 @@ impl YoutubeChat {
                     }
                 });
             }) as Box<dyn Fn(ChatItem) + Send + Sync>)
-            .on_end(Box::new(|| {
+            .on_end(Box::new(move || {
                 debug!("YouTube live chat ended");
+                end_ended.store(true, Ordering::Relaxed);
+
+                let live_chat_id = end_live_chat_id.clone();
+                tokio::spawn(async move {
+                    *live_chat_id.lock().await = None;
+                });
             }) as Box<dyn Fn() + Send + Sync>)
             .build();
 
         Ok(Self {
             live_chat: Arc::new(Mutex::new(live_chat)),
+            client,
+            live_chat_id,
+            backoff_until: Mutex::new(None),
+            ended,
+            errors,
+            last_error,
+            chat_handle: std::sync::Mutex::new(None),
+            restarting: Mutex::new(()),
         })
     }
 
+    /// Keeps reading the live chat in the background.
+    ///
+    /// When the broadcast ends or fetching keeps failing the live chat gets
+    /// started again, which looks up the new broadcast of the channel.
     pub async fn start(&self) {
-        let live_chat = self.live_chat.clone();
-        let chat_handle = task::spawn(async move {
-            let mut live_chat = live_chat.lock().await;
-            live_chat.start().await.unwrap();
-        });
+        self.spawn_reader(false);
+    }
+
+    /// Stops reading the live chat and starts it again, which looks up the
+    /// current broadcast of the channel. Keeps trying in the background
+    /// when the channel isn't live.
+    pub async fn restart(&self) -> Result<(), ChatError> {
+        let _restarting = self.restarting.lock().await;
+
+        if let Some(old) = self.chat_handle.lock().unwrap().take() {
+            old.abort();
+        }
+
+        *self.live_chat_id.lock().await = None;
 
-        chat_handle.await.unwrap();
+        let started = self
+            .live_chat
+            .lock()
+            .await
+            .start()
+            .await
+            .map(|_| ())
+            .map_err(|e| ChatError::classify(&e));
 
+        self.spawn_reader(started.is_ok());
+
+        started
+    }
+
+    /// Reads the live chat in a new task, `started` skips starting the
+    /// live chat the first time
+    fn spawn_reader(&self, mut started: bool) {
         let live_chat = self.live_chat.clone();
-        let fetch_handle = task::spawn(async move {
-            let mut interval = time::interval(Duration::from_millis(3000));
+        let ended = self.ended.clone();
+        let errors = self.errors.clone();
+        let last_error = self.last_error.clone();
+
+        let chat_handle = task::spawn(async move {
+            let mut retry_grow = 1;
+
             loop {
-                interval.tick().await;
-                let mut live_chat = live_chat.lock().await;
-                live_chat.execute().await;
+                let start = if std::mem::take(&mut started) {
+                    Ok(())
+                } else {
+                    live_chat.lock().await.start().await.map(|_| ())
+                };
+
+                if let Err(e) = start {
+                    let e = ChatError::classify(&e);
+                    let wait = e
+                        .backoff()
+                        .unwrap_or_else(|| Duration::from_secs(1 << retry_grow));
+
+                    match e {
+                        ChatError::NotLive => debug!("YouTube channel isn't live yet"),
+                        e => warn!("Unable to start YouTube live chat: {}", e),
+                    }
+                    info!(
+                        "trying to start YouTube live chat again in {} seconds",
+                        wait.as_secs()
+                    );
+                    time::sleep(wait).await;
+
+                    if retry_grow < 5 {
+                        retry_grow += 1;
+                    }
+
+                    continue;
+                }
+
+                info!("Reading YouTube live chat");
+                retry_grow = 1;
+                ended.store(false, Ordering::Relaxed);
+
+                let mut interval = time::interval(CHAT_POLL_INTERVAL);
+                let mut consecutive_errors = 0;
+
+                loop {
+                    interval.tick().await;
+
+                    let errors_before = errors.load(Ordering::Relaxed);
+                    live_chat.lock().await.execute().await;
+
+                    if errors.load(Ordering::Relaxed) == errors_before {
+                        consecutive_errors = 0;
+                    } else {
+                        consecutive_errors += 1;
+                    }
+
+                    if ended.load(Ordering::Relaxed) {
+                        info!("YouTube live chat ended, waiting for a new broadcast");
+                        break;
+                    }
+
+                    // Retrying right away won't help with rejected credentials or a quota
+                    let backoff = last_error
+                        .lock()
+                        .unwrap()
+                        .take()
+                        .and_then(|e| e.backoff().map(|backoff| (e, backoff)));
+
+                    if let Some((e, backoff)) = backoff {
+                        warn!(
+                            "YouTube live chat failed ({}), restarting in {} seconds",
+                            e,
+                            backoff.as_secs()
+                        );
+                        time::sleep(backoff).await;
+                        break;
+                    }
+
+                    if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                        warn!("YouTube live chat keeps failing, restarting");
+                        break;
+                    }
+                }
             }
         });
 
-        fetch_handle.await.unwrap();
+        if let Some(old) = self.chat_handle.lock().unwrap().replace(chat_handle) {
+            old.abort();
+        }
+    }
+
+    async fn is_backing_off(&self) -> bool {
+        let mut backoff_until = self.backoff_until.lock().await;
+
+        match *backoff_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                *backoff_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    async fn back_off(&self, duration: Duration) {
+        *self.backoff_until.lock().await = Some(Instant::now() + duration);
+    }
+}
+
+impl Drop for YoutubeChat {
+    fn drop(&mut self) {
+        if let Some(handle) = self.chat_handle.lock().unwrap().take() {
+            handle.abort();
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl ChatLogic for YoutubeChat {
-    async fn send_message(&self, _channel: String, _message: String) {
-        // YouTube live chat API does not support sending messages directly
-        // So we will leave this method unimplemented
-        tracing::debug!("Sending messages to YouTube chat is not supported.");
+    async fn restart(&self) -> Option<Result<(), ChatError>> {
+        Some(YoutubeChat::restart(self).await)
     }
+
+    async fn send_message(&self, _channel: String, message: String) {
+        let Some(client) = &self.client else {
+            debug!("No YouTube OAuth token set, not sending message");
+            return;
+        };
+
+        if self.is_backing_off().await {
+            debug!("Backing off, not sending message to YouTube chat");
+            return;
+        }
+
+        let Some(live_chat_id) = self.live_chat_id.lock().await.clone() else {
+            debug!("No active YouTube live chat, not sending message");
+            return;
+        };
+
+        let body = serde_json::json!({
+            "snippet": {
+                "liveChatId": live_chat_id,
+                "type": "textMessageEvent",
+                "textMessageDetails": {
+                    "messageText": message,
+                }
+            }
+        });
+
+        let res = match client
+            .post(format!("{}/liveChat/messages", YOUTUBE_API))
+            .query(&[("part", "snippet")])
+            .json(&body)
+            .send()
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                error!("Error sending message to YouTube: {}", e);
+                return;
+            }
+        };
+
+        let status = res.status();
+
+        if status.is_success() {
+            return;
+        }
+
+        let text = res.text().await.unwrap_or_default();
+        let e = ChatError::from_status(status, &text);
+
+        match e.backoff() {
+            Some(backoff) => {
+                error!(
+                    "Error sending message to YouTube ({}), pausing messages for {} seconds",
+                    e,
+                    backoff.as_secs()
+                );
+                self.back_off(backoff).await;
+            }
+            None => error!("Error sending message to YouTube: {}", e),
+        }
+    }
+}
+
+fn authorized_client(token: &str) -> Result<reqwest::Client, ChatError> {
+    let mut auth = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+        .map_err(|_| ChatError::AuthFailed("YOUTUBE_OAUTH isn't a valid token".to_string()))?;
+    auth.set_sensitive(true);
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(reqwest::header::AUTHORIZATION, auth);
+
+    Ok(reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?)
+}
+
+/// Gets the liveChatId that belongs to the live video
+async fn resolve_live_chat_id(
+    client: &reqwest::Client,
+    live_id: &str,
+) -> Result<String, ChatError> {
+    let res = client
+        .get(format!("{}/videos", YOUTUBE_API))
+        .query(&[("part", "liveStreamingDetails"), ("id", live_id)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<serde_json::Value>()
+        .await?;
+
+    res["items"][0]["liveStreamingDetails"]["activeLiveChatId"]
+        .as_str()
+        .map(String::from)
+        .ok_or(ChatError::NotLive)
 }
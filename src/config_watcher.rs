@@ -0,0 +1,213 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use notify::Watcher;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{self, Config},
+    error, noalbs,
+    state::State,
+};
+
+/// Editors often write a file in multiple steps, wait for them to finish
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Reloads the config when the file changes and applies it to the state
+pub async fn run(
+    storage: Arc<dyn config::ConfigLogic>,
+    path: PathBuf,
+    user_state: noalbs::UserState,
+) {
+    let (tx, mut rx) = mpsc::channel(1);
+
+    let file_name = path.file_name().map(|f| f.to_owned());
+    let mut watcher =
+        match notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let Ok(event) = res else {
+                return;
+            };
+
+            if !(event.kind.is_modify() || event.kind.is_create()) {
+                return;
+            }
+
+            if event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == file_name.as_deref())
+            {
+                let _ = tx.try_send(());
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("Unable to watch the config: {}", e);
+                return;
+            }
+        };
+
+    // Watch the directory since editors may replace the file
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => PathBuf::from("."),
+    };
+
+    if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+        error!("Unable to watch {}: {}", dir.display(), e);
+        return;
+    }
+
+    info!("Watching {} for changes", path.display());
+
+    while rx.recv().await.is_some() {
+        tokio::time::sleep(DEBOUNCE).await;
+        while rx.try_recv().is_ok() {}
+
+        match reload(storage.as_ref(), &user_state).await {
+            Ok(Some(restart)) => {
+                info!("Reloaded the config");
+
+                for setting in restart {
+                    warn!("Changing {} requires a restart to take effect", setting);
+                }
+            }
+            Ok(None) => debug!("Config file changed but the config is the same"),
+            Err(e) => error!(
+                "Unable to reload the config, keeping the current one: {}",
+                e
+            ),
+        }
+    }
+}
+
+/// Loads the config from storage and applies it when it changed.
+/// Returns the settings that need a restart, or `None` when nothing changed.
+/// The current config is kept when loading fails.
+pub async fn reload(
+    storage: &dyn config::ConfigLogic,
+    user_state: &noalbs::UserState,
+) -> Result<Option<Vec<&'static str>>, error::Error> {
+    let config = storage.load()?;
+
+    let mut state = user_state.write().await;
+
+    if !changed(&state.config, &config) {
+        return Ok(None);
+    }
+
+    let restart = apply(&mut state, config);
+    noalbs::attach_obs_state(&mut state.config.switcher.stream_servers, user_state);
+
+    Ok(Some(restart))
+}
+
+/// Applies the new config to the state, returns the settings that
+/// can't be changed without restarting
+pub fn apply(state: &mut State, config: Config) -> Vec<&'static str> {
+    let restart = needs_restart(&state.config, &config);
+    let enable_switcher =
+        !state.config.switcher.bitrate_switcher_enabled && config.switcher.bitrate_switcher_enabled;
+
+    // Everything is replaced so saving the config won't undo the changes
+    state.config = config;
+
+    state.switcher_state.switchable_scenes.clear();
+    state.set_all_switchable_scenes();
+
+    if enable_switcher {
+        state
+            .switcher_state
+            .switcher_enabled_notifier()
+            .notify_waiters();
+    }
+
+    restart
+}
+
+fn needs_restart(old: &Config, new: &Config) -> Vec<&'static str> {
+    let mut restart = Vec::new();
+
+    if changed(&old.user, &new.user) {
+        restart.push("user");
+    }
+
+    if changed(&old.software, &new.software) {
+        restart.push("software");
+    }
+
+    let chat_connection = |c: &Option<config::Chat>| {
+        c.as_ref()
+            .map(|c| (c.platform.to_owned(), c.username.to_owned()))
+    };
+
+    if chat_connection(&old.chat) != chat_connection(&new.chat) {
+        restart.push("chat platform or username");
+    }
+
+    let old_options = &old.optional_options;
+    let new_options = &new.optional_options;
+
+    if changed(&old_options.metrics, &new_options.metrics) {
+        restart.push("metrics");
+    }
+
+    if old_options.watch_config != new_options.watch_config {
+        restart.push("watchConfig");
+    }
+
+    restart
+}
+
+fn changed<T: Serialize>(old: &T, new: &T) -> bool {
+    serde_json::to_value(old).ok() != serde_json::to_value(new).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state;
+
+    fn example_config() -> Config {
+        serde_json::from_str(include_str!("../config.json")).unwrap()
+    }
+
+    #[test]
+    fn apply_reloaded_config() {
+        let mut state = State {
+            config: example_config(),
+            switcher_state: state::SwitcherState::default(),
+            broadcasting_software: state::BroadcastingSoftwareState::default(),
+            event_senders: Vec::new(),
+        };
+
+        let mut config = example_config();
+        config.switcher.triggers.low = Some(1234);
+        config.switcher.switching_scenes.low = "reloaded low".to_string();
+
+        assert!(apply(&mut state, config).is_empty());
+        assert_eq!(state.config.switcher.triggers.low, Some(1234));
+        assert!(state
+            .switcher_state
+            .switchable_scenes
+            .contains("reloaded low"));
+    }
+
+    #[test]
+    fn restart_needed_for_connections() {
+        let old = example_config();
+        let mut new = example_config();
+
+        new.software = config::SoftwareConnection::Obs(config::ObsConfig {
+            host: "10.0.0.2".to_string(),
+            password: None,
+            port: 4455,
+            collections: None,
+            switch_through_preview: false,
+        });
+        new.optional_options.metrics.port = 9102;
+
+        assert_eq!(needs_restart(&old, &new), vec!["software", "metrics"]);
+    }
+}
@@ -3,6 +3,12 @@ pub enum Error {
     #[error("Can't access stats page")]
     StatsPageNotAvailable,
 
+    #[error("Stats page rejected the credentials ({0})")]
+    StatsPageUnauthorized(u16),
+
+    #[error("Stats page returned a status configured in httpStatuses ({0})")]
+    StatsPageConfiguredStatus(u16),
+
     #[error("Reqwest error {0}")]
     PageRequest(#[from] reqwest::Error),
 
@@ -32,6 +38,57 @@ pub enum Error {
     #[error("No source found with that name")]
     NoSourceFound,
 
+    #[error("The software didn't return an image")]
+    InvalidScreenshot,
+
+    #[error("No screenshot directory or upload URL set")]
+    NoScreenshotTarget,
+
+    #[error("Screenshot upload failed ({0})")]
+    ScreenshotUpload(u16),
+
+    #[error("Scene {0} not found")]
+    SceneNotFound(String),
+
+    #[error("Switched to {0} but the software stayed on another scene")]
+    SceneSwitchNotConfirmed(String),
+
+    #[error("No collection found with the name {0}")]
+    CollectionNotFound(String),
+
+    #[error("Scenes of collection {0} didn't finish loading")]
+    CollectionNotLoaded(String),
+
+    #[error("Alias {0} is already used")]
+    AliasAlreadyUsed(String),
+
+    #[error("Alias {0} is the name of a command")]
+    AliasIsCommand(String),
+
+    #[error("No privacy scene set")]
+    NoPrivacyScene,
+
+    #[error("No refresh scene set")]
+    NoRefreshScene,
+
+    #[error("No trigger found with that name")]
+    UnknownTrigger,
+
+    #[error("Trigger value must be between {0} and {1}")]
+    TriggerOutOfRange(u32, u32),
+
+    #[error("Retry attempts must be between {0} and {1}")]
+    RetryAttemptsOutOfRange(u8, u8),
+
+    #[error("No stream server found with that name")]
+    NoStreamServerFound,
+
+    #[error("A stream server named {0} already exists")]
+    StreamServerNameTaken(String),
+
+    #[error("Invalid JSON path {0}: {1}")]
+    InvalidJsonPath(String, String),
+
     #[error("Unable to convert enabled to bool")]
     EnabledToBoolConversionError,
 
@@ -41,9 +98,27 @@ pub enum Error {
     #[error("Json error: {0}")]
     Json(#[from] serde_json::error::Error),
 
+    #[error("Toml error: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[error("Toml error: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+
+    #[error("Yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+
     #[error("No chat configured")]
     NoChat,
 
+    #[error("Chat is already configured")]
+    ChatAlreadyEnabled,
+
+    #[error("A user named {0} already exists")]
+    UserExists(String),
+
+    #[error("Chat error: {0}")]
+    Chat(#[from] crate::chat::ChatError),
+
     #[error("Language not supported")]
     LangNotSupported,
 
@@ -52,4 +127,22 @@ pub enum Error {
 
     #[error("No server info available")]
     NoServerInfo,
+
+    #[error("Not supported by the broadcasting software")]
+    NotSupported,
+
+    #[error("Unknown placeholder {{{0}}} in notification template {1}")]
+    UnknownPlaceholder(String, String),
+
+    #[error("Stream servers depend on each other: {0}")]
+    DependencyCycle(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Invalid trigger expression at {0}: {1}")]
+    InvalidTriggerExpression(usize, String),
+
+    #[error("Can't convert the OBS address {0} of the old config")]
+    InvalidObsAddress(String),
 }
@@ -1,15 +1,27 @@
+pub mod api;
 pub mod broadcasting_software;
 pub mod chat;
 pub mod config;
+pub mod config_watcher;
 pub mod error;
 pub mod events;
+pub mod metrics;
 pub mod noalbs;
+pub mod preflight;
+pub mod screenshot;
+pub mod secret;
 pub mod state;
+pub mod stats;
 pub mod stream_servers;
+pub mod switch_log;
 pub mod switcher;
+pub mod trigger_expression;
+pub mod twitch_eventsub;
 pub mod twitch_pubsub;
+pub mod twitch_transcoding;
 pub mod user_manager;
 pub mod web_server;
+pub mod webhook;
 pub mod ws;
 
 pub use crate::noalbs::ChatSender;
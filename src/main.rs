@@ -1,12 +1,14 @@
-use std::path::PathBuf;
-use std::{env, sync::Arc};
+use std::{env, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use tokio::signal;
 
 use noalbs::chat::youtube::YoutubeChat;
-use noalbs::{chat::ChatPlatform, config, Noalbs};
-use tracing::warn;
+use noalbs::chat::ChatPlatform;
+use tracing::{info, warn};
+
+/// How long stopping the users may take before exiting anyway
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -40,15 +42,13 @@ async fn main() -> Result<()> {
     let (chat_tx, chat_rx) = tokio::sync::mpsc::channel(100);
     let mut chat_handler = noalbs::chat::ChatHandler::new(chat_rx, user_manager.clone());
 
-    if env::var("CONFIG_DIR").is_ok() {
-        let users = load_users_from_dir(env::var("CONFIG_DIR")?, chat_tx.clone()).await?;
-
-        for user in users {
-            user_manager.add(user?).await;
-        }
+    if let Ok(dir) = env::var("CONFIG_DIR") {
+        let added = user_manager.load_dir(&dir, chat_tx.clone()).await?;
+        info!("Loaded {} users from {}", added, dir);
     } else {
-        let user = load_user_from_file("config.json".to_owned(), chat_tx.clone()).await?;
-        user_manager.add(user).await;
+        user_manager
+            .add_from_file("config.json", chat_tx.clone())
+            .await?;
     }
 
     if env::var("TWITCH_BOT_USERNAME").is_ok() {
@@ -90,9 +90,47 @@ async fn main() -> Result<()> {
         chat_handler.add_chat_sender(ChatPlatform::Kick, Arc::new(kick));
     }
 
+    if env::var("DISCORD_BOT_TOKEN").is_ok() {
+        let token = env::var("DISCORD_BOT_TOKEN")?;
+        let discord = noalbs::chat::Discord::new(token, chat_tx.clone())?;
+
+        for (platform, username) in user_manager
+            .get_all_chat()
+            .await
+            .iter()
+            .filter(|(platform, _)| platform.kind() == ChatPlatform::Discord)
+        {
+            discord
+                .join_channel(platform.clone(), username.to_owned())
+                .await;
+        }
+
+        chat_handler.add_chat_sender(ChatPlatform::Discord, Arc::new(discord));
+    }
+
+    if env::var("TROVO_CLIENT_ID").is_ok() {
+        let client_id = env::var("TROVO_CLIENT_ID")?;
+        let access_token = env::var("TROVO_ACCESS_TOKEN")?;
+        let trovo = noalbs::chat::Trovo::new(client_id, access_token, chat_tx.clone())?;
+
+        for (platform, username) in user_manager
+            .get_all_chat()
+            .await
+            .iter()
+            .filter(|(platform, _)| platform.kind() == ChatPlatform::Trovo)
+        {
+            trovo
+                .join_channel(platform.clone(), username.to_owned())
+                .await;
+        }
+
+        chat_handler.add_chat_sender(ChatPlatform::Trovo, Arc::new(trovo));
+    }
+
     if env::var("YOUTUBE_CHANNEL_ID").is_ok() {
         let yt_channel_id = env::var("YOUTUBE_CHANNEL_ID")?;
-        let youtube = YoutubeChat::new(yt_channel_id, chat_tx.clone()).await?;
+        let yt_oauth = env::var("YOUTUBE_OAUTH").ok();
+        let youtube = YoutubeChat::new(yt_channel_id, yt_oauth, chat_tx.clone()).await?;
         youtube.start().await;
         chat_handler.add_chat_sender(ChatPlatform::Youtube, Arc::new(youtube));
     }
@@ -104,54 +142,57 @@ async fn main() -> Result<()> {
     if env::var("API_PORT").is_ok() {
         let port: u16 = env::var("API_PORT")?.parse()?;
         let webserver = noalbs::web_server::WebServer::new(port, user_manager.clone());
-        webserver.run().await;
+        tokio::task::spawn(async move {
+            webserver.run().await;
+        });
     }
 
-    match signal::ctrl_c().await {
-        Ok(()) => {}
-        Err(err) => {
-            eprintln!("Unable to listen for shutdown signal: {}", err);
-        }
+    for address in noalbs::metrics::addresses(&user_manager).await {
+        tokio::task::spawn(noalbs::metrics::run(address, user_manager.clone()));
+    }
+
+    shutdown_signal().await;
+    info!("Shutting down");
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, user_manager.shutdown())
+        .await
+        .is_err()
+    {
+        warn!("Shutdown took longer than {:?}, exiting", SHUTDOWN_TIMEOUT);
     }
 
     Ok(())
 }
 
-pub async fn load_user_from_file<P>(
-    path: P,
-    broadcast_tx: noalbs::ChatSender,
-) -> Result<Noalbs, noalbs::error::Error>
-where
-    P: Into<PathBuf>,
-{
-    let path = path.into();
-    let file = config::File { name: path };
-
-    Noalbs::new(Box::new(file), broadcast_tx).await
-}
+/// Waits for ctrl-c or, on unix, SIGTERM like when a container is stopped
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            eprintln!("Unable to listen for shutdown signal: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
 
-pub async fn load_users_from_dir<P>(
-    dir: P,
-    broadcast_tx: noalbs::ChatSender,
-) -> Result<Vec<Result<Noalbs, noalbs::error::Error>>>
-where
-    P: Into<PathBuf>,
-{
-    let dir = dir.into();
-
-    let noalbs_users = std::fs::read_dir(dir)?
-        .filter_map(|f| f.ok())
-        .map(|f| f.path())
-        .filter(|e| match e.extension() {
-            Some(extension) => extension == "json",
-            None => false,
-        })
-        .map(|p| Noalbs::new(Box::new(config::File { name: p }), broadcast_tx.clone()))
-        .collect::<Vec<_>>();
-
-    let noalbs_users = futures_util::future::join_all(noalbs_users).await;
-
-    Ok(noalbs_users)
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                eprintln!("Unable to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
 
 async fn print_if_new_version() -> Result<(), noalbs::error::Error> {
@@ -0,0 +1,229 @@
+use std::{fmt::Write, net::SocketAddr};
+
+use tracing::{error, info};
+use warp::Filter;
+
+use crate::{
+    state::State, stream_servers::Bitrate, switcher::TriggerType, user_manager::UserManager,
+};
+
+/// Addresses the users enabled the metrics on, users sharing an address
+/// share a single server
+pub async fn addresses(user_manager: &UserManager) -> Vec<SocketAddr> {
+    let mut addresses = Vec::new();
+
+    for user in user_manager.get().read().await.values() {
+        let state = user.state.read().await;
+        let config = &state.config.optional_options.metrics;
+
+        if !config.enabled {
+            continue;
+        }
+
+        match config.address() {
+            Ok(address) if !addresses.contains(&address) => addresses.push(address),
+            Ok(_) => {}
+            Err(e) => error!(
+                "Invalid metrics address {}:{} for {}: {}",
+                config.bind_address, config.port, state.config.user.name, e
+            ),
+        }
+    }
+
+    addresses
+}
+
+/// Serves the prometheus metrics on /metrics of every user that enabled
+/// them on this address
+pub async fn run(address: SocketAddr, user_manager: UserManager) {
+    let metrics = warp::path("metrics")
+        .and(warp::get())
+        .and(warp::any().map(move || user_manager.clone()))
+        .then(move |user_manager: UserManager| async move {
+            warp::reply::with_header(
+                render(&user_manager, address).await,
+                "Content-Type",
+                "text/plain; version=0.0.4",
+            )
+        });
+
+    match warp::serve(metrics).try_bind_ephemeral(address) {
+        Ok((address, server)) => {
+            info!("Serving metrics on http://{}/metrics", address);
+            server.await;
+        }
+        Err(e) => error!("Unable to serve metrics on {}: {}", address, e),
+    }
+}
+
+/// Renders the metrics of the users on the address in the prometheus text format
+async fn render(user_manager: &UserManager, address: SocketAddr) -> String {
+    let users = user_manager
+        .get()
+        .read()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut out = String::new();
+    let mut samples = Samples::default();
+
+    for user in users {
+        let state = user.state.read().await;
+        let config = &state.config.optional_options.metrics;
+
+        if config.enabled && config.address() == Ok(address) {
+            samples.add(&state);
+        }
+    }
+
+    write_help(
+        &mut out,
+        "noalbs_bitrate_kbps",
+        "Bitrate reported by the stream server",
+        "gauge",
+        &samples.bitrate,
+    );
+    write_help(
+        &mut out,
+        "noalbs_rtt_ms",
+        "Round trip time reported by the stream server",
+        "gauge",
+        &samples.rtt,
+    );
+    write_help(
+        &mut out,
+        "noalbs_current_scene",
+        "The scene that is currently active",
+        "gauge",
+        &samples.current_scene,
+    );
+    write_help(
+        &mut out,
+        "noalbs_streaming",
+        "Whether the broadcasting software is streaming",
+        "gauge",
+        &samples.streaming,
+    );
+    write_help(
+        &mut out,
+        "noalbs_trigger_total",
+        "Scene switches caused by a trigger",
+        "counter",
+        &samples.trigger,
+    );
+
+    out
+}
+
+/// Lines of every metric, all users of a metric have to be grouped
+/// under a single HELP and TYPE
+#[derive(Default)]
+struct Samples {
+    bitrate: Vec<String>,
+    rtt: Vec<String>,
+    current_scene: Vec<String>,
+    streaming: Vec<String>,
+    trigger: Vec<String>,
+}
+
+impl Samples {
+    /// Adds the samples of a user, the stream servers aren't requested
+    /// again, it uses what the switcher last polled
+    fn add(&mut self, state: &State) {
+        let user = escape_label(&state.config.user.name);
+
+        for server in state.config.switcher.stream_servers.iter() {
+            if !server.enabled {
+                continue;
+            }
+
+            let name = escape_label(&server.name);
+            let Bitrate { kbps, rtt, .. } = server.last_poll.bitrate();
+
+            self.bitrate.push(format!(
+                "noalbs_bitrate_kbps{{user=\"{}\",server=\"{}\"}} {}",
+                user,
+                name,
+                kbps.unwrap_or(0)
+            ));
+
+            if let Some(rtt) = rtt {
+                self.rtt.push(format!(
+                    "noalbs_rtt_ms{{user=\"{}\",server=\"{}\"}} {}",
+                    user, name, rtt
+                ));
+            }
+        }
+
+        self.current_scene.push(format!(
+            "noalbs_current_scene{{user=\"{}\",scene=\"{}\"}} 1",
+            user,
+            escape_label(&state.broadcasting_software.current_scene)
+        ));
+
+        self.streaming.push(format!(
+            "noalbs_streaming{{user=\"{}\"}} {}",
+            user, state.broadcasting_software.is_streaming as u8
+        ));
+
+        for trigger in TriggerType::ALL {
+            let count = state
+                .switcher_state
+                .triggers_fired
+                .get(&trigger)
+                .copied()
+                .unwrap_or(0);
+
+            self.trigger.push(format!(
+                "noalbs_trigger_total{{user=\"{}\",trigger=\"{}\"}} {}",
+                user,
+                trigger.as_str(),
+                count
+            ));
+        }
+    }
+}
+
+/// Writes the HELP and TYPE of the metric followed by its lines
+fn write_help(out: &mut String, name: &str, help: &str, kind: &str, lines: &[String]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+    for line in lines {
+        let _ = writeln!(out, "{}", line);
+    }
+}
+
+/// Escapes a label value as described in the prometheus exposition format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_label_value() {
+        assert_eq!(escape_label("plain"), "plain");
+        assert_eq!(escape_label("a \"quoted\" name"), "a \\\"quoted\\\" name");
+        assert_eq!(
+            escape_label("back\\slash\nnewline"),
+            "back\\\\slash\\nnewline"
+        );
+    }
+
+    #[test]
+    fn metrics_address() {
+        let mut config = crate::config::Metrics::default();
+        assert_eq!(config.address().unwrap().to_string(), "127.0.0.1:9101");
+
+        config.bind_address = "localhost".to_string();
+        assert!(config.address().is_err());
+    }
+}
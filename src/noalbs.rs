@@ -1,16 +1,27 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::{mpsc, RwLock};
-use tracing::{debug, info};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    broadcasting_software::{obs::Obs, obs_v5::Obsv5, BroadcastingSoftwareLogic},
-    chat, config, error,
+    broadcasting_software::{self, obs::Obs, obs_v5::Obsv5, vmix::Vmix, BroadcastingSoftwareLogic},
+    chat, config, config_watcher, error, preflight, screenshot,
     state::{self, State},
     stream_servers,
     switcher::{self, Switcher},
+    twitch_transcoding,
 };
 
+/// How long the switcher gets to finish its current check when stopping
+const SWITCHER_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the scenes of a new collection get to finish loading
+const COLLECTION_LOAD_TIMEOUT: Duration = Duration::from_secs(15);
+
 /// The state of the current user
 pub type UserState = Arc<RwLock<State>>;
 
@@ -24,8 +35,20 @@ pub struct Noalbs {
     // does this really need to be an option?
     pub switcher_handler: Option<tokio::task::JoinHandle<()>>,
 
+    /// Reloads the config when the file changes
+    pub config_watcher: Option<tokio::task::JoinHandle<()>>,
+
+    /// Checks that Twitch transcodes the stream when it starts
+    pub transcoding_watcher: Option<tokio::task::JoinHandle<()>>,
+
+    /// Tells chat when the connection to OBS is lost and back
+    pub connection_watcher: Option<tokio::task::JoinHandle<()>>,
+
+    /// Sends the stream start and end to the webhook
+    pub stream_state_watcher: Option<tokio::task::JoinHandle<()>>,
+
     /// Used to save the config
-    storage: Box<dyn config::ConfigLogic>,
+    storage: Arc<dyn config::ConfigLogic>,
 }
 
 impl Noalbs {
@@ -56,53 +79,177 @@ impl Noalbs {
         {
             let mut w_state = state.write().await;
 
-            let connection: Box<dyn BroadcastingSoftwareLogic> = match w_state.config.software {
-                config::SoftwareConnection::ObsOld(ref obs_conf) => {
-                    let obs = Obs::new(obs_conf.clone(), state.clone());
-                    Box::new(obs)
-                }
-                config::SoftwareConnection::Obs(ref obs_conf) => {
-                    let obs = Obsv5::new(obs_conf.clone(), state.clone());
-                    Box::new(obs)
+            let software = w_state.config.software.clone();
+
+            let (main, extra) = match software {
+                config::SoftwareConnection::Multiple { instances } => {
+                    let mut instances = instances.into_iter();
+                    let main = instances.next().map(|i| i.software);
+                    (main, instances.collect())
                 }
+                software => (Some(software), Vec::new()),
             };
 
+            let connection = main.and_then(|software| connect_software(software, &state, true));
+
+            for instance in extra {
+                if let Some(connection) = connect_software(instance.software, &state, false) {
+                    w_state
+                        .broadcasting_software
+                        .instances
+                        .insert(instance.name, connection);
+                }
+            }
+
             // Do i need this option here?
-            w_state.broadcasting_software.connection = Some(connection);
+            w_state.broadcasting_software.connection = connection;
         }
 
-        // Add state to any OBS stream servers
-        let obs_state = state.clone();
         {
-            let mut r_state = state.write().await;
-            let stream_servers = &mut r_state.config.switcher.stream_servers;
-
-            for ss in stream_servers {
-                if let Some(obs) = ss
-                    .stream_server
-                    .as_any_mut()
-                    .downcast_mut::<stream_servers::Obs>()
-                {
-                    obs.state = Some(obs_state.clone());
-                    if let Some(scenes) = &ss.override_scenes {
-                        obs.scenes = Some(scenes.to_owned());
-                    }
-                }
-            }
+            let mut w_state = state.write().await;
+            attach_obs_state(&mut w_state.config.switcher.stream_servers, &state);
         }
 
+        let storage: Arc<dyn config::ConfigLogic> = Arc::from(storage);
+
+        let watch_config = { state.read().await.config.optional_options.watch_config };
+        let config_watcher = match storage.path() {
+            Some(path) if watch_config => Some(tokio::spawn(config_watcher::run(
+                storage.clone(),
+                path.to_owned(),
+                state.clone(),
+            ))),
+            _ => None,
+        };
+
+        let transcoding_watcher = Some(tokio::spawn(twitch_transcoding::watch(
+            state.clone(),
+            chat_sender.clone(),
+        )));
+
+        let connection_watcher = Some(tokio::spawn(broadcasting_software::supervisor::watch(
+            state.clone(),
+            chat_sender.clone(),
+        )));
+
+        let stream_state_watcher = Some(tokio::spawn(switcher::watch_stream_state(state.clone())));
+
         let mut user = Self {
             state,
             chat_sender,
             switcher_handler: None,
+            config_watcher,
+            transcoding_watcher,
+            connection_watcher,
+            stream_state_watcher,
             storage,
         };
 
+        let preflight = {
+            user.state
+                .read()
+                .await
+                .config
+                .optional_options
+                .preflight
+                .clone()
+        };
+
+        if preflight.enabled {
+            let report = user.preflight().await;
+            report.log();
+
+            if preflight.required && report.critical_failed() {
+                error!("Startup checks failed, not starting the switcher");
+                return Ok(user);
+            }
+        }
+
         user.start_switcher().await;
 
         Ok(user)
     }
 
+    /// Checks that the broadcasting software connects with all the switching
+    /// scenes, the stats pages of the stream servers can be reached and the
+    /// chat credentials work
+    pub async fn preflight(&self) -> preflight::Report {
+        let mut report = preflight::Report::default();
+        let deadline = Instant::now() + preflight::CONNECT_TIMEOUT;
+
+        while self.state.read().await.broadcasting_software.status != state::ClientStatus::Connected
+            && Instant::now() < deadline
+        {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        let state = self.state.read().await;
+        let bs = &state.broadcasting_software;
+
+        let connection = match &bs.connection {
+            Some(connection) if bs.status == state::ClientStatus::Connected => {
+                report.add("Broadcasting software connects", true, Ok(()));
+                Some(connection)
+            }
+            _ => {
+                report.add(
+                    "Broadcasting software connects",
+                    true,
+                    Err(format!(
+                        "not connected after {:?}",
+                        preflight::CONNECT_TIMEOUT
+                    )),
+                );
+                None
+            }
+        };
+
+        if let Some(connection) = connection {
+            let result = match connection.scene_list().await {
+                Ok(scenes) => {
+                    let missing =
+                        preflight::missing_scenes(&scenes, &state.config.switcher.switching_scenes);
+
+                    if missing.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(format!("missing {}", missing.join(", ")))
+                    }
+                }
+                Err(e) => Err(e.to_string()),
+            };
+
+            report.add("Switching scenes exist", true, result);
+        }
+
+        for server in state
+            .config
+            .switcher
+            .stream_servers
+            .iter()
+            .filter(|s| s.enabled)
+        {
+            server.stream_server.bitrate().await;
+
+            let result = if server.stream_server.request_failed() {
+                Err("stats page unreachable".to_string())
+            } else {
+                Ok(())
+            };
+
+            report.add(format!("Stream server {}", server.name), false, result);
+        }
+
+        if let Some(chat) = &state.config.chat {
+            let platform = chat.platform.kind();
+            let result = preflight::check_chat(platform.clone()).await;
+
+            report.add(format!("Chat {:?}", platform), false, result);
+        }
+
+        report
+    }
+
     pub async fn add_stream_server(&self, stream_server: stream_servers::StreamServer) {
         let mut state = self.state.write().await;
         state.config.switcher.add_stream_server(stream_server);
@@ -115,23 +262,200 @@ impl Noalbs {
         let span = tracing::span!(tracing::Level::INFO, "NOALBS", %user);
         let _enter = span.enter();
 
-        let switcher = Some(Switcher::run(Switcher {
-            state: self.state.clone(),
-            chat_sender: self.chat_sender.clone(),
-        }));
+        if self.state.read().await.config.switcher.dry_run {
+            warn!("Dry run is enabled, the switcher only logs the scenes it would switch to");
+        }
 
-        self.switcher_handler = switcher;
+        self.switcher_handler = Some(Switcher::supervise(
+            self.state.clone(),
+            self.chat_sender.clone(),
+        ));
     }
 
+    /// Stops the switcher between two checks and optionally switches back
+    /// to the stop scene before the connection gets dropped
     pub async fn stop(&self) {
-        let mut state = self.state.write().await;
-        println!("> Stopping NOALBS {}", state.config.user.name);
-        state.broadcasting_software.connection = None;
+        let (user, shutdown) = {
+            let state = self.state.read().await;
+            (
+                state.config.user.name.to_owned(),
+                state.switcher_state.shutdown_notifier(),
+            )
+        };
+        println!("> Stopping NOALBS {}", user);
 
         if let Some(handler) = &self.switcher_handler {
             info!("Stopping switcher");
+            shutdown.notify_one();
+            stop_task(handler, SWITCHER_STOP_TIMEOUT).await;
+        }
+
+        self.switch_to_stop_scene().await;
+
+        let mut state = self.state.write().await;
+        state.broadcasting_software.connection = None;
+        state.broadcasting_software.instances.clear();
+
+        if let Some(handler) = &self.config_watcher {
             handler.abort();
         }
+
+        if let Some(handler) = &self.transcoding_watcher {
+            handler.abort();
+        }
+
+        if let Some(handler) = &self.connection_watcher {
+            handler.abort();
+        }
+
+        if let Some(handler) = &self.stream_state_watcher {
+            handler.abort();
+        }
+    }
+
+    /// Stops everything and saves the config, used when NOALBS is exiting
+    pub async fn shutdown(&self) {
+        self.stop().await;
+
+        if let Err(e) = self.save_config().await {
+            error!("Error saving config on shutdown: {}", e);
+        }
+    }
+
+    /// Leaves OBS on the stop scene instead of the low or offline scene
+    async fn switch_to_stop_scene(&self) {
+        let state = self.state.read().await;
+        let options = &state.config.optional_options;
+
+        if !options.switch_scene_on_stop {
+            return;
+        }
+
+        let scene = options
+            .stop_scene
+            .as_ref()
+            .unwrap_or(&state.config.switcher.switching_scenes.normal);
+
+        if state.broadcasting_software.status != state::ClientStatus::Connected
+            || &state.broadcasting_software.current_scene == scene
+        {
+            return;
+        }
+
+        let connections = state
+            .broadcasting_software
+            .connection
+            .iter()
+            .chain(state.broadcasting_software.instances.values());
+
+        for connection in connections {
+            if let Err(e) = connection.switch_scene(scene).await {
+                error!("Unable to switch to {} when stopping: {}", scene, e);
+            }
+        }
+
+        info!("Switched to {} before stopping", scene);
+    }
+
+    /// Switches to the profile and scene collection with the name, waits
+    /// for the scenes to load and checks the switching scenes against them
+    pub async fn switch_collection(&self, name: &str) -> Result<(), error::Error> {
+        let collection = {
+            let state = self.state.read().await;
+
+            state
+                .config
+                .software
+                .obs_config()
+                .and_then(|o| o.collections.as_ref())
+                .and_then(|c| c.get(&name.to_lowercase()))
+                .cloned()
+                .ok_or_else(|| error::Error::CollectionNotFound(name.to_owned()))?
+        };
+
+        {
+            let state = self.state.read().await;
+            let bsc = state
+                .broadcasting_software
+                .connection
+                .as_ref()
+                .ok_or(error::Error::UnableInitialConnection)?;
+
+            bsc.set_collection_and_profile(&collection).await?;
+        }
+
+        let scenes = self
+            .wait_for_scenes()
+            .await
+            .ok_or_else(|| error::Error::CollectionNotLoaded(name.to_owned()))?;
+
+        let current_scene = {
+            let state = self.state.read().await;
+
+            let switcher = &state.config.switcher;
+            let switching_scenes = std::iter::once(&switcher.switching_scenes).chain(
+                switcher
+                    .stream_servers
+                    .iter()
+                    .filter_map(|s| s.override_scenes.as_ref()),
+            );
+
+            for switching_scenes in switching_scenes {
+                for missing in preflight::missing_scenes(&scenes, switching_scenes) {
+                    warn!("Collection {} doesn't have the scene {}", name, missing);
+                }
+            }
+
+            match &state.broadcasting_software.connection {
+                Some(bsc) => bsc.current_scene().await.ok(),
+                None => None,
+            }
+        };
+
+        if let Some(scene) = current_scene {
+            self.state
+                .write()
+                .await
+                .broadcasting_software
+                .set_current_scene(scene);
+        }
+
+        info!("Switched to collection {}", name);
+        Ok(())
+    }
+
+    /// OBS reloads the scenes after switching collections, the scene list
+    /// is settled once it's the same twice in a row
+    async fn wait_for_scenes(&self) -> Option<Vec<String>> {
+        let deadline = Instant::now() + COLLECTION_LOAD_TIMEOUT;
+        let mut previous = None;
+
+        while Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let scenes = {
+                let state = self.state.read().await;
+                match &state.broadcasting_software.connection {
+                    Some(bsc) => bsc.scene_list().await,
+                    None => continue,
+                }
+            };
+
+            match scenes {
+                Ok(scenes) if !scenes.is_empty() && previous.as_ref() == Some(&scenes) => {
+                    return Some(scenes);
+                }
+                Ok(scenes) => previous = Some(scenes),
+                Err(e) => debug!("Scenes not loaded yet: {}", e),
+            }
+        }
+
+        None
+    }
+
+    /// Reloads the config from storage, see [`config_watcher::reload`]
+    pub async fn reload_config(&self) -> Result<Option<Vec<&'static str>>, error::Error> {
+        config_watcher::reload(self.storage.as_ref(), &self.state).await
     }
 
     pub async fn save_config(&self) -> Result<(), error::Error> {
@@ -142,22 +466,12 @@ impl Noalbs {
     pub async fn contains_alias(&self, alias: &str) -> Result<bool, error::Error> {
         let state = self.state.read().await;
         let chat = &state.config.chat.as_ref().ok_or(error::Error::NoChat)?;
-        let commands = &chat.commands;
 
-        if commands.is_none() {
-            return Ok(false);
-        }
-
-        let commands = commands.as_ref().unwrap();
-
-        let contains = commands.iter().any(|(_, v)| match &v.alias {
-            Some(vec_alias) => vec_alias.iter().any(|a| a == alias),
-            None => false,
-        });
-
-        Ok(contains)
+        Ok(chat.contains_alias(alias))
     }
 
+    /// Adds the alias to the command, the alias can't already be used
+    /// by another command or be the name of a command
     pub async fn add_alias(
         &self,
         alias: String,
@@ -166,6 +480,17 @@ impl Noalbs {
         let mut state = self.state.write().await;
         let chat = state.config.chat.as_mut().ok_or(error::Error::NoChat)?;
 
+        if chat.contains_alias(&alias) {
+            return Err(error::Error::AliasAlreadyUsed(alias));
+        }
+
+        if !matches!(
+            chat::Command::from(alias.as_str()),
+            chat::Command::Unknown(_)
+        ) {
+            return Err(error::Error::AliasIsCommand(alias));
+        }
+
         let commands = chat.commands.get_or_insert(HashMap::new());
         let command = commands.entry(command).or_insert(config::CommandInfo {
             alias: Some(Vec::new()),
@@ -221,20 +546,54 @@ impl Noalbs {
 
     pub async fn get_trigger_by_type(&self, kind: switcher::TriggerType) -> Option<u32> {
         let state = &self.state.read().await;
-        let triggers = &state.config.switcher.triggers;
 
-        match kind {
-            switcher::TriggerType::Low => triggers.low,
-            switcher::TriggerType::Rtt => triggers.rtt,
-            switcher::TriggerType::Offline => triggers.offline,
-            switcher::TriggerType::RttOffline => triggers.rtt_offline,
-        }
+        state.config.switcher.triggers.get(kind)
+    }
+
+    pub async fn get_triggers(&self) -> switcher::Triggers {
+        self.state.read().await.config.switcher.triggers.clone()
+    }
+
+    /// Keeps a copy of the current triggers to go back to, replaces an
+    /// earlier copy. Returns the saved triggers
+    pub async fn save_triggers(&self) -> switcher::Triggers {
+        let mut state = self.state.write().await;
+        let triggers = state.config.switcher.triggers.clone();
+
+        state.switcher_state.triggers_snapshot = Some(triggers.clone());
+
+        triggers
     }
 
-    pub async fn update_trigger(&self, kind: switcher::TriggerType, value: u32) -> Option<u32> {
+    /// Puts the saved triggers back all at once, the copy is kept so it
+    /// can be restored again. Returns none when nothing was saved
+    pub async fn restore_triggers(&self) -> Option<switcher::Triggers> {
+        let mut state = self.state.write().await;
+        let triggers = state.switcher_state.triggers_snapshot.clone()?;
+
+        state.config.switcher.triggers = triggers.clone();
+        info!("Restored the saved triggers");
+
+        Some(triggers)
+    }
+
+    /// Sets the trigger, 0 disables it. Returns the value that was set,
+    /// values outside the trigger bounds are rejected
+    pub async fn update_trigger(
+        &self,
+        kind: switcher::TriggerType,
+        value: u32,
+    ) -> Result<Option<u32>, error::Error> {
         let mut state = self.state.write().await;
-        let triggers = &mut state.config.switcher.triggers;
+        let switcher = &mut state.config.switcher;
+
+        let bounds = switcher.trigger_bounds.get(kind);
+
+        if value != 0 && !bounds.contains(value) {
+            return Err(error::Error::TriggerOutOfRange(bounds.min, bounds.max));
+        }
 
+        let triggers = &mut switcher.triggers;
         let real_value = if value == 0 { None } else { Some(value) };
 
         match kind {
@@ -242,9 +601,11 @@ impl Noalbs {
             switcher::TriggerType::Rtt => triggers.rtt = real_value,
             switcher::TriggerType::Offline => triggers.offline = real_value,
             switcher::TriggerType::RttOffline => triggers.rtt_offline = real_value,
+            switcher::TriggerType::DroppedFrames => triggers.dropped_frames = real_value,
+            switcher::TriggerType::PacketLoss => triggers.packet_loss = real_value,
         }
 
-        real_value
+        Ok(real_value)
     }
 
     pub async fn get_autostop(&self) -> Result<bool, error::Error> {
@@ -317,16 +678,40 @@ impl Noalbs {
         state.config.switcher.auto_switch_notification = enabled;
     }
 
+    /// Mutes the auto switch notifications for a while, unmutes when `None`
+    pub async fn mute_notifications(&self, duration: Option<Duration>) {
+        let mut state = self.state.write().await;
+
+        state.switcher_state.notifications_muted_until =
+            duration.and_then(|d| Instant::now().checked_add(d));
+    }
+
+    pub async fn notifications_muted(&self) -> Option<Duration> {
+        let state = self.state.read().await;
+
+        state.switcher_state.notifications_muted()
+    }
+
     pub async fn get_retry_attempts(&self) -> u8 {
         let state = self.state.read().await;
 
         state.config.switcher.retry_attempts
     }
 
-    pub async fn set_retry_attempts(&self, value: u8) {
-        let mut state = self.state.write().await;
+    pub async fn set_retry_attempts(&self, value: u8) -> Result<u8, error::Error> {
+        let range = config::RETRY_ATTEMPTS_RANGE;
+
+        if !range.contains(&value) {
+            return Err(error::Error::RetryAttemptsOutOfRange(
+                *range.start(),
+                *range.end(),
+            ));
+        }
 
+        let mut state = self.state.write().await;
         state.config.switcher.retry_attempts = value;
+
+        Ok(value)
     }
 
     pub async fn set_prefix(&self, prefix: String) -> Result<(), error::Error> {
@@ -338,16 +723,298 @@ impl Noalbs {
         Ok(())
     }
 
-    pub async fn set_bitrate_switcher_state(&self, enabled: bool) {
+    /// Enables or disables the switcher and saves the config so the
+    /// state survives a restart
+    pub async fn set_bitrate_switcher_state(&self, enabled: bool) -> Result<(), error::Error> {
+        {
+            let mut state = self.state.write().await;
+
+            state.config.switcher.set_bitrate_switcher_enabled(enabled);
+
+            if enabled {
+                state
+                    .switcher_state
+                    .switcher_enabled_notifier()
+                    .notify_waiters();
+            }
+        }
+
+        self.save_config().await
+    }
+
+    /// Enables or disables the stream server, returns its name as configured
+    pub async fn set_stream_server_enabled(
+        &self,
+        name: &str,
+        enabled: bool,
+    ) -> Result<String, error::Error> {
         let mut state = self.state.write().await;
 
-        state.config.switcher.set_bitrate_switcher_enabled(enabled);
+        let server = state
+            .config
+            .switcher
+            .stream_servers
+            .iter_mut()
+            .find(|s| s.name.eq_ignore_ascii_case(name))
+            .ok_or(error::Error::NoStreamServerFound)?;
+
+        server.enabled = enabled;
 
-        if enabled {
-            state
+        Ok(server.name.to_owned())
+    }
+
+    /// Renames the stream server, returns the old name as configured
+    pub async fn rename_stream_server(
+        &self,
+        name: &str,
+        new_name: &str,
+    ) -> Result<String, error::Error> {
+        let mut state = self.state.write().await;
+
+        let old_name = state.config.switcher.rename_stream_server(name, new_name)?;
+
+        let switcher_state = &mut state.switcher_state;
+        switcher_state.bitrate_history.rename(&old_name, new_name);
+
+        if switcher_state.last_used_server.as_deref() == Some(old_name.as_str()) {
+            switcher_state.last_used_server = Some(new_name.to_owned());
+        }
+
+        Ok(old_name)
+    }
+
+    /// Switches to the scene, returns the name of the scene in the software
+    pub async fn switch_scene(&self, scene: &str) -> Result<String, error::Error> {
+        switch_scene(&self.state, scene).await
+    }
+
+    /// Shows the refresh scene for a moment to reconnect the media sources,
+    /// returns the scene it switched back to
+    pub async fn refresh(&self) -> Result<String, error::Error> {
+        refresh(&self.state).await
+    }
+
+    /// Takes a screenshot of the program scene and saves or uploads it,
+    /// returns the link
+    pub async fn screenshot(&self) -> Result<String, error::Error> {
+        let (config, user) = {
+            let state = self.state.read().await;
+            (
+                state.config.optional_options.screenshot.clone(),
+                state.config.user.name.to_owned(),
+            )
+        };
+
+        if !config.enabled() {
+            return Err(error::Error::NoScreenshotTarget);
+        }
+
+        let image = {
+            let state = self.state.read().await;
+            let bsc = state
+                .broadcasting_software
+                .connection
+                .as_ref()
+                .ok_or(error::Error::NoSoftwareSet)?;
+
+            let scene = bsc.current_scene().await?;
+            bsc.screenshot(&scene, Some(config.width)).await?
+        };
+
+        screenshot::publish(&config, &user, image).await
+    }
+
+    /// Switches to the privacy scene and pauses switching until privacy ends,
+    /// returns the privacy scene
+    pub async fn enable_privacy(&self) -> Result<String, error::Error> {
+        let (scene, timeout) = {
+            let state = self.state.read().await;
+            let scene = state
+                .config
+                .optional_scenes
+                .privacy
+                .to_owned()
+                .ok_or(error::Error::NoPrivacyScene)?;
+
+            (scene, state.config.optional_options.privacy_timeout)
+        };
+
+        let since = Instant::now();
+
+        {
+            let mut state = self.state.write().await;
+            let previous_scene = state.broadcasting_software.prev_scene.to_owned();
+
+            state.switcher_state.privacy = Some(state::Privacy {
+                previous_scene,
+                since,
+            });
+        }
+
+        if let Err(e) = self.switch_scene(&scene).await {
+            self.state.write().await.switcher_state.privacy = None;
+            return Err(e);
+        }
+
+        if let Some(minutes) = timeout {
+            let state = self.state.clone();
+
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(u64::from(minutes) * 60)).await;
+
+                match end_privacy(&state, Some(since)).await {
+                    Ok(Some(scene)) => info!("Privacy timed out, switched back to {}", scene),
+                    Ok(None) => {}
+                    Err(e) => error!("Unable to switch back from the privacy scene: {}", e),
+                }
+            });
+        }
+
+        Ok(scene)
+    }
+
+    /// Switches back to the scene from before privacy and resumes switching,
+    /// returns the scene or none when privacy wasn't active
+    pub async fn disable_privacy(&self) -> Result<Option<String>, error::Error> {
+        end_privacy(&self.state, None).await
+    }
+
+    pub async fn privacy_active(&self) -> bool {
+        self.state.read().await.switcher_state.privacy.is_some()
+    }
+
+    /// Switches to the scene and ignores the triggers until maintenance mode
+    /// is turned off. Uses the starting scene or the current scene when no
+    /// scene is given, returns the held scene
+    pub async fn enable_maintenance(&self, scene: Option<String>) -> Result<String, error::Error> {
+        let (scene, previous_scene) = {
+            let state = self.state.read().await;
+            let current_scene = state.broadcasting_software.current_scene.to_owned();
+
+            let scene = scene
+                .or_else(|| state.config.optional_scenes.starting.to_owned())
+                .unwrap_or_else(|| current_scene.to_owned());
+
+            // Keep the original scene when the held scene changes
+            let previous_scene = match &state.switcher_state.maintenance {
+                Some(maintenance) => maintenance.previous_scene.to_owned(),
+                None => current_scene,
+            };
+
+            (scene, previous_scene)
+        };
+
+        let previous =
+            self.state
+                .write()
+                .await
                 .switcher_state
-                .switcher_enabled_notifier()
-                .notify_waiters();
+                .maintenance
+                .replace(state::Maintenance {
+                    scene: scene.to_owned(),
+                    previous_scene,
+                });
+
+        if let Err(e) = self.switch_scene(&scene).await {
+            self.state.write().await.switcher_state.maintenance = previous;
+            return Err(e);
+        }
+
+        info!("Maintenance mode enabled, holding {}", scene);
+
+        Ok(scene)
+    }
+
+    /// Switches back to the scene from before maintenance mode and resumes
+    /// switching, returns the scene or none when maintenance wasn't active
+    pub async fn disable_maintenance(&self) -> Result<Option<String>, error::Error> {
+        let Some(maintenance) = self.state.write().await.switcher_state.maintenance.take() else {
+            return Ok(None);
+        };
+
+        info!("Maintenance mode disabled");
+
+        self.switch_scene(&maintenance.previous_scene)
+            .await
+            .map(Some)
+    }
+
+    /// The scene held by maintenance mode
+    pub async fn maintenance_scene(&self) -> Option<String> {
+        self.state
+            .read()
+            .await
+            .switcher_state
+            .maintenance
+            .as_ref()
+            .map(|m| m.scene.to_owned())
+    }
+
+    /// Switches to the live scene and keeps it over the low scene for a while,
+    /// uses the configured duration when none is given. The offline scene
+    /// still ends it, returns the scene and how long it's kept
+    pub async fn force_live(
+        &self,
+        duration: Option<Duration>,
+    ) -> Result<(String, Duration), error::Error> {
+        let (scene, duration) = {
+            let state = self.state.read().await;
+            let seconds = state.config.optional_options.force_live_seconds;
+
+            (
+                state.config.switcher.switching_scenes.normal.to_owned(),
+                duration.unwrap_or(Duration::from_secs(seconds)),
+            )
+        };
+
+        let previous = std::mem::replace(
+            &mut self.state.write().await.switcher_state.force_live_until,
+            Instant::now().checked_add(duration),
+        );
+
+        if let Err(e) = self.switch_scene(&scene).await {
+            self.state.write().await.switcher_state.force_live_until = previous;
+            return Err(e);
+        }
+
+        info!("Forcing {} for {:?}", scene, duration);
+
+        Ok((scene, duration))
+    }
+
+    /// Lets the switcher decide again, returns whether the live scene was forced
+    pub async fn end_force_live(&self) -> bool {
+        let mut state = self.state.write().await;
+        let active = state
+            .switcher_state
+            .force_live_remaining(Instant::now())
+            .is_some();
+        state.switcher_state.force_live_until = None;
+
+        active
+    }
+
+    pub async fn force_live_remaining(&self) -> Option<Duration> {
+        let state = self.state.read().await;
+
+        state.switcher_state.force_live_remaining(Instant::now())
+    }
+
+    /// Live state reported by the chat platform, switching waits while the
+    /// platform says the channel is offline
+    pub async fn set_platform_stream_online(&self, online: Option<bool>) {
+        let mut state = self.state.write().await;
+        let switcher_state = &mut state.switcher_state;
+
+        if switcher_state.platform_stream_online == online {
+            return;
+        }
+
+        debug!("Platform stream online: {:?}", online);
+        switcher_state.platform_stream_online = online;
+
+        if online != Some(false) {
+            switcher_state.platform_online_notifier().notify_one();
         }
     }
 
@@ -384,6 +1051,54 @@ impl Noalbs {
         Ok(())
     }
 
+    /// Sets up chat with the default settings when the user started without
+    /// it, the chat handler joins the channel if the platform is connected
+    pub async fn enable_chat(
+        &self,
+        platform: config::ConfigChatPlatform,
+        username: String,
+    ) -> Result<(), error::Error> {
+        let mut username = username.trim().to_owned();
+        username.make_ascii_lowercase();
+
+        if username.is_empty() {
+            return Err(error::Error::InvalidConfig(
+                "chat username can't be empty".to_string(),
+            ));
+        }
+
+        {
+            let mut state = self.state.write().await;
+
+            if state.config.chat.is_some() {
+                return Err(error::Error::ChatAlreadyEnabled);
+            }
+
+            state.config.chat = Some(config::Chat {
+                platform: platform.clone(),
+                username: username.to_owned(),
+                ..Default::default()
+            });
+        }
+
+        if let Err(e) = self.save_config().await {
+            self.state.write().await.config.chat = None;
+            return Err(e);
+        }
+
+        info!("Enabled {:?} chat for {}", platform.kind(), username);
+
+        let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+            platform: platform.kind(),
+            channel: username,
+            kind: chat::InternalUpdate::ChatEnabled,
+        });
+
+        let _ = self.chat_sender.send(message).await;
+
+        Ok(())
+    }
+
     pub async fn remove_event_sender(&self, token: &str) {
         let mut state = self.state.write().await;
         let pos = state
@@ -414,5 +1129,323 @@ impl Drop for Noalbs {
         if let Some(handler) = &self.switcher_handler {
             handler.abort();
         }
+
+        if let Some(handler) = &self.config_watcher {
+            handler.abort();
+        }
+
+        if let Some(handler) = &self.transcoding_watcher {
+            handler.abort();
+        }
+
+        if let Some(handler) = &self.connection_watcher {
+            handler.abort();
+        }
+
+        if let Some(handler) = &self.stream_state_watcher {
+            handler.abort();
+        }
+    }
+}
+
+async fn switch_scene(state: &UserState, scene: &str) -> Result<String, error::Error> {
+    let switched = state
+        .read()
+        .await
+        .broadcasting_software
+        .connection
+        .as_ref()
+        .ok_or(error::Error::NoSoftwareSet)?
+        .switch_scene(scene)
+        .await?;
+
+    switch_instance(state, scene).await;
+
+    Ok(switched)
+}
+
+/// Switches the scene on the extra OBS instance of the stream server
+/// that was used last, if it has one
+pub async fn switch_instance(state: &UserState, scene: &str) {
+    let Some((instance, connection)) = state.read().await.active_instance() else {
+        return;
+    };
+
+    if let Err(error) = connection.switch_scene(scene).await {
+        error!("Switch scene error on instance {} {:?}", instance, error);
+        return;
+    }
+
+    info!("Scene on instance {} switched to {}", instance, scene);
+}
+
+/// Switches to the refresh scene and back to the current scene after the
+/// refresh interval, OBS reconnects the media sources that aren't in the
+/// refresh scene
+pub async fn refresh(state: &UserState) -> Result<String, error::Error> {
+    let (scene, previous_scene, interval) = {
+        let state = state.read().await;
+        let scene = state
+            .config
+            .optional_scenes
+            .refresh
+            .to_owned()
+            .ok_or(error::Error::NoRefreshScene)?;
+        let interval = state.config.optional_options.refresh_interval_seconds;
+
+        (
+            scene,
+            state.broadcasting_software.current_scene.to_owned(),
+            Duration::from_secs(interval),
+        )
+    };
+
+    switch_scene(state, &scene).await?;
+    tokio::time::sleep(interval).await;
+    switch_scene(state, &previous_scene).await
+}
+
+/// Ends privacy, only when it started at `since` if given so an auto revert
+/// doesn't end a later privacy
+async fn end_privacy(
+    state: &UserState,
+    since: Option<Instant>,
+) -> Result<Option<String>, error::Error> {
+    let privacy = {
+        let mut state = state.write().await;
+        let switcher_state = &mut state.switcher_state;
+
+        match &switcher_state.privacy {
+            Some(privacy) if since.map_or(true, |since| since == privacy.since) => {}
+            _ => return Ok(None),
+        }
+
+        let privacy = switcher_state.privacy.take();
+        switcher_state.privacy_ended_notifier().notify_one();
+        privacy
+    };
+
+    let Some(privacy) = privacy else {
+        return Ok(None);
+    };
+
+    switch_scene(state, &privacy.previous_scene).await.map(Some)
+}
+
+/// Gives the task time to finish on its own, aborts it after the timeout
+async fn stop_task(handler: &tokio::task::JoinHandle<()>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+
+    while !handler.is_finished() {
+        if Instant::now() >= deadline {
+            error!("Task didn't stop in {:?}, aborting", timeout);
+            handler.abort();
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Adds the state to any OBS stream servers
+pub fn attach_obs_state(servers: &mut [stream_servers::StreamServer], state: &UserState) {
+    for ss in servers {
+        if let Some(obs) = ss
+            .stream_server
+            .as_any_mut()
+            .downcast_mut::<stream_servers::Obs>()
+        {
+            obs.state = Some(state.clone());
+            if let Some(scenes) = &ss.override_scenes {
+                obs.scenes = Some(scenes.to_owned());
+            }
+        }
+    }
+}
+
+/// Connects to the broadcasting software, only the primary connection updates the state
+fn connect_software(
+    software: config::SoftwareConnection,
+    state: &UserState,
+    primary: bool,
+) -> Option<Arc<dyn BroadcastingSoftwareLogic>> {
+    let connection: Arc<dyn BroadcastingSoftwareLogic> = match (software, primary) {
+        (config::SoftwareConnection::ObsOld(obs_conf), true) => {
+            Arc::new(Obs::new(obs_conf, state.clone()))
+        }
+        (config::SoftwareConnection::ObsOld(obs_conf), false) => {
+            Arc::new(Obs::new_secondary(obs_conf, state.clone()))
+        }
+        (config::SoftwareConnection::Obs(obs_conf), true) => {
+            Arc::new(Obsv5::new(obs_conf, state.clone()))
+        }
+        (config::SoftwareConnection::Obs(obs_conf), false) => {
+            Arc::new(Obsv5::new_secondary(obs_conf, state.clone()))
+        }
+        (config::SoftwareConnection::Vmix(vmix_conf), true) => {
+            Arc::new(Vmix::new(vmix_conf, state.clone()))
+        }
+        (config::SoftwareConnection::Vmix(vmix_conf), false) => {
+            Arc::new(Vmix::new_secondary(vmix_conf, state.clone()))
+        }
+        (config::SoftwareConnection::Multiple { .. }, _) => {
+            error!("Multiple OBS instances can't be nested");
+            return None;
+        }
+    };
+
+    Some(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MemoryStorage;
+
+    impl config::ConfigLogic for MemoryStorage {
+        fn load(&self) -> Result<config::Config, error::Error> {
+            Ok(serde_json::from_str(include_str!("../config.json"))?)
+        }
+
+        fn save(&self, _: &config::Config) -> Result<(), error::Error> {
+            Ok(())
+        }
+    }
+
+    fn user() -> Noalbs {
+        let (chat_sender, _) = mpsc::channel(1);
+
+        Noalbs {
+            state: Arc::new(RwLock::new(State {
+                config: MemoryStorage.load().unwrap(),
+                switcher_state: state::SwitcherState::default(),
+                broadcasting_software: state::BroadcastingSoftwareState::default(),
+                event_senders: Vec::new(),
+            })),
+            chat_sender,
+            switcher_handler: None,
+            config_watcher: None,
+            transcoding_watcher: None,
+            connection_watcher: None,
+            stream_state_watcher: None,
+            storage: Arc::new(MemoryStorage),
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_duplicate_alias() {
+        let user = user();
+
+        user.add_alias("b2".to_string(), chat::Command::Bitrate)
+            .await
+            .unwrap();
+        assert!(user.contains_alias("b2").await.unwrap());
+
+        assert!(matches!(
+            user.add_alias("b2".to_string(), chat::Command::Fix).await,
+            Err(error::Error::AliasAlreadyUsed(a)) if a == "b2"
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_alias_named_like_a_command() {
+        let user = user();
+
+        assert!(matches!(
+            user.add_alias("switch".to_string(), chat::Command::Bitrate)
+                .await,
+            Err(error::Error::AliasIsCommand(a)) if a == "switch"
+        ));
+        assert!(!user.contains_alias("switch").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn enable_chat_only_without_chat() {
+        let user = user();
+        let twitch = config::ConfigChatPlatform::Twitch;
+
+        assert!(matches!(
+            user.enable_chat(twitch.clone(), "someone".to_string())
+                .await,
+            Err(error::Error::ChatAlreadyEnabled)
+        ));
+
+        user.state.write().await.config.chat = None;
+        assert!(matches!(
+            user.set_autostop(true).await,
+            Err(error::Error::NoChat)
+        ));
+
+        user.enable_chat(twitch, " someone ".to_string())
+            .await
+            .unwrap();
+
+        let state = user.state.read().await;
+        let chat = state.config.chat.as_ref().unwrap();
+        assert_eq!(chat.username, "someone");
+        assert_eq!(chat.prefix, "!");
+    }
+
+    #[tokio::test]
+    async fn enabled_chat_is_found_by_lowercase_channel() {
+        let user_manager = crate::user_manager::UserManager::new();
+        let user = user_manager.add(user()).await.unwrap();
+
+        user.state.write().await.config.chat = None;
+        user.enable_chat(config::ConfigChatPlatform::Twitch, "MyChannel".to_string())
+            .await
+            .unwrap();
+
+        let found = user_manager
+            .get_user_by_chat_platform("mychannel", &chat::ChatPlatform::Twitch)
+            .await;
+
+        assert!(found.is_some_and(|found| Arc::ptr_eq(&found, &user)));
+    }
+
+    #[tokio::test]
+    async fn reject_retry_attempts_out_of_range() {
+        let user = user();
+
+        assert!(matches!(
+            user.set_retry_attempts(0).await,
+            Err(error::Error::RetryAttemptsOutOfRange(1, 30))
+        ));
+        assert!(matches!(
+            user.set_retry_attempts(31).await,
+            Err(error::Error::RetryAttemptsOutOfRange(1, 30))
+        ));
+        assert_eq!(user.get_retry_attempts().await, 5);
+
+        assert_eq!(user.set_retry_attempts(12).await.unwrap(), 12);
+        assert_eq!(user.get_retry_attempts().await, 12);
+    }
+
+    #[tokio::test]
+    async fn reject_trigger_out_of_range() {
+        let user = user();
+
+        assert!(matches!(
+            user.update_trigger(switcher::TriggerType::Low, 80000).await,
+            Err(error::Error::TriggerOutOfRange(1, 20000))
+        ));
+        assert!(matches!(
+            user.update_trigger(switcher::TriggerType::Rtt, 6000).await,
+            Err(error::Error::TriggerOutOfRange(1, 5000))
+        ));
+
+        assert_eq!(
+            user.update_trigger(switcher::TriggerType::Low, 800)
+                .await
+                .unwrap(),
+            Some(800)
+        );
+        assert_eq!(
+            user.update_trigger(switcher::TriggerType::Low, 0)
+                .await
+                .unwrap(),
+            None
+        );
     }
 }
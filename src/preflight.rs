@@ -0,0 +1,132 @@
+use std::{env, time::Duration};
+
+use tracing::{error, info};
+
+use crate::{chat::ChatPlatform, switcher::SwitchingScenes};
+
+/// How long to wait for the broadcasting software to connect
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Result of a single startup check
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub result: Result<(), String>,
+
+    /// The switcher can't do anything useful when this check fails
+    pub critical: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn add(&mut self, name: impl Into<String>, critical: bool, result: Result<(), String>) {
+        self.checks.push(Check {
+            name: name.into(),
+            result,
+            critical,
+        });
+    }
+
+    pub fn critical_failed(&self) -> bool {
+        self.checks.iter().any(|c| c.critical && c.result.is_err())
+    }
+
+    pub fn log(&self) {
+        for check in &self.checks {
+            match &check.result {
+                Ok(()) => info!("[PASS] {}", check.name),
+                Err(reason) => error!("[FAIL] {}: {}", check.name, reason),
+            }
+        }
+    }
+}
+
+/// Finds the switching scenes that don't exist, with a hint when
+/// only the capitalization is different
+pub fn missing_scenes(scenes: &[String], switching_scenes: &SwitchingScenes) -> Vec<String> {
+    switching_scenes
+        .all()
+        .filter(|wanted| !scenes.contains(wanted))
+        .map(|wanted| {
+            match scenes
+                .iter()
+                .find(|scene| scene.to_lowercase() == wanted.to_lowercase())
+            {
+                Some(scene) => format!("\"{}\" (did you mean \"{}\"?)", wanted, scene),
+                None => format!("\"{}\"", wanted),
+            }
+        })
+        .collect()
+}
+
+/// Checks the chat credentials, only Twitch can be verified without
+/// connecting so the others only check they're set
+pub async fn check_chat(platform: ChatPlatform) -> Result<(), String> {
+    let required = match platform {
+        ChatPlatform::Twitch => &["TWITCH_BOT_USERNAME", "TWITCH_BOT_OAUTH"][..],
+        ChatPlatform::Discord => &["DISCORD_BOT_TOKEN"][..],
+        ChatPlatform::Trovo => &["TROVO_CLIENT_ID", "TROVO_ACCESS_TOKEN"][..],
+        ChatPlatform::Youtube => &["YOUTUBE_CHANNEL_ID"][..],
+        ChatPlatform::Kick => &[][..],
+    };
+
+    if let Some(missing) = required.iter().find(|var| env::var(var).is_err()) {
+        return Err(format!("{} is not set", missing));
+    }
+
+    if platform == ChatPlatform::Twitch {
+        let oauth = env::var("TWITCH_BOT_OAUTH").unwrap_or_default();
+        return validate_twitch_token(oauth.trim_start_matches("oauth:")).await;
+    }
+
+    Ok(())
+}
+
+async fn validate_twitch_token(token: &str) -> Result<(), String> {
+    let res = reqwest::Client::new()
+        .get("https://id.twitch.tv/oauth2/validate")
+        .header("Authorization", format!("OAuth {}", token))
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| format!("unable to reach Twitch: {}", e))?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => Ok(()),
+        reqwest::StatusCode::UNAUTHORIZED => Err("TWITCH_BOT_OAUTH is invalid or expired".into()),
+        status => Err(format!("Twitch answered with {}", status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_scenes_hint_at_capitalization() {
+        let scenes = vec!["Live".to_string(), "low".to_string(), "BRB".to_string()];
+        let switching_scenes = SwitchingScenes::new("live", "low", "offline");
+
+        assert_eq!(
+            missing_scenes(&scenes, &switching_scenes),
+            vec![
+                "\"live\" (did you mean \"Live\"?)".to_string(),
+                "\"offline\"".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn critical_failure() {
+        let mut report = Report::default();
+        report.add("Stream server", false, Err("unreachable".to_string()));
+        assert!(!report.critical_failed());
+
+        report.add("OBS", true, Err("not connected".to_string()));
+        assert!(report.critical_failed());
+    }
+}
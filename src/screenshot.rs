@@ -0,0 +1,147 @@
+//! Saves or uploads screenshots of the program scene so mods that can't
+//! watch the stream can check the feed isn't frozen.
+
+use std::{path::Path, time::Duration};
+
+use once_cell::sync::Lazy;
+use rand::Rng;
+use tracing::{debug, error};
+
+use crate::{config, error::Error, webhook};
+
+/// Saved screenshots start with this, only those are served and removed
+const FILE_PREFIX: &str = "noalbs-";
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to create reqwest client")
+});
+
+/// Uploads or saves the screenshot, returns the link to post in chat
+pub async fn publish(
+    config: &config::Screenshot,
+    user: &str,
+    image: Vec<u8>,
+) -> Result<String, Error> {
+    if let Some(url) = &config.upload_url {
+        return upload(url, image).await;
+    }
+
+    let directory = config.directory.as_ref().ok_or(Error::NoScreenshotTarget)?;
+
+    let name = file_name(webhook::timestamp(), rand::thread_rng().gen());
+    tokio::fs::create_dir_all(directory).await?;
+    tokio::fs::write(Path::new(directory).join(&name), image).await?;
+    debug!("Saved screenshot {}", name);
+
+    if let Err(e) = prune(directory, config.keep).await {
+        error!("Unable to remove old screenshots: {}", e);
+    }
+
+    Ok(link(config.public_url.as_deref(), user, &name))
+}
+
+async fn upload(url: &str, image: Vec<u8>) -> Result<String, Error> {
+    let res = CLIENT
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "image/jpeg")
+        .body(image)
+        .send()
+        .await?;
+
+    if !res.status().is_success() {
+        return Err(Error::ScreenshotUpload(res.status().as_u16()));
+    }
+
+    let link = res.text().await?.trim().to_owned();
+
+    if link.is_empty() {
+        return Err(Error::ScreenshotUpload(200));
+    }
+
+    Ok(link)
+}
+
+/// Removes the oldest screenshots until only `keep` are left
+async fn prune(directory: &str, keep: usize) -> Result<(), Error> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(directory).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str().filter(|n| is_screenshot(n)) {
+            names.push(name.to_owned());
+        }
+    }
+
+    // The timestamp makes the names sort from old to new
+    names.sort();
+
+    let remove = names.len().saturating_sub(keep);
+
+    for name in &names[..remove] {
+        tokio::fs::remove_file(Path::new(directory).join(name)).await?;
+    }
+
+    Ok(())
+}
+
+fn file_name(timestamp: u64, token: u32) -> String {
+    format!("{}{:010}-{:08x}.jpg", FILE_PREFIX, timestamp, token)
+}
+
+/// Whether the name is one of the saved screenshots, anything else is never served
+pub fn is_screenshot(name: &str) -> bool {
+    name.starts_with(FILE_PREFIX)
+        && name.ends_with(".jpg")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && !name.contains("..")
+}
+
+/// Link to a saved screenshot, only the file name without a public URL
+fn link(public_url: Option<&str>, user: &str, name: &str) -> String {
+    let Some(mut url) = public_url.and_then(|u| reqwest::Url::parse(u).ok()) else {
+        return name.to_owned();
+    };
+
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().extend(["screenshots", user, name]);
+    }
+
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_saved_screenshots_are_served() {
+        let name = file_name(1_700_000_000, 0xbeef);
+
+        assert_eq!(name, "noalbs-1700000000-0000beef.jpg");
+        assert!(is_screenshot(&name));
+        assert!(!is_screenshot("config.json"));
+        assert!(!is_screenshot("noalbs-../config.jpg"));
+        assert!(!is_screenshot("noalbs-1/../../a.jpg"));
+    }
+
+    #[test]
+    fn link_uses_the_public_url() {
+        let name = "noalbs-1700000000-0000beef.jpg";
+
+        assert_eq!(
+            link(Some("https://example.com/noalbs/"), "715209", name),
+            "https://example.com/noalbs/screenshots/715209/noalbs-1700000000-0000beef.jpg"
+        );
+        assert_eq!(link(None, "715209", name), name);
+    }
+}
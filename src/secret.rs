@@ -0,0 +1,209 @@
+//! Optional encryption of the secrets stored in the config file.
+//!
+//! When `NOALBS_CONFIG_KEY` is set, secret fields are encrypted when saving
+//! the config and decrypted when loading it. Without a key the config is
+//! saved in plaintext like before.
+
+use std::cell::Cell;
+
+use argon2::Argon2;
+use base64::Engine as _;
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use once_cell::sync::Lazy;
+use rand_core::{OsRng, RngCore};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Environment variable containing the passphrase used to derive the key
+pub const KEY_ENV: &str = "NOALBS_CONFIG_KEY";
+
+/// Every encrypted value starts with this followed by the format version
+const PREFIX: &str = "enc:";
+
+/// Argon2id key derivation with XChaCha20-Poly1305,
+/// stored as base64 of `salt | nonce | ciphertext`
+const VERSION_1: &str = "v1";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+static CIPHER: Lazy<Option<Cipher>> = Lazy::new(|| {
+    std::env::var(KEY_ENV)
+        .ok()
+        .filter(|k| !k.is_empty())
+        .map(Cipher::new)
+});
+
+thread_local! {
+    static ENCRYPT: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("encrypted value found but {} is not set", KEY_ENV)]
+    NoKey,
+
+    #[error("unsupported encryption version {0}")]
+    UnsupportedVersion(String),
+
+    #[error("malformed encrypted value")]
+    Malformed,
+
+    #[error("unable to decrypt, is {} correct?", KEY_ENV)]
+    Decrypt,
+
+    #[error("unable to encrypt")]
+    Encrypt,
+
+    #[error("unable to derive the key")]
+    Key,
+}
+
+pub struct Cipher {
+    passphrase: String,
+}
+
+impl Cipher {
+    pub fn new(passphrase: String) -> Self {
+        Self { passphrase }
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let cipher = self.cipher(&salt)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| Error::Encrypt)?;
+
+        let mut blob = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+        blob.extend_from_slice(&salt);
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+
+        Ok(format!(
+            "{}{}:{}",
+            PREFIX,
+            VERSION_1,
+            base64::engine::general_purpose::STANDARD.encode(blob)
+        ))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, Error> {
+        let encrypted = value.strip_prefix(PREFIX).ok_or(Error::Malformed)?;
+        let (version, data) = encrypted.split_once(':').ok_or(Error::Malformed)?;
+
+        if version != VERSION_1 {
+            return Err(Error::UnsupportedVersion(version.to_owned()));
+        }
+
+        let blob = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|_| Error::Malformed)?;
+
+        if blob.len() < SALT_LEN + NONCE_LEN {
+            return Err(Error::Malformed);
+        }
+
+        let (salt, rest) = blob.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let plaintext = self
+            .cipher(salt)?
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::Decrypt)?;
+
+        String::from_utf8(plaintext).map_err(|_| Error::Decrypt)
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<XChaCha20Poly1305, Error> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|_| Error::Key)?;
+
+        Ok(XChaCha20Poly1305::new(&key.into()))
+    }
+}
+
+/// Whether a config value is encrypted
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// Encrypts the secret fields serialized by `f` when a key is configured
+pub fn encrypting<T>(f: impl FnOnce() -> T) -> T {
+    ENCRYPT.with(|e| e.set(true));
+    let res = f();
+    ENCRYPT.with(|e| e.set(false));
+
+    res
+}
+
+/// Serde helpers for optional secret fields, use with `#[serde(default, with = "crate::secret")]`
+pub fn serialize<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let cipher = CIPHER.as_ref().filter(|_| ENCRYPT.with(|e| e.get()));
+
+    match (value, cipher) {
+        (Some(value), Some(cipher)) if !is_encrypted(value) => {
+            let encrypted = cipher.encrypt(value).map_err(ser::Error::custom)?;
+            serializer.serialize_some(&encrypted)
+        }
+        _ => value.serialize(serializer),
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    if !is_encrypted(&value) {
+        return Ok(Some(value));
+    }
+
+    let cipher = CIPHER
+        .as_ref()
+        .ok_or_else(|| de::Error::custom(Error::NoKey))?;
+
+    cipher.decrypt(&value).map(Some).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_round_trip() {
+        let cipher = Cipher::new("hunter2".to_string());
+        let encrypted = cipher.encrypt("obs password").unwrap();
+
+        assert!(encrypted.starts_with("enc:v1:"));
+        assert!(!encrypted.contains("obs password"));
+        assert_eq!(cipher.decrypt(&encrypted).unwrap(), "obs password");
+
+        let wrong = Cipher::new("hunter3".to_string());
+        assert!(matches!(wrong.decrypt(&encrypted), Err(Error::Decrypt)));
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let cipher = Cipher::new("hunter2".to_string());
+
+        assert!(matches!(
+            cipher.decrypt("enc:v9:AAAA"),
+            Err(Error::UnsupportedVersion(v)) if v == "v9"
+        ));
+        assert!(matches!(cipher.decrypt("enc:v1"), Err(Error::Malformed)));
+    }
+}
@@ -1,9 +1,13 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use serde::Serialize;
 use tokio::sync::{mpsc, Notify};
 
-use crate::{broadcasting_software::BroadcastingSoftwareLogic, config};
+use crate::{broadcasting_software::BroadcastingSoftwareLogic, config, switcher};
 
 pub struct State {
     pub config: config::Config,
@@ -18,35 +22,55 @@ impl State {
         let all_scenes = &mut self.switcher_state.switchable_scenes;
 
         let scenes = &self.config.switcher.switching_scenes;
-        all_scenes.insert(scenes.low.to_owned());
-        all_scenes.insert(scenes.normal.to_owned());
-        all_scenes.insert(scenes.offline.to_owned());
+        all_scenes.extend(scenes.all().cloned());
 
         for servers in &self.config.switcher.stream_servers {
             if let Some(scenes) = &servers.override_scenes {
-                all_scenes.insert(scenes.low.to_owned());
-                all_scenes.insert(scenes.normal.to_owned());
-                all_scenes.insert(scenes.offline.to_owned());
+                all_scenes.extend(scenes.all().cloned());
             }
 
-            if let Some(depends_on) = &servers.depends_on {
-                let scenes = &depends_on.backup_scenes;
-                all_scenes.insert(scenes.low.to_owned());
-                all_scenes.insert(scenes.normal.to_owned());
-                all_scenes.insert(scenes.offline.to_owned());
+            if let Some(scenes) = servers
+                .depends_on
+                .as_ref()
+                .and_then(|d| d.backup_scenes.as_ref())
+            {
+                all_scenes.extend(scenes.all().cloned());
             }
         }
 
         if let Some(starting_scene) = &self.config.optional_scenes.starting {
-            if self
-                .config
-                .optional_options
-                .switch_from_starting_scene_to_live_scene
+            let options = &self.config.optional_options;
+
+            if options.switch_from_starting_scene_to_live_scene
+                || options.starting_scene_duration_seconds.is_some()
             {
                 all_scenes.insert(starting_scene.to_owned());
             }
         }
     }
+
+    /// Extra OBS instance of the stream server that was used last, it
+    /// follows the scene of the main instance
+    pub fn active_instance(&self) -> Option<(String, Arc<dyn BroadcastingSoftwareLogic>)> {
+        let last_used = self.switcher_state.last_used_server.as_deref()?;
+
+        let instance = self
+            .config
+            .switcher
+            .stream_servers
+            .iter()
+            .find(|s| s.name == last_used)?
+            .instance
+            .to_owned()?;
+
+        let connection = self.broadcasting_software.instances.get(&instance).cloned();
+
+        if connection.is_none() {
+            tracing::error!("OBS instance {} not found", instance);
+        }
+
+        connection.map(|connection| (instance, connection))
+    }
 }
 
 pub struct SwitcherState {
@@ -55,17 +79,124 @@ pub struct SwitcherState {
     /// All switchable scenes
     pub switchable_scenes: HashSet<String>,
 
+    /// How many times each trigger caused a scene switch
+    pub triggers_fired: HashMap<switcher::TriggerType, u64>,
+
+    /// When and to which type the switcher last switched scenes
+    pub last_switch: Option<(std::time::Instant, switcher::SwitchType)>,
+
+    /// Why the switcher last switched scenes
+    pub last_decision: Option<SwitchDecision>,
+
+    /// Recent bitrate samples of every stream server
+    pub bitrate_history: BitrateHistory,
+
+    /// Recent RTT samples of every stream server, used to smooth the RTT
+    pub rtt_history: RttHistory,
+
+    /// Moving average of the bitrate of every stream server, used to smooth the bitrate
+    pub bitrate_ema: BitrateEma,
+
+    /// How long the offline scene has been active
+    pub offline_timer: OfflineTimer,
+
+    /// Set while the privacy scene is active, switching is paused
+    pub privacy: Option<Privacy>,
+
+    /// Auto switch notifications aren't sent before this instant
+    pub notifications_muted_until: Option<Instant>,
+
+    /// Set while maintenance mode holds a scene, the triggers are ignored
+    pub maintenance: Option<Maintenance>,
+
+    /// Triggers saved by !triggers save, only kept in memory
+    pub triggers_snapshot: Option<switcher::Triggers>,
+
+    /// Set by !forcelive, the live scene is kept over the low scene until
+    /// this instant. The offline scene still ends it
+    pub force_live_until: Option<Instant>,
+
+    /// Whether the chat platform reports the channel as live, None when unknown
+    pub platform_stream_online: Option<bool>,
+
+    /// Set while the starting scene is shown after the stream started,
+    /// the switcher doesn't decide before this instant
+    pub startup_until: Option<Instant>,
+
+    /// Last time the switcher went through its loop, read by the watchdog
+    heartbeat: Arc<Heartbeat>,
+
     switcher_enabled_notifier: Arc<Notify>,
+    shutdown_notifier: Arc<Notify>,
+    privacy_ended_notifier: Arc<Notify>,
+    platform_online_notifier: Arc<Notify>,
 }
 
 impl SwitcherState {
+    /// Remaining time the auto switch notifications are muted
+    pub fn notifications_muted(&self) -> Option<Duration> {
+        self.notifications_muted_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Remaining time the live scene is forced
+    pub fn force_live_remaining(&self, now: Instant) -> Option<Duration> {
+        self.force_live_until
+            .map(|until| until.saturating_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
     pub fn switcher_enabled_notifier(&self) -> Arc<Notify> {
         self.switcher_enabled_notifier.clone()
     }
 
+    /// Notified once to stop the switcher between two checks
+    pub fn shutdown_notifier(&self) -> Arc<Notify> {
+        self.shutdown_notifier.clone()
+    }
+
+    pub fn privacy_ended_notifier(&self) -> Arc<Notify> {
+        self.privacy_ended_notifier.clone()
+    }
+
+    pub fn platform_online_notifier(&self) -> Arc<Notify> {
+        self.platform_online_notifier.clone()
+    }
+
+    /// Shared without the state lock so a stuck switcher can still be noticed
+    pub fn heartbeat(&self) -> Arc<Heartbeat> {
+        self.heartbeat.clone()
+    }
+
     pub async fn wait_till_enabled(&self) {
         self.switcher_enabled_notifier().notified().await;
     }
+
+    /// Where the startup sequence is, ends it once the starting scene
+    /// has been shown long enough
+    pub fn startup_step(&mut self, now: Instant) -> StartupStep {
+        match self.startup_until {
+            None => StartupStep::Done,
+            Some(until) if now < until => StartupStep::Showing,
+            Some(_) => {
+                self.startup_until = None;
+                StartupStep::Handoff
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupStep {
+    /// Starting scene is shown, the switcher waits
+    Showing,
+
+    /// Starting scene was shown long enough, the switcher decides right away
+    Handoff,
+
+    /// No startup sequence running
+    Done,
 }
 
 impl Default for SwitcherState {
@@ -73,11 +204,118 @@ impl Default for SwitcherState {
         Self {
             last_used_server: None,
             switcher_enabled_notifier: Arc::new(Notify::new()),
+            shutdown_notifier: Arc::new(Notify::new()),
+            privacy_ended_notifier: Arc::new(Notify::new()),
+            platform_online_notifier: Arc::new(Notify::new()),
             switchable_scenes: HashSet::new(),
+            triggers_fired: HashMap::new(),
+            last_switch: None,
+            last_decision: None,
+            bitrate_history: BitrateHistory::default(),
+            rtt_history: RttHistory::default(),
+            bitrate_ema: BitrateEma::default(),
+            offline_timer: OfflineTimer::default(),
+            privacy: None,
+            notifications_muted_until: None,
+            maintenance: None,
+            force_live_until: None,
+            triggers_snapshot: None,
+            platform_stream_online: None,
+            startup_until: None,
+            heartbeat: Arc::new(Heartbeat::default()),
         }
     }
 }
 
+/// Updated by the switcher every cycle
+#[derive(Debug)]
+pub struct Heartbeat {
+    inner: std::sync::Mutex<Tick>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tick {
+    last_tick: Instant,
+
+    /// How long the switcher sleeps before the next tick
+    interval: Duration,
+
+    /// Waiting on a notifier, no tick is expected until it wakes up
+    waiting: bool,
+}
+
+impl Heartbeat {
+    pub fn tick(&self, interval: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_tick = Instant::now();
+        inner.interval = interval;
+        inner.waiting = false;
+    }
+
+    pub fn set_waiting(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_tick = Instant::now();
+        inner.waiting = true;
+    }
+
+    /// How long the next tick is overdue, None while on time or waiting
+    pub fn overdue(&self, now: Instant) -> Option<Duration> {
+        let inner = *self.inner.lock().unwrap();
+
+        if inner.waiting {
+            return None;
+        }
+
+        now.checked_duration_since(inner.last_tick + inner.interval)
+            .filter(|overdue| !overdue.is_zero())
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            inner: std::sync::Mutex::new(Tick {
+                last_tick: Instant::now(),
+                interval: Duration::ZERO,
+                waiting: false,
+            }),
+        }
+    }
+}
+
+/// What the switcher saw when it switched scenes
+#[derive(Debug, Clone)]
+pub struct SwitchDecision {
+    pub at: Instant,
+    pub switch_type: switcher::SwitchType,
+
+    /// Trigger that caused a switch to the low or offline scene
+    pub trigger: Option<switcher::TriggerType>,
+
+    pub scene: String,
+    pub stream_server: Option<String>,
+    pub bitrate: Option<u32>,
+    pub rtt: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Privacy {
+    /// Scene to switch back to when privacy ends
+    pub previous_scene: String,
+
+    /// When privacy started, used to tell an old auto revert apart
+    pub since: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    /// Scene held while in maintenance mode
+    pub scene: String,
+
+    /// Scene to switch back to when maintenance mode ends
+    pub previous_scene: String,
+}
+
 pub struct BroadcastingSoftwareState {
     pub prev_scene: String,
     pub current_scene: String,
@@ -87,23 +325,83 @@ pub struct BroadcastingSoftwareState {
     pub initial_stream_status: Option<StreamStatus>,
     pub stream_status: Option<StreamStatus>,
 
+    /// Dropped frames reported by the output of the broadcasting software
+    pub dropped_frames: DroppedFrames,
+
+    /// Follows the streaming state to record while streaming
+    pub recording_sync: RecordingSync,
+
+    /// Set while !start restarts the stream until Twitch transcodes it
+    pub transcoding_restart: bool,
+
+    /// When the current scene became active
+    pub scene_since: Option<Instant>,
+
+    /// Set while the connection is lost and being retried
+    pub reconnect: Reconnect,
+
     // TODO?
-    pub connection: Option<Box<dyn BroadcastingSoftwareLogic>>,
+    pub connection: Option<Arc<dyn BroadcastingSoftwareLogic>>,
+
+    /// Extra named instances that only get scene switches
+    pub instances: HashMap<String, Arc<dyn BroadcastingSoftwareLogic>>,
 
     connected_notifier: Arc<Notify>,
+    disconnected_notifier: Arc<Notify>,
     start_streaming_notifier: Arc<Notify>,
+    stop_streaming_notifier: Arc<Notify>,
     switch_scene_notifier: Arc<Notify>,
 }
 
 impl BroadcastingSoftwareState {
+    /// Updates the current scene, keeps the time it became active when
+    /// it's the same scene
+    pub fn set_current_scene(&mut self, scene: String) {
+        if self.scene_since.is_none() || self.current_scene != scene {
+            self.scene_since = Some(Instant::now());
+        }
+
+        self.current_scene = scene;
+    }
+
+    /// Marks the connection as established, the switcher resumes
+    pub fn set_connected(&mut self) {
+        self.status = ClientStatus::Connected;
+        self.reconnect.since = None;
+        self.connected_notifier.notify_waiters();
+    }
+
+    /// Marks the connection as lost, the switcher holds until it's back
+    pub fn set_disconnected(&mut self) {
+        if self.status == ClientStatus::Connected {
+            self.reconnect = Reconnect {
+                since: Some(Instant::now()),
+                attempts: 0,
+                was_streaming: self.is_streaming,
+            };
+        }
+
+        self.status = ClientStatus::Disconnected;
+        self.is_streaming = false;
+        self.disconnected_notifier.notify_waiters();
+    }
+
     pub fn connected_notifier(&self) -> Arc<Notify> {
         self.connected_notifier.clone()
     }
 
+    pub fn disconnected_notifier(&self) -> Arc<Notify> {
+        self.disconnected_notifier.clone()
+    }
+
     pub fn start_streaming_notifier(&self) -> Arc<Notify> {
         self.start_streaming_notifier.clone()
     }
 
+    pub fn stop_streaming_notifier(&self) -> Arc<Notify> {
+        self.stop_streaming_notifier.clone()
+    }
+
     pub fn switch_scene_notifier(&self) -> Arc<Notify> {
         self.switch_scene_notifier.clone()
     }
@@ -129,16 +427,36 @@ impl Default for BroadcastingSoftwareState {
             status: ClientStatus::Disconnected,
             is_streaming: false,
             connection: None,
+            instances: HashMap::new(),
             connected_notifier: Arc::new(Notify::new()),
+            disconnected_notifier: Arc::new(Notify::new()),
             start_streaming_notifier: Arc::new(Notify::new()),
+            stop_streaming_notifier: Arc::new(Notify::new()),
             switch_scene_notifier: Arc::new(Notify::new()),
             last_stream_started_at: std::time::Instant::now(),
             stream_status: None,
             initial_stream_status: None,
+            dropped_frames: DroppedFrames::default(),
+            recording_sync: RecordingSync::default(),
+            transcoding_restart: false,
+            scene_since: None,
+            reconnect: Reconnect::default(),
         }
     }
 }
 
+#[derive(Debug, Default, Clone)]
+pub struct Reconnect {
+    /// When the connection was lost, none while connected
+    pub since: Option<Instant>,
+
+    /// Failed attempts to connect again during the last time it was lost
+    pub attempts: u32,
+
+    /// Whether it was streaming when the connection was lost
+    pub was_streaming: bool,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClientStatus {
     Connected,
@@ -172,6 +490,236 @@ impl StreamStatus {
     }
 }
 
+/// Keeps the frame counters of the output over a sampling window
+#[derive(Debug, Default)]
+pub struct DroppedFrames {
+    samples: VecDeque<FrameSample>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameSample {
+    at: Instant,
+    total_frames: u64,
+    dropped_frames: u64,
+}
+
+impl DroppedFrames {
+    /// Adds the cumulative frame counters and forgets samples outside the window
+    pub fn add_sample(&mut self, total_frames: u64, dropped_frames: u64, window: Duration) {
+        self.add_sample_at(Instant::now(), total_frames, dropped_frames, window);
+    }
+
+    fn add_sample_at(
+        &mut self,
+        at: Instant,
+        total_frames: u64,
+        dropped_frames: u64,
+        window: Duration,
+    ) {
+        // Counters went back, the output got restarted
+        if let Some(last) = self.samples.back() {
+            if total_frames < last.total_frames || dropped_frames < last.dropped_frames {
+                self.samples.clear();
+            }
+        }
+
+        self.samples.push_back(FrameSample {
+            at,
+            total_frames,
+            dropped_frames,
+        });
+
+        // Keep one sample older than the window to measure from
+        while self.samples.len() > 2 && at.duration_since(self.samples[1].at) >= window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Percentage of frames dropped over the sampling window
+    pub fn percentage(&self) -> Option<f64> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+
+        let total = last.total_frames - first.total_frames;
+        if total == 0 {
+            return None;
+        }
+
+        let dropped = last.dropped_frames - first.dropped_frames;
+        Some(dropped as f64 / total as f64 * 100.0)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Max amount of bitrate samples kept per stream server, a minute
+/// at the fastest poll interval
+const BITRATE_HISTORY_CAPACITY: usize = 240;
+
+/// Keeps the recent bitrate samples of every stream server, the samples
+/// are added by the switcher while only holding a read lock on the state
+#[derive(Debug, Default)]
+pub struct BitrateHistory {
+    servers: std::sync::Mutex<HashMap<String, VecDeque<BitrateSample>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BitrateSample {
+    at: Instant,
+    kbps: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BitrateSummary {
+    pub min: u32,
+    pub avg: u32,
+    pub max: u32,
+}
+
+impl BitrateHistory {
+    /// Adds a sample, the oldest sample is dropped when full
+    pub fn add_sample(&self, server: &str, kbps: u32) {
+        self.add_sample_at(server, Instant::now(), kbps);
+    }
+
+    fn add_sample_at(&self, server: &str, at: Instant, kbps: u32) {
+        let mut servers = self.servers.lock().unwrap();
+        let samples = servers.entry(server.to_owned()).or_default();
+
+        if samples.len() == BITRATE_HISTORY_CAPACITY {
+            samples.pop_front();
+        }
+
+        samples.push_back(BitrateSample { at, kbps });
+    }
+
+    /// Keeps the samples of a renamed stream server
+    pub fn rename(&self, from: &str, to: &str) {
+        let mut servers = self.servers.lock().unwrap();
+
+        if let Some(samples) = servers.remove(from) {
+            servers.insert(to.to_owned(), samples);
+        }
+    }
+
+    /// Min, average and max bitrate of the samples inside the window
+    pub fn summary(&self, server: &str, window: Duration) -> Option<BitrateSummary> {
+        self.summary_at(server, Instant::now(), window)
+    }
+
+    fn summary_at(&self, server: &str, now: Instant, window: Duration) -> Option<BitrateSummary> {
+        let servers = self.servers.lock().unwrap();
+        let samples = servers
+            .get(server)?
+            .iter()
+            .filter(|s| now.saturating_duration_since(s.at) <= window)
+            .map(|s| s.kbps);
+
+        let (mut min, mut max, mut total, mut count) = (u32::MAX, 0, 0u64, 0u64);
+
+        for kbps in samples {
+            min = min.min(kbps);
+            max = max.max(kbps);
+            total += kbps as u64;
+            count += 1;
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        Some(BitrateSummary {
+            min,
+            avg: (total / count) as u32,
+            max,
+        })
+    }
+}
+
+/// Keeps the last RTT samples of every stream server, the samples are
+/// added while only holding a read lock on the state
+#[derive(Debug, Default)]
+pub struct RttHistory {
+    servers: std::sync::Mutex<HashMap<String, VecDeque<f64>>>,
+}
+
+impl RttHistory {
+    /// Adds the sample and returns the median of the last `window` samples,
+    /// a single spike won't move the median. Without a sample the feed is
+    /// gone and the window starts over.
+    pub fn add_sample(&self, server: &str, rtt: Option<f64>, window: usize) -> Option<f64> {
+        let mut servers = self.servers.lock().unwrap();
+
+        let Some(rtt) = rtt else {
+            servers.remove(server);
+            return None;
+        };
+
+        let samples = servers.entry(server.to_owned()).or_default();
+        samples.push_back(rtt);
+
+        while samples.len() > window.max(1) {
+            samples.pop_front();
+        }
+
+        median(samples.iter().copied())
+    }
+
+    /// Median of the samples in the window without adding one
+    pub fn median(&self, server: &str) -> Option<f64> {
+        let servers = self.servers.lock().unwrap();
+
+        median(servers.get(server)?.iter().copied())
+    }
+}
+
+/// Exponential moving average of the bitrate of every stream server, the
+/// samples are added while only holding a read lock on the state
+#[derive(Debug, Default)]
+pub struct BitrateEma {
+    servers: std::sync::Mutex<HashMap<String, f64>>,
+}
+
+impl BitrateEma {
+    /// Adds the sample and returns the average, `alpha` is the weight of the
+    /// new sample. Without a sample the stream is gone and the average starts over.
+    pub fn add_sample(&self, server: &str, kbps: Option<u32>, alpha: f64) -> Option<f64> {
+        let mut servers = self.servers.lock().unwrap();
+
+        let Some(kbps) = kbps.map(f64::from) else {
+            servers.remove(server);
+            return None;
+        };
+
+        let average = servers
+            .entry(server.to_owned())
+            .and_modify(|average| *average += alpha * (kbps - *average))
+            .or_insert(kbps);
+
+        Some(*average)
+    }
+
+    /// The average without adding a sample
+    pub fn average(&self, server: &str) -> Option<f64> {
+        self.servers.lock().unwrap().get(server).copied()
+    }
+}
+
+fn median(samples: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut samples = samples.collect::<Vec<_>>();
+    samples.sort_by(f64::total_cmp);
+
+    let mid = samples.len() / 2;
+
+    match samples.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((samples[mid - 1] + samples[mid]) / 2.0),
+        _ => Some(samples[mid]),
+    }
+}
+
 #[derive(Debug)]
 pub struct BroadcastClient {
     /// Unique token for the current client
@@ -193,3 +741,470 @@ impl BroadcastClient {
         }
     }
 }
+
+/// Warn this long before the offline timeout stops the stream
+const OFFLINE_TIMEOUT_WARNING: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OfflineTimeoutAction {
+    Warn,
+    Stop,
+}
+
+/// Keeps track of how long the offline scene has been active without interruption
+#[derive(Debug, Default)]
+pub struct OfflineTimer {
+    since: Option<Instant>,
+    warned: bool,
+}
+
+impl OfflineTimer {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Any other active scene resets the timer
+    pub fn update(
+        &mut self,
+        offline: bool,
+        timeout: Duration,
+        now: Instant,
+    ) -> Option<OfflineTimeoutAction> {
+        if !offline {
+            self.reset();
+            return None;
+        }
+
+        let elapsed = now.saturating_duration_since(*self.since.get_or_insert(now));
+
+        if elapsed >= timeout {
+            return Some(OfflineTimeoutAction::Stop);
+        }
+
+        if !self.warned && elapsed >= timeout.saturating_sub(OFFLINE_TIMEOUT_WARNING) {
+            self.warned = true;
+            return Some(OfflineTimeoutAction::Warn);
+        }
+
+        None
+    }
+}
+
+/// Keeps track of how long the feed has been healthy while on the offline scene
+#[derive(Debug, Default)]
+pub struct RecoverTimer {
+    since: Option<Instant>,
+}
+
+impl RecoverTimer {
+    /// Whether the feed has been healthy for at least the delay,
+    /// an unhealthy feed resets the timer
+    pub fn update(&mut self, healthy: bool, delay: Duration, now: Instant) -> bool {
+        if !healthy {
+            self.since = None;
+            return false;
+        }
+
+        now.saturating_duration_since(*self.since.get_or_insert(now)) >= delay
+    }
+}
+
+/// Keeps track of how long the low scene has been active to refresh once
+/// every time it stays active for a while
+#[derive(Debug, Default)]
+pub struct LowRefreshTimer {
+    since: Option<Instant>,
+    refreshed: bool,
+}
+
+impl LowRefreshTimer {
+    /// Whether to refresh now, leaving the low scene resets the timer
+    pub fn update(&mut self, low: bool, after: Duration, now: Instant) -> bool {
+        if !low {
+            self.since = None;
+            self.refreshed = false;
+            return false;
+        }
+
+        let since = *self.since.get_or_insert(now);
+
+        if self.refreshed || now.saturating_duration_since(since) < after {
+            return false;
+        }
+
+        self.refreshed = true;
+        true
+    }
+}
+
+/// Keeps track of the bitrate being below the low warning trigger to only
+/// warn once every time it drops into the warning band
+#[derive(Debug, Default)]
+pub struct LowWarning {
+    warned: bool,
+}
+
+impl LowWarning {
+    /// Whether to warn now, getting back above the warning trigger resets it
+    pub fn update(&mut self, kbps: u32, warn: u32, low: Option<u32>) -> bool {
+        if kbps >= warn {
+            self.warned = false;
+            return false;
+        }
+
+        if std::mem::replace(&mut self.warned, true) {
+            return false;
+        }
+
+        // Below the low trigger the switch notification takes over
+        kbps > 0 && low.map_or(true, |low| kbps > low)
+    }
+
+    pub fn reset(&mut self) {
+        self.warned = false;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordAction {
+    Start,
+    Stop,
+}
+
+/// Turns changes of the streaming state into a single record action
+#[derive(Debug, Default)]
+pub struct RecordingSync {
+    streaming: Option<bool>,
+}
+
+impl RecordingSync {
+    /// A stream that was already running when first seen also starts
+    /// the recording, a stopped one is left alone
+    pub fn update(&mut self, streaming: bool) -> Option<RecordAction> {
+        let previous = self.streaming.replace(streaming);
+
+        match (previous, streaming) {
+            (Some(true), true) | (Some(false) | None, false) => None,
+            (Some(false) | None, true) => Some(RecordAction::Start),
+            (Some(true), false) => Some(RecordAction::Stop),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_is_not_overdue_while_waiting() {
+        let heartbeat = Heartbeat::default();
+        heartbeat.tick(Duration::from_secs(2));
+
+        let later = Instant::now() + Duration::from_secs(10);
+        assert!(heartbeat.overdue(Instant::now()).is_none());
+        assert!(heartbeat.overdue(later).unwrap() >= Duration::from_secs(7));
+
+        heartbeat.set_waiting();
+        assert!(heartbeat.overdue(later).is_none());
+    }
+
+    #[test]
+    fn bitrate_ema_rides_out_one_dip() {
+        let ema = BitrateEma::default();
+        let low = 800.0;
+
+        let smoothed: Vec<f64> = [2500, 2400, 100, 2500, 2450]
+            .into_iter()
+            .map(|kbps| ema.add_sample("server", Some(kbps), 0.3).unwrap())
+            .collect();
+
+        // The raw dip is under the trigger, the average never is
+        assert!(smoothed.iter().all(|kbps| *kbps > low));
+        assert!((smoothed[2] - 1759.0).abs() < 0.01);
+
+        assert_eq!(ema.average("server"), Some(smoothed[4]));
+
+        // Gone offline, the average starts over
+        assert_eq!(ema.add_sample("server", None, 0.3), None);
+        assert_eq!(ema.average("server"), None);
+        assert_eq!(ema.add_sample("server", Some(500), 0.3), Some(500.0));
+    }
+
+    #[test]
+    fn startup_hands_off_once() {
+        let start = Instant::now();
+        let mut state = SwitcherState::default();
+
+        assert_eq!(state.startup_step(start), StartupStep::Done);
+
+        state.startup_until = Some(start + Duration::from_secs(30));
+        assert_eq!(state.startup_step(start), StartupStep::Showing);
+        assert_eq!(
+            state.startup_step(start + Duration::from_secs(29)),
+            StartupStep::Showing
+        );
+        assert_eq!(
+            state.startup_step(start + Duration::from_secs(30)),
+            StartupStep::Handoff
+        );
+        assert_eq!(
+            state.startup_step(start + Duration::from_secs(31)),
+            StartupStep::Done
+        );
+    }
+
+    #[test]
+    fn dropped_frames_over_window() {
+        let window = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut df = DroppedFrames::default();
+
+        assert_eq!(df.percentage(), None);
+
+        df.add_sample_at(start, 0, 0, window);
+        df.add_sample_at(start + Duration::from_secs(2), 60, 0, window);
+        assert_eq!(df.percentage(), Some(0.0));
+
+        df.add_sample_at(start + Duration::from_secs(4), 120, 30, window);
+        assert_eq!(df.percentage(), Some(25.0));
+
+        // Only the frames inside the window are used
+        df.add_sample_at(start + Duration::from_secs(16), 420, 30, window);
+        df.add_sample_at(start + Duration::from_secs(18), 480, 30, window);
+        assert_eq!(df.percentage(), Some(0.0));
+
+        // Restarted output
+        df.add_sample_at(start + Duration::from_secs(20), 10, 5, window);
+        assert_eq!(df.percentage(), None);
+    }
+
+    #[test]
+    fn bitrate_history_summary() {
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+        let history = BitrateHistory::default();
+
+        assert_eq!(history.summary_at("belabox", start, window), None);
+
+        history.add_sample_at("belabox", start, 100);
+        history.add_sample_at("belabox", start + Duration::from_secs(30), 3000);
+        history.add_sample_at("belabox", start + Duration::from_secs(60), 2000);
+        history.add_sample_at("belabox", start + Duration::from_secs(90), 1000);
+
+        let summary = history.summary_at("belabox", start + Duration::from_secs(90), window);
+        assert_eq!(
+            summary,
+            Some(BitrateSummary {
+                min: 1000,
+                avg: 2000,
+                max: 3000
+            })
+        );
+
+        assert_eq!(
+            history.summary_at("nginx", start + Duration::from_secs(90), window),
+            None
+        );
+    }
+
+    #[test]
+    fn notifications_mute_expires() {
+        let mut state = SwitcherState::default();
+        assert_eq!(state.notifications_muted(), None);
+
+        state.notifications_muted_until = Some(Instant::now() + Duration::from_secs(600));
+        assert!(state.notifications_muted().is_some());
+
+        state.notifications_muted_until = Some(Instant::now());
+        assert_eq!(state.notifications_muted(), None);
+    }
+
+    #[test]
+    fn force_live_expires() {
+        let now = Instant::now();
+        let mut state = SwitcherState::default();
+        assert_eq!(state.force_live_remaining(now), None);
+
+        state.force_live_until = Some(now + Duration::from_secs(120));
+        assert_eq!(
+            state.force_live_remaining(now + Duration::from_secs(20)),
+            Some(Duration::from_secs(100))
+        );
+        assert_eq!(
+            state.force_live_remaining(now + Duration::from_secs(120)),
+            None
+        );
+    }
+
+    #[test]
+    fn rtt_spike_is_smoothed() {
+        let history = RttHistory::default();
+        let window = 5;
+        let trigger = 1000.0;
+
+        for rtt in [120.0, 130.0, 4000.0, 125.0, 118.0] {
+            let smoothed = history.add_sample("belabox", Some(rtt), window).unwrap();
+            assert!(smoothed < trigger, "{} triggered after {}", smoothed, rtt);
+        }
+
+        // Consistently high RTT still triggers
+        for rtt in [1500.0, 1600.0, 1550.0] {
+            history.add_sample("belabox", Some(rtt), window);
+        }
+
+        assert_eq!(history.median("belabox"), Some(1500.0));
+
+        // A spike from before the feed dropped doesn't count once it's back
+        assert_eq!(history.add_sample("belabox", None, window), None);
+        assert_eq!(history.median("belabox"), None);
+        assert_eq!(
+            history.add_sample("belabox", Some(120.0), window),
+            Some(120.0)
+        );
+    }
+
+    #[test]
+    fn bitrate_history_is_bounded() {
+        let history = BitrateHistory::default();
+        let start = Instant::now();
+
+        for i in 0..BITRATE_HISTORY_CAPACITY + 10 {
+            history.add_sample_at("belabox", start, i as u32);
+        }
+
+        let servers = history.servers.lock().unwrap();
+        assert_eq!(servers["belabox"].len(), BITRATE_HISTORY_CAPACITY);
+        assert_eq!(servers["belabox"].front().unwrap().kbps, 10);
+    }
+
+    #[test]
+    fn offline_timer_only_counts_continuous_offline() {
+        let timeout = Duration::from_secs(300);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut timer = OfflineTimer::default();
+
+        assert_eq!(timer.update(true, timeout, at(0)), None);
+        assert_eq!(
+            timer.update(true, timeout, at(240)),
+            Some(OfflineTimeoutAction::Warn)
+        );
+        assert_eq!(timer.update(true, timeout, at(250)), None);
+
+        // The starting scene or any other scene resets it
+        assert_eq!(timer.update(false, timeout, at(260)), None);
+        assert_eq!(timer.update(true, timeout, at(270)), None);
+        assert_eq!(timer.update(true, timeout, at(500)), None);
+        assert_eq!(
+            timer.update(true, timeout, at(510)),
+            Some(OfflineTimeoutAction::Warn)
+        );
+        assert_eq!(
+            timer.update(true, timeout, at(570)),
+            Some(OfflineTimeoutAction::Stop)
+        );
+    }
+
+    #[test]
+    fn recover_timer_needs_sustained_health() {
+        let delay = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut timer = RecoverTimer::default();
+
+        assert!(!timer.update(true, delay, at(0)));
+        assert!(!timer.update(true, delay, at(5)));
+
+        // Going offline again starts over
+        assert!(!timer.update(false, delay, at(6)));
+        assert!(!timer.update(true, delay, at(7)));
+        assert!(!timer.update(true, delay, at(16)));
+        assert!(timer.update(true, delay, at(17)));
+    }
+
+    #[test]
+    fn low_refresh_once_per_low_period() {
+        let after = Duration::from_secs(30);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut timer = LowRefreshTimer::default();
+
+        assert!(!timer.update(true, after, at(0)));
+        assert!(!timer.update(true, after, at(29)));
+        assert!(timer.update(true, after, at(30)));
+        assert!(!timer.update(true, after, at(90)));
+
+        // Back on live, the next low period refreshes again
+        assert!(!timer.update(false, after, at(91)));
+        assert!(!timer.update(true, after, at(92)));
+        assert!(timer.update(true, after, at(122)));
+    }
+
+    #[test]
+    fn reconnect_remembers_streaming() {
+        let mut bs = BroadcastingSoftwareState::default();
+
+        // Never connected yet
+        bs.set_disconnected();
+        assert!(bs.reconnect.since.is_none());
+
+        bs.set_connected();
+        bs.is_streaming = true;
+        bs.set_disconnected();
+
+        assert!(!bs.is_streaming);
+        assert!(bs.reconnect.since.is_some());
+        assert!(bs.reconnect.was_streaming);
+
+        // Still down on the next failed attempt
+        bs.reconnect.attempts += 1;
+        bs.set_disconnected();
+        assert!(bs.reconnect.was_streaming);
+        assert_eq!(bs.reconnect.attempts, 1);
+
+        bs.set_connected();
+        assert_eq!(bs.status, ClientStatus::Connected);
+        assert!(bs.reconnect.since.is_none());
+    }
+
+    #[test]
+    fn low_warning_once_per_band_transition() {
+        let mut warning = LowWarning::default();
+        let low = Some(800);
+
+        assert!(!warning.update(2000, 1200, low));
+        assert!(warning.update(1000, 1200, low));
+        assert!(!warning.update(900, 1200, low));
+        assert!(!warning.update(1100, 1200, low));
+
+        // Recovered above the warning, the next drop warns again
+        assert!(!warning.update(1500, 1200, low));
+        assert!(warning.update(1100, 1200, low));
+
+        // Dropping straight below the low trigger doesn't warn
+        warning.reset();
+        assert!(!warning.update(500, 1200, low));
+        assert!(!warning.update(1000, 1200, low));
+    }
+
+    #[test]
+    fn recording_follows_stream_once_per_transition() {
+        let mut sync = RecordingSync::default();
+
+        assert_eq!(sync.update(false), None);
+        assert_eq!(sync.update(true), Some(RecordAction::Start));
+        assert_eq!(sync.update(true), None);
+        assert_eq!(sync.update(true), None);
+        assert_eq!(sync.update(false), Some(RecordAction::Stop));
+        assert_eq!(sync.update(false), None);
+        assert_eq!(sync.update(true), Some(RecordAction::Start));
+    }
+
+    #[test]
+    fn recording_starts_when_already_streaming() {
+        let mut sync = RecordingSync::default();
+
+        assert_eq!(sync.update(true), Some(RecordAction::Start));
+        assert_eq!(sync.update(true), None);
+    }
+}
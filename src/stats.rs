@@ -0,0 +1,284 @@
+//! Optional SQLite database with the bitrate, RTT, scene and switches of
+//! every stream, to look back at a whole stream afterwards.
+
+use std::{sync::Mutex, thread};
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+use crate::switch_log;
+
+/// Rows waiting to be written, new rows are dropped when the database
+/// can't keep up so the switcher never waits on it
+const QUEUE_SIZE: usize = 1000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS samples (
+    timestamp INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    stream_server TEXT NOT NULL,
+    scene TEXT NOT NULL,
+    bitrate INTEGER,
+    rtt REAL
+);
+CREATE INDEX IF NOT EXISTS samples_user_timestamp ON samples (user, timestamp);
+
+CREATE TABLE IF NOT EXISTS switches (
+    timestamp INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    switch_type TEXT NOT NULL,
+    trigger_type TEXT,
+    previous_scene TEXT NOT NULL,
+    scene TEXT NOT NULL,
+    stream_server TEXT,
+    bitrate INTEGER,
+    rtt REAL
+);
+CREATE INDEX IF NOT EXISTS switches_user_timestamp ON switches (user, timestamp);
+";
+
+/// Bitrate of a stream server at a point in time
+#[derive(Debug)]
+pub struct Sample {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub stream_server: String,
+    pub scene: String,
+    pub bitrate: Option<u32>,
+    pub rtt: Option<f64>,
+}
+
+#[derive(Debug)]
+enum Row {
+    Sample(Sample),
+    Switch(switch_log::Entry),
+}
+
+/// Writes the rows on a separate thread, the database is opened on the
+/// first row and again when the path changed in the config
+#[derive(Default)]
+pub struct Stats {
+    writer: Mutex<Option<(String, mpsc::Sender<(String, Row)>)>>,
+}
+
+impl Stats {
+    pub fn add_sample(&self, path: &str, user: &str, sample: Sample) {
+        self.send(path, user, Row::Sample(sample));
+    }
+
+    pub fn add_switch(&self, path: &str, user: &str, entry: switch_log::Entry) {
+        self.send(path, user, Row::Switch(entry));
+    }
+
+    fn send(&self, path: &str, user: &str, row: Row) {
+        let mut writer = self.writer.lock().unwrap();
+
+        if !matches!(&*writer, Some((current, _)) if current == path) {
+            *writer = Some((path.to_owned(), spawn_writer(path)));
+        }
+
+        let (_, tx) = writer.as_ref().unwrap();
+
+        if tx.try_send((user.to_owned(), row)).is_err() {
+            debug!("Stats database {} isn't keeping up, dropping a row", path);
+        }
+    }
+}
+
+fn spawn_writer(path: &str) -> mpsc::Sender<(String, Row)> {
+    let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+    let path = path.to_owned();
+
+    thread::spawn(move || {
+        let conn = match open(&path) {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Unable to open the stats database {}: {}", path, e);
+                return;
+            }
+        };
+
+        while let Some((user, row)) = rx.blocking_recv() {
+            if let Err(e) = insert(&conn, &user, &row) {
+                error!("Unable to write to the stats database {}: {}", path, e);
+            }
+        }
+    });
+
+    tx
+}
+
+/// Opens the database, creating the tables when they don't exist yet
+pub fn open(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    Ok(conn)
+}
+
+fn insert(conn: &Connection, user: &str, row: &Row) -> rusqlite::Result<usize> {
+    match row {
+        Row::Sample(s) => conn.execute(
+            "INSERT INTO samples (timestamp, user, stream_server, scene, bitrate, rtt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                s.timestamp as i64,
+                user,
+                s.stream_server,
+                s.scene,
+                s.bitrate,
+                s.rtt
+            ],
+        ),
+        Row::Switch(e) => conn.execute(
+            "INSERT INTO switches (timestamp, user, outcome, switch_type, trigger_type,
+                previous_scene, scene, stream_server, bitrate, rtt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                e.timestamp as i64,
+                user,
+                name(&e.outcome),
+                name(&e.switch_type),
+                e.trigger.map(|t| t.as_str()),
+                e.previous_scene,
+                e.scene,
+                e.stream_server,
+                e.bitrate,
+                e.rtt
+            ],
+        ),
+    }
+}
+
+/// Same name as used in the switch log and webhook
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Everything recorded for a user within a period
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub samples: u64,
+    pub avg_bitrate: Option<f64>,
+    pub min_bitrate: Option<u32>,
+    pub max_rtt: Option<f64>,
+    pub switches: u64,
+    pub low_switches: u64,
+    pub offline_switches: u64,
+}
+
+/// Summary of a stream from `from` until `to`, both unix timestamps in seconds
+pub fn session_summary(
+    conn: &Connection,
+    user: &str,
+    from: u64,
+    to: u64,
+) -> rusqlite::Result<Summary> {
+    let (from, to) = (from as i64, to as i64);
+
+    let (samples, avg_bitrate, min_bitrate, max_rtt) = conn.query_row(
+        "SELECT COUNT(*), AVG(bitrate), MIN(bitrate), MAX(rtt) FROM samples
+         WHERE user = ?1 AND timestamp BETWEEN ?2 AND ?3",
+        params![user, from, to],
+        |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+
+    let (switches, low_switches, offline_switches) = conn.query_row(
+        "SELECT COUNT(*),
+            COALESCE(SUM(switch_type = 'low'), 0),
+            COALESCE(SUM(switch_type = 'offline'), 0)
+         FROM switches
+         WHERE user = ?1 AND outcome = 'switched' AND timestamp BETWEEN ?2 AND ?3",
+        params![user, from, to],
+        |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        },
+    )?;
+
+    Ok(Summary {
+        samples: samples as u64,
+        avg_bitrate,
+        min_bitrate,
+        max_rtt,
+        switches: switches as u64,
+        low_switches: low_switches as u64,
+        offline_switches: offline_switches as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::switcher::{SwitchType, TriggerType};
+
+    fn sample(timestamp: u64, bitrate: u32, rtt: f64) -> Row {
+        Row::Sample(Sample {
+            timestamp,
+            stream_server: "belabox".to_string(),
+            scene: "live".to_string(),
+            bitrate: Some(bitrate),
+            rtt: Some(rtt),
+        })
+    }
+
+    fn switch(timestamp: u64, switch_type: SwitchType) -> Row {
+        Row::Switch(switch_log::Entry {
+            timestamp,
+            outcome: switch_log::Outcome::Switched,
+            switch_type,
+            trigger: Some(TriggerType::Low),
+            previous_scene: "live".to_string(),
+            scene: "low".to_string(),
+            stream_server: Some("belabox".to_string()),
+            bitrate: Some(400),
+            rtt: None,
+        })
+    }
+
+    #[test]
+    fn summary_of_a_session() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+
+        for row in [
+            sample(100, 6000, 20.0),
+            sample(102, 400, 900.0),
+            sample(104, 5000, 30.0),
+            switch(102, SwitchType::Low),
+            switch(104, SwitchType::Normal),
+            // Another stream
+            sample(500, 100, 10.0),
+            switch(500, SwitchType::Offline),
+        ] {
+            insert(&conn, "715209", &row).unwrap();
+        }
+
+        insert(&conn, "someone", &sample(101, 1, 1.0)).unwrap();
+
+        let summary = session_summary(&conn, "715209", 100, 200).unwrap();
+
+        assert_eq!(
+            summary,
+            Summary {
+                samples: 3,
+                avg_bitrate: Some(3800.0),
+                min_bitrate: Some(400),
+                max_rtt: Some(900.0),
+                switches: 2,
+                low_switches: 1,
+                offline_switches: 0,
+            }
+        );
+    }
+}
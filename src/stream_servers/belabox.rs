@@ -13,10 +13,19 @@ pub struct Stat {
     pub dropped_pkts: i32,
 }
 
+impl Stat {
+    /// Percentage of packets lost
+    pub fn packet_loss(&self) -> Option<f64> {
+        let received = super::estimated_srt_packets(self.bitrate.max(0) as f64);
+        super::packet_percentage(self.dropped_pkts.max(0) as f64, received)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Belabox {
-    /// URL to the BELABOX stats page (ex; http://127.0.0.1:8181/stats )
+    /// URL to the BELABOX stats page (ex; http://127.0.0.1:8181/stats ),
+    /// both self-hosted and cloud relay stats are detected
     pub stats_url: String,
 
     /// StreamID of the where you are publishing the feed. (ex; publish/live/feed1 )
@@ -25,6 +34,14 @@ pub struct Belabox {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl Belabox {
@@ -32,21 +49,31 @@ impl Belabox {
         let res = match self.client.get(&self.stats_url).send().await {
             Ok(res) => res,
             Err(e) => {
-                error!("Stats page is unreachable, {}", e);
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
         let data: Value = serde_json::from_str(&text).ok()?;
-        let publisher = &data["publishers"][&self.publisher];
 
-        let stream: Stat = match serde_json::from_value(publisher.to_owned()) {
+        let mut stream = match parse_publisher(&data, &self.publisher) {
             Ok(stats) => stats,
             Err(error) => {
                 trace!("{}", &data);
@@ -55,21 +82,17 @@ impl Belabox {
             }
         };
 
+        // Converted right away so the stats are in Kbps everywhere
+        if let Some(unit) = self.bitrate_unit {
+            stream.bitrate = unit.to_kbps(stream.bitrate as f64).into();
+        }
+
         trace!("{:#?}", stream);
         Some(stream)
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Belabox {
-    /// Which scene to switch to
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
 
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stat, triggers: &Triggers) -> SwitchType {
         if let Some(offline) = triggers.offline {
             if stats.bitrate > 0 && stats.bitrate <= offline.into() {
                 return SwitchType::Offline;
@@ -102,26 +125,93 @@ impl SwitchLogic for Belabox {
             }
         }
 
+        if super::high_packet_loss(stats.packet_loss(), triggers) {
+            return SwitchType::Low;
+        }
+
         return SwitchType::Normal;
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl StreamServersCommands for Belabox {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
-        };
 
+    fn stats_bitrate(&self, stats: &Stat) -> super::Bitrate {
         if stats.bitrate == 0 {
-            return super::Bitrate { message: None };
+            return super::Bitrate::default();
         }
 
         let message = format!("{}, {} ms", stats.bitrate, stats.rtt.round());
         super::Bitrate {
             message: Some(message),
+            kbps: Some(stats.bitrate as u32),
+            rtt: Some(stats.rtt),
+            packet_loss: stats.packet_loss(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Shape of the stats page
+#[derive(Debug, PartialEq, Eq)]
+enum Format {
+    /// Self-hosted SRT relay, publishers at the top level
+    SelfHosted,
+
+    /// BELABOX cloud, publishers nested under every relay
+    Cloud,
+}
+
+fn detect_format(data: &Value) -> Option<Format> {
+    if data["publishers"].is_object() {
+        Some(Format::SelfHosted)
+    } else if data["relays"].is_object() {
+        Some(Format::Cloud)
+    } else {
+        None
+    }
+}
+
+fn parse_publisher(data: &Value, publisher: &str) -> Result<Stat, serde_json::Error> {
+    let stats = match detect_format(data) {
+        Some(Format::Cloud) => data["relays"]
+            .as_object()
+            .and_then(|relays| {
+                relays
+                    .values()
+                    .map(|relay| &relay["publishers"][publisher])
+                    .find(|stats| !stats.is_null())
+            })
+            .unwrap_or(&Value::Null),
+        Some(Format::SelfHosted) | None => &data["publishers"][publisher],
+    };
+
+    serde_json::from_value(stats.to_owned())
+}
+
+#[async_trait]
+#[typetag::serde]
+impl SwitchLogic for Belabox {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
+        };
+
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Belabox {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -140,4 +230,101 @@ impl Bsl for Belabox {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Kbps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_belabox_stats() {
+        let data: Value = serde_json::from_str(
+            r#"{
+                "publishers": {
+                    "publish/live/feed1": {
+                        "bitrate": 6580,
+                        "buffer": 2000,
+                        "dropped_pkts": 125,
+                        "latency": 2000,
+                        "rtt": 82.31,
+                        "uptime": 1542
+                    }
+                },
+                "consumers": {}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(detect_format(&data), Some(Format::SelfHosted));
+
+        let stats = parse_publisher(&data, "publish/live/feed1").unwrap();
+
+        assert_eq!(stats.bitrate, 6580);
+        assert_eq!(stats.dropped_pkts, 125);
+        assert!(stats.packet_loss().unwrap() > 10.0);
+
+        assert!(parse_publisher(&data, "publish/live/feed2").is_err());
+    }
+
+    #[test]
+    fn parse_belabox_cloud_stats() {
+        let data: Value = serde_json::from_str(
+            r#"{
+                "relays": {
+                    "eu-west": {
+                        "publishers": {}
+                    },
+                    "us-east": {
+                        "publishers": {
+                            "live_abc123": {
+                                "bitrate": 4200,
+                                "buffer": 2000,
+                                "dropped_pkts": 3,
+                                "latency": 2000,
+                                "rtt": 143.7,
+                                "uptime": 601
+                            }
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(detect_format(&data), Some(Format::Cloud));
+
+        let stats = parse_publisher(&data, "live_abc123").unwrap();
+
+        assert_eq!(stats.bitrate, 4200);
+        assert_eq!(stats.rtt, 143.7);
+        assert_eq!(stats.dropped_pkts, 3);
+
+        assert!(parse_publisher(&data, "live_other").is_err());
+    }
+
+    #[test]
+    fn unknown_format_is_an_error() {
+        let data: Value = serde_json::from_str(r#"{ "status": "ok" }"#).unwrap();
+
+        assert_eq!(detect_format(&data), None);
+        assert!(parse_publisher(&data, "publish/live/feed1").is_err());
+    }
 }
@@ -0,0 +1,499 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{default_reqwest_client, Bsl, StreamServersCommands, SwitchLogic};
+use crate::{
+    error,
+    switcher::{SwitchType, Triggers},
+};
+
+/// Path to a value in a JSON document such as `$.publishers[0].bitrate`,
+/// keys with dots or brackets can be quoted like `stats["recv(kbit/s)"]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct JsonPath {
+    raw: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    pub fn parse(raw: &str) -> Result<Self, error::Error> {
+        let invalid = |reason: &str| error::Error::InvalidJsonPath(raw.to_owned(), reason.into());
+
+        let path = raw.trim();
+        let path = path.strip_prefix('$').unwrap_or(path);
+        let mut chars = path.chars().peekable();
+        let mut segments = Vec::new();
+        let mut key = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    if !key.is_empty() {
+                        segments.push(Segment::Key(std::mem::take(&mut key)));
+                    } else if segments.is_empty() && !raw.trim().starts_with('$') {
+                        return Err(invalid("empty key"));
+                    }
+
+                    if matches!(chars.peek(), None | Some('.')) {
+                        return Err(invalid("empty key"));
+                    }
+                }
+                '[' => {
+                    if !key.is_empty() {
+                        segments.push(Segment::Key(std::mem::take(&mut key)));
+                    }
+
+                    let mut inner = String::new();
+                    let mut closed = false;
+
+                    for c in chars.by_ref() {
+                        if c == ']' && !is_open_quote(&inner) {
+                            closed = true;
+                            break;
+                        }
+
+                        inner.push(c);
+                    }
+
+                    if !closed {
+                        return Err(invalid("missing ]"));
+                    }
+
+                    let inner = inner.trim();
+                    let quoted = inner
+                        .strip_prefix('"')
+                        .and_then(|i| i.strip_suffix('"'))
+                        .or_else(|| inner.strip_prefix('\'').and_then(|i| i.strip_suffix('\'')));
+
+                    let segment = match quoted {
+                        Some(quoted) => Segment::Key(quoted.to_owned()),
+                        None => Segment::Index(
+                            inner
+                                .parse()
+                                .map_err(|_| invalid("index must be a number"))?,
+                        ),
+                    };
+
+                    segments.push(segment);
+                }
+                ']' => return Err(invalid("unexpected ]")),
+                c => key.push(c),
+            }
+        }
+
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        }
+
+        if segments.is_empty() {
+            return Err(invalid("empty path"));
+        }
+
+        Ok(Self {
+            raw: raw.to_owned(),
+            segments,
+        })
+    }
+
+    /// The value at the path, numeric keys also index into arrays so
+    /// `streams.0.bitrate` works the same as `streams[0].bitrate`
+    pub fn find<'a>(&self, json: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(json, |value, segment| match (segment, value) {
+                (Segment::Key(key), Value::Object(map)) => map.get(key),
+                (Segment::Key(key), Value::Array(items)) => items.get(key.parse::<usize>().ok()?),
+                (Segment::Index(index), Value::Array(items)) => items.get(*index),
+                _ => None,
+            })
+    }
+
+    /// Numbers and numeric strings at the path
+    pub fn number(&self, json: &Value) -> Option<f64> {
+        match self.find(json)? {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+fn is_open_quote(inner: &str) -> bool {
+    let inner = inner.trim_start();
+
+    match inner.chars().next() {
+        Some(q @ ('"' | '\'')) => inner.len() == 1 || !inner.ends_with(q),
+        _ => false,
+    }
+}
+
+impl TryFrom<String> for JsonPath {
+    type Error = error::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<JsonPath> for String {
+    fn from(path: JsonPath) -> Self {
+        path.raw
+    }
+}
+
+impl fmt::Display for JsonPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// When the stream counts as online
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OnlineCheck {
+    /// Path to the value to check
+    pub path: JsonPath,
+
+    /// Online when the value equals this, otherwise online when the value
+    /// exists and isn't null, false, 0 or empty
+    pub equals: Option<Value>,
+}
+
+impl OnlineCheck {
+    pub fn is_online(&self, json: &Value) -> bool {
+        let value = self.path.find(json);
+
+        match (&self.equals, value) {
+            (Some(expected), Some(value)) => value == expected,
+            (Some(_), None) => false,
+            (None, value) => is_truthy(value),
+        }
+    }
+}
+
+fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(Value::Array(a)) => !a.is_empty(),
+        Some(Value::Object(o)) => !o.is_empty(),
+        Some(Value::Bool(true)) => true,
+    }
+}
+
+/// Stats of the stream taken from the JSON, already converted to kbps and ms
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub bitrate: Option<f64>,
+    pub rtt: Option<f64>,
+}
+
+/// Any stats page that returns JSON, the values are looked up by path
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GenericJson {
+    /// URL to the stats page
+    pub stats_url: String,
+
+    /// Path to the bitrate
+    pub bitrate: JsonPath,
+
+    /// Multiplies the bitrate to get kbps, 0.001 when the page reports bits per second
+    #[serde(default = "default_multiplier")]
+    pub bitrate_multiplier: f64,
+
+    /// Path to the round trip time
+    pub rtt: Option<JsonPath>,
+
+    /// Multiplies the RTT to get milliseconds, 1000 when the page reports seconds
+    #[serde(default = "default_multiplier")]
+    pub rtt_multiplier: f64,
+
+    /// Without a check the stream is online while the bitrate can be found
+    pub online: Option<OnlineCheck>,
+
+    /// Client to make HTTP requests with
+    #[serde(skip, default = "default_reqwest_client")]
+    pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+}
+
+fn default_multiplier() -> f64 {
+    1.0
+}
+
+impl GenericJson {
+    pub async fn get_stats(&self) -> Option<Stats> {
+        let res = match self.client.get(&self.stats_url).send().await {
+            Ok(res) => res,
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
+        };
+
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
+        if !res.status().is_success() {
+            error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
+            return None;
+        }
+
+        self.health.set_failed(false);
+
+        let json: Value = match res.json().await {
+            Ok(json) => json,
+            Err(e) if e.is_timeout() => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
+            Err(e) => {
+                error!("Stats page ({}) didn't return JSON: {}", self.stats_url, e);
+                return None;
+            }
+        };
+
+        trace!("{:#?}", json);
+        self.extract(&json)
+    }
+
+    /// None when the stream is offline
+    pub fn extract(&self, json: &Value) -> Option<Stats> {
+        let bitrate = self
+            .bitrate
+            .number(json)
+            .map(|b| b * self.bitrate_multiplier);
+
+        let online = match &self.online {
+            Some(check) => check.is_online(json),
+            None => bitrate.is_some(),
+        };
+
+        if !online {
+            return None;
+        }
+
+        let rtt = self
+            .rtt
+            .as_ref()
+            .and_then(|path| path.number(json))
+            .map(|rtt| rtt * self.rtt_multiplier);
+
+        Some(Stats { bitrate, rtt })
+    }
+
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stats, triggers: &Triggers) -> SwitchType {
+        let bitrate = stats.bitrate.unwrap_or_default();
+
+        if let Some(offline) = triggers.offline {
+            if bitrate > 0.0 && bitrate <= offline.into() {
+                return SwitchType::Offline;
+            }
+        }
+
+        if let (Some(rtt_offline), Some(rtt)) = (triggers.rtt_offline, stats.rtt) {
+            if rtt >= rtt_offline.into() {
+                return SwitchType::Offline;
+            }
+        }
+
+        if bitrate == 0.0 {
+            return SwitchType::Previous;
+        }
+
+        if let Some(low) = triggers.low {
+            if bitrate <= low.into() {
+                return SwitchType::Low;
+            }
+        }
+
+        if let (Some(rtt_trigger), Some(rtt)) = (triggers.rtt, stats.rtt) {
+            if rtt >= rtt_trigger.into() {
+                return SwitchType::Low;
+            }
+        }
+
+        SwitchType::Normal
+    }
+
+    fn stats_bitrate(&self, stats: &Stats) -> super::Bitrate {
+        let kbps = stats.bitrate.unwrap_or_default().round();
+
+        let message = match stats.rtt {
+            Some(rtt) => format!("{}, {} ms", kbps, rtt.round()),
+            None => format!("{}", kbps),
+        };
+
+        super::Bitrate {
+            message: Some(message),
+            kbps: Some(kbps as u32),
+            rtt: stats.rtt,
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl SwitchLogic for GenericJson {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
+        };
+
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for GenericJson {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
+        }
+    }
+
+    async fn source_info(&self) -> Option<String> {
+        None
+    }
+}
+
+#[typetag::serde]
+impl Bsl for GenericJson {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(config: Value) -> GenericJson {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn paths() {
+        let json = serde_json::json!({
+            "streams": [{ "name": "live", "recv.kbps": 2500, "rtt": "12.5" }]
+        });
+
+        let number = |path: &str| JsonPath::parse(path).unwrap().number(&json);
+
+        assert_eq!(number("$.streams[0]['recv.kbps']"), Some(2500.0));
+        assert_eq!(number("streams.0.rtt"), Some(12.5));
+        assert_eq!(number("$.streams[1].rtt"), None);
+        assert_eq!(number("streams[0].name"), None);
+
+        for invalid in [
+            "",
+            "$",
+            "streams..rtt",
+            "streams[a]",
+            "streams[0",
+            "streams]",
+        ] {
+            assert!(JsonPath::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn invalid_path_in_config() {
+        let config = serde_json::json!({ "statsUrl": "http://localhost", "bitrate": "a..b" });
+        assert!(serde_json::from_value::<GenericJson>(config).is_err());
+    }
+
+    #[test]
+    fn srt_relay_payload() {
+        let generic = server(serde_json::json!({
+            "statsUrl": "http://localhost:8181/stats",
+            "bitrate": "$.publishers[\"live/feed\"].bitrate",
+            "bitrateMultiplier": 0.001,
+            "rtt": "$.publishers[\"live/feed\"].rtt",
+            "rttMultiplier": 1000.0
+        }));
+
+        let live = serde_json::json!({
+            "publishers": { "live/feed": { "bitrate": 4500000, "rtt": 0.045 } }
+        });
+
+        assert_eq!(
+            generic.extract(&live),
+            Some(Stats {
+                bitrate: Some(4500.0),
+                rtt: Some(45.0)
+            })
+        );
+
+        let offline = serde_json::json!({ "publishers": {} });
+        assert_eq!(generic.extract(&offline), None);
+    }
+
+    #[test]
+    fn online_check() {
+        let generic = server(serde_json::json!({
+            "statsUrl": "http://localhost/api",
+            "bitrate": "data.video.kbps",
+            "online": { "path": "data.state", "equals": "publishing" }
+        }));
+
+        let publishing = serde_json::json!({
+            "data": { "state": "publishing", "video": { "kbps": 3000 } }
+        });
+        let idle = serde_json::json!({
+            "data": { "state": "idle", "video": { "kbps": 0 } }
+        });
+
+        assert_eq!(
+            generic.extract(&publishing),
+            Some(Stats {
+                bitrate: Some(3000.0),
+                rtt: None
+            })
+        );
+        assert_eq!(generic.extract(&idle), None);
+    }
+}
@@ -121,6 +121,10 @@ pub struct Mediamtx {
 
     #[serde(skip)]
     pub cache: Arc<Mutex<Cache>>,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
 }
 
 pub struct Cache {
@@ -154,23 +158,40 @@ impl Mediamtx {
 
         let res = match request.send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
+        // Path not found, the server itself is fine
         if res.status() == reqwest::StatusCode::INTERNAL_SERVER_ERROR {
+            self.health.set_failed(false);
             return None;
         }
 
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
+        self.health.set_failed(false);
+
         let stream = match res.json::<StreamStats>().await {
             Ok(stats) => stats,
+            Err(e) if e.is_timeout() => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
             Err(e) => {
                 error!("Error parsing stats ({}) {}", self.stats_url, e);
                 return None;
@@ -239,17 +260,10 @@ impl Mediamtx {
         trace!("{:#?}", stats);
         Some(stats)
     }
-}
 
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Mediamtx {
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let Some(stats) = self.get_stats().await else {
-            return SwitchType::Offline;
-        };
-
-        let ms_rtt = stats.srt.map(|s| s.ms_rtt);
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stats, triggers: &Triggers) -> SwitchType {
+        let ms_rtt = stats.srt.as_ref().map(|s| s.ms_rtt);
 
         if let Some(offline) = triggers.offline {
             if stats.bitrate > 0 && stats.bitrate <= offline {
@@ -285,24 +299,50 @@ impl SwitchLogic for Mediamtx {
 
         SwitchType::Normal
     }
+
+    fn stats_bitrate(&self, stats: &Stats) -> super::Bitrate {
+        let mut message = format!("{}", stats.bitrate);
+        let rtt = stats.srt.as_ref().map(|srt| srt.ms_rtt);
+
+        if let Some(rtt) = rtt {
+            message += &format!(", {} ms", rtt.round());
+        }
+
+        super::Bitrate {
+            message: Some(message),
+            kbps: Some(stats.bitrate),
+            rtt,
+            ..Default::default()
+        }
+    }
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for Mediamtx {
-    async fn bitrate(&self) -> super::Bitrate {
+impl SwitchLogic for Mediamtx {
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
         let Some(stats) = self.get_stats().await else {
-            return super::Bitrate { message: None };
+            return (SwitchType::Offline, Default::default());
         };
 
-        let mut message = format!("{}", stats.bitrate);
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
 
-        if let Some(srt) = stats.srt {
-            message += &format!(", {} ms", srt.ms_rtt.round());
-        }
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
 
-        super::Bitrate {
-            message: Some(message),
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Mediamtx {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -339,4 +379,13 @@ impl Bsl for Mediamtx {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
 }
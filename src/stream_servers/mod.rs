@@ -1,35 +1,53 @@
-use std::any::Any;
+use std::{
+    any::Any,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
 
-use crate::switcher;
+use crate::{config, switcher};
 
 pub mod belabox;
+pub mod generic;
 pub mod mediamtx;
 pub mod nginx;
 pub mod nimble;
 pub mod nms;
 pub mod obs;
+pub mod ome;
 pub mod rist;
 pub mod sls;
+pub mod srs;
 pub mod xiu;
 
 pub use belabox::Belabox;
+pub use generic::GenericJson;
 pub use mediamtx::Mediamtx;
 pub use nginx::Nginx;
 pub use nimble::Nimble;
 pub use nms::NodeMediaServer;
 pub use obs::Obs;
+pub use ome::OvenMediaEngine;
 pub use rist::Rist;
 pub use sls::SrtLiveServer;
+pub use srs::Srs;
 pub use xiu::Xiu;
 
 #[async_trait]
 #[typetag::serde(tag = "type")]
 pub trait SwitchLogic {
-    /// Which scene to switch to
-    async fn switch(&self, triggers: &switcher::Triggers) -> switcher::SwitchType;
+    /// Which scene to switch to, along with the bitrate of the same stats
+    /// request so the switcher doesn't have to request them again
+    async fn switch(&self, triggers: &switcher::Triggers) -> (switcher::SwitchType, Bitrate);
+
+    /// Whether the last request to the stream server failed
+    fn request_failed(&self) -> bool {
+        false
+    }
 }
 
 /// Chat commands
@@ -43,13 +61,108 @@ pub trait StreamServersCommands {
 #[typetag::serde(tag = "type")]
 pub trait Bsl: SwitchLogic + StreamServersCommands + Send + Sync {
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Unit the stats page reports the bitrate in, `None` when the bitrate
+    /// isn't a reported value
+    fn bitrate_unit(&self) -> Option<BitrateUnit> {
+        None
+    }
+
+    /// Reads the reported bitrate in this unit instead, false when the
+    /// stream server doesn't support it
+    fn set_bitrate_unit(&mut self, _unit: BitrateUnit) -> bool {
+        false
+    }
+
+    /// Makes the stats requests with this client, stream servers without
+    /// a stats page ignore it
+    fn set_http_client(&mut self, _client: reqwest::Client) {}
+
+    /// Reads these HTTP statuses of the stats page as offline or as an
+    /// error, false when the stream server doesn't request a stats page
+    fn set_http_statuses(&mut self, _statuses: HttpStatuses) -> bool {
+        false
+    }
+}
+
+/// Unit of a bitrate reported by a stats page. Everything is compared in
+/// Kbps of 1024 bits, the same as NGINX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BitrateUnit {
+    Bps,
+    Kbps,
+    Mbps,
+}
+
+impl BitrateUnit {
+    pub fn to_kbps(self, value: f64) -> u32 {
+        let kbps = match self {
+            BitrateUnit::Bps => value / 1024.0,
+            BitrateUnit::Kbps => value,
+            BitrateUnit::Mbps => value * 1024.0,
+        };
+
+        kbps.max(0.0) as u32
+    }
+}
+
+/// Converts the reported bitrate with the configured unit, `default` is
+/// the conversion of the stream server when there's none
+fn configured_kbps(unit: Option<BitrateUnit>, value: f64, default: u32) -> u32 {
+    unit.map_or(default, |unit| unit.to_kbps(value))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct Bitrate {
     pub message: Option<String>,
+
+    /// Bitrate in Kbps
+    pub kbps: Option<u32>,
+
+    /// Round trip time in ms
+    pub rtt: Option<f64>,
+
+    /// Percentage of packets lost
+    pub packet_loss: Option<f64>,
+
+    /// Percentage of packets that had to be retransmitted
+    pub retransmit_ratio: Option<f64>,
 }
 
+/// Max payload of a SRT packet in bytes
+const SRT_PAYLOAD_SIZE: f64 = 1316.0;
+
+/// Estimates the SRT packets received per second when the stats page
+/// doesn't report the packet count
+fn estimated_srt_packets(kbps: f64) -> f64 {
+    kbps * 1000.0 / 8.0 / SRT_PAYLOAD_SIZE
+}
+
+/// Percentage of `packets` compared to all packets received
+fn packet_percentage(packets: f64, received: f64) -> Option<f64> {
+    let total = received + packets;
+
+    if total <= 0.0 {
+        return None;
+    }
+
+    Some(packets / total * 100.0)
+}
+
+/// Checks the packet loss against the trigger
+pub fn high_packet_loss(packet_loss: Option<f64>, triggers: &switcher::Triggers) -> bool {
+    match (packet_loss, triggers.packet_loss) {
+        (Some(loss), Some(trigger)) => loss >= trigger as f64,
+        _ => false,
+    }
+}
+
+/// How often a stream server is polled without a `poll_interval_ms`
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Polling any faster would only hammer the stats page
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
 // TODO: This needs a better name
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -60,35 +173,489 @@ pub struct StreamServer {
     /// A name to differentiate in case of multiple stream servers
     pub name: String,
 
-    /// Priority
+    /// Lowest number is checked first, without a priority it's checked
+    /// after the servers that have one
     pub priority: Option<i32>,
 
     /// Override default scenes
     pub override_scenes: Option<switcher::SwitchingScenes>,
 
+    /// Override default triggers, unset triggers will use the default
+    pub triggers: Option<switcher::Triggers>,
+
     pub depends_on: Option<DependsOn>,
 
     /// Stream server enabled
     #[serde(default = "default_server_enabled")]
     pub enabled: bool,
+
+    /// Name of an extra OBS instance that should also switch scenes
+    /// when this stream server is used
+    pub instance: Option<String>,
+
+    /// OBS media source that plays the feed, switching back to the live
+    /// scene waits until it's playing
+    pub source: Option<String>,
+
+    /// How often the stats are requested, uses the default when not set
+    pub poll_interval_ms: Option<u64>,
+
+    /// Stream servers with the same group are bonded links of one stream,
+    /// their bitrates are combined before checking the triggers
+    pub group: Option<String>,
+
+    /// Unit the stats page reports the bitrate in, only needed when it's
+    /// not the unit the stream server normally uses
+    pub bitrate_unit: Option<BitrateUnit>,
+
+    /// HTTP statuses of the stats page that mean offline or an error,
+    /// for relays that only tell whether they're live with the status
+    pub http_statuses: Option<HttpStatuses>,
+
+    /// Slows down polling while the stream server can't be reached
+    #[serde(skip)]
+    pub backoff: Backoff,
+
+    /// Bitrate of the last time the switcher polled the stream server
+    #[serde(skip)]
+    pub last_poll: LastPoll,
+}
+
+impl StreamServer {
+    /// The poll interval, raised to the minimum when set too low
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_POLL_INTERVAL)
+            .max(MIN_POLL_INTERVAL)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DependsOn {
     pub name: String,
-    pub backup_scenes: switcher::SwitchingScenes,
+
+    /// Scenes to use while the depended on server is offline, without them
+    /// this server is skipped until the depended on server is live again
+    pub backup_scenes: Option<switcher::SwitchingScenes>,
 }
 
 fn default_server_enabled() -> bool {
     true
 }
 
+/// HTTP statuses of the stats page that don't need to be read by the
+/// stream server, a status that's configured exactly wins over a class
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HttpStatuses {
+    /// The stream is offline, e.g. `404`
+    pub offline: Vec<StatusPattern>,
+
+    /// The stats page failed and gets polled less often, e.g. `"5xx"`
+    pub error: Vec<StatusPattern>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusOutcome {
+    Offline,
+    Error,
+}
+
+impl HttpStatuses {
+    /// What the status means, None when it's not configured
+    pub fn outcome(&self, status: u16) -> Option<StatusOutcome> {
+        let find = |exact: bool| {
+            let matches = |patterns: &[StatusPattern]| {
+                patterns
+                    .iter()
+                    .any(|p| p.is_exact() == exact && p.matches(status))
+            };
+
+            if matches(&self.offline) {
+                Some(StatusOutcome::Offline)
+            } else if matches(&self.error) {
+                Some(StatusOutcome::Error)
+            } else {
+                None
+            }
+        };
+
+        find(true).or_else(|| find(false))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum StatusPattern {
+    /// One status, e.g. `404`
+    Code(u16),
+
+    /// Every status of a class, e.g. `"5xx"`
+    Class(String),
+}
+
+impl StatusPattern {
+    fn is_exact(&self) -> bool {
+        matches!(self, StatusPattern::Code(_))
+    }
+
+    fn matches(&self, status: u16) -> bool {
+        match self {
+            StatusPattern::Code(code) => *code == status,
+            StatusPattern::Class(class) => {
+                let mut chars = class.chars();
+                let digit = chars.next().and_then(|c| c.to_digit(10));
+                let rest: String = chars.collect();
+
+                rest.eq_ignore_ascii_case("xx") && digit == Some(u32::from(status / 100))
+            }
+        }
+    }
+}
+
+/// Keeps track of whether the last request to a stream server failed
+#[derive(Debug, Default)]
+pub struct RequestHealth {
+    failed: AtomicBool,
+
+    /// Configured meaning of some HTTP statuses
+    statuses: Option<HttpStatuses>,
+}
+
+impl RequestHealth {
+    pub fn set_failed(&self, failed: bool) {
+        self.failed.store(failed, Ordering::Relaxed);
+    }
+
+    pub fn set_statuses(&mut self, statuses: HttpStatuses) {
+        self.statuses = Some(statuses);
+    }
+
+    /// Handles a status of the stats page that's configured as offline or
+    /// as an error, None when the stream server should read the response
+    pub fn configured_status(
+        &self,
+        url: &str,
+        status: reqwest::StatusCode,
+    ) -> Option<StatusOutcome> {
+        let outcome = self.statuses.as_ref()?.outcome(status.as_u16())?;
+
+        match outcome {
+            StatusOutcome::Offline => {
+                debug!(
+                    "Stats page ({}) returned {}, stream is offline",
+                    url, status
+                );
+                self.set_failed(false);
+            }
+            StatusOutcome::Error => {
+                error!("Stats page ({}) returned {}", url, status);
+                self.set_failed(true);
+            }
+        }
+
+        Some(outcome)
+    }
+
+    /// Marks the request as failed, a stats page that doesn't answer in
+    /// time isn't an offline stream
+    pub fn request_error(&self, url: &str, error: &reqwest::Error) {
+        if error.is_timeout() {
+            error!("Stats page ({}) didn't answer in time", url);
+        } else {
+            error!("Stats page ({}) is unreachable: {}", url, error);
+        }
+
+        self.set_failed(true);
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Backoff {
+    state: std::sync::Mutex<BackoffState>,
+}
+
+#[derive(Debug, Default)]
+struct BackoffState {
+    /// Consecutive failed requests
+    failures: u32,
+
+    /// Don't poll the stream server again before this instant
+    retry_at: Option<Instant>,
+
+    /// Switch type of the last poll, used until the next poll
+    last_switch_type: Option<switcher::SwitchType>,
+}
+
+impl Backoff {
+    /// The switch type to keep using when it's not time to poll again
+    pub fn cached(&self) -> Option<switcher::SwitchType> {
+        let state = self.state.lock().unwrap();
+
+        match state.retry_at {
+            Some(retry_at) if Instant::now() < retry_at => state.last_switch_type,
+            _ => None,
+        }
+    }
+
+    /// Updates the backoff after a poll, returns the delay when backing off
+    pub fn update(
+        &self,
+        failed: bool,
+        switch_type: switcher::SwitchType,
+        interval: Duration,
+        config: &config::PollBackoff,
+    ) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        state.last_switch_type = Some(switch_type);
+
+        if !failed {
+            state.failures = 0;
+            state.retry_at = Some(Instant::now() + interval);
+            return None;
+        }
+
+        state.failures = state.failures.saturating_add(1);
+
+        let delay = with_jitter(backoff_delay(state.failures, interval, config));
+        state.retry_at = Some(Instant::now() + delay);
+
+        Some(delay)
+    }
+}
+
+/// Keeps the bitrate the switcher last polled so everything else reads
+/// the same sample instead of requesting the stats page again
+#[derive(Debug, Default)]
+pub struct LastPoll {
+    polled: std::sync::Mutex<Option<(Instant, Bitrate)>>,
+}
+
+impl LastPoll {
+    pub fn set(&self, bitrate: Bitrate) {
+        *self.polled.lock().unwrap() = Some((Instant::now(), bitrate));
+    }
+
+    /// Bitrate of the last poll, empty when it hasn't been polled yet
+    pub fn bitrate(&self) -> Bitrate {
+        self.polled
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(_, bitrate)| bitrate.clone())
+            .unwrap_or_default()
+    }
+
+    /// Bitrate of the last poll when it was polled at or after `since`
+    pub fn polled_since(&self, since: Instant) -> Option<Bitrate> {
+        self.polled
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(at, _)| *at >= since)
+            .map(|(_, bitrate)| bitrate.clone())
+    }
+}
+
+/// Doubles the base interval for every consecutive failure up to the max interval,
+/// the base interval is the poll interval when that's longer
+fn backoff_delay(failures: u32, interval: Duration, config: &config::PollBackoff) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let base = Duration::from_secs(config.base_interval).max(interval);
+    let delay = base.saturating_mul(1 << exponent);
+
+    delay.min(Duration::from_secs(config.max_interval))
+}
+
+/// Picks a random delay between half and the full delay so servers
+/// recovering from an outage don't get polled in sync
+fn with_jitter(delay: Duration) -> Duration {
+    use rand::Rng;
+
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Reads the body of the stats response, counts as a failed request
+/// when it doesn't arrive
+async fn response_text(
+    res: reqwest::Response,
+    url: &str,
+    health: &RequestHealth,
+) -> Option<String> {
+    match res.text().await {
+        Ok(text) => Some(text),
+        Err(e) => {
+            health.request_error(url, &e);
+            None
+        }
+    }
+}
+
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
-fn default_reqwest_client() -> reqwest::Client {
+/// How long a stats request may take without a `requestTimeoutMs`
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Any shorter and a stats page that's just a bit slow is never reached
+pub const MIN_REQUEST_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// A stats page that doesn't accept the connection by then is down
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Used until the client of the config is set, clones share the connection pool
+static DEFAULT_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| http_client(DEFAULT_REQUEST_TIMEOUT));
+
+/// Client shared by the stream servers so the connections to the stats
+/// pages are reused
+pub fn http_client(request_timeout: Duration) -> reqwest::Client {
     reqwest::Client::builder()
         .user_agent(APP_USER_AGENT)
+        .connect_timeout(CONNECT_TIMEOUT.min(request_timeout))
+        .timeout(request_timeout)
         .build()
         .expect("Failed to create reqwest client")
 }
+
+pub fn default_reqwest_client() -> reqwest::Client {
+    DEFAULT_CLIENT.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitrate_units_convert_to_the_same_kbps() {
+        assert_eq!(BitrateUnit::Bps.to_kbps(5_242_880.0), 5120);
+        assert_eq!(BitrateUnit::Kbps.to_kbps(5120.0), 5120);
+        assert_eq!(BitrateUnit::Mbps.to_kbps(5.0), 5120);
+
+        assert_eq!(BitrateUnit::Mbps.to_kbps(0.5), 512);
+        assert_eq!(BitrateUnit::Bps.to_kbps(-1.0), 0);
+    }
+
+    #[test]
+    fn default_conversion_without_unit() {
+        assert_eq!(configured_kbps(None, 5_242_880.0, 5000), 5000);
+        assert_eq!(
+            configured_kbps(Some(BitrateUnit::Bps), 5_242_880.0, 5000),
+            5120
+        );
+    }
+
+    #[test]
+    fn bitrate_unit_by_name() {
+        let units: Vec<BitrateUnit> = serde_json::from_str(r#"["Bps", "Kbps", "Mbps"]"#).unwrap();
+
+        assert_eq!(
+            units,
+            vec![BitrateUnit::Bps, BitrateUnit::Kbps, BitrateUnit::Mbps]
+        );
+    }
+
+    #[test]
+    fn configured_statuses_are_offline_or_error() {
+        let statuses: HttpStatuses =
+            serde_json::from_str(r#"{ "offline": [404], "error": ["5xx"] }"#).unwrap();
+
+        let health = RequestHealth {
+            statuses: Some(statuses),
+            ..Default::default()
+        };
+
+        let url = "http://localhost/stats";
+
+        assert_eq!(
+            health.configured_status(url, reqwest::StatusCode::NOT_FOUND),
+            Some(StatusOutcome::Offline)
+        );
+        assert!(!health.failed());
+
+        assert_eq!(
+            health.configured_status(url, reqwest::StatusCode::SERVICE_UNAVAILABLE),
+            Some(StatusOutcome::Error)
+        );
+        assert!(health.failed());
+
+        assert_eq!(health.configured_status(url, reqwest::StatusCode::OK), None);
+    }
+
+    #[test]
+    fn exact_status_wins_over_class() {
+        let statuses = HttpStatuses {
+            offline: vec![StatusPattern::Class("4xx".to_string())],
+            error: vec![StatusPattern::Code(429)],
+        };
+
+        assert_eq!(statuses.outcome(404), Some(StatusOutcome::Offline));
+        assert_eq!(statuses.outcome(429), Some(StatusOutcome::Error));
+        assert_eq!(statuses.outcome(500), None);
+    }
+
+    #[test]
+    fn backoff_delay_is_capped() {
+        let config = config::PollBackoff {
+            base_interval: 2,
+            max_interval: 30,
+        };
+
+        let interval = DEFAULT_POLL_INTERVAL;
+
+        assert_eq!(backoff_delay(1, interval, &config), Duration::from_secs(2));
+        assert_eq!(backoff_delay(2, interval, &config), Duration::from_secs(4));
+        assert_eq!(backoff_delay(4, interval, &config), Duration::from_secs(16));
+        assert_eq!(backoff_delay(5, interval, &config), Duration::from_secs(30));
+        assert_eq!(
+            backoff_delay(u32::MAX, interval, &config),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn backoff_multiplies_longer_poll_interval() {
+        let config = config::PollBackoff {
+            base_interval: 2,
+            max_interval: 60,
+        };
+        let interval = Duration::from_secs(5);
+
+        assert_eq!(backoff_delay(1, interval, &config), Duration::from_secs(5));
+        assert_eq!(backoff_delay(3, interval, &config), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn waits_for_poll_interval() {
+        let config = config::PollBackoff::default();
+        let backoff = Backoff::default();
+
+        backoff.update(
+            false,
+            switcher::SwitchType::Normal,
+            Duration::from_secs(60),
+            &config,
+        );
+        assert_eq!(backoff.cached(), Some(switcher::SwitchType::Normal));
+    }
+
+    #[test]
+    fn backoff_resets_on_success() {
+        let config = config::PollBackoff::default();
+        let backoff = Backoff::default();
+
+        assert!(backoff
+            .update(true, switcher::SwitchType::Offline, Duration::ZERO, &config)
+            .is_some());
+        assert_eq!(backoff.cached(), Some(switcher::SwitchType::Offline));
+
+        assert!(backoff
+            .update(false, switcher::SwitchType::Normal, Duration::ZERO, &config)
+            .is_none());
+        assert_eq!(backoff.cached(), None);
+    }
+}
@@ -1,17 +1,39 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
 use log::{error, trace};
 use serde::{Deserialize, Serialize};
 
 use super::{default_reqwest_client, Bsl, StreamServersCommands, SwitchLogic};
-use crate::switcher::{SwitchType, Triggers};
+use crate::{
+    error::Error,
+    switcher::{SwitchType, Triggers},
+};
 
 #[derive(Deserialize, Debug)]
 struct NginxRtmpStats {
-    server: NginxRtmpServer,
+    /// Every `server` block in the rtmp config
+    #[serde(default)]
+    server: Vec<NginxRtmpServer>,
+}
+
+impl NginxRtmpStats {
+    /// The stream publishing with the key in the application, the same key
+    /// in another application or a stream that's only played is ignored
+    fn publishing_stream(self, application: &str, key: &str) -> Option<NginxRtmpStream> {
+        self.server
+            .into_iter()
+            .flat_map(|server| server.application)
+            .filter(|app| app.name == application)
+            .filter_map(|app| app.live?.stream)
+            .flatten()
+            .find(|stream| stream.name == key && stream.publishing.is_some())
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct NginxRtmpServer {
+    #[serde(default)]
     application: Vec<NginxRtmpApp>,
 }
 
@@ -29,9 +51,11 @@ struct NginxRtmpLive {
 #[derive(Deserialize, Debug)]
 pub struct NginxRtmpStream {
     pub name: String,
+    #[serde(default)]
     pub bw_video: u32,
     pub meta: Option<Meta>,
     pub active: Option<()>,
+    pub publishing: Option<()>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -60,6 +84,13 @@ pub struct Audio {
     sample_rate: Option<u32>,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Auth {
+    username: String,
+    #[serde(default, with = "crate::secret")]
+    password: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Nginx {
@@ -72,30 +103,98 @@ pub struct Nginx {
     /// Stream key
     pub key: String,
 
+    /// HTTP basic auth for the stats page
+    pub auth: Option<Auth>,
+
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default, with = "crate::secret")]
+    pub bearer_token: Option<String>,
+
+    /// Extra headers sent with the stats request, e.g. for a reverse proxy
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl Nginx {
+    /// The stats request with the configured auth and headers
+    fn request(&self) -> reqwest::RequestBuilder {
+        let mut request = self.client.get(&self.stats_url);
+
+        if let Some(auth) = &self.auth {
+            request = request.basic_auth(&auth.username, auth.password.as_ref());
+        }
+
+        if let Some(token) = &self.bearer_token {
+            request = request.bearer_auth(token);
+        }
+
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        request
+    }
+
+    async fn fetch_stats(&self) -> Result<String, Error> {
+        let res = self.request().send().await?;
+
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return Err(Error::StatsPageConfiguredStatus(res.status().as_u16()));
+        }
+
+        match res.status() {
+            reqwest::StatusCode::OK => Ok(res.text().await?),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(Error::StatsPageUnauthorized(res.status().as_u16()))
+            }
+            _ => Err(Error::StatsPageNotAvailable),
+        }
+    }
+
     /// 0 bitrate means the stream just started.
     /// the stats update every 10 seconds.
     pub async fn get_stats(&self) -> Option<NginxRtmpStream> {
-        //TODO: keep the reqwest object around for future requests
-        let res = match self.client.get(&self.stats_url).send().await {
-            Ok(res) => res,
+        let text = match self.fetch_stats().await {
+            Ok(text) => text,
+            Err(Error::PageRequest(e)) => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
+            Err(Error::StatsPageUnauthorized(status)) => {
+                error!(
+                    "Stats page ({}) rejected the request ({}), check the auth in the config",
+                    self.stats_url, status
+                );
+                self.health.set_failed(true);
+                return None;
+            }
+            // Already logged and marked as failed or not
+            Err(Error::StatsPageConfiguredStatus(_)) => return None,
             Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+                error!("Error accessing stats page ({})", self.stats_url);
+                self.health.set_failed(true);
                 return None;
             }
         };
 
-        if res.status() != reqwest::StatusCode::OK {
-            error!("Error accessing stats page ({})", self.stats_url);
-            return None;
-        }
+        self.health.set_failed(false);
 
-        let text = res.text().await.ok()?;
         let parsed: NginxRtmpStats = match quick_xml::de::from_str(&text) {
             Ok(stats) => stats,
             Err(error) => {
@@ -105,38 +204,21 @@ impl Nginx {
             }
         };
 
-        let filter: Option<NginxRtmpStream> = parsed
-            .server
-            .application
-            .into_iter()
-            .filter_map(|x| {
-                if x.live.is_some() && x.name == self.application {
-                    x.live.unwrap().stream
-                } else {
-                    None
-                }
-            })
-            .flatten()
-            .filter(|x| x.name == self.key)
-            .collect::<Vec<NginxRtmpStream>>()
-            .pop();
+        // Not publishing is offline, even when other streams are live
+        let stream = parsed.publishing_stream(&self.application, &self.key);
 
-        trace!("{:#?}", filter);
-        filter
+        trace!("{:#?}", stream);
+        stream
     }
-}
 
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Nginx {
-    /// Which scene to switch to
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
+    /// NGINX reports the video bitrate in bits per second
+    fn kbps(&self, bw_video: u32) -> u32 {
+        super::configured_kbps(self.bitrate_unit, bw_video.into(), bw_video / 1024)
+    }
 
-        let bitrate = stats.bw_video / 1024;
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &NginxRtmpStream, triggers: &Triggers) -> SwitchType {
+        let bitrate = self.kbps(stats.bw_video);
 
         if stats.active.is_none() {
             return SwitchType::Offline;
@@ -160,20 +242,45 @@ impl SwitchLogic for Nginx {
 
         return SwitchType::Normal;
     }
+
+    fn stats_bitrate(&self, stats: &NginxRtmpStream) -> super::Bitrate {
+        let bitrate = self.kbps(stats.bw_video);
+        super::Bitrate {
+            message: Some(format!("{}", bitrate)),
+            kbps: Some(bitrate),
+            rtt: None,
+            ..Default::default()
+        }
+    }
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for Nginx {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
+impl SwitchLogic for Nginx {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
         };
 
-        let bitrate = stats.bw_video / 1024;
-        super::Bitrate {
-            message: Some(format!("{}", bitrate)),
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Nginx {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -183,7 +290,7 @@ impl StreamServersCommands for Nginx {
         let video = meta.video;
         let audio = meta.audio;
 
-        let bitrate = format!("{} Kbps", stats.bw_video / 1024);
+        let bitrate = format!("{} Kbps", self.kbps(stats.bw_video));
 
         let v_info = format!(
             "{}p{} | {} {} {}",
@@ -211,6 +318,24 @@ impl Bsl for Nginx {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Bps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
 }
 
 // impl From<db::StreamServer> for Nginx {
@@ -230,6 +355,65 @@ impl Bsl for Nginx {
 mod tests {
     use super::*;
 
+    fn nginx() -> Nginx {
+        Nginx {
+            stats_url: "http://localhost/stat".to_string(),
+            application: "publish".to_string(),
+            key: "live".to_string(),
+            auth: None,
+            bearer_token: None,
+            headers: HashMap::new(),
+            client: reqwest::Client::new(),
+            health: Default::default(),
+            bitrate_unit: None,
+        }
+    }
+
+    #[test]
+    fn basic_auth_header_is_sent() {
+        let nginx = Nginx {
+            auth: Some(Auth {
+                username: "user".to_string(),
+                password: Some("pass".to_string()),
+            }),
+            ..nginx()
+        };
+
+        let request = nginx.request().build().unwrap();
+
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Basic dXNlcjpwYXNz"
+        );
+    }
+
+    #[test]
+    fn bearer_and_custom_headers_are_sent() {
+        let nginx = Nginx {
+            bearer_token: Some("secret".to_string()),
+            headers: HashMap::from([("X-Proxy-Key".to_string(), "abc".to_string())]),
+            ..nginx()
+        };
+
+        let request = nginx.request().build().unwrap();
+
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Bearer secret"
+        );
+        assert_eq!(request.headers()["x-proxy-key"], "abc");
+    }
+
+    #[test]
+    fn no_auth_by_default() {
+        let request = nginx().request().build().unwrap();
+
+        assert!(request
+            .headers()
+            .get(reqwest::header::AUTHORIZATION)
+            .is_none());
+    }
+
     #[test]
     fn invalid_digit() {
         let text = r#"
@@ -302,6 +486,53 @@ mod tests {
         println!("{:#?}", parsed);
     }
 
+    #[test]
+    fn multiple_streams() {
+        let stream = |name: &str, bw_video: u32, publishing: bool| {
+            format!(
+                "<stream><name>{}</name><bw_video>{}</bw_video>{}<active/></stream>",
+                name,
+                bw_video,
+                if publishing { "<publishing/>" } else { "" }
+            )
+        };
+        let application = |name: &str, streams: &[String]| {
+            format!(
+                "<application><name>{}</name><live>{}<nclients>1</nclients></live></application>",
+                name,
+                streams.concat()
+            )
+        };
+
+        let text = format!(
+            "<rtmp><server>{}{}</server><server>{}</server></rtmp>",
+            application(
+                "publish",
+                &[stream("other", 1024000, true), stream("played", 0, false)]
+            ),
+            application("backup", &[stream("live", 512000, true)]),
+            application("publish", &[stream("live", 2048000, true)]),
+        );
+
+        let find = |application: &str, key: &str| {
+            let parsed: NginxRtmpStats = quick_xml::de::from_str(&text).unwrap();
+            parsed
+                .publishing_stream(application, key)
+                .map(|s| s.bw_video / 1024)
+        };
+
+        assert_eq!(find("publish", "live"), Some(2000));
+        assert_eq!(find("backup", "live"), Some(500));
+        assert_eq!(find("publish", "other"), Some(1000));
+
+        // Listed but nobody is publishing
+        assert_eq!(find("publish", "played"), None);
+
+        // Not in the stats while other streams are live
+        assert_eq!(find("publish", "missing"), None);
+        assert_eq!(find("missing", "live"), None);
+    }
+
     #[test]
     fn application_without_live() {
         let text = r#"
@@ -99,6 +99,14 @@ pub struct Nimble {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl Nimble {
@@ -107,18 +115,29 @@ impl Nimble {
 
         let res = match self.client.get(&url).send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
         let srt_stats: NimbleSrtStats = serde_json::from_str(&text).ok()?;
 
         let srt_receiver = srt_stats
@@ -134,20 +153,31 @@ impl Nimble {
         // Probably requires nimble to be configured to make the video from SRT available on RTMP even though it's not used anywhere
         let url = format!("{}/manage/rtmp_status", &self.stats_url);
 
-        let res = match reqwest::get(&url).await {
+        let res = match self.client.get(&url).send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
         let rtmp_stats: Vec<NimbleRtmpStats> = serde_json::from_str(&text).ok()?;
 
         let rtmp_app = rtmp_stats.iter().find(|x| x.app == self.application)?;
@@ -160,19 +190,11 @@ impl Nimble {
 
         Some(stat)
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Nimble {
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
 
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stat, triggers: &Triggers) -> SwitchType {
         let bitrate = stats.rtmp.bandwidth.parse::<u32>().unwrap();
-        let bitrate = bitrate / 1024;
+        let bitrate = super::configured_kbps(self.bitrate_unit, bitrate.into(), bitrate / 1024);
 
         if let Some(offline) = triggers.offline {
             if bitrate > 0 && bitrate <= offline {
@@ -204,23 +226,47 @@ impl SwitchLogic for Nimble {
 
         return SwitchType::Normal;
     }
+
+    fn stats_bitrate(&self, stats: &Stat) -> super::Bitrate {
+        let bitrate = stats.rtmp.bandwidth.parse::<u32>().unwrap();
+        let bitrate = super::configured_kbps(self.bitrate_unit, bitrate.into(), bitrate / 1024);
+
+        let message = format!("{}, {} ms", bitrate, stats.srt.stats.link.rtt.round());
+        super::Bitrate {
+            message: Some(message),
+            kbps: Some(bitrate),
+            rtt: Some(stats.srt.stats.link.rtt),
+            ..Default::default()
+        }
+    }
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for Nimble {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
+impl SwitchLogic for Nimble {
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
         };
 
-        let bitrate = stats.rtmp.bandwidth.parse::<u32>().unwrap();
-        let bitrate = bitrate / 1024;
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
 
-        let message = format!("{}, {} ms", bitrate, stats.srt.stats.link.rtt.round());
-        super::Bitrate {
-            message: Some(message),
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Nimble {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -234,4 +280,22 @@ impl Bsl for Nimble {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Bps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
 }
@@ -37,6 +37,14 @@ pub struct NodeMediaServer {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl NodeMediaServer {
@@ -52,35 +60,42 @@ impl NodeMediaServer {
 
         let res = match request.send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
-        let stream: Stat = serde_json::from_str(&text).ok()?;
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
+        let mut stream: Stat = serde_json::from_str(&text).ok()?;
+
+        // Converted right away so the stats are in Kbps everywhere
+        if let Some(unit) = self.bitrate_unit {
+            stream.bitrate = unit.to_kbps(stream.bitrate as f64).into();
+        }
 
         trace!("{:#?}", stream);
         Some(stream)
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for NodeMediaServer {
-    /// Which scene to switch to
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
 
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stat, triggers: &Triggers) -> SwitchType {
         if !stats.is_live {
             return SwitchType::Offline;
         }
@@ -103,23 +118,48 @@ impl SwitchLogic for NodeMediaServer {
 
         return SwitchType::Normal;
     }
+
+    fn stats_bitrate(&self, stats: &Stat) -> super::Bitrate {
+        if !stats.is_live {
+            return super::Bitrate::default();
+        }
+
+        super::Bitrate {
+            message: Some(format!("{}", stats.bitrate)),
+            kbps: Some(stats.bitrate as u32),
+            rtt: None,
+            ..Default::default()
+        }
+    }
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for NodeMediaServer {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
+impl SwitchLogic for NodeMediaServer {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
         };
 
-        if !stats.is_live {
-            return super::Bitrate { message: None };
-        }
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
 
-        super::Bitrate {
-            message: Some(format!("{}", stats.bitrate)),
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for NodeMediaServer {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -135,4 +175,22 @@ impl Bsl for NodeMediaServer {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Kbps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
 }
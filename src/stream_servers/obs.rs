@@ -59,17 +59,19 @@ impl Obs {
 #[async_trait]
 #[typetag::serde]
 impl SwitchLogic for Obs {
-    async fn switch(&self, _: &Triggers) -> SwitchType {
+    async fn switch(&self, _: &Triggers) -> (SwitchType, super::Bitrate) {
         let (state, sec) = match self.get_stats().await {
             Some(stats) => stats,
-            None => return SwitchType::Offline,
+            None => return (SwitchType::Offline, Default::default()),
         };
 
+        let bitrate = media_bitrate(&state);
+
         if matches!(state, MediaState::Playing) && sec >= 3 {
-            return SwitchType::Normal;
+            return (SwitchType::Normal, bitrate);
         }
 
-        SwitchType::Offline
+        (SwitchType::Offline, bitrate)
     }
 }
 
@@ -77,18 +79,9 @@ impl SwitchLogic for Obs {
 #[typetag::serde]
 impl StreamServersCommands for Obs {
     async fn bitrate(&self) -> super::Bitrate {
-        let (state, _) = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
-        };
-
-        if !matches!(state, MediaState::Playing) {
-            return super::Bitrate { message: None };
-        }
-
-        let message = format!("{:?}", state);
-        super::Bitrate {
-            message: Some(message),
+        match self.get_stats().await {
+            Some((state, _)) => media_bitrate(&state),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -103,6 +96,18 @@ impl StreamServersCommands for Obs {
     }
 }
 
+/// The media source only reports whether it's playing
+fn media_bitrate(state: &MediaState) -> super::Bitrate {
+    if !matches!(state, MediaState::Playing) {
+        return super::Bitrate::default();
+    }
+
+    super::Bitrate {
+        message: Some(format!("{:?}", state)),
+        ..Default::default()
+    }
+}
+
 #[typetag::serde]
 impl Bsl for Obs {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
@@ -0,0 +1,345 @@
+use async_trait::async_trait;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use tracing::{error, trace};
+
+use super::{default_reqwest_client, Bsl, StreamServersCommands, SwitchLogic};
+use crate::switcher::{SwitchType, Triggers};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status_code: u16,
+    pub message: String,
+    pub response: Option<Stats>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    pub created_time: String,
+
+    /// Bits per second received during the last second
+    pub last_throughput_in: u64,
+
+    /// Average bits per second received since the stream was created
+    pub avg_throughput_in: u64,
+
+    pub total_bytes_in: u64,
+
+    /// Viewers of the stream
+    pub total_connections: u64,
+}
+
+impl Stats {
+    pub fn kbps(&self) -> u32 {
+        (self.last_throughput_in / 1000) as u32
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OvenMediaEngine {
+    /// URL to the OvenMediaEngine REST API (ex; http://localhost:8081 )
+    pub api_url: String,
+
+    #[serde(default = "default_vhost")]
+    pub vhost: String,
+
+    #[serde(default = "default_app")]
+    pub app: String,
+
+    /// Name of the input stream
+    pub stream: String,
+
+    /// The `AccessToken` from the API section of Server.xml
+    #[serde(default, with = "crate::secret")]
+    pub access_token: Option<String>,
+
+    /// Client to make HTTP requests with
+    #[serde(skip, default = "default_reqwest_client")]
+    pub client: reqwest::Client,
+
+    /// Whether the API could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
+}
+
+fn default_vhost() -> String {
+    "default".to_string()
+}
+
+fn default_app() -> String {
+    "app".to_string()
+}
+
+impl OvenMediaEngine {
+    pub fn stats_url(&self) -> String {
+        format!(
+            "{}/v1/stats/current/vhosts/{}/apps/{}/streams/{}",
+            self.api_url.trim_end_matches('/'),
+            self.vhost,
+            self.app,
+            self.stream
+        )
+    }
+
+    /// OvenMediaEngine expects the access token base64 encoded
+    fn request(&self) -> reqwest::RequestBuilder {
+        let request = self.client.get(self.stats_url());
+
+        match &self.access_token {
+            Some(token) => {
+                let token = base64::engine::general_purpose::STANDARD.encode(token);
+                request.header(reqwest::header::AUTHORIZATION, format!("Basic {}", token))
+            }
+            None => request,
+        }
+    }
+
+    pub async fn get_stats(&self) -> Option<Stats> {
+        let stats_url = self.stats_url();
+
+        let res = match self.request().send().await {
+            Ok(res) => res,
+            Err(e) => {
+                self.health.request_error(&stats_url, &e);
+                return None;
+            }
+        };
+
+        if self
+            .health
+            .configured_status(&stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
+        match res.status() {
+            reqwest::StatusCode::OK => {}
+            // The stream only exists while there's an input connection
+            reqwest::StatusCode::NOT_FOUND => {
+                self.health.set_failed(false);
+                return None;
+            }
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                error!("OvenMediaEngine rejected the access token ({})", stats_url);
+                self.health.set_failed(true);
+                return None;
+            }
+            _ => {
+                error!("Error accessing stats page ({})", stats_url);
+                self.health.set_failed(true);
+                return None;
+            }
+        }
+
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &stats_url, &self.health).await?;
+
+        match parse_stats(&text) {
+            Ok(stats) => {
+                trace!("{:#?}", stats);
+                stats
+            }
+            Err(e) => {
+                error!("Error parsing stats ({}) {}", stats_url, e);
+                None
+            }
+        }
+    }
+
+    /// OvenMediaEngine reports bits per second
+    fn kbps(&self, stats: &Stats) -> u32 {
+        super::configured_kbps(
+            self.bitrate_unit,
+            stats.last_throughput_in as f64,
+            stats.kbps(),
+        )
+    }
+
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stats, triggers: &Triggers) -> SwitchType {
+        let bitrate = self.kbps(stats);
+
+        if bitrate == 0 {
+            return SwitchType::Offline;
+        }
+
+        if let Some(offline) = triggers.offline {
+            if bitrate <= offline {
+                return SwitchType::Offline;
+            }
+        }
+
+        if let Some(low) = triggers.low {
+            if bitrate <= low {
+                return SwitchType::Low;
+            }
+        }
+
+        SwitchType::Normal
+    }
+
+    fn stats_bitrate(&self, stats: &Stats) -> super::Bitrate {
+        let kbps = self.kbps(stats);
+
+        super::Bitrate {
+            message: Some(kbps.to_string()),
+            kbps: Some(kbps),
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_stats(body: &str) -> Result<Option<Stats>, serde_json::Error> {
+    let res: Response = serde_json::from_str(body)?;
+
+    if res.status_code != 200 {
+        trace!("OvenMediaEngine responded with {}", res.message);
+        return Ok(None);
+    }
+
+    Ok(res.response)
+}
+
+#[async_trait]
+#[typetag::serde]
+impl SwitchLogic for OvenMediaEngine {
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
+        };
+
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for OvenMediaEngine {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
+        }
+    }
+
+    async fn source_info(&self) -> Option<String> {
+        let stats = self.get_stats().await?;
+
+        Some(format!(
+            "{} Kbps, average {} Kbps, {} viewers",
+            self.kbps(&stats),
+            stats.avg_throughput_in / 1000,
+            stats.total_connections
+        ))
+    }
+}
+
+#[typetag::serde]
+impl Bsl for OvenMediaEngine {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Bps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = r#"{
+        "message": "OK",
+        "response": {
+            "avgThroughputIn": 4781296,
+            "avgThroughputOut": 0,
+            "createdTime": "2024-03-14T18:21:43.113+00:00",
+            "lastRecvTime": "2024-03-14T18:25:10.482+00:00",
+            "lastSentTime": "2024-03-14T18:21:43.113+00:00",
+            "lastThroughputIn": 5124816,
+            "lastThroughputOut": 0,
+            "lastUpdatedTime": "2024-03-14T18:25:10.482+00:00",
+            "maxThroughputIn": 6014522,
+            "maxThroughputOut": 0,
+            "maxTotalConnectionTime": "2024-03-14T18:21:43.113+00:00",
+            "maxTotalConnections": 2,
+            "totalBytesIn": 124213821,
+            "totalBytesOut": 0,
+            "totalConnections": 1
+        },
+        "statusCode": 200
+    }"#;
+
+    fn ome() -> OvenMediaEngine {
+        OvenMediaEngine {
+            api_url: "http://localhost:8081/".to_string(),
+            vhost: default_vhost(),
+            app: default_app(),
+            stream: "irl".to_string(),
+            access_token: Some("ome-access-token".to_string()),
+            client: reqwest::Client::new(),
+            health: Default::default(),
+            bitrate_unit: None,
+        }
+    }
+
+    #[test]
+    fn parse_current_stats() {
+        let stats = parse_stats(STATS).unwrap().unwrap();
+
+        assert_eq!(stats.kbps(), 5124);
+        assert_eq!(stats.total_connections, 1);
+    }
+
+    #[test]
+    fn not_found_has_no_stats() {
+        let body = r#"{"message": "Could not find the stream: [default/#default#app/irl]", "statusCode": 404}"#;
+
+        assert!(parse_stats(body).unwrap().is_none());
+    }
+
+    #[test]
+    fn request_has_access_token() {
+        let request = ome().request().build().unwrap();
+
+        assert_eq!(
+            request.url().as_str(),
+            "http://localhost:8081/v1/stats/current/vhosts/default/apps/app/streams/irl"
+        );
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Basic b21lLWFjY2Vzcy10b2tlbg=="
+        );
+    }
+}
@@ -44,6 +44,14 @@ pub struct Rist {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl Rist {
@@ -51,18 +59,33 @@ impl Rist {
         let res = match self.client.get(&self.stats_url).send().await {
             Ok(res) => res,
             Err(e) => {
-                error!("Stats page ({}) is unreachable ({})", self.stats_url, e);
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
+        self.health.set_failed(false);
+
         let stream = match res.json::<RistStats>().await {
             Ok(stats) => stats,
+            Err(e) if e.is_timeout() => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
             Err(e) => {
                 error!("Error parsing stats ({}) {}", self.stats_url, e);
                 return None;
@@ -74,22 +97,21 @@ impl Rist {
     }
 }
 
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Rist {
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self
-            .get_stats()
-            .await
-            .and_then(|stats| stats.receiver_stats)
-        {
-            Some(s) => s.flowinstant.peers,
-            None => return SwitchType::Offline,
-        };
+impl Rist {
+    /// Combined bitrate of all peers, RIST reports bits per second
+    fn kbps(&self, peers: &[Peer]) -> u32 {
+        let bits = peers.iter().map(|p| p.stats.bitrate).sum::<usize>();
+
+        super::configured_kbps(
+            self.bitrate_unit,
+            bits as f64,
+            (bits / 1024).try_into().unwrap(),
+        )
+    }
 
-        let bitrate: u32 = (stats.iter().map(|p| p.stats.bitrate).sum::<usize>() / 1024)
-            .try_into()
-            .unwrap();
+    /// Which scene to switch to with the stats of these peers
+    fn switch_type(&self, stats: &[Peer], triggers: &Triggers) -> SwitchType {
+        let bitrate = self.kbps(stats);
         let rtt = stats.iter().map(|p| p.stats.rtt).sum::<f64>() / stats.len() as f64;
 
         if let Some(offline) = triggers.offline {
@@ -122,29 +144,54 @@ impl SwitchLogic for Rist {
 
         SwitchType::Normal
     }
+
+    fn stats_bitrate(&self, stats: &[Peer]) -> super::Bitrate {
+        let bitrate = self.kbps(stats);
+        let rtt = stats.iter().map(|p| p.stats.rtt).sum::<f64>() / stats.len() as f64;
+
+        let message = format!("{}, {} ms", bitrate, rtt.round());
+        super::Bitrate {
+            message: Some(message),
+            kbps: Some(bitrate),
+            rtt: Some(rtt),
+            ..Default::default()
+        }
+    }
+
+    async fn get_peers(&self) -> Option<Vec<Peer>> {
+        self.get_stats()
+            .await
+            .and_then(|stats| stats.receiver_stats)
+            .map(|s| s.flowinstant.peers)
+    }
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for Rist {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self
-            .get_stats()
-            .await
-            .and_then(|stats| stats.receiver_stats)
-        {
-            Some(s) => s.flowinstant.peers,
-            None => return super::Bitrate { message: None },
+impl SwitchLogic for Rist {
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_peers().await else {
+            return (SwitchType::Offline, Default::default());
         };
 
-        let bitrate: u32 = (stats.iter().map(|p| p.stats.bitrate).sum::<usize>() / 1024)
-            .try_into()
-            .unwrap();
-        let rtt = stats.iter().map(|p| p.stats.rtt).sum::<f64>() / stats.len() as f64;
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
 
-        let message = format!("{}, {} ms", bitrate, rtt.round());
-        super::Bitrate {
-            message: Some(message),
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Rist {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_peers().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -152,9 +199,7 @@ impl StreamServersCommands for Rist {
     async fn source_info(&self) -> Option<String> {
         let stats = self.get_stats().await?.receiver_stats?.flowinstant.peers;
 
-        let bitrate: u32 = (stats.iter().map(|p| p.stats.bitrate).sum::<usize>() / 1024)
-            .try_into()
-            .unwrap();
+        let bitrate = self.kbps(&stats);
         let rtt = stats.iter().map(|p| p.stats.rtt).sum::<f64>() / stats.len() as f64;
 
         let bitrate = format!("{} Kbps, {} ms", bitrate, rtt.round());
@@ -168,6 +213,24 @@ impl Bsl for Rist {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Bps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
 }
 
 #[cfg(test)]
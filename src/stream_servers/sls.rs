@@ -19,6 +19,27 @@ pub struct Stat {
     pub pkt_rcv_loss: i32,
     pub rtt: f64,
     pub uptime: i64,
+
+    /// Only reported by some SLS versions
+    #[serde(default, alias = "pktRcvRetrans")]
+    pub pkt_retrans: Option<i64>,
+}
+
+impl Stat {
+    fn received_packets(&self) -> f64 {
+        super::estimated_srt_packets(self.mbps_recv_rate * 1000.0)
+    }
+
+    /// Percentage of packets lost
+    pub fn packet_loss(&self) -> Option<f64> {
+        super::packet_percentage(self.pkt_rcv_loss.max(0) as f64, self.received_packets())
+    }
+
+    /// Percentage of packets that were retransmitted
+    pub fn retransmit_ratio(&self) -> Option<f64> {
+        let retrans = self.pkt_retrans?.max(0) as f64;
+        super::packet_percentage(retrans, self.received_packets())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -33,28 +54,49 @@ pub struct SrtLiveServer {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl SrtLiveServer {
     pub async fn get_stats(&self) -> Option<Stat> {
         let res = match self.client.get(&self.stats_url).send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Stats page ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
-        let data: Value = serde_json::from_str(&text).ok()?;
-        let publisher = &data["publishers"][&self.publisher];
+        self.health.set_failed(false);
 
-        let stream: Stat = serde_json::from_value(publisher.to_owned()).ok()?;
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
+        let mut stream = parse_stats(&text, &self.publisher)?;
+
+        // Converted right away so the stats are in Kbps everywhere
+        if let Some(unit) = self.bitrate_unit {
+            stream.bitrate = unit.to_kbps(stream.bitrate as f64).into();
+        }
         // let stream: Stat = match serde_json::from_value(publisher.to_owned()) {
         //     Ok(stats) => stats,
         //     Err(error) => {
@@ -67,17 +109,9 @@ impl SrtLiveServer {
         trace!("{:#?}", stream);
         Some(stream)
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for SrtLiveServer {
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
 
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stat, triggers: &Triggers) -> SwitchType {
         if let Some(offline) = triggers.offline {
             if stats.bitrate > 0 && stats.bitrate <= offline.into() {
                 return SwitchType::Offline;
@@ -106,22 +140,58 @@ impl SwitchLogic for SrtLiveServer {
             }
         }
 
+        if super::high_packet_loss(stats.packet_loss(), triggers) {
+            return SwitchType::Low;
+        }
+
         return SwitchType::Normal;
     }
+
+    fn stats_bitrate(&self, stats: &Stat) -> super::Bitrate {
+        let message = format!("{}, {} ms", stats.bitrate, stats.rtt.round());
+        super::Bitrate {
+            message: Some(message),
+            kbps: Some(stats.bitrate as u32),
+            rtt: Some(stats.rtt),
+            packet_loss: stats.packet_loss(),
+            retransmit_ratio: stats.retransmit_ratio(),
+        }
+    }
+}
+
+fn parse_stats(text: &str, publisher: &str) -> Option<Stat> {
+    let data: Value = serde_json::from_str(text).ok()?;
+    let publisher = &data["publishers"][publisher];
+
+    serde_json::from_value(publisher.to_owned()).ok()
 }
 
 #[async_trait]
 #[typetag::serde]
-impl StreamServersCommands for SrtLiveServer {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
+impl SwitchLogic for SrtLiveServer {
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
         };
 
-        let message = format!("{}, {} ms", stats.bitrate, stats.rtt.round());
-        super::Bitrate {
-            message: Some(message),
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for SrtLiveServer {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -153,4 +223,73 @@ impl Bsl for SrtLiveServer {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Kbps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = r#"{
+        "publishers": {
+            "publish/live/feed1": {
+                "bitrate": 5936,
+                "bytesRcvDrop": 0,
+                "bytesRcvLoss": 13160,
+                "mbpsBandwidth": 19.4,
+                "mbpsRecvRate": 5.9375,
+                "msRcvBuf": 1999,
+                "pktRcvDrop": 0,
+                "pktRcvLoss": 10,
+                "rtt": 45.208,
+                "uptime": 3600
+            }
+        },
+        "status": "ok"
+    }"#;
+
+    #[test]
+    fn parse_sls_stats() {
+        let stats = parse_stats(STATS, "publish/live/feed1").unwrap();
+
+        assert_eq!(stats.bitrate, 5936);
+        assert_eq!(stats.pkt_rcv_loss, 10);
+        assert_eq!(stats.rtt, 45.208);
+        assert_eq!(stats.pkt_retrans, None);
+        assert_eq!(stats.retransmit_ratio(), None);
+
+        let loss = stats.packet_loss().unwrap();
+        assert!(loss > 1.0 && loss < 2.0, "loss was {}", loss);
+
+        assert!(parse_stats(STATS, "publish/live/feed2").is_none());
+    }
+
+    #[test]
+    fn parse_sls_retransmits() {
+        let stats = STATS.replace(
+            r#""uptime": 3600"#,
+            r#""uptime": 3600, "pktRcvRetrans": 56"#,
+        );
+        let stats = parse_stats(&stats, "publish/live/feed1").unwrap();
+
+        assert_eq!(stats.pkt_retrans, Some(56));
+        assert!(stats.retransmit_ratio().unwrap() > 5.0);
+    }
 }
@@ -0,0 +1,309 @@
+use async_trait::async_trait;
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
+
+use super::{default_reqwest_client, Bsl, StreamServersCommands, SwitchLogic};
+use crate::switcher::{SwitchType, Triggers};
+
+#[derive(Deserialize, Debug)]
+struct SrsResponse {
+    code: i32,
+    #[serde(default)]
+    streams: Vec<Stream>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Stream {
+    pub name: String,
+    pub app: String,
+    pub clients: u64,
+    pub kbps: Kbps,
+    pub publish: Publish,
+    pub video: Option<Video>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Kbps {
+    /// Average of the last 30 seconds in kbit/s (1000 bits)
+    pub recv_30s: u64,
+    pub send_30s: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Publish {
+    pub active: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Video {
+    pub codec: String,
+    pub profile: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+}
+
+impl Stream {
+    /// Bitrate in Kbps, SRS uses 1000 bits per kbit while NGINX
+    /// gets divided by 1024 so convert it to match
+    pub fn bitrate(&self) -> u32 {
+        (self.kbps.recv_30s * 1000 / 1024) as u32
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Srs {
+    /// URL to the SRS streams API (ex; http://127.0.0.1:1985/api/v1/streams/ )
+    pub stats_url: String,
+
+    /// Stream application
+    pub application: String,
+
+    /// Stream key
+    pub key: String,
+
+    /// Client to make HTTP requests with
+    #[serde(skip, default = "default_reqwest_client")]
+    pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
+}
+
+impl Srs {
+    pub async fn get_stats(&self) -> Option<Stream> {
+        let res = match self.client.get(&self.stats_url).send().await {
+            Ok(res) => res,
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
+                return None;
+            }
+        };
+
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
+        if res.status() != reqwest::StatusCode::OK {
+            error!("Error accessing stats page ({})", self.stats_url);
+            self.health.set_failed(true);
+            return None;
+        }
+
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
+        let stream = parse_stats(&text, &self.application, &self.key)?;
+
+        trace!("{:#?}", stream);
+        Some(stream)
+    }
+
+    /// SRS reports the bitrate in Kbps
+    fn kbps(&self, stats: &Stream) -> u32 {
+        super::configured_kbps(
+            self.bitrate_unit,
+            stats.kbps.recv_30s as f64,
+            stats.bitrate(),
+        )
+    }
+
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &Stream, triggers: &Triggers) -> SwitchType {
+        let bitrate = self.kbps(stats);
+
+        if let Some(offline) = triggers.offline {
+            if bitrate > 0 && bitrate <= offline {
+                return SwitchType::Offline;
+            }
+        }
+
+        if bitrate == 0 {
+            return SwitchType::Previous;
+        }
+
+        if let Some(low) = triggers.low {
+            if bitrate <= low {
+                return SwitchType::Low;
+            }
+        }
+
+        SwitchType::Normal
+    }
+
+    fn stats_bitrate(&self, stats: &Stream) -> super::Bitrate {
+        let bitrate = self.kbps(stats);
+
+        super::Bitrate {
+            message: Some(format!("{}", bitrate)),
+            kbps: Some(bitrate),
+            ..Default::default()
+        }
+    }
+}
+
+fn parse_stats(text: &str, application: &str, key: &str) -> Option<Stream> {
+    let data: SrsResponse = match serde_json::from_str(text) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Error parsing SRS stats {}", e);
+            return None;
+        }
+    };
+
+    if data.code != 0 {
+        error!("SRS API returned error code {}", data.code);
+        return None;
+    }
+
+    data.streams
+        .into_iter()
+        .find(|s| s.app == application && s.name == key)
+        .filter(|s| s.publish.active)
+}
+
+#[async_trait]
+#[typetag::serde]
+impl SwitchLogic for Srs {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
+        };
+
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Srs {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
+        }
+    }
+
+    async fn source_info(&self) -> Option<String> {
+        let stats = self.get_stats().await?;
+        let bitrate = format!("{} Kbps", self.kbps(&stats));
+
+        let Some(video) = &stats.video else {
+            return Some(bitrate);
+        };
+
+        let resolution = match (video.width, video.height) {
+            (Some(w), Some(h)) => format!("{}x{} ", w, h),
+            _ => String::new(),
+        };
+
+        Some(format!("{}{}, {}", resolution, bitrate, video.codec))
+    }
+}
+
+#[typetag::serde]
+impl Bsl for Srs {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Kbps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS: &str = r#"{
+        "code": 0,
+        "server": "vid-0y0x5s5",
+        "service": "1l3b9v2x",
+        "pid": "1",
+        "streams": [
+            {
+                "id": "vid-3d8m2ol",
+                "name": "feed1",
+                "vhost": "vid-ufd0a3u",
+                "app": "live",
+                "tcUrl": "rtmp://127.0.0.1/live",
+                "url": "/live/feed1",
+                "live_ms": 1700000000000,
+                "clients": 2,
+                "frames": 81234,
+                "send_bytes": 2355132,
+                "recv_bytes": 1022312234,
+                "kbps": { "recv_30s": 6144, "send_30s": 12 },
+                "publish": { "active": true, "cid": "7v68n3w1" },
+                "video": { "codec": "H264", "profile": "High", "level": "4.1", "width": 1920, "height": 1080 },
+                "audio": { "codec": "AAC", "sample_rate": 48000, "channel": 2, "profile": "LC" }
+            },
+            {
+                "id": "vid-8ck72mp",
+                "name": "feed2",
+                "vhost": "vid-ufd0a3u",
+                "app": "live",
+                "tcUrl": "rtmp://127.0.0.1/live",
+                "url": "/live/feed2",
+                "live_ms": 1700000000000,
+                "clients": 1,
+                "frames": 0,
+                "send_bytes": 0,
+                "recv_bytes": 0,
+                "kbps": { "recv_30s": 0, "send_30s": 0 },
+                "publish": { "active": false },
+                "video": null,
+                "audio": null
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_srs_stats() {
+        let stream = parse_stats(STATS, "live", "feed1").unwrap();
+
+        assert_eq!(stream.clients, 2);
+        assert_eq!(stream.kbps.recv_30s, 6144);
+        assert_eq!(stream.bitrate(), 6000);
+        assert_eq!(stream.video.unwrap().width, Some(1920));
+    }
+
+    #[test]
+    fn inactive_or_missing_stream_is_offline() {
+        assert!(parse_stats(STATS, "live", "feed2").is_none());
+        assert!(parse_stats(STATS, "other", "feed1").is_none());
+        assert!(parse_stats(r#"{"code": 0, "streams": []}"#, "live", "feed1").is_none());
+    }
+}
@@ -78,6 +78,14 @@ pub struct Xiu {
     /// Client to make HTTP requests with
     #[serde(skip, default = "default_reqwest_client")]
     pub client: reqwest::Client,
+
+    /// Whether the stats page could be reached
+    #[serde(skip)]
+    pub health: super::RequestHealth,
+
+    /// Unit of the reported bitrate when it's not the usual one
+    #[serde(skip)]
+    pub bitrate_unit: Option<super::BitrateUnit>,
 }
 
 impl Xiu {
@@ -100,18 +108,29 @@ impl Xiu {
 
         let res = match request.send().await {
             Ok(res) => res,
-            Err(_) => {
-                error!("Xiu API ({}) is unreachable", self.stats_url);
+            Err(e) => {
+                self.health.request_error(&self.stats_url, &e);
                 return None;
             }
         };
 
+        if self
+            .health
+            .configured_status(&self.stats_url, res.status())
+            .is_some()
+        {
+            return None;
+        }
+
         if res.status() != reqwest::StatusCode::OK {
             error!("Error accessing Xiu API ({})", self.stats_url);
+            self.health.set_failed(true);
             return None;
         }
 
-        let text = res.text().await.ok()?;
+        self.health.set_failed(false);
+
+        let text = super::response_text(res, &self.stats_url, &self.health).await?;
         let data: XiuResponse = serde_json::from_str(&text).ok()?;
 
         if data.error_code != 0 {
@@ -126,7 +145,7 @@ impl Xiu {
 
         let publisher = serde_json::to_value(&data.data[0].publisher).ok()?;
 
-        let stream: XiuPublisher = match serde_json::from_value(publisher.to_owned()) {
+        let mut stream: XiuPublisher = match serde_json::from_value(publisher.to_owned()) {
             Ok(stats) => stats,
             Err(error) => {
                 trace!("{:?}", &data);
@@ -135,28 +154,21 @@ impl Xiu {
             }
         };
 
+        // Converted right away so the stats are in Kbps everywhere
+        if let Some(unit) = self.bitrate_unit {
+            stream.recv_bitrate = unit.to_kbps(stream.recv_bitrate as f64).into();
+
+            if let Some(video) = &mut stream.video {
+                video.bitrate = unit.to_kbps(video.bitrate as f64).into();
+            }
+        }
+
         trace!("{:#?}", stream);
         Some(stream)
     }
-}
-
-#[derive(Deserialize, Debug)]
-struct XiuResponse {
-    error_code: i32,
-    desp: String,
-    data: Vec<XiuStreamInfo>,
-}
-
-#[async_trait]
-#[typetag::serde]
-impl SwitchLogic for Xiu {
-    /// Which scene to switch to
-    async fn switch(&self, triggers: &Triggers) -> SwitchType {
-        let stats = match self.get_stats().await {
-            Some(b) => b,
-            None => return SwitchType::Offline,
-        };
 
+    /// Which scene to switch to with these stats
+    fn switch_type(&self, stats: &XiuPublisher, triggers: &Triggers) -> SwitchType {
         if let Some(offline) = triggers.offline {
             if stats.recv_bitrate > 0 && stats.recv_bitrate <= offline.into() {
                 return SwitchType::Offline;
@@ -175,24 +187,56 @@ impl SwitchLogic for Xiu {
 
         return SwitchType::Normal;
     }
-}
-
-#[async_trait]
-#[typetag::serde]
-impl StreamServersCommands for Xiu {
-    async fn bitrate(&self) -> super::Bitrate {
-        let stats = match self.get_stats().await {
-            Some(stats) => stats,
-            None => return super::Bitrate { message: None },
-        };
 
+    fn stats_bitrate(&self, stats: &XiuPublisher) -> super::Bitrate {
         if stats.video.is_none() {
-            return super::Bitrate { message: None };
+            return super::Bitrate::default();
         }
 
         let video_bitrate = stats.video.as_ref().unwrap().bitrate;
         super::Bitrate {
             message: Some(format!("{}", video_bitrate)),
+            kbps: Some(video_bitrate as u32),
+            rtt: None,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct XiuResponse {
+    error_code: i32,
+    desp: String,
+    data: Vec<XiuStreamInfo>,
+}
+
+#[async_trait]
+#[typetag::serde]
+impl SwitchLogic for Xiu {
+    /// Which scene to switch to
+    async fn switch(&self, triggers: &Triggers) -> (SwitchType, super::Bitrate) {
+        let Some(stats) = self.get_stats().await else {
+            return (SwitchType::Offline, Default::default());
+        };
+
+        (
+            self.switch_type(&stats, triggers),
+            self.stats_bitrate(&stats),
+        )
+    }
+
+    fn request_failed(&self) -> bool {
+        self.health.failed()
+    }
+}
+
+#[async_trait]
+#[typetag::serde]
+impl StreamServersCommands for Xiu {
+    async fn bitrate(&self) -> super::Bitrate {
+        match self.get_stats().await {
+            Some(stats) => self.stats_bitrate(&stats),
+            None => super::Bitrate::default(),
         }
     }
 
@@ -214,6 +258,24 @@ impl Bsl for Xiu {
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn set_http_client(&mut self, client: reqwest::Client) {
+        self.client = client;
+    }
+
+    fn set_http_statuses(&mut self, statuses: super::HttpStatuses) -> bool {
+        self.health.set_statuses(statuses);
+        true
+    }
+
+    fn bitrate_unit(&self) -> Option<super::BitrateUnit> {
+        Some(self.bitrate_unit.unwrap_or(super::BitrateUnit::Kbps))
+    }
+
+    fn set_bitrate_unit(&mut self, unit: super::BitrateUnit) -> bool {
+        self.bitrate_unit = Some(unit);
+        true
+    }
 }
 
 #[cfg(test)]
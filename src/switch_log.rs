@@ -0,0 +1,110 @@
+use std::{io::Write, path::Path, sync::Mutex};
+
+use serde::Serialize;
+use tracing::error;
+use tracing_appender::rolling::{self, RollingFileAppender};
+
+use crate::switcher::{SwitchType, TriggerType};
+
+/// What the switcher did with a switch decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Switched,
+
+    /// Would have switched but the last switch was within the min scene duration
+    BlockedByMinSceneDuration,
+
+    /// Would have switched but dry run is enabled
+    DryRun,
+}
+
+/// One line in the switch log
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub outcome: Outcome,
+    pub switch_type: SwitchType,
+
+    /// Trigger that caused a switch to the low or offline scene
+    pub trigger: Option<TriggerType>,
+
+    pub previous_scene: String,
+    pub scene: String,
+    pub stream_server: Option<String>,
+    pub bitrate: Option<u32>,
+    pub rtt: Option<f64>,
+}
+
+/// Appends switch decisions as JSON lines to a file that rotates daily,
+/// kept apart from the regular logs
+#[derive(Default)]
+pub struct SwitchLog {
+    file: Mutex<Option<(String, RollingFileAppender)>>,
+}
+
+impl SwitchLog {
+    pub fn write(&self, path: &str, entry: &Entry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Unable to serialize switch log entry: {}", e);
+                return;
+            }
+        };
+
+        let mut file = self.file.lock().unwrap();
+
+        // Reopen when the path changed in the config
+        if !matches!(&*file, Some((current, _)) if current == path) {
+            *file = Some((path.to_owned(), open(path)));
+        }
+
+        let (_, appender) = file.as_mut().unwrap();
+
+        if let Err(e) = writeln!(appender, "{}", line) {
+            error!("Unable to write to the switch log {}: {}", path, e);
+        }
+    }
+}
+
+/// Rotated files get the date appended, e.g. `switches.log.2024-01-31`
+fn open(path: &str) -> RollingFileAppender {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or_else(|| "switches.log".as_ref());
+
+    rolling::daily(dir, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_is_one_json_line() {
+        let entry = Entry {
+            timestamp: 1700000000,
+            outcome: Outcome::BlockedByMinSceneDuration,
+            switch_type: SwitchType::Low,
+            trigger: Some(TriggerType::Low),
+            previous_scene: "live".to_string(),
+            scene: "low".to_string(),
+            stream_server: Some("belabox".to_string()),
+            bitrate: Some(400),
+            rtt: None,
+        };
+
+        let json = serde_json::to_string(&entry).unwrap();
+
+        assert!(!json.contains('\n'));
+        assert!(json.contains(r#""outcome":"blocked_by_min_scene_duration""#));
+        assert!(json.contains(r#""trigger":"low""#));
+        assert!(json.contains(r#""streamServer":"belabox""#));
+    }
+}
@@ -1,57 +1,226 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
 
 use serde::{Deserialize, Serialize};
 use tokio::sync::Notify;
 use tracing::{debug, error, info, Instrument};
 
 use crate::{
-    chat, error,
+    broadcasting_software, chat, config, error,
     noalbs::{self, ChatSender},
-    state::ClientStatus,
-    stream_servers,
+    state::{
+        ClientStatus, LowRefreshTimer, LowWarning, OfflineTimeoutAction, RecoverTimer, StartupStep,
+        State,
+    },
+    stats, stream_servers, switch_log, trigger_expression, webhook,
 };
 
+/// How often the watchdog checks the switcher is still going through its loop
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long past its poll interval the switcher may take before it gets restarted
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The chat notification gets dropped when the state stays locked this long
+const WATCHDOG_NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct Switcher {
     pub state: noalbs::UserState,
     pub chat_sender: ChatSender,
+    pub switch_log: switch_log::SwitchLog,
+    pub stats: stats::Stats,
+
+    /// Scene the switcher would be on when dry run is enabled
+    pub dry_run_scene: std::sync::Mutex<Option<String>>,
+
+    /// Chat was told a switch failed, cleared once a switch is confirmed
+    pub switch_failed_notified: std::sync::atomic::AtomicBool,
+}
+
+/// Stops the switcher along with the watchdog when it gets aborted
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl Switcher {
+    pub fn new(state: noalbs::UserState, chat_sender: ChatSender) -> Self {
+        Self {
+            state,
+            chat_sender,
+            switch_log: Default::default(),
+            stats: Default::default(),
+            dry_run_scene: Default::default(),
+            switch_failed_notified: Default::default(),
+        }
+    }
+
+    /// Runs the switcher and restarts it when it stops checking or
+    /// exits without being asked to
+    pub fn supervise(
+        state: noalbs::UserState,
+        chat_sender: ChatSender,
+    ) -> tokio::task::JoinHandle<()> {
+        let f = async move {
+            let heartbeat = state.read().await.switcher_state.heartbeat();
+            let mut switcher =
+                AbortOnDrop(Self::run(Self::new(state.clone(), chat_sender.clone())));
+
+            loop {
+                tokio::select! {
+                    res = &mut switcher.0 => match res {
+                        Ok(()) => break,
+                        Err(e) => error!("Switcher stopped unexpectedly: {}", e),
+                    },
+                    _ = tokio::time::sleep(WATCHDOG_INTERVAL) => {
+                        match heartbeat.overdue(Instant::now()) {
+                            Some(overdue) if overdue >= STALL_TIMEOUT => {
+                                error!("Switcher didn't check for {:?}, restarting it", overdue);
+                                switcher.0.abort();
+                            }
+                            _ => continue,
+                        }
+                    }
+                }
+
+                let notify = broadcasting_software::supervisor::notify_chat(
+                    &state,
+                    &chat_sender,
+                    chat::InternalUpdate::SwitcherRestarted,
+                );
+
+                if tokio::time::timeout(WATCHDOG_NOTIFY_TIMEOUT, notify)
+                    .await
+                    .is_err()
+                {
+                    error!("Couldn't tell chat the switcher got restarted");
+                }
+
+                // Gives the new switcher a full timeout before checking again
+                heartbeat.tick(Duration::ZERO);
+                switcher = AbortOnDrop(Self::run(Self::new(state.clone(), chat_sender.clone())));
+            }
+        }
+        .instrument(tracing::info_span!("Watchdog"));
+
+        tokio::spawn(f)
+    }
+
     pub fn run(switcher: Self) -> tokio::task::JoinHandle<()> {
         tracing::info!("Running switcher");
 
         let f = async move {
             let mut prev_switch_type: SwitchType = SwitchType::Offline;
             let mut same_type: u8 = 0;
-            let mut same_type_seconds = 0;
+            let mut recover_timer = RecoverTimer::default();
+            let mut refresh_timer = LowRefreshTimer::default();
+            let mut low_warning = LowWarning::default();
+
+            let (shutdown, heartbeat) = {
+                let state = switcher.state.read().await;
+                (
+                    state.switcher_state.shutdown_notifier(),
+                    state.switcher_state.heartbeat(),
+                )
+            };
 
             loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-                tracing::debug!("Switcher loop");
+                let interval = switcher.state.read().await.config.switcher.poll_interval();
+                heartbeat.tick(interval);
 
-                if let Some(notifier) = switcher.get_sleep_notifier_if_necessary().await {
-                    notifier.notified().await;
-                    info!("Switcher running");
-                    continue;
+                tokio::select! {
+                    _ = shutdown.notified() => break,
+                    _ = tokio::time::sleep(interval) => {}
                 }
+                tracing::debug!("Switcher loop");
 
-                if let Err(e) = switcher
-                    .switch(
-                        &mut prev_switch_type,
-                        &mut same_type,
-                        &mut same_type_seconds,
-                    )
-                    .await
-                {
-                    error!("Error when trying to switch: {}", e);
+                // Keeps polling for the bitrate history while the triggers are ignored
+                if switcher.in_maintenance().await {
+                    tracing::debug!("Maintenance mode active, not switching");
+                    switcher.poll_stream_servers().await;
+
+                    switcher
+                        .state
+                        .write()
+                        .await
+                        .switcher_state
+                        .offline_timer
+                        .reset();
+                } else {
+                    if let Some(notifier) = switcher.get_sleep_notifier_if_necessary().await {
+                        switcher
+                            .state
+                            .write()
+                            .await
+                            .switcher_state
+                            .offline_timer
+                            .reset();
+
+                        heartbeat.set_waiting();
+
+                        tokio::select! {
+                            _ = shutdown.notified() => break,
+                            _ = notifier.notified() => {}
+                        }
+
+                        info!("Switcher running");
+                        continue;
+                    }
+
+                    let startup = switcher
+                        .state
+                        .write()
+                        .await
+                        .switcher_state
+                        .startup_step(Instant::now());
+
+                    if startup == StartupStep::Showing {
+                        tracing::debug!("Starting scene shown, not switching yet");
+                        switcher.poll_stream_servers().await;
+                    } else {
+                        if startup == StartupStep::Handoff {
+                            info!("Starting scene shown long enough, switcher takes over");
+                        }
+
+                        if let Err(e) = switcher
+                            .switch(
+                                &mut prev_switch_type,
+                                &mut same_type,
+                                &mut recover_timer,
+                                startup == StartupStep::Handoff,
+                            )
+                            .await
+                        {
+                            error!("Error when trying to switch: {}", e);
+                        }
+
+                        if let Err(e) = switcher.check_offline_timeout().await {
+                            error!("Offline timeout error {:?}", e);
+                        }
+
+                        switcher.check_auto_refresh(&mut refresh_timer).await;
+                        switcher.check_low_warning(&mut low_warning).await;
+                    }
                 }
             }
+
+            info!("Switcher stopped");
         }
         .instrument(tracing::info_span!("Switcher"));
 
         tokio::spawn(f)
     }
 
+    async fn in_maintenance(&self) -> bool {
+        self.state.read().await.switcher_state.maintenance.is_some()
+    }
+
     pub async fn get_sleep_notifier_if_necessary(&self) -> Option<Arc<Notify>> {
         let state = self.state.read().await;
 
@@ -60,6 +229,11 @@ impl Switcher {
             return Some(state.switcher_state.switcher_enabled_notifier());
         }
 
+        if state.switcher_state.privacy.is_some() {
+            info!("Privacy scene active, waiting till it ends");
+            return Some(state.switcher_state.privacy_ended_notifier());
+        }
+
         if state.broadcasting_software.status == ClientStatus::Disconnected {
             info!("Waiting for OBS connection");
             return Some(state.broadcasting_software.connected_notifier());
@@ -74,6 +248,13 @@ impl Switcher {
             return Some(state.broadcasting_software.start_streaming_notifier());
         }
 
+        if state.config.switcher.only_switch_when_streaming
+            && state.switcher_state.platform_stream_online == Some(false)
+        {
+            info!("Waiting till the stream is live on the chat platform");
+            return Some(state.switcher_state.platform_online_notifier());
+        }
+
         if !state
             .switcher_state
             .switchable_scenes
@@ -90,34 +271,207 @@ impl Switcher {
         &self,
         prev_switch_type: &mut SwitchType,
         same_type: &mut u8,
-        same_type_seconds: &mut u32,
+        recover_timer: &mut RecoverTimer,
+        handoff: bool,
     ) -> Result<(), error::Error> {
         let state = self.state.read().await;
 
         let switcher_config = &state.config.switcher;
         let triggers = &switcher_config.triggers;
         let stream_servers = &switcher_config.stream_servers;
-        let retry_attempts = &switcher_config.retry_attempts;
         let instant_recover = &switcher_config.instantly_switch_on_recover;
+        let optional_options = &state.config.optional_options;
 
-        let (mut server, mut current_switch_type) =
-            Self::get_online_stream_server(stream_servers, triggers).await;
+        // Require a higher bitrate to switch back up from the low scene
+        let recover_margin = match state.switcher_state.last_switch {
+            Some((_, SwitchType::Low)) => optional_options.low_recover_margin.unwrap_or_default(),
+            _ => 0,
+        };
 
-        // When stream comes back from offline, instantly switch.
-        let mut force_switch = *instant_recover
-            && *prev_switch_type == SwitchType::Offline
-            && current_switch_type != SwitchType::Offline;
+        // The RTT and low trigger are compared against the smoothed values below
+        let smooth_rtt = switcher_config.rtt_window > 1;
+        let smooth_bitrate = switcher_config.smooth_bitrate();
+        let adjustment = TriggerAdjustment {
+            recover_margin,
+            smooth_rtt,
+            smooth_bitrate,
+        };
 
-        if prev_switch_type == &current_switch_type {
-            *same_type += 1;
-        } else {
-            debug!("Got different type, switching to that");
+        let checked_at = Instant::now();
+
+        // The trigger that decided is kept along with the switch type
+        let (mut server, mut current_switch_type, mut trigger) = Self::get_online_stream_server(
+            stream_servers,
+            triggers,
+            adjustment,
+            &switcher_config.poll_backoff,
+            switcher_config.group_aggregation,
+            switcher_config.offline_policy,
+        )
+        .await;
+
+        self.record_polls(&state, checked_at);
+
+        if server.is_none() && stats_unreachable(stream_servers) {
+            if let Some(fallback) = &switcher_config.stats_fallback {
+                if Self::source_playing(&state, &fallback.source).await {
+                    debug!(
+                        "Stats pages unreachable but {} is playing, keeping the current scene",
+                        fallback.source
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        // The expression replaces the checks of the dropped frames and smoothed values below
+        let expression = server
+            .and_then(|s| s.triggers.as_ref())
+            .and_then(|overrides| overrides.expression.as_ref())
+            .or(triggers.expression.as_ref());
+
+        if let (Some(expression), Some(server)) = (expression, server) {
+            if matches!(current_switch_type, SwitchType::Normal | SwitchType::Low) {
+                let metrics = expression_metrics(
+                    &state,
+                    server,
+                    stream_servers,
+                    switcher_config.group_aggregation,
+                );
+
+                let low = expression.matches(&metrics);
+                debug!("Expression \"{}\" is {} for {:?}", expression, low, metrics);
+
+                current_switch_type = if low {
+                    SwitchType::Low
+                } else {
+                    SwitchType::Normal
+                };
+
+                // None of the triggers decided
+                trigger = None;
+            }
+        }
+
+        if current_switch_type == SwitchType::Normal
+            && state.broadcasting_software.is_streaming
+            && expression.is_none()
+        {
+            let triggers = match server.and_then(|s| s.triggers.as_ref()) {
+                Some(overrides) => triggers.merge(overrides),
+                None => triggers.clone(),
+            };
+
+            let dropped_frames = state.broadcasting_software.dropped_frames.percentage();
+
+            if too_many_dropped_frames(dropped_frames, &triggers) {
+                debug!("Dropped frames {:?}% over trigger", dropped_frames);
+                current_switch_type = SwitchType::Low;
+                trigger = Some(TriggerType::DroppedFrames);
+            }
+        }
+
+        if let Some(server) = server.filter(|_| smooth_rtt) {
+            let rtt_trigger = server
+                .triggers
+                .as_ref()
+                .and_then(|overrides| overrides.rtt)
+                .or(triggers.rtt);
+
+            if let Some(rtt_trigger) = rtt_trigger {
+                let rtt_history = &state.switcher_state.rtt_history;
+
+                // Only a new poll adds a sample, a server that's waiting for
+                // its poll interval or backoff keeps its window
+                let smoothed = match server.last_poll.polled_since(checked_at) {
+                    Some(bitrate) => rtt_history.add_sample(
+                        &server.name,
+                        bitrate.rtt,
+                        switcher_config.rtt_window as usize,
+                    ),
+                    None => rtt_history.median(&server.name),
+                };
+
+                if current_switch_type == SwitchType::Normal
+                    && expression.is_none()
+                    && smoothed.is_some_and(|rtt| rtt >= rtt_trigger as f64)
+                {
+                    debug!("Smoothed RTT {:?} ms over trigger", smoothed);
+                    current_switch_type = SwitchType::Low;
+                    trigger = Some(TriggerType::Rtt);
+                }
+            }
+        }
+
+        // Bonded groups compare their combined bitrate as is
+        if let Some(server) = server.filter(|s| smooth_bitrate && s.group.is_none()) {
+            let low = server
+                .triggers
+                .as_ref()
+                .and_then(|overrides| overrides.low)
+                .or(triggers.low)
+                .map(|low| low.saturating_add(recover_margin));
+
+            let bitrate_ema = &state.switcher_state.bitrate_ema;
+
+            // Same as the RTT, only a new poll moves the average
+            let smoothed = match server.last_poll.polled_since(checked_at) {
+                Some(bitrate) => {
+                    let kbps = match current_switch_type {
+                        SwitchType::Offline | SwitchType::Previous => None,
+                        _ => bitrate.kbps,
+                    };
+
+                    bitrate_ema.add_sample(&server.name, kbps, switcher_config.bitrate_ema_alpha)
+                }
+                None => bitrate_ema.average(&server.name),
+            };
+
+            if let (Some(smoothed), Some(low)) = (smoothed, low) {
+                if current_switch_type == SwitchType::Normal
+                    && expression.is_none()
+                    && smoothed <= low as f64
+                {
+                    debug!("Smoothed bitrate {:.0} Kbps under trigger", smoothed);
+                    current_switch_type = SwitchType::Low;
+                    trigger = Some(TriggerType::Low);
+                }
+            }
+        }
 
-            *prev_switch_type = current_switch_type;
-            *same_type = 0;
-            *same_type_seconds = 0;
+        // Stats that lag behind don't drop the scene while !forcelive is active
+        if state
+            .switcher_state
+            .force_live_remaining(Instant::now())
+            .is_some()
+        {
+            current_switch_type = hold_live(current_switch_type);
         }
 
+        let recover_delay =
+            Duration::from_secs(switcher_config.recover_delay_seconds.unwrap_or_default());
+
+        // With a delay the feed has to stay online for a while on the offline scene
+        let recovering = *instant_recover
+            && !recover_delay.is_zero()
+            && current_switch_type != SwitchType::Offline
+            && switcher_config.is_offline_scene(&state.broadcasting_software.current_scene);
+        let recovered = recover_timer.update(recovering, recover_delay, Instant::now());
+
+        // When stream comes back from offline, instantly switch.
+        let mut force_switch = if recover_delay.is_zero() {
+            *instant_recover
+                && *prev_switch_type == SwitchType::Offline
+                && current_switch_type != SwitchType::Offline
+        } else {
+            recovered
+        };
+
+        // Right after the starting scene the feed decides, without retries
+        force_switch |= handoff;
+
+        count_same_type(prev_switch_type, same_type, current_switch_type);
+
         debug!("type: {:?}, same: {:?}", current_switch_type, same_type);
 
         if let SwitchType::Previous = &current_switch_type {
@@ -131,60 +485,23 @@ impl Switcher {
             }
         }
 
-        if !(same_type == retry_attempts || force_switch) {
+        if recovering && !recovered {
+            debug!(
+                "Feed recovered, waiting {:?} before switching",
+                recover_delay
+            );
             return Ok(());
         }
 
-        if state.broadcasting_software.is_streaming {
-            *same_type_seconds += *same_type as u32;
-            debug!("Same type seconds: {}", same_type_seconds);
-        }
+        let retry_attempts = required_attempts(current_switch_type, switcher_config);
 
-        if !state.broadcasting_software.is_streaming && *same_type_seconds > 0 {
-            *same_type_seconds = 0;
+        if !(*same_type == retry_attempts || force_switch) {
+            return Ok(());
         }
 
         *same_type = 0;
 
         if current_switch_type == SwitchType::Offline {
-            // TODO: Refactor the timeout code
-            if let Some(min) = &state.config.optional_options.offline_timeout {
-                if *same_type_seconds >= (min * 60) {
-                    let bsc = state
-                        .broadcasting_software
-                        .connection
-                        .as_ref()
-                        .ok_or(error::Error::NoSoftwareSet)?;
-
-                    if let Err(error) = bsc.stop_streaming().await {
-                        error!("Offline timeout error {:?}", error);
-                        return Ok(());
-                    }
-
-                    if state.config.optional_options.record_while_streaming
-                        && bsc.is_recording().await?
-                    {
-                        if let Err(error) = bsc.toggle_recording().await {
-                            error!("Offline timeout error {:?}", error);
-                            return Ok(());
-                        }
-                    }
-
-                    if state.broadcasting_software.is_streaming {
-                        if let Some(chat) = &state.config.chat {
-                            let message =
-                                chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
-                                    platform: chat.platform.kind(),
-                                    channel: chat.username.to_owned(),
-                                    kind: chat::InternalUpdate::OfflineTimeout,
-                                });
-
-                            let _ = self.chat_sender.send(message).await;
-                        }
-                    }
-                }
-            }
-
             if let Some(name) = &state.switcher_state.last_used_server {
                 server = stream_servers.iter().find(|s| &s.name == name);
             }
@@ -196,173 +513,1360 @@ impl Switcher {
             &switcher_config.switching_scenes
         };
 
+        // Held live or back to the previous scene, no trigger fired
+        let trigger = trigger
+            .filter(|_| matches!(current_switch_type, SwitchType::Low | SwitchType::Offline));
+
         let scene = if let SwitchType::Previous = &current_switch_type {
             &state.broadcasting_software.prev_scene
         } else {
             // Should be safe since previous is handled
-            scenes.type_to_scene(&current_switch_type).unwrap()
+            scenes
+                .trigger_to_scene(&current_switch_type, trigger)
+                .unwrap()
         }
         .to_owned();
 
+        let record = SwitchRecord {
+            switch_log_path: optional_options.switch_log_path.to_owned(),
+            stats_db_path: optional_options.stats_db_path.to_owned(),
+            user: state.config.user.name.to_owned(),
+        };
+        let previous_scene = state.broadcasting_software.current_scene.to_owned();
+
+        // Same poll the switch was decided on
+        let bitrate = server.map(|s| s.last_poll.bitrate()).unwrap_or_default();
+
+        if current_switch_type == SwitchType::Normal && previous_scene != scene {
+            if let Some(source) = server.and_then(|s| s.source.as_deref()) {
+                if !Self::source_playing(&state, source).await {
+                    debug!(
+                        "Waiting for {} to play before switching to {}",
+                        source, scene
+                    );
+
+                    // Check again on the next poll without counting the attempts again
+                    *same_type = retry_attempts.saturating_sub(1);
+                    return Ok(());
+                }
+            }
+        }
+
+        if switcher_config.dry_run {
+            let server_name = server.map(|s| s.name.to_owned());
+            drop(state);
+
+            self.dry_run_switch(
+                &scene,
+                current_switch_type,
+                trigger,
+                server_name.as_deref(),
+                &bitrate,
+                &record,
+            )
+            .await;
+
+            return Ok(());
+        }
+
+        if let Some(min) = optional_options.min_scene_duration_seconds {
+            if within_min_scene_duration(
+                state.switcher_state.last_switch,
+                current_switch_type,
+                Duration::from_secs(min),
+                Instant::now(),
+            ) {
+                debug!("Last switch was less than {}s ago, not switching", min);
+
+                if record.enabled() && previous_scene != scene {
+                    self.record_switch(
+                        &record,
+                        switch_log::Entry {
+                            timestamp: webhook::timestamp(),
+                            outcome: switch_log::Outcome::BlockedByMinSceneDuration,
+                            switch_type: current_switch_type,
+                            trigger,
+                            previous_scene,
+                            scene,
+                            stream_server: server.map(|s| s.name.to_owned()),
+                            bitrate: bitrate.kbps,
+                            rtt: bitrate.rtt,
+                        },
+                    );
+                }
+
+                return Ok(());
+            }
+        }
+
         let server_name = server.map(|s| s.name.to_owned());
 
+        let webhook = &optional_options.webhook;
+        let webhook_url = webhook.url.to_owned().filter(|_| webhook.enabled);
+        let user = state.config.user.name.to_owned();
+
         drop(state);
 
+        let fired_on = server_name.clone();
+
         {
             let mut state = self.state.write().await;
 
-            // Set the previous scene when switch_type is normal or low
-            if let SwitchType::Normal | SwitchType::Low = current_switch_type {
-                scene.clone_into(&mut state.broadcasting_software.prev_scene);
-            };
-
             if current_switch_type != SwitchType::Offline {
                 debug!("Last used server set to {:?}", server_name);
                 state.switcher_state.last_used_server = server_name;
             }
+
+            // The feed really died, the override shouldn't pull it back up
+            if current_switch_type == SwitchType::Offline
+                && state.switcher_state.force_live_until.take().is_some()
+            {
+                info!("Feed went offline, force live ended");
+            }
         }
 
-        self.switch_if_necessary(&scene, current_switch_type)
+        let switched = self
+            .switch_if_necessary(&scene, current_switch_type)
             .await?;
 
-        Ok(())
-    }
-
-    /// Gets the first online stream server with current status
-    async fn get_online_stream_server<'a>(
-        stream_servers: &'a [stream_servers::StreamServer],
-        triggers: &'a Triggers,
-    ) -> (Option<&'a stream_servers::StreamServer>, SwitchType) {
-        for server in stream_servers {
-            if !server.enabled {
-                continue;
+        {
+            let mut state = self.state.write().await;
+            let bs = &mut state.broadcasting_software;
+
+            // Set the previous scene when switch_type is normal or low,
+            // only once the software is actually on it
+            if matches!(current_switch_type, SwitchType::Normal | SwitchType::Low)
+                && (switched || bs.current_scene == scene)
+            {
+                scene.clone_into(&mut bs.prev_scene);
             }
+        }
 
-            let switch_type = server.stream_server.switch(triggers).await;
+        if switched {
+            self.state.write().await.switcher_state.last_switch =
+                Some((Instant::now(), current_switch_type));
 
-            if switch_type == SwitchType::Offline {
-                continue;
+            self.record_fired_trigger(trigger).await;
+
+            self.state.write().await.switcher_state.last_decision = Some(SwitchDecision {
+                at: Instant::now(),
+                switch_type: current_switch_type,
+                trigger,
+                scene: scene.to_owned(),
+                stream_server: fired_on.clone(),
+                bitrate: bitrate.kbps,
+                rtt: bitrate.rtt,
+            });
+
+            if record.enabled() {
+                self.record_switch(
+                    &record,
+                    switch_log::Entry {
+                        timestamp: webhook::timestamp(),
+                        outcome: switch_log::Outcome::Switched,
+                        switch_type: current_switch_type,
+                        trigger,
+                        previous_scene: previous_scene.to_owned(),
+                        scene: scene.to_owned(),
+                        stream_server: fired_on.clone(),
+                        bitrate: bitrate.kbps,
+                        rtt: bitrate.rtt,
+                    },
+                );
             }
 
-            return (Some(server), switch_type);
+            if let Some(url) = webhook_url {
+                webhook::send(
+                    url,
+                    webhook::SwitchEvent {
+                        user,
+                        switch_type: current_switch_type,
+                        trigger,
+                        previous_scene,
+                        scene,
+                        stream_server: fired_on,
+                        bitrate: bitrate.kbps,
+                        rtt: bitrate.rtt,
+                        timestamp: webhook::timestamp(),
+                    },
+                );
+            }
         }
 
-        (None, SwitchType::Offline)
+        Ok(())
     }
 
-    pub async fn switch_if_necessary(
-        &self,
-        switch_scene: &str,
-        switch_type: SwitchType,
-    ) -> Result<(), error::Error> {
-        debug!(
-            "Switch scene: {} Switch type: {:?}",
-            switch_scene, switch_type
-        );
-
-        let state = &self.state.read().await;
-        let current_scene = &state.broadcasting_software.current_scene;
+    /// Whether the media source in OBS is playing the feed
+    async fn source_playing(state: &State, source: &str) -> bool {
+        let Some(bsc) = &state.broadcasting_software.connection else {
+            return false;
+        };
 
-        if current_scene == switch_scene {
-            return Ok(());
+        match bsc.source_active(source).await {
+            Ok(active) => active,
+            Err(e) => {
+                error!("Unable to get the status of {}: {}", source, e);
+                false
+            }
         }
+    }
 
-        let skip = state
-            .config
-            .optional_scenes
-            .starting
-            .as_ref()
-            .map_or(false, |starting_scene| {
-                let switch_to_live = state
+    /// Stops the stream once an offline scene has been active for the offline timeout,
+    /// warns in chat a minute before
+    async fn check_offline_timeout(&self) -> Result<(), error::Error> {
+        let action = {
+            let mut state = self.state.write().await;
+
+            let Some(minutes) = state.config.optional_options.offline_timeout else {
+                return Ok(());
+            };
+
+            let offline = state.broadcasting_software.is_streaming
+                && state
                     .config
-                    .optional_options
-                    .switch_from_starting_scene_to_live_scene;
-                current_scene == starting_scene
-                    && switch_to_live
-                    && (switch_type == SwitchType::Offline)
-            });
+                    .switcher
+                    .is_offline_scene(&state.broadcasting_software.current_scene);
+            let timeout = Duration::from_secs(u64::from(minutes) * 60);
 
-        if skip
-            || !state
+            state
                 .switcher_state
-                .switchable_scenes
-                .contains(&state.broadcasting_software.current_scene)
-        {
-            return Ok(());
-        }
+                .offline_timer
+                .update(offline, timeout, Instant::now())
+        };
 
-        // Ignore the error.. it should work at some point
-        if let Err(error) = state
-            .broadcasting_software
-            .connection
-            .as_ref()
-            .ok_or(error::Error::NoSoftwareSet)?
-            .switch_scene(switch_scene)
-            .await
-        {
-            error!("Switch scene error {:?}", error);
+        let Some(action) = action else {
             return Ok(());
-        }
+        };
 
-        info!("Scene switched to [{:?}] {}", switch_type, switch_scene);
+        let state = self.state.read().await;
 
-        if state.broadcasting_software.is_streaming
-            && state.config.switcher.auto_switch_notification
-        {
-            if let Some(chat) = &state.config.chat {
-                let message =
-                    chat::HandleMessage::AutomaticSwitchingScene(chat::AutomaticSwitchingScene {
-                        platform: chat.platform.kind(),
-                        channel: chat.username.to_owned(),
-                        scene: switch_scene.to_owned(),
-                        switch_type,
-                    });
+        let kind = match action {
+            OfflineTimeoutAction::Warn => {
+                if !state.config.switcher.auto_switch_notification {
+                    return Ok(());
+                }
+
+                chat::InternalUpdate::OfflineTimeoutWarning
+            }
+            OfflineTimeoutAction::Stop => {
+                let bsc = state
+                    .broadcasting_software
+                    .connection
+                    .as_ref()
+                    .ok_or(error::Error::NoSoftwareSet)?;
+
+                // Recording stops along with the stream when record_while_streaming is enabled
+                bsc.stop_streaming().await?;
 
-                let _ = self.chat_sender.send(message).await;
+                chat::InternalUpdate::OfflineTimeout
             }
+        };
+
+        if let Some(chat) = &state.config.chat {
+            let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+                platform: chat.platform.kind(),
+                channel: chat.username.to_owned(),
+                kind,
+            });
+
+            let _ = self.chat_sender.send(message).await;
+        }
+
+        drop(state);
+
+        if action == OfflineTimeoutAction::Stop {
+            self.state
+                .write()
+                .await
+                .switcher_state
+                .offline_timer
+                .reset();
         }
 
         Ok(())
     }
-}
 
-async fn get_optional_scenes<'a>(
-    server: Option<&'a stream_servers::StreamServer>,
-    state: &tokio::sync::RwLockReadGuard<'_, crate::state::State>,
-) -> Option<&'a SwitchingScenes> {
-    if let Some(depends) = &server?.depends_on {
-        if !is_stream_server_online(&depends.name, state).await {
-            debug!("The depended stream server is offline. Going to use the backup scenes.");
-            return Some(&depends.backup_scenes);
+    /// Refreshes once the low scene has been active for a while, the switcher
+    /// waits until the refresh is done
+    async fn check_auto_refresh(&self, timer: &mut LowRefreshTimer) {
+        let refresh = {
+            let state = self.state.read().await;
+
+            let Some(seconds) = state.config.optional_options.auto_refresh_after_low_seconds else {
+                return;
+            };
+
+            let low = state.broadcasting_software.is_streaming
+                && state
+                    .config
+                    .switcher
+                    .is_low_scene(&state.broadcasting_software.current_scene);
+
+            timer.update(low, Duration::from_secs(seconds), Instant::now())
+        };
+
+        if !refresh {
+            return;
         }
-    }
 
-    server?.override_scenes.as_ref()
-}
+        info!("Low scene active for too long, refreshing");
 
-async fn is_stream_server_online(
-    server_name: &str,
-    state: &tokio::sync::RwLockReadGuard<'_, crate::state::State>,
-) -> bool {
-    match state
-        .config
-        .switcher
-        .stream_servers
-        .iter()
-        .find(|&x| x.name == server_name)
-    {
-        Some(server) => server.stream_server.bitrate().await.message.is_some(),
-        None => false,
+        if let Err(e) = noalbs::refresh(&self.state).await {
+            error!("Unable to refresh: {}", e);
+        }
     }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SwitchingScenes {
-    pub normal: String,
-    pub low: String,
-    pub offline: String,
-}
+
+    /// Warns in chat once when the bitrate of the stream server in use drops
+    /// below the low warning trigger, doesn't switch scenes
+    async fn check_low_warning(&self, warning: &mut LowWarning) {
+        let state = self.state.read().await;
+        let switcher_config = &state.config.switcher;
+
+        if !state.broadcasting_software.is_streaming {
+            warning.reset();
+            return;
+        }
+
+        let server = state
+            .switcher_state
+            .last_used_server
+            .as_ref()
+            .and_then(|name| {
+                switcher_config
+                    .stream_servers
+                    .iter()
+                    .find(|s| &s.name == name)
+            });
+
+        let Some(server) = server else {
+            return;
+        };
+
+        let triggers = match &server.triggers {
+            Some(overrides) => switcher_config.triggers.merge(overrides),
+            None => switcher_config.triggers.clone(),
+        };
+
+        let Some(low_warn) = triggers.low_warn else {
+            return;
+        };
+
+        // Same sample the switcher decided on, no need to request it again
+        let Some(kbps) = server.last_poll.bitrate().kbps else {
+            return;
+        };
+
+        if !warning.update(kbps, low_warn, triggers.low) {
+            return;
+        }
+
+        debug!("Bitrate {} Kbps below the low warning trigger", kbps);
+
+        if !switcher_config.auto_switch_notification
+            || state.switcher_state.notifications_muted().is_some()
+        {
+            return;
+        }
+
+        if let Some(chat) = &state.config.chat {
+            let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+                platform: chat.platform.kind(),
+                channel: chat.username.to_owned(),
+                kind: chat::InternalUpdate::LowBitrateWarning,
+            });
+
+            let _ = self.chat_sender.send(message).await;
+        }
+    }
+
+    /// Polls the stream servers without deciding on a scene
+    async fn poll_stream_servers(&self) {
+        let state = self.state.read().await;
+        let switcher_config = &state.config.switcher;
+        let checked_at = Instant::now();
+
+        Self::get_online_stream_server(
+            &switcher_config.stream_servers,
+            &switcher_config.triggers,
+            TriggerAdjustment::default(),
+            &switcher_config.poll_backoff,
+            switcher_config.group_aggregation,
+            switcher_config.offline_policy,
+        )
+        .await;
+
+        self.record_polls(&state, checked_at);
+    }
+
+    /// Adds the bitrate of every stream server polled since `since` to the
+    /// history and the stats database
+    fn record_polls(&self, state: &State, since: Instant) {
+        let stats_db_path = &state.config.optional_options.stats_db_path;
+
+        for server in &state.config.switcher.stream_servers {
+            let Some(bitrate) = server.last_poll.polled_since(since) else {
+                continue;
+            };
+
+            state
+                .switcher_state
+                .bitrate_history
+                .add_sample(&server.name, bitrate.kbps.unwrap_or_default());
+
+            if let Some(path) = stats_db_path {
+                let sample = stats::Sample {
+                    timestamp: webhook::timestamp(),
+                    stream_server: server.name.to_owned(),
+                    scene: state.broadcasting_software.current_scene.to_owned(),
+                    bitrate: bitrate.kbps,
+                    rtt: bitrate.rtt,
+                };
+
+                self.stats.add_sample(path, &state.config.user.name, sample);
+            }
+        }
+    }
+
+    /// Logs the switch instead of switching. Keeps track of the scene it
+    /// would be on, so every decision is only logged once.
+    async fn dry_run_switch(
+        &self,
+        scene: &str,
+        switch_type: SwitchType,
+        trigger: Option<TriggerType>,
+        server_name: Option<&str>,
+        bitrate: &stream_servers::Bitrate,
+        record: &SwitchRecord,
+    ) {
+        let previous_scene = {
+            let state = self.state.read().await;
+            let real_scene = &state.broadcasting_software.current_scene;
+
+            let mut dry_run_scene = self.dry_run_scene.lock().unwrap();
+            let current = dry_run_scene.get_or_insert_with(|| real_scene.to_owned());
+
+            // Same as a real switch, don't touch scenes that aren't switchable
+            if current.as_str() == scene
+                || !state.switcher_state.switchable_scenes.contains(real_scene)
+            {
+                return;
+            }
+
+            std::mem::replace(current, scene.to_owned())
+        };
+
+        info!(
+            "[Dry run] Would switch from {} to [{:?}] {}, server: {}, bitrate: {}, trigger: {}",
+            previous_scene,
+            switch_type,
+            scene,
+            server_name.unwrap_or("none"),
+            bitrate.message.as_deref().unwrap_or("unknown"),
+            trigger.map_or("none", |t| t.as_str())
+        );
+
+        if record.enabled() {
+            self.record_switch(
+                record,
+                switch_log::Entry {
+                    timestamp: webhook::timestamp(),
+                    outcome: switch_log::Outcome::DryRun,
+                    switch_type,
+                    trigger,
+                    previous_scene,
+                    scene: scene.to_owned(),
+                    stream_server: server_name.map(ToOwned::to_owned),
+                    bitrate: bitrate.kbps,
+                    rtt: bitrate.rtt,
+                },
+            );
+        }
+    }
+
+    /// Writes the switch decision to the switch log and the stats database
+    fn record_switch(&self, record: &SwitchRecord, entry: switch_log::Entry) {
+        if let Some(path) = &record.switch_log_path {
+            self.switch_log.write(path, &entry);
+        }
+
+        if let Some(path) = &record.stats_db_path {
+            self.stats.add_switch(path, &record.user, entry);
+        }
+    }
+
+    /// Counts the trigger that caused the switch to the low or offline scene
+    async fn record_fired_trigger(&self, trigger: Option<TriggerType>) {
+        if let Some(trigger) = trigger {
+            let mut state = self.state.write().await;
+            *state
+                .switcher_state
+                .triggers_fired
+                .entry(trigger)
+                .or_insert(0) += 1;
+        }
+    }
+
+    /// Gets the first online stream server with current status and the
+    /// trigger that decided it
+    ///
+    /// Servers are checked by priority, a server that depends on another
+    /// server is skipped while that server isn't live. A group is checked
+    /// as a whole at the position of its first server.
+    async fn get_online_stream_server<'a>(
+        stream_servers: &'a [stream_servers::StreamServer],
+        triggers: &'a Triggers,
+        adjustment: TriggerAdjustment,
+        poll_backoff: &config::PollBackoff,
+        aggregation: config::GroupAggregation,
+        policy: config::OfflinePolicy,
+    ) -> (
+        Option<&'a stream_servers::StreamServer>,
+        SwitchType,
+        Option<TriggerType>,
+    ) {
+        // Every server is only checked once even when others depend on it
+        let mut checked = HashMap::new();
+        let mut checked_groups = HashSet::new();
+        let mut states = Vec::new();
+
+        for server in stream_servers {
+            if !server.enabled {
+                continue;
+            }
+
+            if let Some(group) = &server.group {
+                if !checked_groups.insert(group.as_str()) {
+                    continue;
+                }
+            }
+
+            if !Self::dependencies_live(
+                server,
+                stream_servers,
+                triggers,
+                adjustment,
+                poll_backoff,
+                &mut checked,
+            )
+            .await
+            {
+                debug!(
+                    "Skipping {}, the stream server it depends on is not live",
+                    server.name
+                );
+                continue;
+            }
+
+            let (server, switch_type, trigger) = match &server.group {
+                Some(group) => {
+                    Self::check_group(
+                        group,
+                        server,
+                        stream_servers,
+                        triggers,
+                        adjustment,
+                        poll_backoff,
+                        aggregation,
+                        &mut checked,
+                    )
+                    .await
+                }
+                None => {
+                    let switch_type = Self::check_stream_server(
+                        server,
+                        triggers,
+                        adjustment,
+                        poll_backoff,
+                        &mut checked,
+                    )
+                    .await;
+
+                    let trigger = polled_trigger(server, switch_type, triggers, adjustment);
+                    (server, switch_type, trigger)
+                }
+            };
+
+            // The other servers don't matter once one is online
+            if policy == config::OfflinePolicy::Priority && switch_type != SwitchType::Offline {
+                return (Some(server), switch_type, trigger);
+            }
+
+            states.push((server, switch_type, trigger));
+        }
+
+        let types: Vec<_> = states.iter().map(|(_, t, _)| *t).collect();
+        let (index, switch_type) = combine_switch_types(&types, policy);
+
+        let trigger = match index {
+            // Degraded since another server is offline
+            Some(i) if states[i].1 != switch_type => Some(TriggerType::Offline),
+            Some(i) => states[i].2,
+            None => states
+                .iter()
+                .find(|(_, t, _)| *t == SwitchType::Offline)
+                .and_then(|(_, _, trigger)| *trigger),
+        };
+
+        (index.map(|i| states[i].0), switch_type, trigger)
+    }
+
+    /// Whether the chain of servers this server depends on is live, the
+    /// chain stops at a dependency with backup scenes since those are used
+    /// instead of skipping the server
+    async fn dependencies_live<'a>(
+        server: &'a stream_servers::StreamServer,
+        stream_servers: &'a [stream_servers::StreamServer],
+        triggers: &Triggers,
+        adjustment: TriggerAdjustment,
+        poll_backoff: &config::PollBackoff,
+        checked: &mut HashMap<&'a str, SwitchType>,
+    ) -> bool {
+        let mut chain = Vec::new();
+        let mut current = server;
+
+        while let Some(depends) = current
+            .depends_on
+            .as_ref()
+            .filter(|d| d.backup_scenes.is_none())
+        {
+            let dependency = stream_servers.iter().find(|s| s.name == depends.name);
+
+            // Cycles are rejected when loading the config, but never loop forever
+            match dependency {
+                Some(dependency) if dependency.enabled && chain.len() < stream_servers.len() => {
+                    chain.push(dependency);
+                    current = dependency;
+                }
+                _ => return false,
+            }
+        }
+
+        // Check the last server in the chain first since the others depend on it
+        for dependency in chain.into_iter().rev() {
+            let switch_type =
+                Self::check_stream_server(dependency, triggers, adjustment, poll_backoff, checked)
+                    .await;
+
+            if switch_type == SwitchType::Offline {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Checks every enabled server in the group and compares the combined
+    /// bitrate to the triggers of the first server in the group. Returns the
+    /// first link that is online so its scenes and stats are used, along
+    /// with the trigger that decided.
+    #[allow(clippy::too_many_arguments)]
+    async fn check_group<'a>(
+        group: &str,
+        first: &'a stream_servers::StreamServer,
+        stream_servers: &'a [stream_servers::StreamServer],
+        triggers: &Triggers,
+        adjustment: TriggerAdjustment,
+        poll_backoff: &config::PollBackoff,
+        aggregation: config::GroupAggregation,
+        checked: &mut HashMap<&'a str, SwitchType>,
+    ) -> (
+        &'a stream_servers::StreamServer,
+        SwitchType,
+        Option<TriggerType>,
+    ) {
+        let mut links = Vec::new();
+        let mut link_triggers = Vec::new();
+        let mut online = None;
+
+        for server in stream_servers
+            .iter()
+            .filter(|s| s.enabled && s.group.as_deref() == Some(group))
+        {
+            let switch_type =
+                Self::check_stream_server(server, triggers, adjustment, poll_backoff, checked)
+                    .await;
+
+            let kbps = match switch_type {
+                SwitchType::Offline => None,
+                _ => {
+                    online.get_or_insert(server);
+                    server.last_poll.bitrate().kbps
+                }
+            };
+
+            links.push((switch_type, kbps));
+            link_triggers.push(polled_trigger(server, switch_type, triggers, adjustment));
+        }
+
+        let triggers = match &first.triggers {
+            Some(overrides) => triggers.merge(overrides),
+            None => triggers.clone(),
+        };
+
+        // The combined bitrate isn't smoothed
+        let adjustment = TriggerAdjustment {
+            smooth_bitrate: false,
+            ..adjustment
+        };
+        let switch_type = group_switch_type(&links, &adjustment.apply(&triggers), aggregation);
+        let trigger = group_trigger(&links, &link_triggers, switch_type);
+
+        debug!("Group {} is {:?}", group, switch_type);
+        (online.unwrap_or(first), switch_type, trigger)
+    }
+
+    async fn check_stream_server<'a>(
+        server: &'a stream_servers::StreamServer,
+        triggers: &Triggers,
+        adjustment: TriggerAdjustment,
+        poll_backoff: &config::PollBackoff,
+        checked: &mut HashMap<&'a str, SwitchType>,
+    ) -> SwitchType {
+        if let Some(switch_type) = checked.get(server.name.as_str()) {
+            return *switch_type;
+        }
+
+        let switch_type = match server.backoff.cached() {
+            Some(switch_type) => switch_type,
+            None => {
+                let triggers = match &server.triggers {
+                    Some(overrides) => triggers.merge(overrides),
+                    None => triggers.clone(),
+                };
+
+                let (switch_type, bitrate) = server
+                    .stream_server
+                    .switch(&adjustment.apply(&triggers))
+                    .await;
+                server.last_poll.set(bitrate);
+
+                let failed = server.stream_server.request_failed();
+                if let Some(delay) =
+                    server
+                        .backoff
+                        .update(failed, switch_type, server.poll_interval(), poll_backoff)
+                {
+                    debug!(
+                        "Unable to reach {}, polling again in {:.1}s",
+                        server.name,
+                        delay.as_secs_f64()
+                    );
+                }
+
+                switch_type
+            }
+        };
+
+        checked.insert(&server.name, switch_type);
+        switch_type
+    }
+
+    pub async fn switch_if_necessary(
+        &self,
+        switch_scene: &str,
+        switch_type: SwitchType,
+    ) -> Result<bool, error::Error> {
+        debug!(
+            "Switch scene: {} Switch type: {:?}",
+            switch_scene, switch_type
+        );
+
+        let state = self.state.read().await;
+        let current_scene = &state.broadcasting_software.current_scene;
+
+        if current_scene == switch_scene {
+            return Ok(false);
+        }
+
+        let skip = state
+            .config
+            .optional_scenes
+            .starting
+            .as_ref()
+            .map_or(false, |starting_scene| {
+                let switch_to_live = state
+                    .config
+                    .optional_options
+                    .switch_from_starting_scene_to_live_scene;
+                current_scene == starting_scene
+                    && switch_to_live
+                    && (switch_type == SwitchType::Offline)
+            });
+
+        if skip
+            || !state
+                .switcher_state
+                .switchable_scenes
+                .contains(&state.broadcasting_software.current_scene)
+        {
+            return Ok(false);
+        }
+
+        let bsc = state
+            .broadcasting_software
+            .connection
+            .clone()
+            .ok_or(error::Error::NoSoftwareSet)?;
+
+        let transition = state.config.switcher.transitions.get(switch_scene).cloned();
+
+        let chat = state
+            .config
+            .chat
+            .as_ref()
+            .map(|chat| (chat.platform.kind(), chat.username.to_owned()));
+
+        // Confirming can take a few seconds, don't block everything else meanwhile
+        drop(state);
+
+        let switched = broadcasting_software::switch_scene_confirmed(
+            bsc.as_ref(),
+            switch_scene,
+            transition.as_ref(),
+        )
+        .await;
+
+        let scene = match switched {
+            Ok(scene) => scene,
+            Err(error::Error::SceneSwitchNotConfirmed(scene)) => {
+                error!("Switch to {} failed, trying again on the next check", scene);
+
+                // Only once until a switch works again, it keeps retrying
+                if let Some((platform, channel)) = chat {
+                    if !self.switch_failed_notified.swap(true, Ordering::Relaxed) {
+                        let message =
+                            chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+                                platform,
+                                channel,
+                                kind: chat::InternalUpdate::SceneSwitchFailed(scene),
+                            });
+
+                        let _ = self.chat_sender.send(message).await;
+                    }
+                }
+
+                return Ok(false);
+            }
+            // Ignore the error.. it should work at some point
+            Err(error) => {
+                error!("Switch scene error {:?}", error);
+                return Ok(false);
+            }
+        };
+
+        info!("Scene switched to [{:?}] {}", switch_type, scene);
+        self.switch_failed_notified.store(false, Ordering::Relaxed);
+        noalbs::switch_instance(&self.state, switch_scene).await;
+
+        let notify = {
+            let mut state = self.state.write().await;
+
+            // Confirmed by reading it back, no need to wait for the event
+            state.broadcasting_software.set_current_scene(scene);
+
+            state.broadcasting_software.is_streaming
+                && state.config.switcher.auto_switch_notification
+                && state.switcher_state.notifications_muted().is_none()
+        };
+
+        if let (true, Some((platform, channel))) = (notify, chat) {
+            let message =
+                chat::HandleMessage::AutomaticSwitchingScene(chat::AutomaticSwitchingScene {
+                    platform,
+                    channel,
+                    scene: switch_scene.to_owned(),
+                    switch_type,
+                });
+
+            let _ = self.chat_sender.send(message).await;
+        }
+
+        Ok(true)
+    }
+}
+
+/// Counts how many times in a row the same switch type was seen
+fn count_same_type(prev_switch_type: &mut SwitchType, same_type: &mut u8, current: SwitchType) {
+    if *prev_switch_type == current {
+        *same_type = same_type.saturating_add(1);
+    } else {
+        debug!("Got different type, switching to that");
+
+        *prev_switch_type = current;
+        *same_type = 0;
+    }
+}
+
+/// Type to switch to while the live scene is forced, only offline gets through
+fn hold_live(switch_type: SwitchType) -> SwitchType {
+    match switch_type {
+        SwitchType::Low | SwitchType::Previous => SwitchType::Normal,
+        other => other,
+    }
+}
+
+/// Attempts of the same switch type needed before switching
+fn required_attempts(switch_type: SwitchType, config: &config::Switcher) -> u8 {
+    match (switch_type, config.offline_retry_attempts) {
+        (SwitchType::Offline, Some(attempts)) => attempts,
+        _ => config.retry_attempts,
+    }
+}
+
+/// Values the trigger expression is checked against, taken from the same
+/// poll as the switch type. A group uses the combined bitrate of its links
+fn expression_metrics(
+    state: &State,
+    server: &stream_servers::StreamServer,
+    stream_servers: &[stream_servers::StreamServer],
+    aggregation: config::GroupAggregation,
+) -> trigger_expression::Metrics {
+    let bitrate = server.last_poll.bitrate();
+
+    let kbps = match &server.group {
+        Some(group) => aggregation.aggregate(
+            stream_servers
+                .iter()
+                .filter(|s| s.enabled && s.group.as_ref() == Some(group))
+                .filter_map(|link| link.last_poll.bitrate().kbps),
+        ),
+        None => bitrate.kbps,
+    };
+
+    trigger_expression::Metrics {
+        bitrate: kbps.map(f64::from),
+        rtt: bitrate.rtt,
+        packet_loss: bitrate.packet_loss,
+        dropped_frames: state.broadcasting_software.dropped_frames.percentage(),
+    }
+}
+
+/// Whether every enabled stream server failed to report its stats,
+/// instead of reporting the stream as offline
+fn stats_unreachable(stream_servers: &[stream_servers::StreamServer]) -> bool {
+    let mut enabled = stream_servers.iter().filter(|s| s.enabled).peekable();
+
+    enabled.peek().is_some() && enabled.all(|s| s.stream_server.request_failed())
+}
+
+/// Switch type of a group of bonded links from the switch type and bitrate
+/// of each link. Offline links don't count towards the combined bitrate.
+fn group_switch_type(
+    links: &[(SwitchType, Option<u32>)],
+    triggers: &Triggers,
+    aggregation: config::GroupAggregation,
+) -> SwitchType {
+    let online = links.iter().filter(|(t, _)| *t != SwitchType::Offline);
+
+    let Some(kbps) = aggregation.aggregate(online.clone().filter_map(|(_, kbps)| *kbps)) else {
+        // No bitrate to combine, use the best link
+        return online
+            .map(|(t, _)| *t)
+            .min_by_key(|t| match t {
+                SwitchType::Normal => 0,
+                SwitchType::Previous => 1,
+                _ => 2,
+            })
+            .unwrap_or(SwitchType::Offline);
+    };
+
+    match (triggers.offline, triggers.low) {
+        (Some(offline), _) if kbps <= offline => SwitchType::Offline,
+        (_, Some(low)) if kbps <= low => SwitchType::Low,
+        _ => SwitchType::Normal,
+    }
+}
+
+/// Trigger that decided the switch type of a group, the combined bitrate
+/// decides when there is one, otherwise the link the type was taken from
+fn group_trigger(
+    links: &[(SwitchType, Option<u32>)],
+    link_triggers: &[Option<TriggerType>],
+    switch_type: SwitchType,
+) -> Option<TriggerType> {
+    let combined = links
+        .iter()
+        .any(|(t, kbps)| *t != SwitchType::Offline && kbps.is_some());
+
+    if combined {
+        return match switch_type {
+            SwitchType::Low => Some(TriggerType::Low),
+            SwitchType::Offline => Some(TriggerType::Offline),
+            _ => None,
+        };
+    }
+
+    links
+        .iter()
+        .zip(link_triggers)
+        .find(|((t, _), _)| *t == switch_type)
+        .and_then(|(_, trigger)| *trigger)
+}
+
+/// Trigger that decided the switch type of a stream server, read from the
+/// bitrate of the same poll with the same triggers
+fn polled_trigger(
+    server: &stream_servers::StreamServer,
+    switch_type: SwitchType,
+    triggers: &Triggers,
+    adjustment: TriggerAdjustment,
+) -> Option<TriggerType> {
+    let triggers = match &server.triggers {
+        Some(overrides) => triggers.merge(overrides),
+        None => triggers.clone(),
+    };
+
+    fired_trigger(
+        switch_type,
+        &server.last_poll.bitrate(),
+        None,
+        &adjustment.apply(&triggers),
+    )
+}
+
+/// Combines the states of the stream servers in priority order, returns the
+/// index of the server whose scenes and stats should be used
+fn combine_switch_types(
+    types: &[SwitchType],
+    policy: config::OfflinePolicy,
+) -> (Option<usize>, SwitchType) {
+    let first_online = types.iter().position(|t| *t != SwitchType::Offline);
+    let some_offline = types.contains(&SwitchType::Offline);
+
+    let Some(index) = first_online else {
+        return (None, SwitchType::Offline);
+    };
+
+    match policy {
+        config::OfflinePolicy::Priority => (Some(index), types[index]),
+        // Degraded, at least one server still works
+        config::OfflinePolicy::OfflineWhenAll if some_offline => (Some(index), SwitchType::Low),
+        config::OfflinePolicy::OfflineWhenAll => (Some(index), types[index]),
+        config::OfflinePolicy::OfflineWhenAny if some_offline => (None, SwitchType::Offline),
+        config::OfflinePolicy::OfflineWhenAny => (Some(index), types[index]),
+    }
+}
+
+/// Where the switch decisions get recorded
+#[derive(Debug)]
+struct SwitchRecord {
+    switch_log_path: Option<String>,
+    stats_db_path: Option<String>,
+    user: String,
+}
+
+impl SwitchRecord {
+    fn enabled(&self) -> bool {
+        self.switch_log_path.is_some() || self.stats_db_path.is_some()
+    }
+}
+
+/// Changes to the triggers before a stream server is checked
+#[derive(Debug, Clone, Copy, Default)]
+struct TriggerAdjustment {
+    /// Raises the low trigger, see [`Triggers::raise_low`]
+    recover_margin: u32,
+
+    /// Leaves out the RTT trigger since the switcher compares it against
+    /// the smoothed RTT instead
+    smooth_rtt: bool,
+
+    /// Leaves out the low trigger since the switcher compares it against
+    /// the smoothed bitrate instead
+    smooth_bitrate: bool,
+}
+
+impl TriggerAdjustment {
+    fn apply(&self, triggers: &Triggers) -> Triggers {
+        let mut triggers = triggers.raise_low(self.recover_margin);
+
+        if self.smooth_rtt {
+            triggers.rtt = None;
+        }
+
+        if self.smooth_bitrate {
+            triggers.low = None;
+        }
+
+        triggers
+    }
+}
+
+/// Figures out which trigger caused a switch of the given type
+pub fn fired_trigger(
+    switch_type: SwitchType,
+    bitrate: &stream_servers::Bitrate,
+    dropped_frames: Option<f64>,
+    triggers: &Triggers,
+) -> Option<TriggerType> {
+    let high_rtt = |trigger: Option<u32>| match (bitrate.rtt, trigger) {
+        (Some(rtt), Some(trigger)) => rtt >= trigger as f64,
+        _ => false,
+    };
+
+    match switch_type {
+        // None of the triggers decided, the expression did
+        SwitchType::Low if triggers.expression.is_some() => None,
+        SwitchType::Low => {
+            let low_bitrate = match (bitrate.kbps, triggers.low) {
+                (Some(kbps), Some(low)) => kbps <= low,
+                _ => false,
+            };
+
+            if low_bitrate {
+                Some(TriggerType::Low)
+            } else if high_rtt(triggers.rtt) {
+                Some(TriggerType::Rtt)
+            } else if stream_servers::high_packet_loss(bitrate.packet_loss, triggers) {
+                Some(TriggerType::PacketLoss)
+            } else if too_many_dropped_frames(dropped_frames, triggers) {
+                Some(TriggerType::DroppedFrames)
+            } else {
+                Some(TriggerType::Low)
+            }
+        }
+        SwitchType::Offline if high_rtt(triggers.rtt_offline) => Some(TriggerType::RttOffline),
+        SwitchType::Offline => Some(TriggerType::Offline),
+        _ => None,
+    }
+}
+
+/// Checks the dropped frames percentage against the trigger
+pub fn too_many_dropped_frames(dropped_frames: Option<f64>, triggers: &Triggers) -> bool {
+    match (dropped_frames, triggers.dropped_frames) {
+        (Some(percentage), Some(trigger)) => percentage >= trigger as f64,
+        _ => false,
+    }
+}
+
+/// Whether the last switch was too recent to switch again,
+/// switching to or from the offline scene is always allowed
+fn within_min_scene_duration(
+    last_switch: Option<(Instant, SwitchType)>,
+    switch_type: SwitchType,
+    min: Duration,
+    now: Instant,
+) -> bool {
+    let Some((switched_at, last_type)) = last_switch else {
+        return false;
+    };
+
+    if switch_type == SwitchType::Offline || last_type == SwitchType::Offline {
+        return false;
+    }
+
+    now.saturating_duration_since(switched_at) < min
+}
+
+/// Sends the stream start and end to the webhook once each. A lost
+/// connection to OBS isn't the stream ending, so a reconnect while
+/// streaming doesn't send another start.
+pub async fn watch_stream_state(user_state: noalbs::UserState) {
+    let (started, stopped, connected) = {
+        let state = user_state.read().await;
+        let bs = &state.broadcasting_software;
+        (
+            bs.start_streaming_notifier(),
+            bs.stop_streaming_notifier(),
+            bs.connected_notifier(),
+        )
+    };
+
+    let mut lifecycle = StreamLifecycle::default();
+
+    loop {
+        // Created before checking so a change in between isn't missed
+        let started = started.notified();
+        let stopped = stopped.notified();
+        let connected = connected.notified();
+
+        let (kind, event) = {
+            let state = user_state.read().await;
+            let bs = &state.broadcasting_software;
+            let webhook = &state.config.optional_options.webhook;
+
+            let kind = lifecycle.update(bs.status == ClientStatus::Connected, bs.is_streaming);
+
+            let event = kind
+                .zip(
+                    webhook
+                        .url
+                        .to_owned()
+                        .filter(|_| webhook.enabled && webhook.stream_events),
+                )
+                .map(|(kind, url)| {
+                    let uptime_seconds = (kind == webhook::StreamEventKind::StreamEnded)
+                        .then(|| bs.last_stream_started_at.elapsed().as_secs());
+
+                    (
+                        url,
+                        webhook::StreamEvent {
+                            user: state.config.user.name.to_owned(),
+                            event: kind,
+                            scene: bs.current_scene.to_owned(),
+                            uptime_seconds,
+                            timestamp: webhook::timestamp(),
+                        },
+                    )
+                });
+
+            (kind, event)
+        };
+
+        if let Some((url, event)) = event {
+            info!("Sending {:?} to the webhook", event.event);
+            webhook::send(url, event);
+        }
+
+        match kind {
+            Some(webhook::StreamEventKind::StreamStarted) => start_sequence(&user_state).await,
+            Some(webhook::StreamEventKind::StreamEnded) => {
+                user_state.write().await.switcher_state.startup_until = None;
+            }
+            None => {}
+        }
+
+        tokio::select! {
+            _ = started => {},
+            _ = stopped => {},
+            _ = connected => {},
+        }
+    }
+}
+
+/// Shows the starting scene when `starting_scene_duration_seconds` is set,
+/// the switcher holds until it has been shown long enough
+async fn start_sequence(user_state: &noalbs::UserState) {
+    let (scene, duration) = {
+        let state = user_state.read().await;
+        let starting = state.config.optional_scenes.starting.to_owned();
+        let duration = state
+            .config
+            .optional_options
+            .starting_scene_duration_seconds;
+
+        match starting.zip(duration) {
+            Some((scene, seconds)) => (scene, Duration::from_secs(seconds)),
+            None => return,
+        }
+    };
+
+    // Held before switching so the switcher doesn't switch away in between
+    user_state.write().await.switcher_state.startup_until = Some(Instant::now() + duration);
+
+    let (connection, transition) = {
+        let state = user_state.read().await;
+
+        (
+            state.broadcasting_software.connection.clone(),
+            state.config.switcher.transitions.get(&scene).cloned(),
+        )
+    };
+
+    let switched = match connection {
+        Some(bsc) => {
+            broadcasting_software::switch_scene_confirmed(bsc.as_ref(), &scene, transition.as_ref())
+                .await
+        }
+        None => Err(error::Error::NoSoftwareSet),
+    };
+
+    if switched.is_ok() {
+        noalbs::switch_instance(user_state, &scene).await;
+    }
+
+    let mut state = user_state.write().await;
+
+    match switched {
+        Ok(scene) => {
+            info!("Showing the starting scene for {:?}", duration);
+            state.broadcasting_software.set_current_scene(scene);
+        }
+        Err(e) => {
+            error!("Unable to switch to the starting scene: {}", e);
+            state.switcher_state.startup_until = None;
+        }
+    }
+}
+
+/// Last known streaming state of OBS, unknown until the first time
+/// it's connected so starting NOALBS mid-stream isn't a start
+#[derive(Debug, Default)]
+struct StreamLifecycle {
+    streaming: Option<bool>,
+}
+
+impl StreamLifecycle {
+    /// Returns the event when the stream started or ended since the last
+    /// update, nothing is known while disconnected
+    fn update(&mut self, connected: bool, streaming: bool) -> Option<webhook::StreamEventKind> {
+        if !connected {
+            return None;
+        }
+
+        match self.streaming.replace(streaming) {
+            Some(false) if streaming => Some(webhook::StreamEventKind::StreamStarted),
+            Some(true) if !streaming => Some(webhook::StreamEventKind::StreamEnded),
+            _ => None,
+        }
+    }
+}
+
+async fn get_optional_scenes<'a>(
+    server: Option<&'a stream_servers::StreamServer>,
+    state: &tokio::sync::RwLockReadGuard<'_, crate::state::State>,
+) -> Option<&'a SwitchingScenes> {
+    if let Some(depends) = &server?.depends_on {
+        if let Some(backup_scenes) = &depends.backup_scenes {
+            if !is_stream_server_online(&depends.name, state).await {
+                debug!("The depended stream server is offline. Going to use the backup scenes.");
+                return Some(backup_scenes);
+            }
+        }
+    }
+
+    server?.override_scenes.as_ref()
+}
+
+async fn is_stream_server_online(
+    server_name: &str,
+    state: &tokio::sync::RwLockReadGuard<'_, crate::state::State>,
+) -> bool {
+    match state
+        .config
+        .switcher
+        .stream_servers
+        .iter()
+        .find(|&x| x.name == server_name)
+    {
+        Some(server) => server.stream_server.bitrate().await.message.is_some(),
+        None => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SwitchingScenes {
+    pub normal: String,
+    pub low: String,
+    pub offline: String,
+
+    /// Low scene when the RTT trigger fired, uses low when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_low: Option<String>,
+
+    /// Low scene when the dropped frames trigger fired, uses low when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dropped_frames_low: Option<String>,
+
+    /// Low scene when the packet loss trigger fired, uses low when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub packet_loss_low: Option<String>,
+
+    /// Offline scene when the RTT offline trigger fired, uses offline when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_offline: Option<String>,
+}
 
 impl SwitchingScenes {
     pub fn new<N, L, O>(normal: N, low: L, offline: O) -> Self
@@ -375,6 +1879,10 @@ impl SwitchingScenes {
             normal: normal.into(),
             low: low.into(),
             offline: offline.into(),
+            rtt_low: None,
+            dropped_frames_low: None,
+            packet_loss_low: None,
+            rtt_offline: None,
         }
     }
 
@@ -386,14 +1894,100 @@ impl SwitchingScenes {
             _ => return Err(error::Error::SwitchTypeNotSupported),
         })
     }
+
+    /// Scene of the trigger that caused the switch, falls back to the
+    /// scene of the switch type
+    pub fn trigger_to_scene(
+        &self,
+        s_type: &SwitchType,
+        trigger: Option<TriggerType>,
+    ) -> Result<&str, error::Error> {
+        let scene = match trigger {
+            Some(TriggerType::Rtt) => self.rtt_low.as_deref(),
+            Some(TriggerType::DroppedFrames) => self.dropped_frames_low.as_deref(),
+            Some(TriggerType::PacketLoss) => self.packet_loss_low.as_deref(),
+            Some(TriggerType::RttOffline) => self.rtt_offline.as_deref(),
+            _ => None,
+        };
+
+        match scene {
+            Some(scene) if matches!(s_type, SwitchType::Low | SwitchType::Offline) => Ok(scene),
+            _ => self.type_to_scene(s_type),
+        }
+    }
+
+    /// Every low scene, including the ones of specific triggers
+    pub fn low_scenes(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.low)
+            .chain(&self.rtt_low)
+            .chain(&self.dropped_frames_low)
+            .chain(&self.packet_loss_low)
+    }
+
+    /// Every offline scene, including the ones of specific triggers
+    pub fn offline_scenes(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.offline).chain(&self.rtt_offline)
+    }
+
+    /// Every scene the switcher can switch to
+    pub fn all(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.normal)
+            .chain(self.low_scenes())
+            .chain(self.offline_scenes())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TriggerType {
     Low,
     Rtt,
     Offline,
     RttOffline,
+    DroppedFrames,
+    PacketLoss,
+}
+
+impl TriggerType {
+    /// Unit the trigger value is in
+    pub fn unit(&self) -> &'static str {
+        match self {
+            TriggerType::Low | TriggerType::Offline => "Kbps",
+            TriggerType::Rtt | TriggerType::RttOffline => "ms",
+            TriggerType::DroppedFrames | TriggerType::PacketLoss => "%",
+        }
+    }
+
+    pub const ALL: [TriggerType; 6] = [
+        TriggerType::Low,
+        TriggerType::Rtt,
+        TriggerType::Offline,
+        TriggerType::RttOffline,
+        TriggerType::DroppedFrames,
+        TriggerType::PacketLoss,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TriggerType::Low => "low",
+            TriggerType::Rtt => "rtt",
+            TriggerType::Offline => "offline",
+            TriggerType::RttOffline => "rtt_offline",
+            TriggerType::DroppedFrames => "dropped_frames",
+            TriggerType::PacketLoss => "packet_loss",
+        }
+    }
+}
+
+impl std::str::FromStr for TriggerType {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TriggerType::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or(error::Error::UnknownTrigger)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -402,6 +1996,10 @@ pub struct Triggers {
     /// Trigger to switch to the low scene
     pub low: Option<u32>,
 
+    /// Warns in chat when the bitrate drops below this, never switches.
+    /// Should be higher than the low trigger
+    pub low_warn: Option<u32>,
+
     /// Trigger to switch to the low scene when RTT is high
     pub rtt: Option<u32>,
 
@@ -410,29 +2008,567 @@ pub struct Triggers {
 
     /// Trigger to switch to the offline scene when RTT is high
     pub rtt_offline: Option<u32>,
+
+    /// Trigger to switch to the low scene when the percentage of dropped
+    /// frames in OBS is high
+    pub dropped_frames: Option<u32>,
+
+    /// Trigger to switch to the low scene when the percentage of lost
+    /// SRT packets is high
+    pub packet_loss: Option<u32>,
+
+    /// Decides between the live and the low scene instead of the triggers
+    /// above, the offline triggers still switch to the offline scene
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<trigger_expression::Expression>,
 }
 
 impl Triggers {
+    pub fn get(&self, kind: TriggerType) -> Option<u32> {
+        match kind {
+            TriggerType::Low => self.low,
+            TriggerType::Rtt => self.rtt,
+            TriggerType::Offline => self.offline,
+            TriggerType::RttOffline => self.rtt_offline,
+            TriggerType::DroppedFrames => self.dropped_frames,
+            TriggerType::PacketLoss => self.packet_loss,
+        }
+    }
+
     pub fn set_low(&mut self, value: Option<u32>) {
         self.low = value;
     }
+
+    /// Uses the triggers set in overrides, anything unset will be inherited
+    pub fn merge(&self, overrides: &Triggers) -> Triggers {
+        Triggers {
+            low: overrides.low.or(self.low),
+            low_warn: overrides.low_warn.or(self.low_warn),
+            rtt: overrides.rtt.or(self.rtt),
+            offline: overrides.offline.or(self.offline),
+            rtt_offline: overrides.rtt_offline.or(self.rtt_offline),
+            dropped_frames: overrides.dropped_frames.or(self.dropped_frames),
+            packet_loss: overrides.packet_loss.or(self.packet_loss),
+            expression: overrides
+                .expression
+                .clone()
+                .or_else(|| self.expression.clone()),
+        }
+    }
+
+    /// Raises the low trigger, used to switch back from the low scene
+    /// only when the bitrate is above the trigger by a margin
+    pub fn raise_low(&self, margin: u32) -> Triggers {
+        Triggers {
+            low: self.low.map(|low| low.saturating_add(margin)),
+            ..self.clone()
+        }
+    }
 }
 
 impl Default for Triggers {
     fn default() -> Self {
         Self {
             low: Some(800),
+            low_warn: None,
             rtt: Some(2500),
             offline: None,
             rtt_offline: None,
+            dropped_frames: None,
+            packet_loss: None,
+            expression: None,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SwitchType {
     Normal,
     Low,
     Previous,
     Offline,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_triggers() {
+        let global = Triggers {
+            low: Some(800),
+            low_warn: None,
+            rtt: Some(2500),
+            offline: None,
+            rtt_offline: Some(3000),
+            dropped_frames: Some(10),
+            packet_loss: None,
+            expression: None,
+        };
+
+        let overrides = Triggers {
+            low: Some(400),
+            low_warn: None,
+            rtt: None,
+            offline: Some(100),
+            rtt_offline: None,
+            dropped_frames: None,
+            packet_loss: Some(5),
+            expression: None,
+        };
+
+        let merged = global.merge(&overrides);
+
+        assert_eq!(merged.low, Some(400));
+        assert_eq!(merged.rtt, Some(2500));
+        assert_eq!(merged.offline, Some(100));
+        assert_eq!(merged.rtt_offline, Some(3000));
+        assert_eq!(merged.dropped_frames, Some(10));
+        assert_eq!(merged.packet_loss, Some(5));
+    }
+
+    #[test]
+    fn scene_per_trigger() {
+        let mut scenes = SwitchingScenes::new("live", "low", "offline");
+        scenes.rtt_low = Some("high rtt".to_string());
+        scenes.packet_loss_low = Some("packet loss".to_string());
+        scenes.rtt_offline = Some("rtt offline".to_string());
+
+        let scene = |s_type, trigger| scenes.trigger_to_scene(&s_type, trigger).unwrap();
+
+        assert_eq!(scene(SwitchType::Normal, None), "live");
+        assert_eq!(scene(SwitchType::Low, Some(TriggerType::Low)), "low");
+        assert_eq!(scene(SwitchType::Low, Some(TriggerType::Rtt)), "high rtt");
+        assert_eq!(
+            scene(SwitchType::Low, Some(TriggerType::PacketLoss)),
+            "packet loss"
+        );
+        assert_eq!(
+            scene(SwitchType::Offline, Some(TriggerType::Offline)),
+            "offline"
+        );
+        assert_eq!(
+            scene(SwitchType::Offline, Some(TriggerType::RttOffline)),
+            "rtt offline"
+        );
+
+        // Not set falls back to the low scene
+        assert_eq!(
+            scene(SwitchType::Low, Some(TriggerType::DroppedFrames)),
+            "low"
+        );
+        assert_eq!(scene(SwitchType::Low, None), "low");
+    }
+
+    #[test]
+    fn switching_scenes_without_trigger_scenes() {
+        let scenes: SwitchingScenes =
+            serde_json::from_str(r#"{ "normal": "live", "low": "low", "offline": "offline" }"#)
+                .unwrap();
+
+        assert_eq!(scenes.rtt_low, None);
+        assert_eq!(
+            serde_json::to_string(&scenes).unwrap(),
+            r#"{"normal":"live","low":"low","offline":"offline"}"#
+        );
+    }
+
+    #[test]
+    fn raise_low_trigger() {
+        let triggers = Triggers::default();
+
+        assert_eq!(triggers.raise_low(200).low, Some(1000));
+        assert_eq!(triggers.raise_low(200).rtt, triggers.rtt);
+    }
+
+    #[test]
+    fn min_scene_duration() {
+        let now = Instant::now();
+        let min = Duration::from_secs(10);
+        let recent = Some((now - Duration::from_secs(5), SwitchType::Low));
+        let old = Some((now - Duration::from_secs(15), SwitchType::Low));
+
+        assert!(within_min_scene_duration(
+            recent,
+            SwitchType::Normal,
+            min,
+            now
+        ));
+        assert!(!within_min_scene_duration(
+            old,
+            SwitchType::Normal,
+            min,
+            now
+        ));
+        assert!(!within_min_scene_duration(
+            None,
+            SwitchType::Normal,
+            min,
+            now
+        ));
+
+        // Offline always switches
+        assert!(!within_min_scene_duration(
+            recent,
+            SwitchType::Offline,
+            min,
+            now
+        ));
+
+        let from_offline = Some((now - Duration::from_secs(5), SwitchType::Offline));
+        assert!(!within_min_scene_duration(
+            from_offline,
+            SwitchType::Normal,
+            min,
+            now
+        ));
+    }
+
+    #[test]
+    fn fired_trigger_type() {
+        let triggers = Triggers {
+            low: Some(800),
+            low_warn: None,
+            rtt: Some(2500),
+            offline: None,
+            rtt_offline: Some(3000),
+            dropped_frames: Some(10),
+            packet_loss: Some(5),
+            expression: None,
+        };
+
+        let bitrate = |kbps, rtt| stream_servers::Bitrate {
+            message: None,
+            kbps,
+            rtt,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Low,
+                &bitrate(Some(500), Some(100.0)),
+                None,
+                &triggers
+            ),
+            Some(TriggerType::Low)
+        );
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Low,
+                &bitrate(Some(2000), Some(2600.0)),
+                None,
+                &triggers
+            ),
+            Some(TriggerType::Rtt)
+        );
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Offline,
+                &bitrate(Some(2000), Some(3500.0)),
+                None,
+                &triggers
+            ),
+            Some(TriggerType::RttOffline)
+        );
+        assert_eq!(
+            fired_trigger(SwitchType::Offline, &bitrate(None, None), None, &triggers),
+            Some(TriggerType::Offline)
+        );
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Low,
+                &bitrate(Some(2000), Some(100.0)),
+                Some(12.5),
+                &triggers
+            ),
+            Some(TriggerType::DroppedFrames)
+        );
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Low,
+                &stream_servers::Bitrate {
+                    kbps: Some(2000),
+                    packet_loss: Some(7.5),
+                    ..Default::default()
+                },
+                None,
+                &triggers
+            ),
+            Some(TriggerType::PacketLoss)
+        );
+        assert_eq!(
+            fired_trigger(
+                SwitchType::Normal,
+                &bitrate(Some(2000), None),
+                None,
+                &triggers
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn expression_replaces_the_low_triggers() {
+        let triggers = Triggers {
+            offline: Some(100),
+            expression: Some(
+                trigger_expression::Expression::parse("bitrate < 2000 and rtt > 400").unwrap(),
+            ),
+            ..Default::default()
+        };
+
+        let bitrate = stream_servers::Bitrate {
+            kbps: Some(500),
+            rtt: Some(500.0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            fired_trigger(SwitchType::Low, &bitrate, None, &triggers),
+            None
+        );
+        assert_eq!(
+            fired_trigger(SwitchType::Offline, &bitrate, None, &triggers),
+            Some(TriggerType::Offline)
+        );
+
+        let overrides = Triggers {
+            low: None,
+            rtt: None,
+            ..Default::default()
+        };
+        assert!(triggers.merge(&overrides).expression.is_some());
+    }
+
+    #[test]
+    fn offline_blip_does_not_switch() {
+        let config = config::Switcher {
+            retry_attempts: 1,
+            offline_retry_attempts: Some(3),
+            ..Default::default()
+        };
+
+        let mut prev = SwitchType::Normal;
+        let mut same = 0;
+        let mut confirmed = Vec::new();
+
+        for current in [
+            SwitchType::Normal,
+            SwitchType::Offline,
+            SwitchType::Normal,
+            SwitchType::Low,
+            SwitchType::Low,
+        ] {
+            count_same_type(&mut prev, &mut same, current);
+
+            if same == required_attempts(current, &config) {
+                confirmed.push(current);
+                same = 0;
+            }
+        }
+
+        assert_eq!(confirmed, vec![SwitchType::Normal, SwitchType::Low]);
+
+        for _ in 0..4 {
+            count_same_type(&mut prev, &mut same, SwitchType::Offline);
+        }
+
+        assert_eq!(same, required_attempts(SwitchType::Offline, &config));
+    }
+
+    #[test]
+    fn force_live_only_lets_offline_through() {
+        assert_eq!(hold_live(SwitchType::Low), SwitchType::Normal);
+        assert_eq!(hold_live(SwitchType::Previous), SwitchType::Normal);
+        assert_eq!(hold_live(SwitchType::Normal), SwitchType::Normal);
+        assert_eq!(hold_live(SwitchType::Offline), SwitchType::Offline);
+    }
+
+    #[test]
+    fn offline_policies() {
+        use config::OfflinePolicy::*;
+        use SwitchType::{Low, Normal, Offline};
+
+        let cases = [
+            (
+                [Normal, Normal],
+                (Some(0), Normal),
+                (Some(0), Normal),
+                (Some(0), Normal),
+            ),
+            (
+                [Normal, Low],
+                (Some(0), Normal),
+                (Some(0), Normal),
+                (Some(0), Normal),
+            ),
+            (
+                [Low, Normal],
+                (Some(0), Low),
+                (Some(0), Low),
+                (Some(0), Low),
+            ),
+            ([Low, Low], (Some(0), Low), (Some(0), Low), (Some(0), Low)),
+            (
+                [Normal, Offline],
+                (Some(0), Normal),
+                (Some(0), Low),
+                (None, Offline),
+            ),
+            (
+                [Offline, Normal],
+                (Some(1), Normal),
+                (Some(1), Low),
+                (None, Offline),
+            ),
+            (
+                [Low, Offline],
+                (Some(0), Low),
+                (Some(0), Low),
+                (None, Offline),
+            ),
+            (
+                [Offline, Low],
+                (Some(1), Low),
+                (Some(1), Low),
+                (None, Offline),
+            ),
+            (
+                [Offline, Offline],
+                (None, Offline),
+                (None, Offline),
+                (None, Offline),
+            ),
+        ];
+
+        for (types, priority, when_all, when_any) in cases {
+            assert_eq!(
+                combine_switch_types(&types, Priority),
+                priority,
+                "{:?}",
+                types
+            );
+            assert_eq!(
+                combine_switch_types(&types, OfflineWhenAll),
+                when_all,
+                "{:?}",
+                types
+            );
+            assert_eq!(
+                combine_switch_types(&types, OfflineWhenAny),
+                when_any,
+                "{:?}",
+                types
+            );
+        }
+    }
+
+    #[test]
+    fn group_aggregation_modes() {
+        let triggers = Triggers {
+            offline: Some(200),
+            ..Default::default()
+        };
+
+        // One weak modem, one fine and one that dropped out
+        let links = [
+            (SwitchType::Low, Some(500)),
+            (SwitchType::Normal, Some(1500)),
+            (SwitchType::Offline, None),
+        ];
+
+        let switch_type = |aggregation| group_switch_type(&links, &triggers, aggregation);
+
+        assert_eq!(
+            switch_type(config::GroupAggregation::Sum),
+            SwitchType::Normal
+        );
+        assert_eq!(
+            switch_type(config::GroupAggregation::Max),
+            SwitchType::Normal
+        );
+        assert_eq!(switch_type(config::GroupAggregation::Min), SwitchType::Low);
+    }
+
+    #[test]
+    fn group_aggregation_offline() {
+        let triggers = Triggers {
+            offline: Some(200),
+            ..Default::default()
+        };
+
+        let weak = [(SwitchType::Low, Some(100)), (SwitchType::Low, Some(50))];
+        assert_eq!(
+            group_switch_type(&weak, &triggers, config::GroupAggregation::Sum),
+            SwitchType::Offline
+        );
+        assert_eq!(
+            group_switch_type(&weak, &triggers, config::GroupAggregation::Min),
+            SwitchType::Offline
+        );
+
+        let offline = [(SwitchType::Offline, None), (SwitchType::Offline, None)];
+        assert_eq!(
+            group_switch_type(&offline, &triggers, config::GroupAggregation::Max),
+            SwitchType::Offline
+        );
+    }
+
+    #[test]
+    fn group_without_bitrate_uses_best_link() {
+        let links = [(SwitchType::Low, None), (SwitchType::Normal, None)];
+
+        assert_eq!(
+            group_switch_type(&links, &Triggers::default(), config::GroupAggregation::Sum),
+            SwitchType::Normal
+        );
+    }
+
+    #[test]
+    fn group_trigger_follows_the_decision() {
+        let combined = [(SwitchType::Low, Some(300)), (SwitchType::Offline, None)];
+        let triggers = [Some(TriggerType::Rtt), Some(TriggerType::Offline)];
+
+        // The combined bitrate decided, not the RTT of a link
+        assert_eq!(
+            group_trigger(&combined, &triggers, SwitchType::Low),
+            Some(TriggerType::Low)
+        );
+        assert_eq!(
+            group_trigger(&combined, &triggers, SwitchType::Normal),
+            None
+        );
+
+        let offline = [(SwitchType::Offline, None), (SwitchType::Offline, None)];
+        let triggers = [Some(TriggerType::RttOffline), Some(TriggerType::Offline)];
+        assert_eq!(
+            group_trigger(&offline, &triggers, SwitchType::Offline),
+            Some(TriggerType::RttOffline)
+        );
+    }
+
+    #[test]
+    fn stream_events_once_per_transition() {
+        use webhook::StreamEventKind::*;
+
+        let mut lifecycle = StreamLifecycle::default();
+
+        // Already streaming when NOALBS started
+        assert_eq!(lifecycle.update(true, true), None);
+        assert_eq!(lifecycle.update(true, false), Some(StreamEnded));
+        assert_eq!(lifecycle.update(true, false), None);
+
+        assert_eq!(lifecycle.update(true, true), Some(StreamStarted));
+        assert_eq!(lifecycle.update(true, true), None);
+
+        // OBS dropped and came back while still streaming
+        assert_eq!(lifecycle.update(false, false), None);
+        assert_eq!(lifecycle.update(true, true), None);
+
+        // OBS came back after the stream stopped
+        assert_eq!(lifecycle.update(false, false), None);
+        assert_eq!(lifecycle.update(true, false), Some(StreamEnded));
+    }
+}
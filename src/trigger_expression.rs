@@ -0,0 +1,503 @@
+//! Combined trigger conditions like
+//! `(bitrate < 2000 and rtt > 400) or packet_loss > 5%`, the low scene is
+//! used while the expression is true.
+
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::Error;
+
+/// Values of the stream the expression is checked against, a metric the
+/// stream server doesn't report is `None`
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    /// Kbps
+    pub bitrate: Option<f64>,
+
+    /// Milliseconds
+    pub rtt: Option<f64>,
+
+    /// Percentage
+    pub packet_loss: Option<f64>,
+
+    /// Percentage
+    pub dropped_frames: Option<f64>,
+}
+
+/// A parsed trigger expression, written back to the config as it was typed
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: source.chars().count() + 1,
+        };
+
+        let root = parser.or()?;
+
+        if let Some((position, token)) = parser.peek() {
+            return Err(invalid(*position, format!("unexpected {}", token)));
+        }
+
+        Ok(Self {
+            source: source.trim().to_owned(),
+            root,
+        })
+    }
+
+    /// Whether the stream should be on the low scene
+    pub fn matches(&self, metrics: &Metrics) -> bool {
+        self.root.eval(metrics)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+/// Invalid expressions are rejected when the config is loaded
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+
+        Expression::parse(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    Bitrate,
+    Rtt,
+    PacketLoss,
+    DroppedFrames,
+}
+
+impl Metric {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "bitrate" => Metric::Bitrate,
+            "rtt" => Metric::Rtt,
+            "packet_loss" | "loss" => Metric::PacketLoss,
+            "dropped_frames" => Metric::DroppedFrames,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::Bitrate => "bitrate",
+            Metric::Rtt => "rtt",
+            Metric::PacketLoss => "packet_loss",
+            Metric::DroppedFrames => "dropped_frames",
+        }
+    }
+
+    fn value(&self, metrics: &Metrics) -> Option<f64> {
+        match self {
+            Metric::Bitrate => metrics.bitrate,
+            Metric::Rtt => metrics.rtt,
+            Metric::PacketLoss => metrics.packet_loss,
+            Metric::DroppedFrames => metrics.dropped_frames,
+        }
+    }
+
+    /// Multiplier of the units the metric can be compared in
+    fn unit(&self, unit: &str) -> Option<f64> {
+        match (self, unit) {
+            (Metric::Bitrate, "kbps") => Some(1.0),
+            (Metric::Bitrate, "mbps") => Some(1000.0),
+            (Metric::Rtt, "ms") => Some(1.0),
+            (Metric::Rtt, "s") => Some(1000.0),
+            (Metric::PacketLoss | Metric::DroppedFrames, "%") => Some(1.0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn compare(&self, left: f64, right: f64) -> bool {
+        match self {
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare { metric: Metric, op: Op, value: f64 },
+}
+
+impl Node {
+    fn eval(&self, metrics: &Metrics) -> bool {
+        match self {
+            Node::Or(left, right) => left.eval(metrics) || right.eval(metrics),
+            Node::And(left, right) => left.eval(metrics) && right.eval(metrics),
+            Node::Not(node) => !node.eval(metrics),
+            // Unknown values never match
+            Node::Compare { metric, op, value } => metric
+                .value(metrics)
+                .is_some_and(|current| op.compare(current, *value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Percent,
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "\"{}\"", word),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Percent => f.write_str("%"),
+            Token::Op(_) => f.write_str("comparison"),
+            Token::And => f.write_str("and"),
+            Token::Or => f.write_str("or"),
+            Token::Not => f.write_str("not"),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+fn invalid(position: usize, message: impl Into<String>) -> Error {
+    Error::InvalidTriggerExpression(position, message.into())
+}
+
+/// Splits the expression into tokens with their position, counted from 1
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let position = i + 1;
+        let next = chars.get(i + 1).copied();
+
+        let (token, len) = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '%' => (Token::Percent, 1),
+            '<' if next == Some('=') => (Token::Op(Op::Le), 2),
+            '<' => (Token::Op(Op::Lt), 1),
+            '>' if next == Some('=') => (Token::Op(Op::Ge), 2),
+            '>' => (Token::Op(Op::Gt), 1),
+            '=' if next == Some('=') => (Token::Op(Op::Eq), 2),
+            '=' => (Token::Op(Op::Eq), 1),
+            '!' if next == Some('=') => (Token::Op(Op::Ne), 2),
+            '!' => (Token::Not, 1),
+            '&' if next == Some('&') => (Token::And, 2),
+            '|' if next == Some('|') => (Token::Or, 2),
+            c if c.is_ascii_digit() || c == '.' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == '.')
+                    .count();
+                let text: String = chars[i..i + len].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| invalid(position, format!("invalid number {}", text)))?;
+
+                (Token::Number(number), len)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_alphanumeric() || **c == '_')
+                    .count();
+                let word = chars[i..i + len].iter().collect::<String>().to_lowercase();
+
+                let token = match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                };
+
+                (token, len)
+            }
+            c => return Err(invalid(position, format!("unexpected '{}'", c))),
+        };
+
+        tokens.push((position, token));
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+/// `or` binds weaker than `and`, which binds weaker than `not`
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+
+    /// Position reported when the expression ends too early
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.next)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek().is_some_and(|(_, t)| t == token);
+
+        if found {
+            self.next += 1;
+        }
+
+        found
+    }
+
+    fn take(&mut self, expected: &str) -> Result<(usize, Token), Error> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| invalid(self.end, format!("expected {}", expected)))?;
+
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Node, Error> {
+        let mut node = self.and()?;
+
+        while self.eat(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, Error> {
+        let mut node = self.not()?;
+
+        while self.eat(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, Error> {
+        if self.eat(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+
+        if self.eat(&Token::Open) {
+            let node = self.or()?;
+
+            return match self.take("')'")? {
+                (_, Token::Close) => Ok(node),
+                (position, token) => Err(invalid(position, format!("expected ')', got {}", token))),
+            };
+        }
+
+        self.compare()
+    }
+
+    /// `metric op number`, optionally followed by a unit
+    fn compare(&mut self) -> Result<Node, Error> {
+        let metric = match self.take("a metric")? {
+            (position, Token::Word(word)) => Metric::from_name(&word)
+                .ok_or_else(|| invalid(position, format!("unknown metric \"{}\"", word)))?,
+            (position, token) => {
+                return Err(invalid(
+                    position,
+                    format!("expected a metric, got {}", token),
+                ))
+            }
+        };
+
+        let op = match self.take("a comparison")? {
+            (_, Token::Op(op)) => op,
+            (position, token) => {
+                return Err(invalid(
+                    position,
+                    format!("expected a comparison, got {}", token),
+                ))
+            }
+        };
+
+        let value = match self.take("a number")? {
+            (_, Token::Number(value)) => value,
+            (position, token) => {
+                return Err(invalid(
+                    position,
+                    format!("expected a number, got {}", token),
+                ))
+            }
+        };
+
+        let unit = match self.peek() {
+            Some((position, Token::Percent)) => Some((*position, "%".to_owned())),
+            Some((position, Token::Word(word))) => Some((*position, word.to_owned())),
+            _ => None,
+        };
+
+        let multiplier = match unit {
+            Some((position, unit)) => {
+                self.next += 1;
+
+                metric.unit(&unit).ok_or_else(|| {
+                    invalid(
+                        position,
+                        format!("{} can't be compared in {}", metric.name(), unit),
+                    )
+                })?
+            }
+            None => 1.0,
+        };
+
+        Ok(Node::Compare {
+            metric,
+            op,
+            value: value * multiplier,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(bitrate: f64, rtt: f64, packet_loss: f64) -> Metrics {
+        Metrics {
+            bitrate: Some(bitrate),
+            rtt: Some(rtt),
+            packet_loss: Some(packet_loss),
+            dropped_frames: None,
+        }
+    }
+
+    #[test]
+    fn combined_conditions() {
+        let expression =
+            Expression::parse("(bitrate < 2000 AND rtt > 400) OR packet_loss > 5%").unwrap();
+
+        assert!(expression.matches(&metrics(1500.0, 500.0, 0.0)));
+        assert!(!expression.matches(&metrics(1500.0, 300.0, 0.0)));
+        assert!(!expression.matches(&metrics(2500.0, 500.0, 1.0)));
+        assert!(expression.matches(&metrics(2500.0, 100.0, 6.0)));
+    }
+
+    #[test]
+    fn and_binds_stronger_than_or() {
+        let expression = Expression::parse("rtt > 400 or bitrate < 2000 and loss > 5").unwrap();
+
+        assert!(expression.matches(&metrics(3000.0, 500.0, 0.0)));
+        assert!(!expression.matches(&metrics(1000.0, 100.0, 0.0)));
+        assert!(expression.matches(&metrics(1000.0, 100.0, 10.0)));
+    }
+
+    #[test]
+    fn not_and_symbols() {
+        let expression =
+            Expression::parse("!(bitrate >= 1000) && rtt != 0 || loss == 100").unwrap();
+
+        assert!(expression.matches(&metrics(500.0, 20.0, 0.0)));
+        assert!(!expression.matches(&metrics(500.0, 0.0, 0.0)));
+        assert!(!expression.matches(&metrics(1000.0, 20.0, 0.0)));
+        assert!(expression.matches(&metrics(1000.0, 20.0, 100.0)));
+    }
+
+    #[test]
+    fn units_are_converted() {
+        let expression = Expression::parse("bitrate < 1.5 mbps and rtt > 1s").unwrap();
+
+        assert!(expression.matches(&metrics(1400.0, 1200.0, 0.0)));
+        assert!(!expression.matches(&metrics(1600.0, 1200.0, 0.0)));
+        assert!(!expression.matches(&metrics(1400.0, 900.0, 0.0)));
+    }
+
+    #[test]
+    fn unknown_metrics_never_match() {
+        let expression = Expression::parse("dropped_frames > 10 or rtt > 400").unwrap();
+
+        assert!(!expression.matches(&Metrics::default()));
+        assert!(expression.matches(&Metrics {
+            dropped_frames: Some(20.0),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        let position = |source: &str| match Expression::parse(source) {
+            Err(Error::InvalidTriggerExpression(position, _)) => position,
+            other => panic!("{} parsed as {:?}", source, other),
+        };
+
+        assert_eq!(position("viewers > 10"), 1);
+        assert_eq!(position("bitrate 2000"), 9);
+        assert_eq!(position("bitrate < 2000 and"), 19);
+        assert_eq!(position("(rtt > 400"), 11);
+        assert_eq!(position("rtt > 400)"), 10);
+        assert_eq!(position("rtt > 400%"), 10);
+        assert_eq!(position("rtt > 400 # 2"), 11);
+        assert_eq!(position(""), 1);
+    }
+
+    #[test]
+    fn keeps_the_text_as_typed() {
+        let expression: Expression =
+            serde_json::from_str(r#"" bitrate < 2000 OR loss > 5% ""#).unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&expression).unwrap(),
+            r#""bitrate < 2000 OR loss > 5%""#
+        );
+        assert!(serde_json::from_str::<Expression>(r#""bitrate <""#).is_err());
+    }
+}
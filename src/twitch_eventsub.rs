@@ -0,0 +1,504 @@
+//! Twitch EventSub over WebSocket to know when a channel is live on Twitch,
+//! OBS streaming doesn't always mean Twitch received the stream.
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{net::TcpStream, sync::mpsc, sync::Mutex, time};
+use tokio_tungstenite::{tungstenite::Message as TMessage, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::chat;
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const TW_EVENTSUB_WS: &str = "wss://eventsub.wss.twitch.tv/ws";
+const TW_VALIDATE: &str = "https://id.twitch.tv/oauth2/validate";
+const TW_SUBSCRIPTIONS: &str = "https://api.twitch.tv/helix/eventsub/subscriptions";
+const TW_STREAMS: &str = "https://api.twitch.tv/helix/streams";
+
+/// Used when the welcome message doesn't include a keepalive timeout
+const DEFAULT_KEEPALIVE: u64 = 10;
+
+/// Extra time on top of the keepalive timeout before reconnecting
+const KEEPALIVE_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize, Debug)]
+struct Message {
+    metadata: Metadata,
+    #[serde(default)]
+    payload: Payload,
+}
+
+#[derive(Deserialize, Debug)]
+struct Metadata {
+    message_type: MessageType,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum MessageType {
+    SessionWelcome,
+    SessionKeepalive,
+    SessionReconnect,
+    Notification,
+    Revocation,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Payload {
+    session: Option<Session>,
+    subscription: Option<Subscription>,
+    event: Option<StreamEvent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Session {
+    id: String,
+    keepalive_timeout_seconds: Option<u64>,
+    reconnect_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Subscription {
+    #[serde(rename = "type")]
+    kind: String,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct StreamEvent {
+    broadcaster_user_login: String,
+}
+
+/// Who the oauth token belongs to
+#[derive(Deserialize, Debug, Clone)]
+pub struct TokenInfo {
+    pub client_id: String,
+    pub user_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Streams {
+    data: Vec<LiveStream>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LiveStream {
+    user_login: String,
+}
+
+/// Why a session ended
+enum Ended {
+    /// Twitch asked to move to a new URL, the subscriptions move along
+    Reconnect(String, Box<Connection>),
+    Disconnected,
+}
+
+struct State {
+    /// Login of every channel by Twitch id
+    channels: HashMap<String, String>,
+
+    /// Id of the current session, subscriptions are only possible when set
+    session_id: Option<String>,
+}
+
+#[derive(Clone)]
+struct Auth {
+    token: String,
+    client_id: String,
+}
+
+pub struct EventSub {
+    handle: tokio::task::JoinHandle<()>,
+    state: Arc<Mutex<State>>,
+    auth: Arc<Mutex<Option<Auth>>>,
+}
+
+impl EventSub {
+    /// Only connects when `TWITCH_EVENTSUB` is set to true, every channel
+    /// uses part of the subscription limit of the bot oauth token
+    pub fn from_env(
+        oauth: &str,
+        chat_handler_tx: mpsc::Sender<chat::HandleMessage>,
+    ) -> Option<Self> {
+        let enabled = env::var("TWITCH_EVENTSUB")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+
+        enabled.then(|| Self::connect(oauth.to_owned(), chat_handler_tx))
+    }
+
+    pub fn connect(oauth: String, chat_handler_tx: mpsc::Sender<chat::HandleMessage>) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            channels: HashMap::new(),
+            session_id: None,
+        }));
+        let auth = Arc::new(Mutex::new(None));
+
+        let handle = tokio::spawn(run_loop(
+            oauth,
+            auth.clone(),
+            state.clone(),
+            chat_handler_tx,
+        ));
+
+        Self {
+            handle,
+            state,
+            auth,
+        }
+    }
+
+    /// Subscribes to the stream online and offline events of the channel
+    pub async fn add_channel(&self, twitch_id: String, username: String) {
+        let mut state = self.state.lock().await;
+
+        if state.channels.contains_key(&twitch_id) {
+            return;
+        }
+
+        if let (Some(session_id), Some(auth)) = (&state.session_id, &*self.auth.lock().await) {
+            subscribe(auth, session_id, &twitch_id).await;
+        }
+
+        state.channels.insert(twitch_id, username);
+    }
+}
+
+impl Drop for EventSub {
+    // Abort the spawned task
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn run_loop(
+    oauth: String,
+    auth: Arc<Mutex<Option<Auth>>>,
+    state: Arc<Mutex<State>>,
+    chat_handler_tx: mpsc::Sender<chat::HandleMessage>,
+) {
+    let token = oauth.strip_prefix("oauth:").unwrap_or(&oauth).to_owned();
+
+    let client_id = match validate(&token).await {
+        Ok(info) => info.client_id,
+        Err(e) => {
+            error!(
+                "Unable to validate the Twitch oauth token for EventSub: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    *auth.lock().await = Some(Auth { token, client_id });
+
+    let mut reconnect: Option<(String, Box<Connection>)> = None;
+
+    loop {
+        let (ws_stream, previous) = match reconnect.take() {
+            Some((url, previous)) => match tokio_tungstenite::connect_async(url.as_str()).await {
+                Ok((ws_stream, _)) => (ws_stream, Some(previous)),
+                Err(e) => {
+                    warn!("Unable to reconnect to Twitch EventSub: {}", e);
+                    (get_connection().await, None)
+                }
+            },
+            None => (get_connection().await, None),
+        };
+
+        let ended = handle_session(ws_stream, previous, &auth, &state, &chat_handler_tx).await;
+
+        if let Ended::Reconnect(url, previous) = ended {
+            debug!("Twitch EventSub asked to reconnect");
+            reconnect = Some((url, previous));
+            continue;
+        }
+
+        // The subscriptions are gone with the session
+        let channels = {
+            let mut state = state.lock().await;
+            state.session_id = None;
+            state.channels.values().cloned().collect::<Vec<_>>()
+        };
+
+        for channel in channels {
+            send_update(&chat_handler_tx, channel, None).await;
+        }
+    }
+}
+
+async fn get_connection() -> Connection {
+    let mut retry_grow = 1;
+
+    loop {
+        info!("Connecting to Twitch EventSub");
+
+        if let Ok((ws_stream, _)) = tokio_tungstenite::connect_async(TW_EVENTSUB_WS).await {
+            info!("Connected to Twitch EventSub");
+            break ws_stream;
+        }
+
+        let wait = 1 << retry_grow;
+        warn!("Unable to connect to Twitch EventSub");
+        info!("trying to connect again in {} seconds", wait);
+        time::sleep(Duration::from_secs(wait)).await;
+
+        if retry_grow < 5 {
+            retry_grow += 1;
+        }
+    }
+}
+
+/// Reads the messages of a session, `previous` is the connection that asked
+/// to reconnect and is closed once the new session is welcomed
+async fn handle_session(
+    mut ws_stream: Connection,
+    mut previous: Option<Box<Connection>>,
+    auth: &Mutex<Option<Auth>>,
+    state: &Mutex<State>,
+    chat_handler_tx: &mpsc::Sender<chat::HandleMessage>,
+) -> Ended {
+    let mut keepalive = Duration::from_secs(DEFAULT_KEEPALIVE);
+
+    loop {
+        let message = match time::timeout(keepalive + KEEPALIVE_GRACE, ws_stream.next()).await {
+            Ok(Some(Ok(message))) => message,
+            Ok(_) => {
+                warn!("Disconnected from Twitch EventSub");
+                return Ended::Disconnected;
+            }
+            Err(_) => {
+                warn!("No keepalive from Twitch EventSub, reconnecting");
+                return Ended::Disconnected;
+            }
+        };
+
+        let TMessage::Text(text) = message else {
+            continue;
+        };
+
+        let message: Message = match serde_json::from_str(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                trace!(?e, text, "Ignoring EventSub message");
+                continue;
+            }
+        };
+
+        trace!(?message, "Received EventSub message");
+        let payload = message.payload;
+
+        match message.metadata.message_type {
+            MessageType::SessionWelcome => {
+                let Some(session) = payload.session else {
+                    continue;
+                };
+
+                if let Some(seconds) = session.keepalive_timeout_seconds {
+                    keepalive = Duration::from_secs(seconds);
+                }
+
+                // Subscriptions carry over to a session after a reconnect
+                let resumed = previous.take().is_some();
+                debug!("Twitch EventSub session {} started", session.id);
+
+                let Some(auth) = auth.lock().await.clone() else {
+                    continue;
+                };
+
+                let channels = {
+                    let mut state = state.lock().await;
+                    state.session_id = Some(session.id.to_owned());
+                    state.channels.clone()
+                };
+
+                if resumed {
+                    continue;
+                }
+
+                for id in channels.keys() {
+                    subscribe(&auth, &session.id, id).await;
+                }
+
+                sync_live_channels(&auth, &channels, chat_handler_tx).await;
+            }
+            MessageType::SessionKeepalive => {}
+            MessageType::SessionReconnect => {
+                if let Some(url) = payload.session.and_then(|s| s.reconnect_url) {
+                    return Ended::Reconnect(url, Box::new(ws_stream));
+                }
+            }
+            MessageType::Notification => {
+                let (Some(subscription), Some(event)) = (payload.subscription, payload.event)
+                else {
+                    continue;
+                };
+
+                let online = match subscription.kind.as_str() {
+                    "stream.online" => true,
+                    "stream.offline" => false,
+                    _ => continue,
+                };
+
+                info!(
+                    "Twitch reports {} {}",
+                    event.broadcaster_user_login,
+                    if online { "online" } else { "offline" }
+                );
+
+                send_update(chat_handler_tx, event.broadcaster_user_login, Some(online)).await;
+            }
+            MessageType::Revocation => {
+                if let Some(subscription) = payload.subscription {
+                    warn!(
+                        "Twitch revoked the {} EventSub subscription: {}",
+                        subscription.kind, subscription.status
+                    );
+                }
+            }
+        }
+    }
+}
+
+async fn send_update(
+    chat_handler_tx: &mpsc::Sender<chat::HandleMessage>,
+    channel: String,
+    online: Option<bool>,
+) {
+    let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+        channel,
+        platform: chat::ChatPlatform::Twitch,
+        kind: chat::InternalUpdate::PlatformStreamOnline(online),
+    });
+
+    if let Err(e) = chat_handler_tx.send(message).await {
+        error!("Unable to send the Twitch stream state: {}", e);
+    }
+}
+
+/// Returns the client id and user of the token, the client id is needed
+/// for every Helix request
+pub async fn validate(token: &str) -> Result<TokenInfo, reqwest::Error> {
+    reqwest::Client::new()
+        .get(TW_VALIDATE)
+        .header("Authorization", format!("OAuth {}", token))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+async fn subscribe(auth: &Auth, session_id: &str, twitch_id: &str) {
+    let client = reqwest::Client::new();
+
+    for kind in ["stream.online", "stream.offline"] {
+        let body = json!({
+            "type": kind,
+            "version": "1",
+            "condition": { "broadcaster_user_id": twitch_id },
+            "transport": { "method": "websocket", "session_id": session_id },
+        });
+
+        let res = client
+            .post(TW_SUBSCRIPTIONS)
+            .header("Client-Id", &auth.client_id)
+            .bearer_auth(&auth.token)
+            .json(&body)
+            .send()
+            .await;
+
+        match res {
+            Ok(res) if res.status().is_success() => {
+                debug!("Subscribed to {} of {}", kind, twitch_id);
+            }
+            Ok(res) => {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                error!(
+                    "Unable to subscribe to {} of {}: {} {}",
+                    kind, twitch_id, status, text
+                );
+            }
+            Err(e) => error!("Unable to subscribe to {} of {}: {}", kind, twitch_id, e),
+        }
+    }
+}
+
+/// Events only arrive on changes, so ask which channels are live right now
+async fn sync_live_channels(
+    auth: &Auth,
+    channels: &HashMap<String, String>,
+    chat_handler_tx: &mpsc::Sender<chat::HandleMessage>,
+) {
+    if channels.is_empty() {
+        return;
+    }
+
+    let query: Vec<_> = channels.keys().map(|id| ("user_id", id)).collect();
+
+    let res = reqwest::Client::new()
+        .get(TW_STREAMS)
+        .header("Client-Id", &auth.client_id)
+        .bearer_auth(&auth.token)
+        .query(&query)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status());
+
+    let streams: Streams = match res {
+        Ok(res) => match res.json().await {
+            Ok(streams) => streams,
+            Err(e) => {
+                error!("Unable to read the live Twitch streams: {}", e);
+                return;
+            }
+        },
+        Err(e) => {
+            error!("Unable to get the live Twitch streams: {}", e);
+            return;
+        }
+    };
+
+    for channel in channels.values() {
+        let online = streams
+            .data
+            .iter()
+            .any(|s| s.user_login.eq_ignore_ascii_case(channel));
+
+        send_update(chat_handler_tx, channel.to_owned(), Some(online)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_messages() {
+        let welcome = r#"{"metadata":{"message_id":"1","message_type":"session_welcome","message_timestamp":"2023-07-19T14:56:51.634234626Z"},"payload":{"session":{"id":"AQoQILE98gtqShGmLD7AM6yJThAB","status":"connected","connected_at":"2023-07-19T14:56:51.616329898Z","keepalive_timeout_seconds":10,"reconnect_url":null}}}"#;
+        let message: Message = serde_json::from_str(welcome).unwrap();
+        assert_eq!(message.metadata.message_type, MessageType::SessionWelcome);
+        assert_eq!(
+            message.payload.session.unwrap().keepalive_timeout_seconds,
+            Some(10)
+        );
+
+        let keepalive = r#"{"metadata":{"message_id":"2","message_type":"session_keepalive","message_timestamp":"2023-07-19T10:11:12.634234626Z"},"payload":{}}"#;
+        let message: Message = serde_json::from_str(keepalive).unwrap();
+        assert_eq!(message.metadata.message_type, MessageType::SessionKeepalive);
+
+        let online = r#"{"metadata":{"message_id":"3","message_type":"notification","message_timestamp":"2023-07-19T10:11:12.634234626Z","subscription_type":"stream.online","subscription_version":"1"},"payload":{"subscription":{"id":"f1c2a387","status":"enabled","type":"stream.online","version":"1","cost":0,"condition":{"broadcaster_user_id":"1337"},"transport":{"method":"websocket","session_id":"AQoQexAWVYKSTIu4ec_2VAxyuhAB"},"created_at":"2019-11-16T10:11:12.634234626Z"},"event":{"id":"9001","broadcaster_user_id":"1337","broadcaster_user_login":"cool_user","broadcaster_user_name":"Cool_User","type":"live","started_at":"2020-10-11T10:11:12.123Z"}}}"#;
+        let message: Message = serde_json::from_str(online).unwrap();
+        assert_eq!(message.payload.subscription.unwrap().kind, "stream.online");
+        assert_eq!(
+            message.payload.event.unwrap().broadcaster_user_login,
+            "cool_user"
+        );
+    }
+}
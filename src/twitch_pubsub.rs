@@ -383,7 +383,10 @@ async fn handle_messages(
 
                         let (channel, ignore) = {
                             let mut lock = state.lock().await;
-                            let user = lock.users.get_mut(&raid.source_id).unwrap();
+                            let Some(user) = lock.users.get_mut(&raid.source_id) else {
+                                warn!(source_id = ?raid.source_id, "Raid from an unknown channel");
+                                continue;
+                            };
                             let ignore = user.last_raid.elapsed().as_secs() < 10;
                             let channel = user.username.to_owned();
 
@@ -0,0 +1,208 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{chat, config::ConfigChatPlatform, error, noalbs, state::State};
+
+const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+const USHER_BASE: &str = "https://usher.ttvnw.net";
+const GQL_BASE: &str = "https://gql.twitch.tv/gql";
+
+/// Checks the Twitch playlist of the channel for transcoded qualities
+// TODO: Check if not an ad?
+pub async fn is_transcoding(channel: &str) -> Result<bool, error::Error> {
+    let req_string = r#"{"query": "{streamPlaybackAccessToken(channelName: \"%USER%\",params: {platform: \"web\",playerBackend: \"mediaplayer\",playerType: \"site\"}){value signature}}"}"#;
+    let req_string = req_string.replace("%USER%", channel);
+
+    let client = reqwest::Client::new();
+    let res = client
+        .post(GQL_BASE)
+        .header("Client-ID", CLIENT_ID)
+        .body(req_string)
+        .send()
+        .await?;
+
+    let json = res.json::<serde_json::Value>().await?;
+    let json = json["data"]["streamPlaybackAccessToken"].to_owned();
+    let json: StreamPlaybackAccessToken = serde_json::from_value(json)?;
+
+    use rand::Rng;
+    let rng = rand::thread_rng().gen_range(1000000..10000000);
+    let query = M3u8Query {
+        allow_source: String::from("true"),
+        allow_audio_only: String::from("true"),
+        allow_spectre: String::from("true"),
+        p: rng,
+        player: String::from("twitchweb"),
+        playlist_include_framerate: String::from("true"),
+        segment_preference: String::from("4"),
+        sig: json.signature,
+        token: json.value,
+    };
+
+    let res = client
+        .get(format!("{}/api/channel/hls/{}.m3u8", USHER_BASE, channel))
+        .header("Client-ID", CLIENT_ID)
+        .query(&query)
+        .send()
+        .await?;
+
+    let text = res.text().await?;
+
+    Ok(playlist_transcoded(&text))
+}
+
+/// Whether the playlist has transcoded qualities next to the source
+fn playlist_transcoded(playlist: &str) -> bool {
+    !(playlist.contains("TRANSCODESTACK=\"transmux\"")
+        || playlist.contains("Can not find channel")
+        || playlist.contains("transcode_does_not_exist"))
+}
+
+#[derive(Debug, Serialize)]
+struct M3u8Query {
+    allow_source: String,
+    allow_audio_only: String,
+    allow_spectre: String,
+    p: u32,
+    player: String,
+    playlist_include_framerate: String,
+    segment_preference: String,
+    sig: String,
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamPlaybackAccessToken {
+    value: String,
+    signature: String,
+}
+
+/// Checks that Twitch transcodes the stream every time it starts and
+/// lets chat know when it never does so the ingest can be refreshed
+pub async fn watch(user_state: noalbs::UserState, chat_sender: noalbs::ChatSender) {
+    let notifier = {
+        let state = user_state.read().await;
+        state.broadcasting_software.start_streaming_notifier()
+    };
+
+    loop {
+        notifier.notified().await;
+
+        let check = {
+            let state = user_state.read().await;
+            Check::from_state(&state)
+        };
+
+        let Some(check) = check else {
+            continue;
+        };
+
+        if !check.missing(&user_state).await {
+            continue;
+        }
+
+        warn!(
+            "Twitch didn't transcode the stream after {} checks",
+            check.retries
+        );
+
+        let message = chat::HandleMessage::InternalChatUpdate(chat::InternalChatUpdate {
+            platform: chat::ChatPlatform::Twitch,
+            channel: check.channel,
+            kind: chat::InternalUpdate::NoTranscoding,
+        });
+
+        let _ = chat_sender.send(message).await;
+    }
+}
+
+struct Check {
+    channel: String,
+    retries: u64,
+    delay: Duration,
+}
+
+impl Check {
+    fn from_state(state: &State) -> Option<Self> {
+        let options = &state.config.optional_options;
+
+        // !start already restarts the stream until it's transcoded
+        if !options.twitch_transcoding_check
+            || options.twitch_transcoding_retries == 0
+            || state.broadcasting_software.transcoding_restart
+        {
+            return None;
+        }
+
+        let chat = state.config.chat.as_ref()?;
+
+        if !matches!(chat.platform, ConfigChatPlatform::Twitch) {
+            return None;
+        }
+
+        Some(Self {
+            channel: chat.username.to_owned(),
+            retries: options.twitch_transcoding_retries,
+            delay: Duration::from_secs(options.twitch_transcoding_delay_seconds),
+        })
+    }
+
+    /// Polls until the stream is transcoded, true when it never was.
+    /// The attempts start over when the stream restarts in the meantime.
+    async fn missing(&self, user_state: &noalbs::UserState) -> bool {
+        let mut started_at: Option<Instant> = None;
+        let mut attempt = 0;
+
+        while attempt < self.retries {
+            tokio::time::sleep(self.delay).await;
+
+            {
+                let state = user_state.read().await;
+                let bs = &state.broadcasting_software;
+
+                if !bs.is_streaming {
+                    return false;
+                }
+
+                if started_at.is_some_and(|s| s != bs.last_stream_started_at) {
+                    attempt = 0;
+                }
+
+                started_at = Some(bs.last_stream_started_at);
+            }
+
+            attempt += 1;
+
+            match is_transcoding(&self.channel).await {
+                Ok(true) => {
+                    info!("Twitch is transcoding the stream");
+                    return false;
+                }
+                Ok(false) => debug!("[{}] Stream is not transcoded yet", attempt),
+                Err(e) => debug!("[{}] Unable to check transcoding: {}", attempt, e),
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transcoded_playlist() {
+        let source_only = r#"#EXT-X-TWITCH-INFO:NODE="video-edge",TRANSCODESTACK="transmux""#;
+        assert!(!playlist_transcoded(source_only));
+
+        let offline = r#"[{"error":"Can not find channel","error_code":"does_not_exist"}]"#;
+        assert!(!playlist_transcoded(offline));
+
+        let transcoded =
+            r#"#EXT-X-TWITCH-INFO:NODE="video-edge",TRANSCODESTACK="2023-Transcode-QS-V1""#;
+        assert!(playlist_transcoded(transcoded));
+    }
+}
@@ -1,8 +1,13 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use tokio::sync::RwLock;
+use tracing::{error, info};
 
-use crate::{chat, config, Noalbs};
+use crate::{chat, config, error::Error, noalbs::ChatSender, Noalbs};
 
 type User = Arc<RwLock<HashMap<String, Arc<Noalbs>>>>;
 
@@ -22,14 +27,104 @@ impl UserManager {
         self.users.clone()
     }
 
-    pub async fn add(&self, user: Noalbs) {
+    /// Adds the user by its name, a name can only be used once
+    pub async fn add(&self, user: Noalbs) -> Result<Arc<Noalbs>, Error> {
+        let (name, id) = {
+            let state = user.state.read().await;
+            (state.config.user.name.clone(), state.config.user.id)
+        };
+
         let lock = &mut self.users.write().await;
 
-        let state = user.state.read().await;
-        let key = state.config.user.name.clone();
-        drop(state);
+        if lock.contains_key(&name) {
+            return Err(Error::UserExists(name));
+        }
+
+        if let Some(id) = id {
+            for other in lock.values() {
+                if other.state.read().await.config.user.id == Some(id) {
+                    return Err(Error::UserExists(id.to_string()));
+                }
+            }
+        }
+
+        let user = Arc::new(user);
+        lock.insert(name, user.clone());
+
+        Ok(user)
+    }
+
+    /// Loads the config file and starts the user
+    pub async fn add_from_file<P>(
+        &self,
+        path: P,
+        chat_sender: ChatSender,
+    ) -> Result<Arc<Noalbs>, Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let file = config::File { name: path.into() };
+        let user = Noalbs::new(Box::new(file), chat_sender).await?;
+
+        self.add(user).await
+    }
+
+    /// Starts a user for every config file in the directory, a config that
+    /// fails to load only skips that user. Returns how many users were added.
+    pub async fn load_dir<P>(&self, dir: P, chat_sender: ChatSender) -> Result<usize, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let paths = std::fs::read_dir(dir)?
+            .filter_map(|f| f.ok())
+            .map(|f| f.path())
+            .filter(|p| is_config_file(p));
+
+        let users = paths.map(|path| {
+            let chat_sender = chat_sender.clone();
+
+            async move {
+                let user = self.add_from_file(path.clone(), chat_sender).await;
+                (path, user)
+            }
+        });
+
+        let mut added = 0;
+
+        for (path, user) in futures_util::future::join_all(users).await {
+            match user {
+                Ok(_) => added += 1,
+                Err(e) => error!("Skipping user {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(added)
+    }
+
+    /// Stops the user and removes it, returns it when it existed
+    pub async fn remove(&self, name: &str) -> Option<Arc<Noalbs>> {
+        let user = self.users.write().await.remove(name)?;
 
-        lock.insert(key, Arc::new(user));
+        info!("Removing user {}", name);
+        user.shutdown().await;
+
+        Some(user)
+    }
+
+    pub async fn get_user(&self, name: &str) -> Option<Arc<Noalbs>> {
+        self.users.read().await.get(name).cloned()
+    }
+
+    pub async fn get_user_by_id(&self, id: i64) -> Option<Arc<Noalbs>> {
+        let lock = self.users.read().await;
+
+        for user in lock.values() {
+            if user.state.read().await.config.user.id == Some(id) {
+                return Some(user.clone());
+            }
+        }
+
+        None
     }
 
     /// Returns the platform and username
@@ -47,6 +142,14 @@ impl UserManager {
         all_chat
     }
 
+    /// Shuts down all users at the same time
+    pub async fn shutdown(&self) {
+        let lock = self.users.read().await;
+        let users = lock.values().map(|user| user.shutdown());
+
+        futures_util::future::join_all(users).await;
+    }
+
     // TODO: Probably don't want this???
     pub async fn get_user_by_chat_platform(
         &self,
@@ -69,8 +172,28 @@ impl UserManager {
     }
 }
 
+/// Whether the file has the extension of a supported config format
+fn is_config_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| config::FileFormat::EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
 impl Default for UserManager {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_config_files_are_loaded() {
+        assert!(is_config_file(Path::new("configs/715209.json")));
+        assert!(is_config_file(Path::new("configs/b3ck.TOML")));
+        assert!(!is_config_file(Path::new("configs/.env")));
+        assert!(!is_config_file(Path::new("configs/readme")));
+    }
+}
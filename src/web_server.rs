@@ -5,20 +5,22 @@ use tokio::sync::mpsc::{self, UnboundedSender};
 use tracing::info;
 use warp::{ws::WebSocket, Filter};
 
-use crate::{user_manager::UserManager, ws};
+use crate::{api, user_manager::UserManager, ws};
 
 pub struct WebServer {
     port: u16,
     websocket_handler: Arc<ws::WsHandler>,
+    user_manager: UserManager,
 }
 
 impl WebServer {
     pub fn new(port: u16, user_manager: UserManager) -> Self {
-        let websocket_handler = Arc::new(ws::WsHandler::new(user_manager));
+        let websocket_handler = Arc::new(ws::WsHandler::new(user_manager.clone()));
 
         Self {
             port,
             websocket_handler,
+            user_manager,
         }
     }
 
@@ -40,7 +42,7 @@ impl WebServer {
                 },
             );
 
-        let routes = ws;
+        let routes = ws.or(api::routes(self.user_manager.clone()));
 
         info!("Running web server on 127.0.0.1:{}", self.port);
         let serve = warp::serve(routes).run(([127, 0, 0, 1], self.port));
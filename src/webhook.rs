@@ -0,0 +1,161 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tracing::{debug, error, warn};
+
+use crate::switcher::{SwitchType, TriggerType};
+
+/// Attempts before giving up on a webhook
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubles after every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to create reqwest client")
+});
+
+/// Payload sent to the webhook when the switcher switched scenes
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SwitchEvent {
+    pub user: String,
+    pub switch_type: SwitchType,
+
+    /// Trigger that caused a switch to the low or offline scene
+    pub trigger: Option<TriggerType>,
+
+    pub previous_scene: String,
+    pub scene: String,
+    pub stream_server: Option<String>,
+    pub bitrate: Option<u32>,
+    pub rtt: Option<f64>,
+
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+/// Payload sent to the webhook when the stream started or ended
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamEvent {
+    pub user: String,
+    pub event: StreamEventKind,
+    pub scene: String,
+
+    /// How long the stream was live, only set when it ended
+    pub uptime_seconds: Option<u64>,
+
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StreamEventKind {
+    StreamStarted,
+    StreamEnded,
+}
+
+/// Current unix timestamp in seconds
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// POSTs the event in the background so a slow webhook never blocks switching
+pub fn send<T>(url: String, event: T)
+where
+    T: Serialize + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut delay = RETRY_DELAY;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let res = CLIENT
+                .post(&url)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+
+            match res {
+                Ok(_) => {
+                    debug!("Sent event to webhook {}", url);
+                    return;
+                }
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    warn!(
+                        "Webhook error (attempt {}/{}), retrying in {}s: {}",
+                        attempt,
+                        MAX_ATTEMPTS,
+                        delay.as_secs(),
+                        e
+                    );
+                }
+                Err(e) => {
+                    error!("Webhook error, giving up: {}", e);
+                    return;
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_switch_event() {
+        let event = SwitchEvent {
+            user: "715209".to_string(),
+            switch_type: SwitchType::Low,
+            trigger: Some(TriggerType::RttOffline),
+            previous_scene: "live".to_string(),
+            scene: "low".to_string(),
+            stream_server: Some("SLS".to_string()),
+            bitrate: Some(400),
+            rtt: None,
+            timestamp: 1700000000,
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(json["switchType"], "low");
+        assert_eq!(json["trigger"], "rtt_offline");
+        assert_eq!(json["previousScene"], "live");
+        assert_eq!(json["bitrate"], 400);
+        assert!(json["rtt"].is_null());
+    }
+
+    #[test]
+    fn serialize_stream_event() {
+        let event = StreamEvent {
+            user: "715209".to_string(),
+            event: StreamEventKind::StreamEnded,
+            scene: "ending".to_string(),
+            uptime_seconds: Some(3600),
+            timestamp: 1700000000,
+        };
+
+        let json = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(json["event"], "streamEnded");
+        assert_eq!(json["uptimeSeconds"], 3600);
+    }
+}